@@ -1,5 +1,5 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use image::io::Reader as ImageReader;
+use image::{imageops::FilterType, io::Reader as ImageReader};
 use topcodes::Scanner;
 
 fn scan(scanner: &mut Scanner, buffer: &[u8]) {
@@ -47,5 +47,174 @@ fn criterion_benchmark(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, criterion_benchmark);
+fn downsampled_benchmark(c: &mut Criterion) {
+    let img = ImageReader::open("assets/photo.png")
+        .unwrap()
+        .decode()
+        .unwrap();
+    let img = img.resize(img.width() * 2, img.height() * 2, FilterType::Triangle);
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let buffer = img.into_rgb8().into_raw();
+
+    c.bench_function("Scanner (photo, upscaled)", |b| {
+        b.iter_batched(
+            || Scanner::new(width, height),
+            |mut scanner| scan(&mut scanner, &buffer),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+
+    c.bench_function("Scanner (photo, upscaled, downsampled)", |b| {
+        b.iter_batched(
+            || Scanner::new(width, height),
+            |mut scanner| {
+                let topcodes = scanner.scan_downsampled(
+                    &buffer,
+                    |buffer, index| {
+                        (
+                            buffer[index * 3] as u32,
+                            buffer[index * 3 + 1] as u32,
+                            buffer[index * 3 + 2] as u32,
+                        )
+                    },
+                    2,
+                );
+                assert_eq!(3, topcodes.len());
+            },
+            criterion::BatchSize::SmallInput,
+        )
+    });
+
+    c.bench_function("Scanner (photo, upscaled, downsampled 4x)", |b| {
+        b.iter_batched(
+            || Scanner::new(width, height),
+            |mut scanner| {
+                let topcodes = scanner.scan_downsampled(
+                    &buffer,
+                    |buffer, index| {
+                        (
+                            buffer[index * 3] as u32,
+                            buffer[index * 3 + 1] as u32,
+                            buffer[index * 3 + 2] as u32,
+                        )
+                    },
+                    4,
+                );
+                assert_eq!(3, topcodes.len());
+            },
+            criterion::BatchSize::SmallInput,
+        )
+    });
+}
+
+fn stage_benchmark(c: &mut Criterion) {
+    let img = ImageReader::open("assets/photo.png")
+        .unwrap()
+        .decode()
+        .unwrap();
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let buffer = img.into_rgb8().into_raw();
+
+    c.bench_function("Scanner (photo, threshold only)", |b| {
+        b.iter_batched(
+            || Scanner::new(width, height),
+            |mut scanner| {
+                scanner.threshold_only(&buffer, |buffer, index| {
+                    (
+                        buffer[index * 3] as u32,
+                        buffer[index * 3 + 1] as u32,
+                        buffer[index * 3 + 2] as u32,
+                    )
+                })
+            },
+            criterion::BatchSize::SmallInput,
+        )
+    });
+
+    c.bench_function("Scanner (photo, full scan)", |b| {
+        b.iter_batched(
+            || Scanner::new(width, height),
+            |mut scanner| scan(&mut scanner, &buffer),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+}
+
+fn gray_benchmark(c: &mut Criterion) {
+    let img = ImageReader::open("assets/photo.png")
+        .unwrap()
+        .decode()
+        .unwrap();
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let buffer = img.into_luma8().into_raw();
+
+    c.bench_function("Scanner (photo, gray as RGB)", |b| {
+        b.iter_batched(
+            || Scanner::new(width, height),
+            |mut scanner| {
+                scanner.scan(&buffer, |buffer, index| {
+                    let v = buffer[index] as u32;
+                    (v, v, v)
+                })
+            },
+            criterion::BatchSize::SmallInput,
+        )
+    });
+
+    c.bench_function("Scanner (photo, gray)", |b| {
+        b.iter_batched(
+            || Scanner::new(width, height),
+            |mut scanner| scanner.scan_gray_u8(&buffer).unwrap(),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+}
+
+fn pixel_format_benchmark(c: &mut Criterion) {
+    let img = ImageReader::open("assets/photo.png")
+        .unwrap()
+        .decode()
+        .unwrap();
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let buffer = img.into_rgb8().into_raw();
+
+    // Compare with and without the `simd` feature, which only speeds up the packed formats.
+    c.bench_function("Scanner (photo, RGB closure)", |b| {
+        b.iter_batched(
+            || Scanner::new(width, height),
+            |mut scanner| scan(&mut scanner, &buffer),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+
+    c.bench_function("Scanner (photo, packed RGB)", |b| {
+        b.iter_batched(
+            || Scanner::new(width, height),
+            |mut scanner| assert_eq!(3, scanner.scan_rgb_u8(&buffer).unwrap().len()),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+
+    // Bands are only thresholded in parallel with the `rayon` feature.
+    c.bench_function("Scanner (photo, packed RGB, 8 bands)", |b| {
+        b.iter_batched(
+            || {
+                let mut scanner = Scanner::new(width, height);
+                scanner.set_threshold_bands(8);
+                scanner
+            },
+            |mut scanner| assert_eq!(3, scanner.scan_rgb_u8(&buffer).unwrap().len()),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(
+    benches,
+    criterion_benchmark,
+    downsampled_benchmark,
+    stage_benchmark,
+    gray_benchmark,
+    pixel_format_benchmark
+);
 criterion_main!(benches);
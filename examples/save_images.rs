@@ -1,5 +1,7 @@
 #[cfg(feature = "visualize")]
 use image::io::Reader as ImageReader;
+#[cfg(feature = "visualize")]
+use topcodes::Scanner;
 
 fn main() {
     #[cfg(feature = "visualize")]
@@ -0,0 +1,222 @@
+//! Command line interface to scan images for TopCodes, generate codes for printing and annotate
+//! images with the codes found in them. Built with the `cli` feature.
+
+use std::{
+    error::Error,
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use image::{DynamicImage, GenericImage, GrayImage, Rgba};
+use topcodes::{
+    export::{self, FrameMetadata},
+    print::{self, SheetLayout},
+    Polarity, Scanner, SvgUnit, TopCode,
+};
+
+#[derive(Parser)]
+#[command(
+    name = "topcodes-cli",
+    version,
+    about = "Scan, generate and annotate TopCodes"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Scans an image and prints the codes found in it
+    Scan {
+        image: PathBuf,
+        #[command(flatten)]
+        options: ScanOptions,
+        /// Prints CSV rows rather than JSON
+        #[arg(long)]
+        csv: bool,
+    },
+    /// Writes images of codes for printing
+    Generate {
+        /// The codes to generate
+        #[arg(required = true)]
+        codes: Vec<u32>,
+        #[arg(long, value_enum, default_value_t = Format::Png)]
+        format: Format,
+        /// Width of PNG images in pixels
+        #[arg(long, default_value_t = 256)]
+        size: usize,
+        /// Diameter of the codes in SVG images and PDF sheets, in millimeters
+        #[arg(long, default_value_t = 40.0)]
+        diameter: f64,
+        /// Directory the images are written to
+        #[arg(long, short, default_value = ".")]
+        output: PathBuf,
+    },
+    /// Writes a copy of an image with the codes found in it drawn over it
+    Annotate {
+        image: PathBuf,
+        output: PathBuf,
+        #[command(flatten)]
+        options: ScanOptions,
+    },
+}
+
+#[derive(clap::Args)]
+struct ScanOptions {
+    /// Largest diameter of the codes in pixels
+    #[arg(long)]
+    max_diameter: Option<usize>,
+    /// Colors of the codes to detect
+    #[arg(long, value_enum, default_value_t = PolarityArg::Normal)]
+    polarity: PolarityArg,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PolarityArg {
+    /// Dark codes on a light background
+    Normal,
+    /// Light codes on a dark background
+    Inverted,
+    /// Both normal and inverted codes
+    Both,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    /// A PNG image per code
+    Png,
+    /// An SVG image per code
+    Svg,
+    /// A single PDF document with sheets of codes, `topcodes.pdf`
+    Pdf,
+}
+
+fn main() {
+    if let Err(err) = run(Cli::parse().command) {
+        eprintln!("error: {err}");
+        process::exit(1);
+    }
+}
+
+fn run(command: Command) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Scan {
+            image,
+            options,
+            csv,
+        } => {
+            let (scanner, codes) = scan(&image::open(&image)?, &image, &options)?;
+            let metadata = FrameMetadata::for_scanner(0, &scanner);
+            let mut stdout = io::stdout().lock();
+            if csv {
+                export::write_csv_header(&mut stdout)?;
+                export::write_csv(&mut stdout, &codes, Some(&metadata))?;
+            } else {
+                export::write_json(&mut stdout, &codes, Some(&metadata))?;
+            }
+        }
+        Command::Generate {
+            codes,
+            format,
+            size,
+            diameter,
+            output,
+        } => {
+            fs::create_dir_all(&output)?;
+            if format == Format::Pdf {
+                let layout = SheetLayout {
+                    diameter,
+                    ..Default::default()
+                };
+                let pdf = print::sheet_pdf(&codes, &layout)?;
+                fs::write(output.join("topcodes.pdf"), pdf)?;
+                return Ok(());
+            }
+            for code in codes {
+                let path = output.join(format!("topcode-{code}"));
+                if format == Format::Svg {
+                    let svg = TopCode::to_svg(code, diameter, SvgUnit::Millimeters)?;
+                    fs::write(path.with_extension("svg"), svg)?;
+                } else {
+                    let pixels = TopCode::draw(code, size)?;
+                    let image = GrayImage::from_raw(size as u32, size as u32, pixels)
+                        .ok_or("image size overflows")?;
+                    image.save(path.with_extension("png"))?;
+                }
+            }
+        }
+        Command::Annotate {
+            image,
+            output,
+            options,
+        } => {
+            let source = image::open(&image)?;
+            let (_, codes) = scan(&source, &image, &options)?;
+            let mut annotated = DynamicImage::ImageRgba8(source.into_rgba8());
+            for code in &codes {
+                annotate(&mut annotated, code);
+            }
+            annotated.save(&output)?;
+            eprintln!("Found {} TopCodes.", codes.len());
+        }
+    }
+    Ok(())
+}
+
+/// Scans the image read from `path`, returning the codes found in it along with the scanner, which
+/// is labelled with the path.
+fn scan(
+    image: &DynamicImage,
+    path: &Path,
+    options: &ScanOptions,
+) -> Result<(Scanner, Vec<TopCode>), Box<dyn Error>> {
+    let mut scanner = Scanner::for_image(image)?;
+    scanner.with_source_label(&path.display().to_string());
+    if let Some(diameter) = options.max_diameter {
+        scanner.set_max_code_diameter(diameter);
+    }
+    scanner.set_polarity(match options.polarity {
+        PolarityArg::Normal => Polarity::Normal,
+        PolarityArg::Inverted => Polarity::Inverted,
+        PolarityArg::Both => Polarity::Both,
+    });
+    let codes = scanner.scan_image(image)?;
+    Ok((scanner, codes))
+}
+
+/// Draws the outline of a code, its center and a mark in the direction of its orientation.
+fn annotate(image: &mut DynamicImage, code: &TopCode) {
+    // The casts are no-ops with the `f32` feature.
+    #[allow(clippy::unnecessary_cast)]
+    let (x, y, radius) = (code.x as f32, code.y as f32, code.radius() as f32);
+    #[allow(clippy::unnecessary_cast)]
+    let (sin, cos) = (code.orientation as f32).sin_cos();
+    let outline = [255, 170, 0];
+    for i in 0..720 {
+        let angle = i as f32 / 720.0 * std::f32::consts::TAU;
+        for r in [radius - 1.0, radius] {
+            set_pixel(image, x + r * angle.cos(), y + r * angle.sin(), outline);
+        }
+    }
+    fill_square(image, x + radius * cos, y + radius * sin, 3, [0, 127, 255]);
+    fill_square(image, x, y, 2, [255, 0, 0]);
+}
+
+fn fill_square(image: &mut DynamicImage, x: f32, y: f32, half: i32, color: [u8; 3]) {
+    for dy in -half..=half {
+        for dx in -half..=half {
+            set_pixel(image, x + dx as f32, y + dy as f32, color);
+        }
+    }
+}
+
+/// Sets the pixel at (x, y) to `color`, if it lies within the image.
+fn set_pixel(image: &mut DynamicImage, x: f32, y: f32, [r, g, b]: [u8; 3]) {
+    let (x, y) = (x.round(), y.round());
+    if x >= 0.0 && y >= 0.0 && x < image.width() as f32 && y < image.height() as f32 {
+        image.put_pixel(x as u32, y as u32, Rgba([r, g, b, 255]));
+    }
+}
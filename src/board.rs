@@ -0,0 +1,354 @@
+use alloc::vec::Vec;
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::{
+    homography::Homography,
+    topcode::{Code, Real, TopCode},
+};
+
+/// The center of a detected code in the image, and its position on the board.
+pub(crate) type Match = ((Real, Real), (Real, Real));
+
+/// A rigid target made of several TopCodes at known positions, e.g. a printed grid. Locating the
+/// board as a whole is more robust than relying on any single code: the board is found as long as
+/// two of its codes are detected, and detections which don't fit the others are discarded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Board {
+    /// The codes of the board, with the positions of their centers in board units (e.g. mm)
+    markers: Vec<(Code, (Real, Real))>,
+    max_error: Real,
+}
+
+/// The location of a [Board] in an image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoardPose {
+    /// Maps points in the image to board coordinates
+    pub homography: Homography,
+    /// Number of the board's codes the pose was estimated from
+    pub markers: usize,
+    /// Largest distance (in board units) between the center of one of those codes and its
+    /// position on the board, as mapped by the homography
+    pub error: Real,
+}
+
+impl Board {
+    /// Creates a board from its codes and the positions of their centers, in any unit. Each code
+    /// should only appear once.
+    pub fn new(markers: Vec<(Code, (Real, Real))>) -> Self {
+        let spacing = markers
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (_, a))| markers[i + 1..].iter().map(move |(_, b)| distance(*a, *b)))
+            .fold(Real::INFINITY, Real::min);
+        Self {
+            markers,
+            max_error: if spacing.is_finite() {
+                spacing / 4.0
+            } else {
+                Real::INFINITY
+            },
+        }
+    }
+
+    /// Creates a board of `codes` laid out in rows of `columns` codes, from left to right and
+    /// top to bottom, with their centers `spacing` units apart.
+    pub fn grid(codes: &[Code], columns: usize, spacing: Real) -> Self {
+        let columns = columns.max(1);
+        Self::new(
+            codes
+                .iter()
+                .enumerate()
+                .map(|(i, &code)| {
+                    let (x, y) = (i % columns, i / columns);
+                    (code, (x as Real * spacing, y as Real * spacing))
+                })
+                .collect(),
+        )
+    }
+
+    /// Sets the largest distance (in board units) between a detected code and its position on the
+    /// board for the detection to be used. Defaults to a quarter of the smallest distance between
+    /// two codes of the board. Since a homography fits any four points, a pose fitted to exactly
+    /// four codes is only used if they also fit a rotation, scaling and translation to within
+    /// four times that distance.
+    pub fn set_max_error(&mut self, max_error: Real) {
+        self.max_error = max_error;
+    }
+
+    /// Locates the board from the codes detected in an image. Codes which don't belong to the
+    /// board are ignored, and so are detections which don't fit the others (e.g. a loose code with
+    /// the same ID as one of the board's): the pose is fitted to the largest set of detections
+    /// which agree with each other. With four or more codes, the pose is a full homography, and
+    /// with two or three codes it is limited to a rotation, scaling and translation. Returns
+    /// `None` if fewer than two of the board's codes were detected, or if they don't fit within
+    /// the maximum error.
+    pub fn locate(&self, codes: &[TopCode]) -> Option<BoardPose> {
+        self.locate_matches(codes).map(|(pose, _)| pose)
+    }
+
+    /// Same as [Board::locate], along with the detections the pose was estimated from.
+    pub(crate) fn locate_matches(&self, codes: &[TopCode]) -> Option<(BoardPose, Vec<Match>)> {
+        // Every detection of each of the board's codes, so that a stray code with the ID of one
+        // of them can't hide the real one.
+        let candidates: Vec<Vec<Match>> = self
+            .markers
+            .iter()
+            .map(|&(code, position)| {
+                codes
+                    .iter()
+                    .filter(|c| c.code == Some(code))
+                    .map(|c| ((c.x, c.y), position))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|detections| !detections.is_empty())
+            .collect();
+
+        // Every pair of detections of two different codes proposes a pose, which is refined with
+        // the detections that agree with it. The pose agreeing with the most detections wins,
+        // and among those the least distorted one.
+        let mut best: Option<(BoardPose, Vec<Match>, Real)> = None;
+        for (i, first) in candidates.iter().enumerate() {
+            for second in &candidates[i + 1..] {
+                for (&a, &b) in first
+                    .iter()
+                    .flat_map(|a| second.iter().map(move |b| (a, b)))
+                {
+                    let Some(located) = self.refine(&candidates, &[a, b]) else {
+                        continue;
+                    };
+                    let better = best.as_ref().is_none_or(|(pose, _, distortion)| {
+                        (located.0.markers, -located.2) > (pose.markers, -distortion)
+                    });
+                    if better {
+                        best = Some(located);
+                    }
+                }
+            }
+        }
+        best.map(|(pose, matches, _)| (pose, matches))
+    }
+
+    /// Fits the board to the detections which lie close to where a rotation, scaling and
+    /// translation fitted to `seed` expects them, then refits it to the detections within the
+    /// maximum error until they no longer change. Returns the pose along with its detections
+    /// and how far they are from fitting a rotation, scaling and translation.
+    fn refine(
+        &self,
+        candidates: &[Vec<Match>],
+        seed: &[Match],
+    ) -> Option<(BoardPose, Vec<Match>, Real)> {
+        // Similarity transforms only approximate a board seen in perspective.
+        let tolerance = self.max_error * 4.0;
+        let mut matches = closest(candidates, &fit(seed)?, tolerance);
+        for _ in 0..MAX_REFINEMENTS {
+            let refined = closest(candidates, &fit(&matches)?, self.max_error);
+            if refined == matches {
+                break;
+            }
+            matches = refined;
+        }
+
+        let (homography, error) = fit_with_error(&matches)?;
+        let distortion = largest_error(&fit_similarity(&matches)?, &matches);
+        if error > self.max_error || (matches.len() == 4 && distortion > tolerance) {
+            return None;
+        }
+        let pose = BoardPose {
+            homography,
+            markers: matches.len(),
+            error,
+        };
+        Some((pose, matches, distortion))
+    }
+}
+
+/// Number of times the detections used for a pose are updated after refitting it.
+const MAX_REFINEMENTS: usize = 8;
+
+/// The detection of each of the board's codes which `homography` maps closest to its position on
+/// the board, for the codes with a detection within `tolerance` of it.
+fn closest(candidates: &[Vec<Match>], homography: &Homography, tolerance: Real) -> Vec<Match> {
+    candidates
+        .iter()
+        .filter_map(|detections| {
+            detections
+                .iter()
+                .map(|&((x, y), position)| {
+                    (distance(homography.map(x, y), position), ((x, y), position))
+                })
+                .min_by(|(a, _), (b, _)| a.total_cmp(b))
+                .filter(|&(error, _)| error <= tolerance)
+                .map(|(_, detection)| detection)
+        })
+        .collect()
+}
+
+/// Same as [fit], along with the largest distance between a board point and its image point as
+/// mapped by the fitted homography.
+fn fit_with_error(matches: &[Match]) -> Option<(Homography, Real)> {
+    let homography = fit(matches)?;
+    Some((homography, largest_error(&homography, matches)))
+}
+
+/// The largest distance between a board point and its image point as mapped by `homography`.
+fn largest_error(homography: &Homography, matches: &[Match]) -> Real {
+    matches
+        .iter()
+        .map(|&((x, y), position)| distance(homography.map(x, y), position))
+        .fold(0.0, Real::max)
+}
+
+/// The homography mapping the image points to the board points of the matches, or a similarity
+/// transform for fewer than four matches.
+fn fit(matches: &[Match]) -> Option<Homography> {
+    if matches.len() >= 4 {
+        let (image, board): (Vec<_>, Vec<_>) = matches.iter().copied().unzip();
+        return Homography::from_points(&image, &board);
+    }
+    fit_similarity(matches)
+}
+
+/// The rotation, scaling and translation mapping the image points to the board points of the
+/// matches with the least squared error.
+fn fit_similarity(matches: &[Match]) -> Option<Homography> {
+    if matches.len() < 2 {
+        return None;
+    }
+    let (image, board): (Vec<_>, Vec<_>) = matches.iter().copied().unzip();
+
+    // Least squares fit of `w = a z + b` over complex numbers z in the image and w on the board.
+    let n = matches.len() as Real;
+    let mean = |points: &[(Real, Real)]| {
+        let (x, y) = points
+            .iter()
+            .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+        (x / n, y / n)
+    };
+    let (zx, zy) = mean(&image);
+    let (wx, wy) = mean(&board);
+    let (mut re, mut im, mut norm) = (0.0, 0.0, 0.0);
+    for (&(x, y), &(u, v)) in image.iter().zip(&board) {
+        let (x, y, u, v) = (x - zx, y - zy, u - wx, v - wy);
+        re += u * x + v * y;
+        im += v * x - u * y;
+        norm += x * x + y * y;
+    }
+    if norm == 0.0 {
+        return None;
+    }
+    let (a, b) = (re / norm, im / norm);
+    Some(Homography::from_matrix([
+        [a, -b, wx - a * zx + b * zy],
+        [b, a, wy - b * zx - a * zy],
+        [0.0, 0.0, 1.0],
+    ]))
+}
+
+fn distance((x0, y0): (Real, Real), (x1, y1): (Real, Real)) -> Real {
+    ((x1 - x0) * (x1 - x0) + (y1 - y0) * (y1 - y0)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    const CODES: [Code; 9] = [31, 47, 55, 59, 61, 79, 87, 91, 93];
+
+    /// Detections of the board's codes seen through `view`, which maps board to image positions.
+    fn detect(board: &Board, view: &Homography, visible: &[Code]) -> Vec<TopCode> {
+        board
+            .markers
+            .iter()
+            .filter(|(code, _)| visible.contains(code))
+            .map(|&(code, (u, v))| {
+                let (x, y) = view.map(u, v);
+                TopCode::mock(code, 4.0, 0.0, x, y)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn boards_are_located_despite_occlusions_and_outliers() {
+        let board = Board::grid(&CODES, 3, 50.0);
+        let view = Homography::from_points(
+            &[(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)],
+            &[(120.0, 80.0), (260.0, 90.0), (280.0, 230.0), (100.0, 210.0)],
+        )
+        .unwrap();
+
+        // Five of the nine codes are occluded.
+        let codes = detect(&board, &view, &[31, 55, 87, 93]);
+        let pose = board.locate(&codes).unwrap();
+        assert_eq!(pose.markers, 4);
+        assert!(pose.error < 1e-3);
+        let (x, y) = view.map(50.0, 50.0);
+        let (u, v) = pose.homography.map(x, y);
+        assert!((u - 50.0).abs() < 1e-3 && (v - 50.0).abs() < 1e-3);
+
+        // A stray code with the ID of an occluded one, far from where it belongs on the board.
+        let mut codes = detect(&board, &view, &[31, 47, 55, 87, 93]);
+        codes.push(TopCode::mock(61, 4.0, 0.0, 20.0, 20.0));
+        let pose = board.locate(&codes).unwrap();
+        assert_eq!(pose.markers, 5);
+        assert!(pose.error < 1e-3);
+
+        assert_eq!(board.locate(&detect(&board, &view, &[31])), None);
+    }
+
+    #[test]
+    fn outliers_among_four_or_five_codes_are_rejected() {
+        let board = Board::grid(&CODES, 3, 50.0);
+        let view = Homography::from_points(
+            &[(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)],
+            &[(120.0, 80.0), (250.0, 95.0), (240.0, 225.0), (105.0, 210.0)],
+        )
+        .unwrap();
+        let maps_center = |pose: &BoardPose, tolerance: Real| {
+            let (x, y) = view.map(50.0, 50.0);
+            let (u, v) = pose.homography.map(x, y);
+            (u - 50.0).abs() < tolerance && (v - 50.0).abs() < tolerance
+        };
+
+        // Any four points fit a homography exactly, so the stray code needs to be rejected
+        // against the other three.
+        let mut codes = detect(&board, &view, &[31, 55, 87]);
+        codes.push(TopCode::mock(93, 4.0, 0.0, 20.0, 20.0));
+        let pose = board.locate(&codes).unwrap();
+        assert_eq!(pose.markers, 3);
+        assert!(maps_center(&pose, board.max_error));
+
+        // Dropping any one of five codes leaves four which fit exactly, stray or not.
+        let mut codes = detect(&board, &view, &[31, 55, 87, 93]);
+        let (x, y) = view.map(75.0, 60.0);
+        codes.push(TopCode::mock(61, 4.0, 0.0, x, y));
+        let pose = board.locate(&codes).unwrap();
+        assert_eq!(pose.markers, 4);
+        assert!(maps_center(&pose, 1e-3));
+
+        // A stray code detected before the real one with the same ID.
+        let mut codes = vec![TopCode::mock(55, 4.0, 0.0, 20.0, 20.0)];
+        codes.extend(detect(&board, &view, &[31, 55, 87, 93]));
+        let pose = board.locate(&codes).unwrap();
+        assert_eq!(pose.markers, 4);
+        assert!(pose.error < 1e-3);
+    }
+
+    #[test]
+    fn two_codes_locate_a_board_facing_the_camera() {
+        let board = Board::grid(&CODES, 3, 50.0);
+        // Rotated by 90 degrees, scaled by 2 and moved.
+        let view = Homography::from_matrix([[0.0, -2.0, 300.0], [2.0, 0.0, 40.0], [0.0, 0.0, 1.0]]);
+        let pose = board.locate(&detect(&board, &view, &[47, 91])).unwrap();
+        assert_eq!(pose.markers, 2);
+        let (u, v) = pose.homography.map(200.0, 140.0);
+        assert!(
+            (u - 50.0).abs() < 1e-3 && (v - 50.0).abs() < 1e-3,
+            "{} {}",
+            u,
+            v
+        );
+    }
+}
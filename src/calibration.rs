@@ -0,0 +1,314 @@
+use alloc::vec::Vec;
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::{
+    board::{Board, Match},
+    homography::{solve, Homography},
+    lens::LensModel,
+    pose::CameraIntrinsics,
+    topcode::{Real, TopCode},
+};
+
+/// Least number of the board's codes which need to be detected for a frame to be used.
+const MIN_MARKERS: usize = 6;
+
+/// Number of times the intrinsics and distortion are alternately re-estimated.
+const ITERATIONS: usize = 10;
+
+/// Estimates a camera's intrinsics and lens distortion from frames of a [Board] held at different
+/// angles, following Zhang's method ("A Flexible New Technique for Camera Calibration", 2000).
+///
+/// The board needs to be seen from at least two clearly different directions, ideally tilted
+/// towards each of the edges of the frame in turn. Codes should cover as much of the frame as
+/// possible, as the distortion is only measured where there are codes. Pixels are assumed to be
+/// rectangular, without skew.
+#[derive(Clone, Debug)]
+pub struct Calibration {
+    board: Board,
+    /// The detections of the board's codes in each frame
+    frames: Vec<Vec<Match>>,
+}
+
+/// The result of a [Calibration].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraCalibration {
+    /// Focal lengths and principal point of the camera
+    pub intrinsics: CameraIntrinsics,
+    /// Radial distortion of the lens, with the principal point of the intrinsics and their mean
+    /// focal length
+    pub lens: LensModel,
+    /// Root mean square distance (in pixels) between the detected codes and their positions on
+    /// the board projected through the calibrated camera
+    pub error: Real,
+}
+
+impl Calibration {
+    pub fn new(board: Board) -> Self {
+        Self {
+            board,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Adds the codes detected in a frame, returning whether enough of the board was found for the
+    /// frame to be used. Codes should be detected without a lens model, as the distortion is part
+    /// of what is calibrated.
+    pub fn add_frame(&mut self, codes: &[TopCode]) -> bool {
+        match self.board.locate_matches(codes) {
+            Some((_, matches)) if matches.len() >= MIN_MARKERS => {
+                self.frames.push(matches);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The number of frames added so far.
+    pub fn frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Estimates the camera's intrinsics and distortion from the frames added so far. Returns
+    /// `None` for fewer than two frames, or if the frames don't constrain the camera, e.g. when the
+    /// board faced the camera in the same way in all of them.
+    pub fn calibrate(&self) -> Option<CameraCalibration> {
+        if self.frames.len() < 2 {
+            return None;
+        }
+        // Scaling pixel coordinates to around 1 keeps the equations well conditioned.
+        let scale = self
+            .frames
+            .iter()
+            .flatten()
+            .map(|((x, y), _)| x.abs().max(y.abs()))
+            .fold(1.0, Real::max);
+
+        let mut lens = LensModel {
+            k1: 0.0,
+            k2: 0.0,
+            principal_point: (0.0, 0.0),
+            focal_length: 1.0,
+        };
+        let mut intrinsics = None;
+        for _ in 0..ITERATIONS {
+            // Homographies from the board to the image as it would be without distortion.
+            let homographies = self
+                .frames
+                .iter()
+                .map(|matches| fit_undistorted(matches, &lens, scale))
+                .collect::<Option<Vec<_>>>()?;
+            let estimate = solve_intrinsics(&homographies, scale)?;
+            lens = LensModel {
+                principal_point: (estimate.cx, estimate.cy),
+                focal_length: (estimate.fx + estimate.fy) / 2.0,
+                ..lens
+            };
+            intrinsics = Some(estimate);
+            (lens.k1, lens.k2) = self.refine_distortion(&lens, scale)?;
+        }
+
+        let residuals = self.residuals(&lens, scale)?;
+        let squared: Real = residuals.iter().map(|r| r * r).sum();
+        Some(CameraCalibration {
+            intrinsics: intrinsics?,
+            lens,
+            // Two residuals per detection.
+            error: (2.0 * squared / residuals.len() as Real).sqrt(),
+        })
+    }
+
+    /// Differences (in pixels) between the coordinates of the detected codes and their positions on
+    /// the board, projected by homographies fitted to the detections undistorted by `lens`, and
+    /// distorted again.
+    fn residuals(&self, lens: &LensModel, scale: Real) -> Option<Vec<Real>> {
+        let mut residuals = Vec::new();
+        for matches in &self.frames {
+            let homography = fit_undistorted(matches, lens, scale)?;
+            for &((x, y), (u, v)) in matches {
+                let (px, py) = homography.map(u, v);
+                let (px, py) = lens.distort(px * scale, py * scale);
+                residuals.extend([px - x, py - y]);
+            }
+        }
+        Some(residuals)
+    }
+
+    /// One Gauss-Newton step from the distortion coefficients `(k1, k2)` of `lens` towards those
+    /// minimizing the [residuals](Calibration::residuals).
+    fn refine_distortion(&self, lens: &LensModel, scale: Real) -> Option<(Real, Real)> {
+        const STEP: Real = 1e-3;
+        let residuals = self.residuals(lens, scale)?;
+        // Derivatives of the residuals by finite differences.
+        let derivatives = [
+            LensModel {
+                k1: lens.k1 + STEP,
+                ..*lens
+            },
+            LensModel {
+                k2: lens.k2 + STEP,
+                ..*lens
+            },
+        ]
+        .map(|lens| self.residuals(&lens, scale));
+        let [Some(d1), Some(d2)] = derivatives else {
+            return None;
+        };
+
+        let mut jtj = [[0.0; 2]; 2];
+        let mut jtr = [0.0; 2];
+        for ((r, r1), r2) in residuals.iter().zip(&d1).zip(&d2) {
+            let row = [(r1 - r) / STEP, (r2 - r) / STEP];
+            for i in 0..2 {
+                for j in 0..2 {
+                    jtj[i][j] += row[i] * row[j];
+                }
+                jtr[i] += row[i] * r;
+            }
+        }
+        let [d1, d2] = solve(jtj, jtr)?;
+        Some((lens.k1 - d1, lens.k2 - d2))
+    }
+}
+
+/// The homography mapping board positions to the detected positions undistorted by `lens`, divided
+/// by `scale`.
+fn fit_undistorted(matches: &[Match], lens: &LensModel, scale: Real) -> Option<Homography> {
+    let (board, image): (Vec<_>, Vec<_>) = matches
+        .iter()
+        .map(|&((x, y), position)| {
+            let (x, y) = lens.undistort(x, y);
+            (position, (x / scale, y / scale))
+        })
+        .unzip();
+    Homography::from_points(&board, &image)
+}
+
+/// Closed-form estimate of the intrinsics from the homographies of several views of a plane, which
+/// map the plane to pixels divided by `scale`. With zero skew, the image of the absolute conic
+/// `B = K^-T K^-1` has the form `[[b11, 0, b13], [0, b22, b23], [b13, b23, b33]]`, which is
+/// determined up to scale by two constraints per view, so `b11` is fixed to 1.
+fn solve_intrinsics(homographies: &[Homography], scale: Real) -> Option<CameraIntrinsics> {
+    let mut ata = [[0.0; 4]; 4];
+    let mut atb = [0.0; 4];
+    for homography in homographies {
+        let m = homography.matrix();
+        let column = |i: usize| [m[0][i], m[1][i], m[2][i]];
+        let v = |i: usize, j: usize| {
+            let (a, b) = (column(i), column(j));
+            [
+                a[0] * b[0],
+                a[2] * b[0] + a[0] * b[2],
+                a[1] * b[1],
+                a[2] * b[1] + a[1] * b[2],
+                a[2] * b[2],
+            ]
+        };
+        let (v01, v00, v11) = (v(0, 1), v(0, 0), v(1, 1));
+        let difference: [Real; 5] = core::array::from_fn(|k| v00[k] - v11[k]);
+        // Each constraint is `v . (1, b13, b22, b23, b33) = 0`, with the terms of b12 left out.
+        for e in [v01, difference] {
+            let row = [e[1], e[2], e[3], e[4]];
+            for i in 0..4 {
+                for j in 0..4 {
+                    ata[i][j] += row[i] * row[j];
+                }
+                atb[i] -= row[i] * e[0];
+            }
+        }
+    }
+    let [b13, b22, b23, b33] = solve(ata, atb)?;
+
+    let cy = -b23 / b22;
+    let lambda = b33 - b13 * b13 + cy * b23;
+    if lambda <= 0.0 || b22 <= 0.0 {
+        return None;
+    }
+    Some(CameraIntrinsics {
+        fx: lambda.sqrt() * scale,
+        fy: (lambda / b22).sqrt() * scale,
+        cx: -b13 * scale,
+        cy: cy * scale,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::topcode::Code;
+
+    const CODES: [Code; 20] = [
+        31, 47, 55, 59, 61, 79, 87, 91, 93, 103, 107, 109, 115, 117, 121, 143, 151, 155, 157, 167,
+    ];
+
+    /// Detections of a 5x4 board with codes 30 units apart, rotated by `tilt` radians about the x-
+    /// and y-axes and moved by `t`, as seen by the camera.
+    fn view(
+        tilt: (Real, Real),
+        t: [Real; 3],
+        camera: &CameraIntrinsics,
+        lens: &LensModel,
+    ) -> Vec<TopCode> {
+        let (sx, cx) = tilt.0.sin_cos();
+        let (sy, cy) = tilt.1.sin_cos();
+        CODES
+            .iter()
+            .enumerate()
+            .map(|(i, &code)| {
+                let (u, v) = ((i % 5) as Real * 30.0 - 60.0, (i / 5) as Real * 30.0 - 45.0);
+                // Rotation about the x-axis, then about the y-axis.
+                let (y, z) = (v * cx, v * sx);
+                let (x, z) = (u * cy + z * sy, -u * sy + z * cy);
+                let (x, y, z) = (x + t[0], y + t[1], z + t[2]);
+                let px = camera.fx * x / z + camera.cx;
+                let py = camera.fy * y / z + camera.cy;
+                let (px, py) = lens.distort(px, py);
+                TopCode::mock(code, 4.0, 0.0, px, py)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn boards_calibrate_the_camera() {
+        let camera = CameraIntrinsics {
+            fx: 800.0,
+            fy: 800.0,
+            cx: 330.0,
+            cy: 235.0,
+        };
+        let lens = LensModel {
+            k1: -0.15,
+            k2: 0.02,
+            principal_point: (330.0, 235.0),
+            focal_length: 800.0,
+        };
+        let board = Board::grid(&CODES, 5, 30.0);
+        let mut calibration = Calibration::new(board);
+        assert!(!calibration.add_frame(&[]));
+        assert_eq!(calibration.calibrate(), None);
+
+        for (tilt, t) in [
+            ((0.4, 0.0), [0.0, 0.0, 200.0]),
+            ((-0.4, 0.1), [10.0, -5.0, 210.0]),
+            ((0.0, 0.45), [-15.0, 10.0, 190.0]),
+            ((0.1, -0.45), [5.0, 5.0, 200.0]),
+            ((0.3, 0.3), [0.0, 0.0, 220.0]),
+        ] {
+            assert!(calibration.add_frame(&view(tilt, t, &camera, &lens)));
+        }
+        assert_eq!(calibration.frames(), 5);
+
+        let calibrated = calibration.calibrate().unwrap();
+        let intrinsics = calibrated.intrinsics;
+        assert!((intrinsics.fx - camera.fx).abs() < 4.0, "{:?}", calibrated);
+        assert!((intrinsics.fy - camera.fy).abs() < 4.0, "{:?}", calibrated);
+        assert!((intrinsics.cx - camera.cx).abs() < 2.0, "{:?}", calibrated);
+        assert!((intrinsics.cy - camera.cy).abs() < 2.0, "{:?}", calibrated);
+        assert!(
+            (calibrated.lens.k1 - lens.k1).abs() < 0.01,
+            "{:?}",
+            calibrated
+        );
+        assert!(calibrated.error < 0.05, "{:?}", calibrated);
+    }
+}
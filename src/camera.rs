@@ -0,0 +1,368 @@
+//! Scans the frames of a live camera in a loop, converting the pixel formats cameras deliver
+//! internally.
+//!
+//! Capture backends implement [Camera], which hands out each frame as a [Frame] borrowing the
+//! backend's buffer, so frames are scanned without copying them. [spawn] scans frames on a
+//! background thread and sends the codes found in each frame over a channel, while [run] scans
+//! them on the calling thread and passes the codes to a callback.
+//!
+//! With the `v4l` feature, `V4lCamera` captures frames from V4L2 devices on Linux, e.g. for kiosk
+//! installations, scanning the driver's memory-mapped buffers directly.
+//!
+//! Other capture libraries are wrapped by implementing [Camera]. A library which delivers frames
+//! on its own thread can hand them over through a channel, for example:
+//!
+//! ```
+//! use std::{io, sync::mpsc::Receiver};
+//!
+//! use topcodes::{
+//!     camera::{self, Camera, Frame, FrameFormat},
+//!     Scanner,
+//! };
+//!
+//! /// Receives YUYV frames from a capture callback.
+//! struct Webcam {
+//!     frames: Receiver<Vec<u8>>,
+//!     frame: Vec<u8>,
+//! }
+//!
+//! impl Camera for Webcam {
+//!     fn capture(&mut self) -> io::Result<Frame<'_>> {
+//!         self.frame = self
+//!             .frames
+//!             .recv()
+//!             .map_err(|_| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+//!         Ok(Frame::new(&self.frame, FrameFormat::Yuyv))
+//!     }
+//! }
+//!
+//! let (sender, frames) = std::sync::mpsc::channel();
+//! sender.send(vec![255; 64 * 48 * 2]).unwrap();
+//! drop(sender);
+//! let webcam = Webcam {
+//!     frames,
+//!     frame: Vec::new(),
+//! };
+//! let results: Vec<_> = camera::spawn(webcam, Scanner::new(64, 48)).iter().collect();
+//! assert_eq!(results[0].as_ref().unwrap().len(), 0);
+//! assert!(results[1].is_err());
+//! ```
+
+use std::{
+    io,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+use crate::{
+    errors::TopCodeError,
+    scanner::{ScanBuffer, ScanLayout, Scanner},
+    topcode::TopCode,
+};
+
+/// Pixel formats of camera frames which can be scanned directly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameFormat {
+    /// Packed 8-bit RGB
+    Rgb,
+    /// Packed 8-bit BGR
+    Bgr,
+    /// 8-bit grayscale
+    Gray,
+    /// Packed YUV 4:2:2, with two pixels in every four bytes `Y0 U Y1 V`
+    Yuyv,
+    /// Semi-planar YUV 4:2:0, a Y plane followed by interleaved U and V samples
+    Nv12,
+}
+
+impl FrameFormat {
+    /// The format of V4L2 frames with the given four-character code, or `None` if their pixels
+    /// can't be scanned directly.
+    pub fn from_fourcc(fourcc: &[u8; 4]) -> Option<Self> {
+        match fourcc {
+            b"YUYV" => Some(Self::Yuyv),
+            b"NV12" => Some(Self::Nv12),
+            b"GREY" => Some(Self::Gray),
+            b"RGB3" => Some(Self::Rgb),
+            b"BGR3" => Some(Self::Bgr),
+            _ => None,
+        }
+    }
+}
+
+/// A frame captured by a [Camera], borrowing the camera's buffer.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame<'a> {
+    /// The bytes of the frame
+    pub data: &'a [u8],
+    /// The pixel format of the frame
+    pub format: FrameFormat,
+    /// Number of bytes between the starts of consecutive rows (of the Y plane, for NV12), or
+    /// `None` if rows are not padded
+    pub stride: Option<usize>,
+}
+
+impl<'a> Frame<'a> {
+    /// A frame whose rows are not padded.
+    pub fn new(data: &'a [u8], format: FrameFormat) -> Self {
+        Self {
+            data,
+            format,
+            stride: None,
+        }
+    }
+}
+
+/// A source of camera frames, such as a webcam opened with a capture library.
+pub trait Camera {
+    /// Waits for the next frame.
+    fn capture(&mut self) -> io::Result<Frame<'_>>;
+}
+
+impl<B: ScanBuffer> Scanner<B> {
+    /// Scan a camera frame in its own pixel format. Only the luma of YUV frames is read, so no
+    /// color conversion is needed. Fails if the frame is too short for the scanner's dimensions.
+    pub fn scan_frame(&mut self, frame: Frame<'_>) -> Result<Vec<TopCode>, TopCodeError> {
+        let width = self.image_width();
+        let Frame {
+            data,
+            format,
+            stride,
+        } = frame;
+        let packed = |bytes_per_pixel| stride.unwrap_or(width * bytes_per_pixel);
+        match format {
+            FrameFormat::Rgb => self.scan_layout(data, ScanLayout::new(packed(3), 3), |p| {
+                (p[0] as u32, p[1] as u32, p[2] as u32)
+            }),
+            FrameFormat::Bgr => self.scan_layout(data, ScanLayout::new(packed(3), 3), |p| {
+                (p[2] as u32, p[1] as u32, p[0] as u32)
+            }),
+            FrameFormat::Gray if stride.is_none() => self.scan_gray_u8(data),
+            FrameFormat::Gray | FrameFormat::Nv12 => self.scan_yuv420(data, packed(1)),
+            // Every pixel's two bytes start with its luma sample.
+            FrameFormat::Yuyv => self.scan_layout(data, ScanLayout::new(packed(2), 2), |p| {
+                let y = p[0] as u32;
+                (y, y, y)
+            }),
+        }
+    }
+}
+
+/// Scans the frames of `camera` on a background thread, sending the codes found in each frame.
+/// The thread stops after sending the first error, or once the receiver is dropped.
+pub fn spawn<C>(mut camera: C, mut scanner: Scanner) -> Receiver<io::Result<Vec<TopCode>>>
+where
+    C: Camera + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || loop {
+        let codes = scan_next(&mut camera, &mut scanner);
+        let failed = codes.is_err();
+        if sender.send(codes).is_err() || failed {
+            break;
+        }
+    });
+    receiver
+}
+
+/// Scans the frames of `camera` on the calling thread, passing the codes found in each frame to
+/// `callback` until it returns `false`.
+pub fn run(
+    camera: &mut impl Camera,
+    scanner: &mut Scanner<impl ScanBuffer>,
+    mut callback: impl FnMut(Vec<TopCode>) -> bool,
+) -> io::Result<()> {
+    while callback(scan_next(camera, scanner)?) {}
+    Ok(())
+}
+
+/// A V4L2 capture device on Linux, streaming frames into buffers memory-mapped from the driver.
+/// Frames are scanned in place, without copying them or converting them to RGB.
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use topcodes::{camera::{self, V4lCamera}, Scanner};
+///
+/// let camera = V4lCamera::open(&v4l::Device::new(0)?)?;
+/// let scanner = Scanner::new(camera.width(), camera.height());
+/// for codes in camera::spawn(camera, scanner) {
+///     println!("{:?}", codes?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "v4l")]
+pub struct V4lCamera {
+    stream: v4l::io::mmap::Stream<'static>,
+    format: FrameFormat,
+    stride: usize,
+    width: usize,
+    height: usize,
+}
+
+#[cfg(feature = "v4l")]
+impl V4lCamera {
+    /// Number of buffers the driver captures into while frames are scanned.
+    const BUFFERS: u32 = 4;
+
+    /// Starts streaming from `device` in its current format, which can be changed beforehand with
+    /// [v4l::video::Capture::set_format]. Fails with [io::ErrorKind::Unsupported] unless the
+    /// format is YUYV, NV12, GREY, RGB3 or BGR3.
+    pub fn open(device: &v4l::Device) -> io::Result<Self> {
+        use v4l::video::Capture;
+
+        let format = device.format()?;
+        let frame_format = FrameFormat::from_fourcc(&format.fourcc.repr).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("pixel format {} is not supported", format.fourcc),
+            )
+        })?;
+        let stream = v4l::io::mmap::Stream::with_buffers(
+            device,
+            v4l::buffer::Type::VideoCapture,
+            Self::BUFFERS,
+        )?;
+        Ok(Self {
+            stream,
+            format: frame_format,
+            stride: format.stride as usize,
+            width: format.width as usize,
+            height: format.height as usize,
+        })
+    }
+
+    /// Width of the device's frames in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Height of the device's frames in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+}
+
+#[cfg(feature = "v4l")]
+impl Camera for V4lCamera {
+    fn capture(&mut self) -> io::Result<Frame<'_>> {
+        use v4l::io::traits::CaptureStream;
+
+        let (buffer, metadata) = CaptureStream::next(&mut self.stream)?;
+        Ok(Frame {
+            data: used_bytes(buffer, metadata.bytesused),
+            format: self.format,
+            stride: Some(self.stride),
+        })
+    }
+}
+
+/// The part of a driver's buffer which holds the frame, given the number of bytes the driver
+/// reports as used. Drivers may report more bytes than the buffer holds, which are ignored.
+#[cfg_attr(not(feature = "v4l"), allow(dead_code))]
+fn used_bytes(buffer: &[u8], bytes_used: u32) -> &[u8] {
+    &buffer[..(bytes_used as usize).min(buffer.len())]
+}
+
+fn scan_next(
+    camera: &mut impl Camera,
+    scanner: &mut Scanner<impl ScanBuffer>,
+) -> io::Result<Vec<TopCode>> {
+    let frame = camera.capture()?;
+    scanner
+        .scan_frame(frame)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replays an image as frames of the given format.
+    struct Replay {
+        data: Vec<u8>,
+        format: FrameFormat,
+        frames: usize,
+    }
+
+    impl Camera for Replay {
+        fn capture(&mut self) -> io::Result<Frame<'_>> {
+            if self.frames == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.frames -= 1;
+            Ok(Frame::new(&self.data, self.format))
+        }
+    }
+
+    fn photo() -> (usize, usize, Vec<u8>) {
+        let image = image::open("assets/photo.png").unwrap().into_luma8();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        (width, height, image.into_raw())
+    }
+
+    #[test]
+    fn frames_are_scanned_in_their_own_format() {
+        let (width, height, luma) = photo();
+        let mut scanner = Scanner::new(width, height);
+        let expected = scanner.scan_gray_u8(&luma).unwrap();
+        assert!(!expected.is_empty());
+
+        let yuyv: Vec<u8> = luma.iter().flat_map(|&y| [y, 128]).collect();
+        let mut nv12 = luma.clone();
+        nv12.resize(width * height * 3 / 2, 128);
+        let rgb: Vec<u8> = luma.iter().flat_map(|&y| [y; 3]).collect();
+        for (data, format) in [
+            (yuyv, FrameFormat::Yuyv),
+            (nv12, FrameFormat::Nv12),
+            (rgb, FrameFormat::Rgb),
+        ] {
+            let codes = scanner.scan_frame(Frame::new(&data, format)).unwrap();
+            assert_eq!(codes, expected, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn v4l_frames_are_mapped_to_scannable_formats() {
+        assert_eq!(FrameFormat::from_fourcc(b"YUYV"), Some(FrameFormat::Yuyv));
+        assert_eq!(FrameFormat::from_fourcc(b"NV12"), Some(FrameFormat::Nv12));
+        assert_eq!(FrameFormat::from_fourcc(b"GREY"), Some(FrameFormat::Gray));
+        assert_eq!(FrameFormat::from_fourcc(b"RGB3"), Some(FrameFormat::Rgb));
+        assert_eq!(FrameFormat::from_fourcc(b"BGR3"), Some(FrameFormat::Bgr));
+        assert_eq!(FrameFormat::from_fourcc(b"MJPG"), None);
+
+        let buffer = [1, 2, 3, 4];
+        assert_eq!(used_bytes(&buffer, 3), &[1, 2, 3]);
+        assert_eq!(used_bytes(&buffer, 4), &buffer);
+        assert_eq!(used_bytes(&buffer, 100), &buffer);
+    }
+
+    #[test]
+    fn frames_are_scanned_until_the_camera_fails() {
+        let (width, height, data) = photo();
+        let count = Scanner::new(width, height)
+            .scan_gray_u8(&data)
+            .unwrap()
+            .len();
+        let camera = Replay {
+            data,
+            format: FrameFormat::Gray,
+            frames: 2,
+        };
+        let results: Vec<_> = spawn(camera, Scanner::new(width, height)).iter().collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[..2]
+            .iter()
+            .all(|codes| codes.as_ref().unwrap().len() == count));
+        assert!(results[2].is_err());
+
+        let mut camera = Replay {
+            data: Vec::new(),
+            format: FrameFormat::Gray,
+            frames: 1,
+        };
+        let mut scanner = Scanner::new(width, height);
+        let err = run(&mut camera, &mut scanner, |_| true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
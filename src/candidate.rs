@@ -1,12 +1,45 @@
-/// A candidate [TopCode].
-#[derive(Clone, Copy, Debug)]
-pub(crate) struct Candidate {
-    pub x: usize,
-    pub y: usize,
+/// A candidate [TopCode](crate::TopCode) center, found while thresholding an image. Candidates
+/// have not been decoded, so they may not be valid TopCodes, and a single TopCode usually produces
+/// several candidates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Candidate {
+    pub(crate) x: usize,
+    pub(crate) y: usize,
+    /// Whether the candidate is a light bullseye on a dark background
+    pub(crate) inverted: bool,
 }
 
 impl Candidate {
-    pub fn new(x: usize, y: usize) -> Self {
-        Self { x, y }
+    pub(crate) fn new(x: usize, y: usize) -> Self {
+        Self {
+            x,
+            y,
+            inverted: false,
+        }
+    }
+
+    /// Marks the candidate as a light bullseye on a dark background.
+    pub(crate) fn into_inverted(self) -> Self {
+        Self {
+            inverted: true,
+            ..self
+        }
+    }
+
+    /// Horizontal position of the candidate in pixels
+    pub fn x(&self) -> usize {
+        self.x
+    }
+
+    /// Vertical position of the candidate in pixels
+    pub fn y(&self) -> usize {
+        self.y
+    }
+
+    /// Whether the candidate was found as a light bullseye on a dark background, which is only
+    /// searched for with [Polarity::Both](crate::Polarity::Both)
+    pub fn is_inverted(&self) -> bool {
+        self.inverted
     }
 }
@@ -0,0 +1,210 @@
+use crate::topcode::{Code, SECTORS};
+
+/// Number of valid codes.
+pub const COUNT: usize = 99;
+
+/// All valid codes, in ascending order. A code is valid if exactly five of its 13 bits are set (see
+/// [TopCode::checksum](crate::TopCode::checksum)) and it is the lowest of its 13 rotations, since
+/// the scanner reads each symbol as the lowest rotation of its data ring.
+pub const CODES: [Code; COUNT] = generate();
+
+/// The number of valid codes, which is [COUNT].
+pub fn count() -> usize {
+    COUNT
+}
+
+/// The `i`th valid code in ascending order, or `None` if `i` is not below [COUNT].
+pub fn nth(i: usize) -> Option<Code> {
+    CODES.get(i).copied()
+}
+
+/// Maps marker numbers 0 to 98 to valid codes, so that applications can number their markers
+/// without caring about the encoded values. The ordering is ascending by code value and will not
+/// change, so marker `i` is always drawn by `TopCode::draw(from_index(i).unwrap(), size)` and
+/// scanned back as that code. Same as [nth].
+pub fn from_index(index: usize) -> Option<Code> {
+    nth(index)
+}
+
+/// The marker number of `code`, the inverse of [from_index]. Returns `None` if `code` is not a
+/// valid code.
+pub fn to_index(code: Code) -> Option<usize> {
+    CODES.binary_search(&code).ok()
+}
+
+/// Whether `code` is one of the valid codes in [CODES], e.g. to validate configuration files
+/// listing the codes an application expects.
+pub fn is_valid_code(code: Code) -> bool {
+    to_index(code).is_some()
+}
+
+/// Number of patterns of the second data ring of the extended symbology, which are the 13-bit
+/// values with exactly five bits set. Unlike the inner data ring, the second ring's rotation is
+/// fixed by the inner ring, so all of its rotations are distinct.
+pub const SECOND_RING_COUNT: usize = 1287;
+
+/// Number of distinct IDs of the extended symbology, with a second data ring around the
+/// standard symbol (see [Scanner::set_extended_symbology](crate::Scanner::set_extended_symbology)).
+pub const EXTENDED_COUNT: usize = COUNT * SECOND_RING_COUNT;
+
+/// The inner code and second ring pattern of the extended symbology ID `id`, or `None` if `id`
+/// is not below [EXTENDED_COUNT]. IDs are ordered by inner code first, so the IDs of a given
+/// inner code are contiguous.
+pub fn extended_from_id(id: usize) -> Option<(Code, Code)> {
+    let code = nth(id / SECOND_RING_COUNT)?;
+    Some((code, unrank_pattern(id % SECOND_RING_COUNT)))
+}
+
+/// The extended symbology ID of an inner code and second ring pattern, the inverse of
+/// [extended_from_id]. Returns `None` if `code` is not valid or `second` doesn't have exactly
+/// five of its 13 bits set.
+pub fn extended_to_id(code: Code, second: Code) -> Option<usize> {
+    if !has_five_bits(second) {
+        return None;
+    }
+    Some(to_index(code)? * SECOND_RING_COUNT + rank_pattern(second))
+}
+
+/// Rank of a 13-bit pattern with five bits set among all such patterns, in ascending order, using
+/// the combinatorial number system.
+fn rank_pattern(bits: Code) -> usize {
+    (0..SECTORS)
+        .filter(|i| (bits >> i) & 0x01 == 1)
+        .enumerate()
+        .map(|(k, position)| binomial(position, k + 1))
+        .sum()
+}
+
+/// The 13-bit pattern with five bits set of the given rank, the inverse of [rank_pattern].
+fn unrank_pattern(mut rank: usize) -> Code {
+    let mut bits = 0;
+    let mut position = SECTORS;
+    for k in (1..=5).rev() {
+        // The highest remaining bit is the largest position whose binomial fits in the rank.
+        position = (k - 1..position)
+            .rev()
+            .find(|&p| binomial(p, k) <= rank)
+            .unwrap_or(k - 1);
+        rank -= binomial(position, k);
+        bits |= 1 << position;
+    }
+    bits
+}
+
+fn binomial(n: usize, k: usize) -> usize {
+    if k > n {
+        return 0;
+    }
+    (0..k).fold(1, |acc, i| acc * (n - i) / (i + 1))
+}
+
+/// Iterates over all valid codes in ascending order.
+pub fn iter() -> impl Iterator<Item = Code> {
+    CODES.into_iter()
+}
+
+/// Whether `bits` has exactly five of the 13 data bits set, and no others.
+const fn has_five_bits(bits: Code) -> bool {
+    bits >> SECTORS == 0 && bits.count_ones() == 5
+}
+
+/// Whether `bits` is lower than all of its other rotations.
+const fn is_lowest_rotation(bits: Code) -> bool {
+    let mask = (1 << SECTORS) - 1;
+    let mut rotated = bits;
+    let mut i = 1;
+    while i < SECTORS {
+        rotated = ((rotated << 1) & mask) | (rotated >> (SECTORS - 1));
+        if rotated <= bits {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+const fn generate() -> [Code; COUNT] {
+    let mut codes = [0; COUNT];
+    let mut count = 0;
+    let mut bits = 0;
+    while bits < 1 << SECTORS {
+        if has_five_bits(bits) && is_lowest_rotation(bits) {
+            codes[count] = bits;
+            count += 1;
+        }
+        bits += 1;
+    }
+    assert!(count == COUNT);
+    codes
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::TopCode;
+
+    #[test]
+    fn the_dictionary_holds_every_valid_code() {
+        assert_eq!(count(), 99);
+        assert_eq!(nth(0), Some(31));
+        assert_eq!(nth(98), Some(CODES[98]));
+        assert_eq!(nth(99), None);
+        assert!(CODES.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(iter().all(TopCode::checksum));
+
+        // Every 13-bit pattern with five bits set is a rotation of exactly one code.
+        let patterns = (0..1 << SECTORS).filter(|&bits| TopCode::checksum(bits));
+        assert_eq!(patterns.count(), COUNT * SECTORS);
+    }
+
+    #[test]
+    fn indices_map_to_codes_and_back() {
+        for index in 0..COUNT {
+            let code = from_index(index).unwrap();
+            assert_eq!(to_index(code), Some(index));
+        }
+        // The ordering is part of the API, so pin a few entries.
+        assert_eq!(from_index(0), Some(31));
+        assert_eq!(from_index(1), Some(47));
+        assert_eq!(from_index(50), Some(345));
+        assert_eq!(from_index(98), Some(1189));
+        assert_eq!(from_index(COUNT), None);
+        assert_eq!(to_index(0), None);
+        assert_eq!(to_index(0b1111100000000), None);
+    }
+
+    #[test]
+    fn only_dictionary_codes_are_valid() {
+        assert!(iter().all(is_valid_code));
+        assert_eq!(
+            (0..1 << 16).filter(|&code| is_valid_code(code)).count(),
+            COUNT
+        );
+        // A rotation of 31 passes the checksum, but is read back as 31.
+        assert!(TopCode::checksum(31 << 1));
+        assert!(!is_valid_code(31 << 1));
+    }
+
+    #[test]
+    fn extended_ids_map_to_codes_and_back() {
+        let patterns: Vec<Code> = (0..1 << SECTORS)
+            .filter(|&bits| has_five_bits(bits))
+            .collect();
+        assert_eq!(patterns.len(), SECOND_RING_COUNT);
+        for (rank, &pattern) in patterns.iter().enumerate() {
+            assert_eq!(rank_pattern(pattern), rank);
+            assert_eq!(unrank_pattern(rank), pattern);
+        }
+
+        for id in (0..EXTENDED_COUNT).step_by(97).chain([EXTENDED_COUNT - 1]) {
+            let (code, second) = extended_from_id(id).unwrap();
+            assert_eq!(extended_to_id(code, second), Some(id));
+        }
+        assert_eq!(extended_from_id(0), Some((31, 31)));
+        assert_eq!(extended_from_id(EXTENDED_COUNT), None);
+        assert_eq!(extended_to_id(31, 0b111), None);
+        assert_eq!(extended_to_id(32, 31), None);
+    }
+}
@@ -1,4 +1,57 @@
-#[derive(Clone, Copy, Debug)]
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TopCodeError {
     IncorrectBufferSize,
+    /// The image is too small to be scanned
+    DimensionsTooSmall {
+        width: usize,
+        height: usize,
+    },
+    /// The number of pixels in an image of the given dimensions does not fit in a `usize`
+    DimensionsOverflow {
+        width: usize,
+        height: usize,
+    },
+    /// Pixel bit depths must be between 1 and 16
+    UnsupportedBitDepth(u32),
+    /// The pixels of the image are of a type the scanner can't read
+    UnsupportedPixelFormat,
+    /// The code is not one of the valid TopCodes, see [crate::is_valid_code]
+    InvalidCode(u32),
+    /// Tiles must be larger than the largest code diameter, so that every code fits in a tile
+    TileTooSmall {
+        width: usize,
+        height: usize,
+    },
+    /// No GPU could be opened for thresholding, or it failed to return its results
+    GpuUnavailable,
 }
+
+impl fmt::Display for TopCodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IncorrectBufferSize => write!(f, "buffer size does not match the image"),
+            Self::DimensionsTooSmall { width, height } => write!(
+                f,
+                "image dimensions {width}x{height} are below the minimum of 3x3"
+            ),
+            Self::DimensionsOverflow { width, height } => write!(
+                f,
+                "image dimensions {width}x{height} overflow the addressable pixel count"
+            ),
+            Self::UnsupportedBitDepth(bits) => {
+                write!(f, "bit depth {bits} is not between 1 and 16")
+            }
+            Self::UnsupportedPixelFormat => write!(f, "pixel format is not supported"),
+            Self::InvalidCode(code) => write!(f, "{code} is not a valid TopCode"),
+            Self::TileTooSmall { width, height } => write!(
+                f,
+                "tiles of {width}x{height} pixels are too small for the largest code diameter"
+            ),
+            Self::GpuUnavailable => write!(f, "no GPU is available for thresholding"),
+        }
+    }
+}
+
+impl core::error::Error for TopCodeError {}
@@ -0,0 +1,202 @@
+//! Writes detections to CSV and JSON, e.g. to log the codes found in each frame of a video.
+//!
+//! CSV rows have the columns of [CSV_COLUMNS], one row per code, so that frames can be appended to
+//! the same file after writing the header once with [write_csv_header]. JSON is written as one
+//! object per frame and line (JSON Lines), holding the frame's metadata and an array of its codes
+//! in the format of [TopCode::to_json]. Invalid codes are written with the code -1.
+
+use std::io::{self, Write};
+
+use crate::{
+    scanner::{ScanBuffer, Scanner},
+    topcode::TopCode,
+};
+
+/// The columns of CSV rows, in order. Metadata columns are left empty for frames written without
+/// metadata.
+pub const CSV_COLUMNS: [&str; 8] = [
+    "frame",
+    "timestamp",
+    "source",
+    "code",
+    "x",
+    "y",
+    "orientation",
+    "unit",
+];
+
+/// Information about the frame codes were detected in.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameMetadata {
+    /// Number of the frame
+    pub frame: u64,
+    /// Time of the frame, e.g. in seconds since the start of a video
+    pub timestamp: Option<f64>,
+    /// Label of the input, e.g. a file name or camera, see [Scanner::with_source_label]
+    ///
+    /// [Scanner::with_source_label]: crate::Scanner::with_source_label
+    pub source: Option<String>,
+}
+
+impl FrameMetadata {
+    /// Metadata of the `frame`th frame scanned by `scanner`, labelled with the scanner's source
+    /// label so that the rows and objects written for it can be traced back to their input.
+    pub fn for_scanner(frame: u64, scanner: &Scanner<impl ScanBuffer>) -> Self {
+        Self {
+            frame,
+            timestamp: None,
+            source: scanner.source_label().map(String::from),
+        }
+    }
+}
+
+/// Writes the header row of [CSV_COLUMNS].
+pub fn write_csv_header(writer: &mut impl Write) -> io::Result<()> {
+    writeln!(writer, "{}", CSV_COLUMNS.join(","))
+}
+
+/// Writes a CSV row for each of the codes, without a header.
+pub fn write_csv(
+    writer: &mut impl Write,
+    codes: &[TopCode],
+    metadata: Option<&FrameMetadata>,
+) -> io::Result<()> {
+    let (frame, timestamp, source) = match metadata {
+        Some(metadata) => (
+            metadata.frame.to_string(),
+            metadata
+                .timestamp
+                .map(|t| t.to_string())
+                .unwrap_or_default(),
+            metadata
+                .source
+                .as_deref()
+                .map(csv_field)
+                .unwrap_or_default(),
+        ),
+        None => Default::default(),
+    };
+    for code in codes {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{}",
+            frame,
+            timestamp,
+            source,
+            code.code.map_or(-1, |code| code as i64),
+            code.x,
+            code.y,
+            code.orientation,
+            code.unit
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes the codes of a frame as a JSON object on a single line, such as
+/// `{"frame":3,"timestamp":0.1,"source":"cam0","codes":[...]}`. Metadata which isn't known is
+/// left out of the object.
+pub fn write_json(
+    writer: &mut impl Write,
+    codes: &[TopCode],
+    metadata: Option<&FrameMetadata>,
+) -> io::Result<()> {
+    write!(writer, "{{")?;
+    if let Some(metadata) = metadata {
+        write!(writer, "\"frame\":{},", metadata.frame)?;
+        if let Some(timestamp) = metadata.timestamp {
+            write!(writer, "\"timestamp\":{},", timestamp)?;
+        }
+        if let Some(source) = &metadata.source {
+            write!(writer, "\"source\":{},", json_string(source))?;
+        }
+    }
+    let codes: Vec<String> = codes.iter().map(TopCode::to_json).collect();
+    writeln!(writer, "\"codes\":[{}]}}", codes.join(","))
+}
+
+/// Quotes a CSV field if it contains separators, quotes or line breaks.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// Quotes a string for JSON.
+fn json_string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes() -> [TopCode; 2] {
+        let mut invalid = TopCode::mock(31, 2.0, 0.0, 1.0, 2.0);
+        invalid.code = None;
+        [TopCode::mock(55, 4.0, 0.5, 10.0, 20.5), invalid]
+    }
+
+    #[test]
+    fn codes_are_written_as_csv() {
+        let metadata = FrameMetadata {
+            frame: 3,
+            timestamp: Some(0.1),
+            source: Some(String::from("table, left")),
+        };
+        let mut csv = Vec::new();
+        write_csv_header(&mut csv).unwrap();
+        write_csv(&mut csv, &codes(), Some(&metadata)).unwrap();
+        write_csv(&mut csv, &codes()[..1], None).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "frame,timestamp,source,code,x,y,orientation,unit\n\
+             3,0.1,\"table, left\",55,10,20.5,0.5,4\n\
+             3,0.1,\"table, left\",-1,1,2,0,2\n\
+             ,,,55,10,20.5,0.5,4\n"
+        );
+    }
+
+    #[test]
+    fn codes_are_written_as_json_lines() {
+        let metadata = FrameMetadata {
+            frame: 3,
+            timestamp: None,
+            source: Some(String::from("cam \"0\"")),
+        };
+        let mut json = Vec::new();
+        write_json(&mut json, &codes(), Some(&metadata)).unwrap();
+        write_json(&mut json, &[], None).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        let lines: Vec<serde_json::Value> = json
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["frame"], 3);
+        assert_eq!(lines[0]["source"], "cam \"0\"");
+        assert!(lines[0].get("timestamp").is_none());
+        assert_eq!(lines[0]["codes"][0]["code"], 55);
+        assert_eq!(lines[0]["codes"][1]["code"], -1);
+        assert_eq!(lines[1], serde_json::json!({ "codes": [] }));
+
+        let mut scanner = Scanner::new(16, 16);
+        scanner.with_source_label("cam \"0\"");
+        assert_eq!(FrameMetadata::for_scanner(3, &scanner), metadata);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{
+    scanner::{ScanBuffer, Scanner, DEFAULT_THRESHOLD_BIAS},
+    topcode::TopCode,
+};
+
+/// Adapts a scanner's threshold bias across frames to keep codes detectable while the lighting of
+/// a scene drifts, e.g. for a fixed camera running throughout the day.
+///
+/// This is a simple hill-climber: while the number of detections improves, the bias keeps moving
+/// in the same direction, and when the number of detections drops by more than the hysteresis, the
+/// direction is reversed. When nothing is detected at all, the bias keeps searching (bouncing
+/// between its bounds) until codes are found again. Otherwise the bias is left alone.
+#[derive(Clone, Debug)]
+pub struct AutoExposure {
+    /// Amount by which the bias is changed per adjustment
+    step: f64,
+    /// Number of detections that may be lost before changing direction
+    hysteresis: usize,
+    /// Lowest bias which will be set
+    min_bias: f64,
+    /// Highest bias which will be set
+    max_bias: f64,
+    /// Direction in which the bias is currently moving, either 1 or -1
+    direction: f64,
+    /// Number of detections in the previous frame
+    last_count: Option<usize>,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self::new(0.005, 0)
+    }
+}
+
+impl AutoExposure {
+    /// Creates a hill-climber which moves the threshold bias (the fraction of the local average
+    /// intensity below which pixels are black, see [Scanner::set_threshold_bias]) by `step` per
+    /// adjustment, e.g. 0.005, and reverses its direction once the number of detections drops by
+    /// more than `hysteresis` codes from one frame to the next.
+    pub fn new(step: f64, hysteresis: usize) -> Self {
+        Self {
+            step,
+            hysteresis,
+            min_bias: 0.85,
+            max_bias: 1.0,
+            direction: 1.0,
+            last_count: None,
+        }
+    }
+
+    /// Sets the range the bias is kept within. Defaults to 0.85 to 1.0.
+    pub fn set_bounds(&mut self, min_bias: f64, max_bias: f64) {
+        self.min_bias = min_bias;
+        self.max_bias = max_bias;
+    }
+
+    /// Adjusts the scanner's threshold bias based on the codes detected in the latest frame.
+    pub fn adjust(&mut self, scanner: &mut Scanner<impl ScanBuffer>, codes: &[TopCode]) {
+        let count = codes.len();
+        let last_count = self.last_count.replace(count);
+
+        let step = match last_count {
+            _ if count == 0 => true,
+            Some(last) if count + self.hysteresis < last => {
+                self.direction = -self.direction;
+                true
+            }
+            Some(last) => count > last && last > 0,
+            None => false,
+        };
+        if !step {
+            return;
+        }
+
+        let mut bias = scanner.threshold_bias() + self.direction * self.step;
+        if bias > self.max_bias || bias < self.min_bias {
+            self.direction = -self.direction;
+            bias = bias.clamp(self.min_bias, self.max_bias);
+        }
+        scanner.set_threshold_bias(bias);
+    }
+
+    /// Restores the scanner's default bias and forgets the previous detections.
+    pub fn reset(&mut self, scanner: &mut Scanner<impl ScanBuffer>) {
+        scanner.set_threshold_bias(DEFAULT_THRESHOLD_BIAS);
+        self.direction = 1.0;
+        self.last_count = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::utils::render_topcode;
+
+    #[test]
+    fn bias_adapts_to_a_darkening_scene() {
+        let (width, height) = (200, 200);
+        let code = render_topcode(55, 10.0, 0.0, (100.0, 100.0), (width, height));
+        let mut scanner = Scanner::new(width, height);
+        let mut exposure = AutoExposure::default();
+
+        // The ink gets closer to the paper's brightness as the scene darkens.
+        let mut detected = Vec::new();
+        for frame in 0..40 {
+            let ink = (0.5 + frame as f64 * 0.02).min(0.98);
+            let buffer: Vec<u8> = code
+                .iter()
+                .map(|&v| if v == 0 { (200.0 * ink) as u8 } else { 200 })
+                .collect();
+            let codes = scanner.scan(&buffer, |buffer, index| {
+                let v = buffer[index] as u32;
+                (v, v, v)
+            });
+            detected.push(codes.len());
+            exposure.adjust(&mut scanner, &codes);
+        }
+
+        assert!(detected.contains(&0));
+        assert!(detected[30..].iter().all(|&count| count == 1));
+        assert!(scanner.threshold_bias() > DEFAULT_THRESHOLD_BIAS);
+    }
+}
@@ -0,0 +1,116 @@
+//! Fixed-point (Q16.16) math for sampling the rings of a symbol while decoding, for targets
+//! without hardware floating point. The directions of the sectors are computed at compile time,
+//! so that decoding runs no trigonometry at all.
+
+use core::f64::consts::PI;
+
+use crate::topcode::SECTORS;
+
+/// Number of fractional bits of fixed-point values.
+const FRACTION_BITS: u32 = 16;
+
+/// 1.0 as a fixed-point value.
+const ONE: i64 = 1 << FRACTION_BITS;
+
+/// Angular width of a sector. The directions are computed in `f64`, even when the decoder uses
+/// `f32`.
+const ARC: f64 = 2.0 * PI / SECTORS as f64;
+
+/// Number of rotation adjustments tried per sector, in tenths of a sector.
+pub(crate) const ADJUSTMENTS: usize = 10;
+
+/// Cosine and sine of the angle `ARC * (sector + tenths / 10)` at index
+/// `sector * ADJUSTMENTS + tenths`, as fixed-point values.
+static DIRECTIONS: [(i64, i64); SECTORS * ADJUSTMENTS] = directions();
+
+const fn directions() -> [(i64, i64); SECTORS * ADJUSTMENTS] {
+    let mut directions = [(0, 0); SECTORS * ADJUSTMENTS];
+    let mut i = 0;
+    while i < directions.len() {
+        // The same expression as the angles of the floating-point decoder.
+        let angle = ARC * (i / ADJUSTMENTS) as f64 + (i % ADJUSTMENTS) as f64 * ARC * 0.1;
+        directions[i] = (
+            round(sin(angle + PI / 2.0) * ONE as f64),
+            round(sin(angle) * ONE as f64),
+        );
+        i += 1;
+    }
+    directions
+}
+
+/// Sine of `x` in `[0, 3 PI)`, from its Taylor series around the nearest multiple of PI / 2.
+const fn sin(x: f64) -> f64 {
+    let quadrant = round(x / (PI / 2.0));
+    let r = x - quadrant as f64 * (PI / 2.0);
+    let r2 = r * r;
+    // Both series are accurate to well below 1 / ONE for |r| <= PI / 4.
+    let sin = r * (1.0 - r2 / 6.0 * (1.0 - r2 / 20.0 * (1.0 - r2 / 42.0 * (1.0 - r2 / 72.0))));
+    let cos = 1.0 - r2 / 2.0 * (1.0 - r2 / 12.0 * (1.0 - r2 / 30.0 * (1.0 - r2 / 56.0)));
+    match quadrant % 4 {
+        0 => sin,
+        1 => cos,
+        2 => -sin,
+        _ => -cos,
+    }
+}
+
+/// Rounds `x` to the nearest integer, away from zero at halves.
+const fn round(x: f64) -> i64 {
+    if x < 0.0 {
+        -((-x + 0.5) as i64)
+    } else {
+        (x + 0.5) as i64
+    }
+}
+
+/// Converts `x` to fixed point, rounding to the nearest representable value.
+pub(crate) fn to_fixed(x: impl Into<f64>) -> i64 {
+    round(x.into() * ONE as f64)
+}
+
+/// Position of sample `i` (0 to 7) across the diameter of a symbol centered on (x, y), along the
+/// direction at `index` (see [DIRECTIONS]) with the given `unit`, all in fixed point. Samples
+/// are 3.5 units from the center at each end, and positions are rounded to the nearest pixel,
+/// saturating at 0 as float to integer casts do.
+pub(crate) fn sample_position(x: i64, y: i64, unit: i64, index: usize, i: usize) -> (usize, usize) {
+    let (dx, dy) = DIRECTIONS[index];
+    let dist = (2 * i as i64 - 7) * unit / 2;
+    let pixel = |v: i64| ((v + ONE / 2) >> FRACTION_BITS).max(0) as usize;
+    (
+        pixel(x + ((dx * dist) >> FRACTION_BITS)),
+        pixel(y + ((dy * dist) >> FRACTION_BITS)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directions_match_the_float_trigonometry() {
+        for (i, &(cos, sin)) in DIRECTIONS.iter().enumerate() {
+            let angle = ARC * (i / ADJUSTMENTS) as f64 + (i % ADJUSTMENTS) as f64 * ARC * 0.1;
+            assert!((cos - to_fixed(angle.cos())).abs() <= 1, "{}", i);
+            assert!((sin - to_fixed(angle.sin())).abs() <= 1, "{}", i);
+        }
+    }
+
+    #[test]
+    fn sample_positions_match_the_float_positions() {
+        // Away from halves, where the float positions round either way depending on rounding errors.
+        let (x, y, unit) = (100.3, 80.7, 6.1);
+        for index in 0..SECTORS * ADJUSTMENTS {
+            let angle =
+                ARC * (index / ADJUSTMENTS) as f64 + (index % ADJUSTMENTS) as f64 * ARC * 0.1;
+            for i in 0..8 {
+                let dist = (i as f64 - 3.5) * unit;
+                let expected = (
+                    (x + angle.cos() * dist).round() as usize,
+                    (y + angle.sin() * dist).round() as usize,
+                );
+                let position = sample_position(to_fixed(x), to_fixed(y), to_fixed(unit), index, i);
+                assert_eq!(position, expected, "{} {}", index, i);
+            }
+        }
+    }
+}
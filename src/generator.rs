@@ -0,0 +1,447 @@
+use alloc::{borrow::ToOwned, format, string::String, vec::Vec};
+use core::f64::consts::PI;
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::{
+    codes::{extended_from_id, is_valid_code},
+    errors::TopCodeError,
+    scanner::Scanner,
+    topcode::{Code, TopCode, SECTORS, WIDTH},
+};
+
+/// Physical unit of the size of an SVG document.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SvgUnit {
+    Millimeters,
+    Centimeters,
+    Inches,
+    Points,
+    Pixels,
+}
+
+impl SvgUnit {
+    /// The suffix of SVG lengths in this unit.
+    fn suffix(self) -> &'static str {
+        match self {
+            Self::Millimeters => "mm",
+            Self::Centimeters => "cm",
+            Self::Inches => "in",
+            Self::Points => "pt",
+            Self::Pixels => "px",
+        }
+    }
+}
+
+/// Angular width of a sector when drawing. Symbols are always drawn in `f64`, even when the
+/// decoder uses `f32`.
+pub(crate) const ARC: f64 = 2.0 * PI / SECTORS as f64;
+
+/// Number of samples taken along each axis of a pixel to anti-alias the edges of the rings.
+const SUPERSAMPLES: usize = 4;
+
+/// Diameter (in pixels) of the symbols rendered to check the scannability of colors.
+const CHECK_SIZE: usize = 96;
+
+/// Width (in pixels) of the margin around the symbols rendered to check the scannability of
+/// colors.
+const CHECK_MARGIN: usize = 20;
+
+impl TopCode {
+    /// Renders `code` as a `size` x `size` grayscale image (0 is black, 255 is white), with the
+    /// symbol's outer data ring touching the edges of the image. The symbol is drawn so that it
+    /// is decoded with an orientation of 0. Fails for codes which are not valid (see
+    /// [is_valid_code]), as the scanner could not read them back.
+    ///
+    /// Leave a white margin around the symbol when printing it, so that it can be told apart
+    /// from whatever surrounds it.
+    pub fn draw(code: Code, size: usize) -> Result<Vec<u8>, TopCodeError> {
+        if !is_valid_code(code) {
+            return Err(TopCodeError::InvalidCode(code));
+        }
+        Ok(render(code, None, size))
+    }
+
+    /// Renders the symbol with the given ID of the extended symbology (see
+    /// [crate::codes::extended_from_id]), which has a second data ring around the standard
+    /// symbol, as a `size` x `size` grayscale image. The symbol is 10 units wide rather than 8,
+    /// but otherwise laid out as in [TopCode::draw]. Fails for IDs which are out of range, as
+    /// [TopCodeError::InvalidCode].
+    pub fn draw_extended(id: usize, size: usize) -> Result<Vec<u8>, TopCodeError> {
+        let invalid = TopCodeError::InvalidCode(u32::try_from(id).unwrap_or(u32::MAX));
+        let (code, second) = extended_from_id(id).ok_or(invalid)?;
+        Ok(render(code, Some(second), size))
+    }
+
+    /// Renders `code` as a standalone SVG document, `diameter` wide and high in the given unit,
+    /// so that it stays crisp when printed at any resolution. The symbol is laid out as in
+    /// [TopCode::draw], and the runs of black data sectors are drawn as single shapes so that no
+    /// seams show between them. Fails for codes which are not valid.
+    pub fn to_svg(code: Code, diameter: f64, unit: SvgUnit) -> Result<String, TopCodeError> {
+        svg(code, diameter, unit, "black", "white")
+    }
+
+    /// Same as [TopCode::to_svg], with the black parts of the symbol drawn in the `foreground`
+    /// color and the white parts in the `background` color, both as RGB. Check that the colors
+    /// contrast enough to be scanned with [TopCode::colors_are_scannable].
+    pub fn to_svg_colored(
+        code: Code,
+        diameter: f64,
+        unit: SvgUnit,
+        foreground: [u8; 3],
+        background: [u8; 3],
+    ) -> Result<String, TopCodeError> {
+        svg(code, diameter, unit, &hex(foreground), &hex(background))
+    }
+
+    /// Same as [TopCode::draw], as opaque RGBA pixels.
+    pub fn draw_rgba(code: Code, size: usize) -> Result<Vec<u8>, TopCodeError> {
+        Ok(Self::draw(code, size)?
+            .into_iter()
+            .flat_map(|v| [v, v, v, 0xff])
+            .collect())
+    }
+
+    /// Same as [TopCode::draw], as RGB pixels with the black parts of the symbol in the
+    /// `foreground` color and the white parts in the `background` color. Anti-aliased edges blend
+    /// the two colors.
+    pub fn draw_colored(
+        code: Code,
+        size: usize,
+        foreground: [u8; 3],
+        background: [u8; 3],
+    ) -> Result<Vec<u8>, TopCodeError> {
+        Ok(Self::draw(code, size)?
+            .into_iter()
+            .flat_map(|v| blend(foreground, background, v))
+            .collect())
+    }
+
+    /// Whether `code` printed in the `foreground` color on the `background` color can be read by
+    /// the scanner. The symbol is rendered with [TopCode::draw_colored] on a margin of the
+    /// background color and scanned with default settings, so this checks that the contrast
+    /// between the colors survives thresholding. It does not account for printing or lighting,
+    /// so prefer colors which pass with a wide margin, e.g. by also checking slightly lighter
+    /// foreground and darker background colors. Fails for codes which are not valid.
+    pub fn colors_are_scannable(
+        code: Code,
+        foreground: [u8; 3],
+        background: [u8; 3],
+    ) -> Result<bool, TopCodeError> {
+        let symbol = Self::draw_colored(code, CHECK_SIZE, foreground, background)?;
+        let width = CHECK_SIZE + 2 * CHECK_MARGIN;
+        let mut buffer = background.repeat(width * width);
+        for (y, row) in symbol.chunks(CHECK_SIZE * 3).enumerate() {
+            let start = ((y + CHECK_MARGIN) * width + CHECK_MARGIN) * 3;
+            buffer[start..start + row.len()].copy_from_slice(row);
+        }
+
+        let mut scanner = Scanner::new(width, width);
+        let found = scanner.scan_rgb_u8(&buffer)?;
+        Ok(found.len() == 1 && found[0].code == Some(code))
+    }
+}
+
+/// Renders `code` as an SVG document, with the black parts of the symbol filled with the
+/// `foreground` paint and the white parts with the `background` paint.
+fn svg(
+    code: Code,
+    diameter: f64,
+    unit: SvgUnit,
+    foreground: &str,
+    background: &str,
+) -> Result<String, TopCodeError> {
+    if !is_valid_code(code) {
+        return Err(TopCodeError::InvalidCode(code));
+    }
+    let size = format!("{}{}", number(diameter), unit.suffix());
+    let radius = WIDTH as f64 / 2.0;
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{size}\" height=\"{size}\" \
+         viewBox=\"{} {} {} {}\">\n",
+        -radius, -radius, WIDTH, WIDTH
+    );
+    svg.push_str(&format!("  <circle r=\"4\" fill=\"{background}\"/>\n"));
+    svg.push_str(&format!("  <circle r=\"2\" fill=\"{foreground}\"/>\n"));
+    svg.push_str(&format!("  <circle r=\"1\" fill=\"{background}\"/>\n"));
+
+    for (start, end) in black_runs(code) {
+        svg.push_str(&black_sectors(start, end, foreground));
+    }
+
+    svg.push_str("</svg>\n");
+    Ok(svg)
+}
+
+/// The color of a pixel with the given coverage of white (0 is fully `foreground`, 255 fully
+/// `background`).
+fn blend(foreground: [u8; 3], background: [u8; 3], white: u8) -> [u8; 3] {
+    let white = white as u32;
+    let mix = |f: u8, b: u8| ((f as u32 * (255 - white) + b as u32 * white + 127) / 255) as u8;
+    [
+        mix(foreground[0], background[0]),
+        mix(foreground[1], background[1]),
+        mix(foreground[2], background[2]),
+    ]
+}
+
+/// Formats an RGB color as an SVG hex color.
+fn hex([r, g, b]: [u8; 3]) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// The runs of consecutive black data sectors of `code`, as ranges from the first sector of each
+/// run up to (but excluding) the first white sector after it. Sectors past the last one wrap
+/// around, so that no run is split in two. A code without white sectors has no runs.
+pub(crate) fn black_runs(code: Code) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    // Start from a white sector, so that no run of black sectors wraps around.
+    let Some(first) = (0..SECTORS).find(|i| (code >> i) & 0x01 == 1) else {
+        return runs;
+    };
+    let mut run: Option<usize> = None;
+    for i in first + 1..=first + SECTORS {
+        let black = i < first + SECTORS && (code >> (i % SECTORS)) & 0x01 == 0;
+        match (run, black) {
+            (None, true) => run = Some(i),
+            (Some(start), false) => {
+                runs.push((start, i));
+                run = None;
+            }
+            _ => {}
+        }
+    }
+    runs
+}
+
+/// An SVG path filling the data ring from the start of sector `start` to the start of sector
+/// `end` with the `fill` paint, where sectors past the last one wrap around.
+fn black_sectors(start: usize, end: usize, fill: &str) -> String {
+    let from = ARC * (start as f64 + 0.15);
+    let to = ARC * (end as f64 + 0.15);
+    let large = if to - from > PI { 1 } else { 0 };
+    let point =
+        |r: f64, angle: f64| format!("{} {}", number(r * angle.cos()), number(r * angle.sin()));
+
+    format!(
+        "  <path d=\"M {} A 4 4 0 {large} 1 {} L {} A 3 3 0 {large} 0 {} Z\" fill=\"{fill}\"/>\n",
+        point(4.0, from),
+        point(4.0, to),
+        point(3.0, to),
+        point(3.0, from),
+    )
+}
+
+/// Formats `value` with at most 4 decimals and no trailing zeros.
+fn number(value: f64) -> String {
+    let formatted = format!("{:.4}", value);
+    let trimmed = formatted.trim_end_matches('0').trim_end_matches('.');
+    match trimmed {
+        "-0" => "0".to_owned(),
+        trimmed => trimmed.to_owned(),
+    }
+}
+
+/// Renders the symbol for `code`, with a second data ring holding `second` if given, as a
+/// `size` x `size` grayscale image spanning the symbol's full width.
+fn render(code: Code, second: Option<Code>, size: usize) -> Vec<u8> {
+    let units = if second.is_some() { WIDTH + 2 } else { WIDTH };
+    let unit = size as f64 / units as f64;
+    let center = size as f64 / 2.0;
+    let step = 1.0 / SUPERSAMPLES as f64;
+
+    let mut buffer = Vec::with_capacity(size * size);
+    for y in 0..size {
+        for x in 0..size {
+            let mut white = 0;
+            for j in 0..SUPERSAMPLES {
+                for i in 0..SUPERSAMPLES {
+                    let dx = x as f64 + (i as f64 + 0.5) * step - center;
+                    let dy = y as f64 + (j as f64 + 0.5) * step - center;
+                    if is_white(code, second, dx / unit, dy / unit) {
+                        white += 1;
+                    }
+                }
+            }
+            let samples = SUPERSAMPLES * SUPERSAMPLES;
+            buffer.push(((white * 255 + samples / 2) / samples) as u8);
+        }
+    }
+    buffer
+}
+
+/// Whether the point at (x, y) units from the center of a symbol for `code` is white. The
+/// second data ring holds `second` if given, and everything beyond the data rings is white.
+fn is_white(code: Code, second: Option<Code>, x: f64, y: f64) -> bool {
+    let r = (x * x + y * y).sqrt();
+    if r < 1.0 {
+        true
+    } else if r < 2.0 {
+        false
+    } else if r < 3.0 {
+        true
+    } else if r < 4.0 || (r < 5.0 && second.is_some()) {
+        // The first data sector starts 0.15 sectors after the orientation.
+        let angle = (y.atan2(x) - ARC * 0.15).rem_euclid(2.0 * PI);
+        let sector = (angle / ARC) as usize % SECTORS;
+        let bits = if r < 4.0 { code } else { second.unwrap_or(0) };
+        (bits >> sector) & 0x01 == 1
+    } else {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::topcode::{Real, PI};
+
+    #[test]
+    fn drawn_codes_are_decoded() {
+        let size = 96;
+        let margin = 20;
+        let width = size + 2 * margin;
+        let mut scanner = Scanner::new(width, width);
+        for code in crate::codes::iter() {
+            let symbol = TopCode::draw(code, size).unwrap();
+            assert_eq!(symbol.len(), size * size);
+
+            let mut buffer = vec![255; width * width];
+            for (y, row) in symbol.chunks(size).enumerate() {
+                let start = (y + margin) * width + margin;
+                buffer[start..start + size].copy_from_slice(row);
+            }
+            let found = scanner.scan_gray_u8(&buffer).unwrap();
+            assert_eq!(found.len(), 1, "{}", code);
+            assert_eq!(found[0].code, Some(code));
+            assert!((found[0].x - width as Real / 2.0).abs() < 1.0);
+            assert!((found[0].y - width as Real / 2.0).abs() < 1.0);
+            assert!((found[0].unit - size as Real / WIDTH as Real).abs() < 1.0);
+            let orientation = found[0].orientation.rem_euclid(2.0 * PI);
+            assert!(
+                orientation.min(2.0 * PI - orientation) < 0.1,
+                "{}",
+                orientation
+            );
+        }
+
+        let rgba = TopCode::draw_rgba(31, size).unwrap();
+        assert!(rgba
+            .chunks(4)
+            .all(|p| p[0] == p[1] && p[1] == p[2] && p[3] == 0xff));
+        assert!(rgba
+            .chunks(4)
+            .map(|p| p[0])
+            .eq(TopCode::draw(31, size).unwrap()));
+
+        assert_eq!(TopCode::draw(62, size), Err(TopCodeError::InvalidCode(62)));
+        assert_eq!(
+            TopCode::draw_rgba(0, size),
+            Err(TopCodeError::InvalidCode(0))
+        );
+    }
+
+    #[test]
+    fn drawn_extended_symbols_are_decoded() {
+        let size = 120;
+        let margin = 20;
+        let width = size + 2 * margin;
+        let mut scanner = Scanner::new(width, width);
+        for id in [0, 1, 1286, 1287, 64000, crate::codes::EXTENDED_COUNT - 1] {
+            let symbol = TopCode::draw_extended(id, size).unwrap();
+            let mut buffer = vec![255; width * width];
+            for (y, row) in symbol.chunks(size).enumerate() {
+                let start = (y + margin) * width + margin;
+                buffer[start..start + size].copy_from_slice(row);
+            }
+
+            scanner.set_extended_symbology(false);
+            let found = scanner.scan_gray_u8(&buffer).unwrap();
+            assert_eq!(found.len(), 1, "{}", id);
+            assert_eq!(found[0].code, extended_from_id(id).map(|(code, _)| code));
+            assert_eq!(found[0].extended_id(), None);
+
+            scanner.set_extended_symbology(true);
+            let found = scanner.scan_gray_u8(&buffer).unwrap();
+            assert_eq!(found.len(), 1, "{}", id);
+            assert_eq!(found[0].extended_id(), Some(id));
+        }
+
+        // Standard symbols have a white second ring, which is not a valid pattern.
+        let symbol = TopCode::draw(55, 96).unwrap();
+        let mut buffer = vec![255; 136 * 136];
+        for (y, row) in symbol.chunks(96).enumerate() {
+            buffer[(y + 20) * 136 + 20..(y + 20) * 136 + 116].copy_from_slice(row);
+        }
+        let mut scanner = Scanner::new(136, 136);
+        scanner.set_extended_symbology(true);
+        let found = scanner.scan_gray_u8(&buffer).unwrap();
+        assert_eq!(found[0].code, Some(55));
+        assert_eq!(found[0].second_ring(), None);
+
+        assert!(TopCode::draw_extended(crate::codes::EXTENDED_COUNT, size).is_err());
+    }
+
+    #[test]
+    fn color_scannability_depends_on_contrast() {
+        let navy = [20, 30, 90];
+        let cream = [250, 240, 200];
+        // A shade darker than the background, which thresholding can't tell apart from it.
+        let shade = [247, 237, 197];
+        assert_eq!(TopCode::colors_are_scannable(93, navy, cream), Ok(true));
+        assert_eq!(TopCode::colors_are_scannable(93, cream, navy), Ok(false));
+        assert_eq!(TopCode::colors_are_scannable(93, shade, cream), Ok(false));
+        assert_eq!(
+            TopCode::colors_are_scannable(62, navy, cream),
+            Err(TopCodeError::InvalidCode(62))
+        );
+
+        let pixels = TopCode::draw_colored(31, 32, navy, cream).unwrap();
+        assert_eq!(pixels.len(), 32 * 32 * 3);
+        // The corners are beyond the data ring, and the center is in the bullseye.
+        assert_eq!(pixels[..3], cream);
+        let center = (16 * 32 + 16) * 3;
+        assert_eq!(pixels[center..center + 3], cream);
+        assert!(pixels.chunks(3).any(|p| p == navy));
+    }
+
+    #[test]
+    fn svgs_have_a_shape_per_run_of_black_sectors() {
+        let svg = TopCode::to_svg(31, 30.0, SvgUnit::Millimeters).unwrap();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"30mm\""));
+        assert!(svg.contains("viewBox=\"-4 -4 8 8\""));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        // 31 has a single run of 8 black sectors, spanning more than half of the ring.
+        assert_eq!(svg.matches("<path").count(), 1);
+        assert!(svg.contains("A 4 4 0 1 1"));
+
+        // 0b0000101010101 has black runs of 1, 1, 1, 1 and 4 sectors.
+        let svg = TopCode::to_svg(0b0000101010101, 1.5, SvgUnit::Inches).unwrap();
+        assert!(svg.contains("width=\"1.5in\" height=\"1.5in\""));
+        assert_eq!(svg.matches("<path").count(), 5);
+        assert!(!svg.contains("A 4 4 0 1 1"));
+
+        assert_eq!(
+            TopCode::to_svg(0, 72.0, SvgUnit::Points),
+            Err(TopCodeError::InvalidCode(0))
+        );
+
+        let svg = TopCode::to_svg_colored(
+            31,
+            30.0,
+            SvgUnit::Millimeters,
+            [0x1a, 0x2b, 0x6d],
+            [0xf5, 0xf0, 0xdc],
+        )
+        .unwrap();
+        assert_eq!(svg.matches("fill=\"#1a2b6d\"").count(), 2);
+        assert_eq!(svg.matches("fill=\"#f5f0dc\"").count(), 2);
+        assert!(!svg.contains("black"));
+
+        assert_eq!(number(-0.00001), "0");
+        assert_eq!(number(2.5), "2.5");
+        assert_eq!(number(3.0), "3");
+    }
+}
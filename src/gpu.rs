@@ -0,0 +1,376 @@
+use std::sync::mpsc;
+
+use crate::{
+    candidate::Candidate,
+    errors::TopCodeError,
+    scanner::{ScanBuffer, Scanner},
+    topcode::TopCode,
+};
+
+/// Largest number of candidates kept per frame. Further candidates found by the shader are dropped.
+const MAX_CANDIDATES: u32 = 1 << 15;
+
+/// Number of invocations in each workgroup of the compute shader.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Storage buffers bound by the compute shader.
+const STORAGE_BUFFERS: u32 = 6;
+
+/// Thresholds packed 8-bit images in a compute shader with [wgpu](https://crates.io/crates/wgpu),
+/// for video at resolutions where thresholding dominates the cost of a scan on the CPU.
+///
+/// Only the binary image and the candidates are downloaded from the GPU, and candidates are
+/// decoded on the CPU by the scanner, which keeps the thresholded image for sampling. The running
+/// sum of each row is seeded from the row above it, as with one band per row in
+/// [Scanner::set_threshold_bands]. Flat-field correction, local contrast thresholding, regions of
+/// interest and excluded pixels are not applied while thresholding.
+pub struct GpuThresholder {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipelines: [wgpu::ComputePipeline; 3],
+    bind_group: wgpu::BindGroup,
+    params: wgpu::Buffer,
+    image: wgpu::Buffer,
+    ceilings: wgpu::Buffer,
+    mask: wgpu::Buffer,
+    candidates: wgpu::Buffer,
+    count: wgpu::Buffer,
+    /// Buffer the mask, candidate count and candidates are copied into to be read back
+    readback: wgpu::Buffer,
+    width: usize,
+    height: usize,
+}
+
+impl GpuThresholder {
+    /// Opens the default GPU for thresholding images of the same dimensions as the scanner's.
+    /// Fails with [TopCodeError::GpuUnavailable] if no adapter supports compute shaders with the
+    /// buffers needed.
+    pub fn new(scanner: &Scanner<impl ScanBuffer>) -> Result<Self, TopCodeError> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&Default::default()))
+            .ok_or(TopCodeError::GpuUnavailable)?;
+        let limits = adapter.limits();
+        let compute = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS);
+        if !compute || limits.max_storage_buffers_per_shader_stage < STORAGE_BUFFERS {
+            return Err(TopCodeError::GpuUnavailable);
+        }
+        let descriptor = wgpu::DeviceDescriptor {
+            label: Some("topcodes"),
+            required_limits: limits,
+            ..Default::default()
+        };
+        let (device, queue) = pollster::block_on(adapter.request_device(&descriptor, None))
+            .map_err(|_| TopCodeError::GpuUnavailable)?;
+
+        let (width, height) = scanner.dimensions();
+        let pixels = (width * height) as u64;
+        let mask_bytes = Scanner::mask_len(width, height) as u64 * 4;
+        let candidate_bytes = MAX_CANDIDATES as u64 * 16;
+        let buffer = |label, size, usage| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let storage = wgpu::BufferUsages::STORAGE;
+        let copy = wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST;
+        let params = buffer(
+            "params",
+            64,
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        );
+        let image = buffer("image", pixels * 4, storage | copy);
+        let ceilings = buffer("ceilings", 256 * 4, storage | copy);
+        let sums = buffer("sums", pixels * 4, storage);
+        let mask = buffer("mask", mask_bytes, storage | copy);
+        let candidates = buffer("candidates", candidate_bytes, storage | copy);
+        let count = buffer("count", 4, storage | copy);
+        let readback = buffer(
+            "readback",
+            mask_bytes + 4 + candidate_bytes,
+            wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        );
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty,
+            count: None,
+        };
+        let storage_entry = |binding, read_only| {
+            entry(
+                binding,
+                wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+            )
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("threshold"),
+            entries: &[
+                entry(
+                    0,
+                    wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                ),
+                storage_entry(1, true),
+                storage_entry(2, true),
+                storage_entry(3, false),
+                storage_entry(4, false),
+                storage_entry(5, false),
+                storage_entry(6, false),
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("threshold"),
+            layout: &layout,
+            entries: &[
+                &params,
+                &image,
+                &ceilings,
+                &sums,
+                &mask,
+                &candidates,
+                &count,
+            ]
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<_>>(),
+        });
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("threshold"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gpu.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("threshold"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipelines = ["running_sums", "classify", "search"].map(|entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &module,
+                entry_point: Some(entry_point),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        });
+
+        Ok(Self {
+            device,
+            queue,
+            pipelines,
+            bind_group,
+            params,
+            image,
+            ceilings,
+            mask,
+            candidates,
+            count,
+            readback,
+            width,
+            height,
+        })
+    }
+
+    /// Same as [Scanner::scan_rgb_u8], with the image thresholded on the GPU. The scanner's
+    /// settings are read for every frame.
+    pub fn scan_rgb_u8(
+        &mut self,
+        scanner: &mut Scanner<impl ScanBuffer>,
+        image_buffer: &[u8],
+    ) -> Result<Vec<TopCode>, TopCodeError> {
+        let candidates = self.threshold_u8(scanner, image_buffer, 3, [0, 1, 2])?;
+        Ok(scanner.find_codes(&candidates))
+    }
+
+    /// Same as [Scanner::scan_rgba_u8], with the image thresholded on the GPU.
+    pub fn scan_rgba_u8(
+        &mut self,
+        scanner: &mut Scanner<impl ScanBuffer>,
+        image_buffer: &[u8],
+    ) -> Result<Vec<TopCode>, TopCodeError> {
+        let candidates = self.threshold_u8(scanner, image_buffer, 4, [0, 1, 2])?;
+        Ok(scanner.find_codes(&candidates))
+    }
+
+    /// Same as [Scanner::scan_gray_u8], with the image thresholded on the GPU.
+    pub fn scan_gray_u8(
+        &mut self,
+        scanner: &mut Scanner<impl ScanBuffer>,
+        image_buffer: &[u8],
+    ) -> Result<Vec<TopCode>, TopCodeError> {
+        let candidates = self.threshold_u8(scanner, image_buffer, 1, [0, 0, 0])?;
+        Ok(scanner.find_codes(&candidates))
+    }
+
+    /// Thresholds an image of packed 8-bit pixels of `bytes_per_pixel` bytes, with their red,
+    /// green and blue channels at the offsets `[r, g, b]`, loading the binary image into the
+    /// scanner and returning the candidates in the order the scanner would find them.
+    fn threshold_u8(
+        &mut self,
+        scanner: &mut Scanner<impl ScanBuffer>,
+        image_buffer: &[u8],
+        bytes_per_pixel: usize,
+        channels: [usize; 3],
+    ) -> Result<Vec<Candidate>, TopCodeError> {
+        if scanner.dimensions() != (self.width, self.height)
+            || image_buffer.len() != self.width * self.height * bytes_per_pixel
+        {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+
+        let params = scanner.gpu_params(bytes_per_pixel, channels, MAX_CANDIDATES);
+        self.queue
+            .write_buffer(&self.params, 0, &words_to_bytes(&params));
+        self.queue.write_buffer(
+            &self.ceilings,
+            0,
+            &words_to_bytes(&scanner.threshold_ceilings()),
+        );
+        // Buffer writes must be a multiple of 4 bytes long, so the last few bytes are padded.
+        let aligned = image_buffer.len() / 4 * 4;
+        self.queue
+            .write_buffer(&self.image, 0, &image_buffer[..aligned]);
+        if aligned < image_buffer.len() {
+            let mut tail = [0; 4];
+            tail[..image_buffer.len() - aligned].copy_from_slice(&image_buffer[aligned..]);
+            self.queue.write_buffer(&self.image, aligned as u64, &tail);
+        }
+
+        let mask_len = Scanner::mask_len(self.width, self.height);
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.clear_buffer(&self.count, 0, None);
+        let rows = (self.height as u32).div_ceil(WORKGROUP_SIZE);
+        let words = (mask_len as u32).div_ceil(WORKGROUP_SIZE);
+        for (pipeline, workgroups) in self.pipelines.iter().zip([rows, words, rows]) {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.dispatch_workgroups(workgroups, 1, 1);
+        }
+        let mask_bytes = mask_len as u64 * 4;
+        encoder.copy_buffer_to_buffer(&self.mask, 0, &self.readback, 0, mask_bytes);
+        encoder.copy_buffer_to_buffer(&self.count, 0, &self.readback, mask_bytes, 4);
+        encoder.copy_buffer_to_buffer(
+            &self.candidates,
+            0,
+            &self.readback,
+            mask_bytes + 4,
+            self.candidates.size(),
+        );
+        self.queue.submit([encoder.finish()]);
+
+        let slice = self.readback.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .ok()
+            .and_then(|result| result.ok())
+            .ok_or(TopCodeError::GpuUnavailable)?;
+
+        let words: Vec<u32> = slice
+            .get_mapped_range()
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        self.readback.unmap();
+
+        let (mask, rest) = words.split_at(mask_len);
+        scanner.load_mask(mask);
+        let count = rest[0].min(MAX_CANDIDATES) as usize;
+        let mut found: Vec<&[u32]> = rest[1..].chunks_exact(4).take(count).collect();
+        // Rows are searched concurrently, so candidates are put back in the order of the sweep.
+        found.sort_unstable_by_key(|candidate| (candidate[1], candidate[2]));
+        Ok(found
+            .into_iter()
+            .map(|candidate| {
+                let c = Candidate::new(candidate[0] as usize, candidate[1] as usize);
+                if candidate[3] == 1 {
+                    c.into_inverted()
+                } else {
+                    c
+                }
+            })
+            .collect())
+    }
+}
+
+/// Little-endian bytes of `words`, as laid out in GPU buffers.
+fn words_to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::io::Reader as ImageReader;
+
+    use super::*;
+    use crate::Polarity;
+
+    #[test]
+    fn shader_is_valid() {
+        let module = naga::front::wgsl::parse_str(include_str!("gpu.wgsl")).unwrap();
+        naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::empty(),
+        )
+        .validate(&module)
+        .unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a GPU adapter, run with `cargo test --features gpu -- --ignored`"]
+    fn gpu_thresholding_matches_one_band_per_row() {
+        let img = ImageReader::open("assets/photo.png")
+            .unwrap()
+            .decode()
+            .unwrap();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let buffer = img.into_rgb8().into_raw();
+        let mut scanner = Scanner::new(width, height);
+        let mut gpu = GpuThresholder::new(&scanner).unwrap();
+
+        scanner.set_threshold_bands(height);
+        for polarity in [Polarity::Normal, Polarity::Inverted, Polarity::Both] {
+            scanner.set_polarity(polarity);
+            let expected = scanner.scan_rgb_u8(&buffer).unwrap();
+            let mask = scanner.threshold_mask();
+            let codes = gpu.scan_rgb_u8(&mut scanner, &buffer).unwrap();
+            let differing = mask
+                .iter()
+                .zip(scanner.threshold_mask())
+                .filter(|(a, b)| **a != *b)
+                .count();
+            assert!(differing * 1000 < mask.len(), "{} pixels differ", differing);
+            assert_eq!(codes, expected);
+        }
+
+        assert_eq!(
+            gpu.scan_rgb_u8(&mut scanner, &buffer[3..]),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
+    }
+}
@@ -0,0 +1,209 @@
+// Adaptive thresholding of packed 8-bit images for `GpuThresholder`, in three passes which follow
+// the scalar code in the scanner: the running sums of each row, the binary pixels of each word of
+// the bitmask, and the search of each row for bullseye patterns.
+
+struct Params {
+    width: u32,
+    height: u32,
+    bytes_per_pixel: u32,
+    // 0 for normal, 1 for inverted and 2 for both polarities
+    polarity: u32,
+    r: u32,
+    g: u32,
+    b: u32,
+    mirrored: u32,
+    max_unit: i32,
+    min_white: f32,
+    max_white: f32,
+    motion_compensation: f32,
+    capacity: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
+}
+
+// State of the search for bullseye patterns along a row, as in `RowSearch`.
+struct Search {
+    level: u32,
+    b1: i32,
+    b2: i32,
+    w1: i32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+// Bytes of the image, four to a word
+@group(0) @binding(1) var<storage, read> image: array<u32>;
+// Smallest white intensity for each threshold
+@group(0) @binding(2) var<storage, read> ceilings: array<u32>;
+// Running sum at each pixel
+@group(0) @binding(3) var<storage, read_write> sums: array<u32>;
+@group(0) @binding(4) var<storage, read_write> mask: array<u32>;
+// Candidates as (x, y, index within the row, inverted)
+@group(0) @binding(5) var<storage, read_write> candidates: array<vec4<u32>>;
+@group(0) @binding(6) var<storage, read_write> count: atomic<u32>;
+
+// Column of the `i`th pixel of row `j` in the serpentine sweep.
+fn column(j: u32, i: u32) -> u32 {
+    if (j % 2u == 0u) {
+        return i;
+    }
+    return params.width - 1u - i;
+}
+
+fn image_byte(i: u32) -> u32 {
+    return (image[i / 4u] >> (8u * (i % 4u))) & 255u;
+}
+
+fn luma(j: u32, x: u32) -> u32 {
+    var source = x;
+    if (params.mirrored != 0u) {
+        source = params.width - 1u - x;
+    }
+    let pixel = (j * params.width + source) * params.bytes_per_pixel;
+    return (image_byte(pixel + params.r) + image_byte(pixel + params.g) + image_byte(pixel + params.b)) / 3u;
+}
+
+// Running sums along each row. The sum entering a row is seeded by a pass over the row above,
+// like the bands of `Scanner::set_threshold_bands`.
+@compute @workgroup_size(64)
+fn running_sums(@builtin(global_invocation_id) id: vec3<u32>) {
+    let j = id.x;
+    if (j >= params.height) {
+        return;
+    }
+    var sum = 128u;
+    if (j > 0u) {
+        for (var i = 0u; i < params.width; i++) {
+            sum = sum + luma(j - 1u, column(j - 1u, i)) - sum / 32u;
+        }
+    }
+    for (var i = 0u; i < params.width; i++) {
+        let x = column(j, i);
+        sum = sum + luma(j, x) - sum / 32u;
+        sums[j * params.width + x] = sum;
+    }
+}
+
+// Binary pixels of each word of the bitmask, with white pixels set.
+@compute @workgroup_size(64)
+fn classify(@builtin(global_invocation_id) id: vec3<u32>) {
+    let w = id.x;
+    if (w >= arrayLength(&mask)) {
+        return;
+    }
+    let pixels = params.width * params.height;
+    var word = 0u;
+    for (var bit = 0u; bit < 32u; bit++) {
+        let k = w * 32u + bit;
+        if (k >= pixels) {
+            break;
+        }
+        let j = k / params.width;
+        var threshold = sums[k] / 32u;
+        if (j > 0u) {
+            threshold = (sums[k] + sums[k - params.width]) / 64u;
+        }
+        var white = select(0u, 1u, luma(j, k % params.width) >= ceilings[min(threshold, 255u)]);
+        if (params.polarity == 1u) {
+            white = 1u - white;
+        }
+        word |= white << bit;
+    }
+    mask[w] = word;
+}
+
+// Advances the search by the binary pixel `a`, returning the distance back to the center of the
+// bullseye if one ends at this pixel, or -1.
+fn advance(search: ptr<function, Search>, a: u32) -> i32 {
+    var dk = -1;
+    switch ((*search).level) {
+        case 0u: {
+            if (a == 0u) {
+                (*search).level = 1u;
+                (*search).b1 = 1;
+                (*search).w1 = 0;
+                (*search).b2 = 0;
+            }
+        }
+        case 1u: {
+            if (a == 0u) {
+                (*search).b1 += 1;
+            } else {
+                (*search).level = 2u;
+                (*search).w1 = 1;
+            }
+        }
+        case 2u: {
+            if (a == 0u) {
+                (*search).level = 3u;
+                (*search).b2 = 1;
+            } else {
+                (*search).w1 += 1;
+            }
+        }
+        default: {
+            if (a == 0u) {
+                (*search).b2 += 1;
+            } else {
+                let b1 = (*search).b1;
+                let b2 = (*search).b2;
+                let w1 = (*search).w1;
+                let u = params.max_unit;
+                let black = f32(b1 + b2);
+                if (b1 >= 2 && b2 >= 2 && b1 <= u && b2 <= u && w1 <= u + u
+                    && f32(w1) >= black * params.min_white && f32(w1) <= black * params.max_white
+                    && abs(b1 - b2) <= b1 && abs(b1 - b2) <= b2) {
+                    dk = 1 + b2 + (w1 >> 1u);
+                }
+                (*search).b1 = b2;
+                (*search).w1 = 1;
+                (*search).b2 = 0;
+                (*search).level = 2u;
+            }
+        }
+    }
+    return dk;
+}
+
+fn emit(j: u32, x: u32, dk: i32, index: u32, inverted: u32) {
+    var center = i32(x) + dk;
+    if (j % 2u == 0u) {
+        center = i32(x) - dk;
+    }
+    // Rounds halves away from zero like `f64::round`, for the centers which aren't clamped to 0.
+    let shifted = floor(f32(center) + params.motion_compensation + 0.5);
+    let cx = u32(clamp(shifted, 0.0, f32(params.width - 1u)));
+    let slot = atomicAdd(&count, 1u);
+    if (slot < params.capacity) {
+        candidates[slot] = vec4<u32>(cx, j, index, inverted);
+    }
+}
+
+// Searches each row for bullseye patterns in the direction of the serpentine sweep.
+@compute @workgroup_size(64)
+fn search(@builtin(global_invocation_id) id: vec3<u32>) {
+    let j = id.x;
+    if (j >= params.height) {
+        return;
+    }
+    var normal = Search(0u, 0, 0, 0);
+    var inverted = Search(0u, 0, 0, 0);
+    var index = 0u;
+    for (var i = 0u; i < params.width; i++) {
+        let x = column(j, i);
+        let k = j * params.width + x;
+        let a = (mask[k / 32u] >> (k % 32u)) & 1u;
+        let dk = advance(&normal, a);
+        if (dk >= 0) {
+            emit(j, x, dk, index, 0u);
+            index += 1u;
+        }
+        if (params.polarity == 2u) {
+            let inverted_dk = advance(&inverted, 1u - a);
+            if (inverted_dk >= 0) {
+                emit(j, x, inverted_dk, index, 1u);
+                index += 1u;
+            }
+        }
+    }
+}
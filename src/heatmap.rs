@@ -0,0 +1,82 @@
+use crate::topcode::{Real, TopCode};
+use alloc::{vec, vec::Vec};
+
+/// Accumulates where TopCodes are detected over many frames by binning their centers into a
+/// `cells` x `cells` grid covering the image. This shows which regions of the camera's field of
+/// view are productive, e.g. when choosing where to place a camera.
+#[derive(Clone, Debug)]
+pub struct DetectionHeatmap {
+    width: usize,
+    height: usize,
+    cells: usize,
+    counts: Vec<u32>,
+}
+
+impl DetectionHeatmap {
+    /// Creates an empty heatmap for images of the given dimensions, split into `cells` columns
+    /// and `cells` rows. A `cells` of 0 is treated as 1, a single cell covering the whole image.
+    pub fn new(width: usize, height: usize, cells: usize) -> Self {
+        let cells = cells.max(1);
+        Self {
+            width,
+            height,
+            cells,
+            counts: vec![0; cells * cells],
+        }
+    }
+
+    /// Adds the centers of the TopCodes detected in a single frame. Centers outside of the image
+    /// are ignored.
+    pub fn record(&mut self, codes: &[TopCode]) {
+        for code in codes {
+            if code.x < 0.0
+                || code.y < 0.0
+                || code.x >= self.width as Real
+                || code.y >= self.height as Real
+            {
+                continue;
+            }
+
+            let col = (code.x * self.cells as Real / self.width as Real) as usize;
+            let row = (code.y * self.cells as Real / self.height as Real) as usize;
+            self.counts[row * self.cells + col] += 1;
+        }
+    }
+
+    /// The number of detections in each cell, in row-major order. This copies the counts, see
+    /// [DetectionHeatmap::cell_counts] to borrow them instead.
+    pub fn counts(&self) -> Vec<u32> {
+        self.counts.clone()
+    }
+
+    /// Same as [DetectionHeatmap::counts], but borrows the counts rather than copying them.
+    pub fn cell_counts(&self) -> &[u32] {
+        &self.counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detections_are_binned_into_cells() {
+        let mut heatmap = DetectionHeatmap::new(300, 200, 3);
+        heatmap.record(&[
+            TopCode::mock(31, 10.0, 0.0, 10.0, 10.0),
+            TopCode::mock(55, 10.0, 0.0, 150.0, 100.0),
+        ]);
+        heatmap.record(&[TopCode::mock(31, 10.0, 0.0, 20.0, 5.0)]);
+        heatmap.record(&[
+            TopCode::mock(93, 10.0, 0.0, 299.0, 199.0),
+            TopCode::mock(93, 10.0, 0.0, 300.0, 10.0),
+        ]);
+
+        assert_eq!(heatmap.counts(), vec![2, 0, 0, 0, 1, 0, 0, 0, 1]);
+        assert_eq!(heatmap.cell_counts(), &heatmap.counts()[..]);
+
+        let mut heatmap = DetectionHeatmap::new(300, 200, 0);
+        heatmap.record(&[TopCode::mock(31, 10.0, 0.0, 150.0, 100.0)]);
+        assert_eq!(heatmap.cell_counts(), &[1]);
+    }
+}
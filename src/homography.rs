@@ -0,0 +1,356 @@
+use alloc::{vec, vec::Vec};
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::topcode::{Code, Real, TopCode};
+
+/// A projective mapping from the image to a plane, such as the surface of a table the camera looks
+/// down on at an angle. Estimated from codes placed at known positions on the surface (e.g. at its
+/// four corners), it maps detections into surface coordinates and renders a rectified top-down
+/// view of the surface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Homography {
+    /// Row-major matrix mapping homogeneous image coordinates to surface coordinates
+    matrix: [[Real; 3]; 3],
+}
+
+impl Homography {
+    /// Estimates the homography mapping each image point to the surface point at the same index,
+    /// by least squares if more than four points are given. Returns `None` for fewer than four
+    /// points, or if they don't determine a homography, e.g. when three of exactly four points
+    /// are collinear, or all of the points are.
+    pub fn from_points(image: &[(Real, Real)], surface: &[(Real, Real)]) -> Option<Self> {
+        let n = image.len().min(surface.len());
+        if n < 4 {
+            return None;
+        }
+        let (image, surface) = (&image[..n], &surface[..n]);
+        // Normalizing both sets of points keeps the equations well conditioned.
+        let from = Normalization::new(image)?;
+        let to = Normalization::new(surface)?;
+
+        let mut ata = [[0.0; 8]; 8];
+        let mut atb = [0.0; 8];
+        for (&p, &q) in image.iter().zip(surface) {
+            let (x, y) = from.apply(p);
+            let (u, v) = to.apply(q);
+            let rows = [
+                ([x, y, 1.0, 0.0, 0.0, 0.0, -x * u, -y * u], u),
+                ([0.0, 0.0, 0.0, x, y, 1.0, -x * v, -y * v], v),
+            ];
+            for (row, b) in rows {
+                for i in 0..8 {
+                    for j in 0..8 {
+                        ata[i][j] += row[i] * row[j];
+                    }
+                    atb[i] += row[i] * b;
+                }
+            }
+        }
+        let h = solve(ata, atb)?;
+        let normalized = [[h[0], h[1], h[2]], [h[3], h[4], h[5]], [h[6], h[7], 1.0]];
+
+        let matrix = multiply(&multiply(&to.inverse(), &normalized), &from.matrix());
+        Some(Self { matrix })
+    }
+
+    /// Estimates the homography from anchor codes placed on the surface, given as the code and the
+    /// surface position of its center. Anchors which were not detected are skipped, and duplicate
+    /// detections of an anchor are ignored. Returns `None` unless at least four anchors were
+    /// detected.
+    pub fn from_anchors(codes: &[TopCode], anchors: &[(Code, (Real, Real))]) -> Option<Self> {
+        let (image, surface): (Vec<_>, Vec<_>) = anchors
+            .iter()
+            .filter_map(|&(code, position)| {
+                let detected = codes.iter().find(|c| c.code == Some(code))?;
+                Some(((detected.x, detected.y), position))
+            })
+            .unzip();
+        Self::from_points(&image, &surface)
+    }
+
+    /// A homography with the given row-major matrix.
+    pub(crate) fn from_matrix(matrix: [[Real; 3]; 3]) -> Self {
+        Self { matrix }
+    }
+
+    /// The row-major matrix of the homography.
+    pub(crate) fn matrix(&self) -> &[[Real; 3]; 3] {
+        &self.matrix
+    }
+
+    /// Maps a point in the image to the surface.
+    pub fn map(&self, x: Real, y: Real) -> (Real, Real) {
+        apply(&self.matrix, (x, y))
+    }
+
+    /// The homography mapping surface points back into the image, or `None` if it is degenerate.
+    pub fn inverse(&self) -> Option<Self> {
+        let m = &self.matrix;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let adjugate = [
+            [
+                cofactor(1, 2, 1, 2),
+                -cofactor(0, 2, 1, 2),
+                cofactor(0, 1, 1, 2),
+            ],
+            [
+                -cofactor(1, 2, 0, 2),
+                cofactor(0, 2, 0, 2),
+                -cofactor(0, 1, 0, 2),
+            ],
+            [
+                cofactor(1, 2, 0, 1),
+                -cofactor(0, 2, 0, 1),
+                cofactor(0, 1, 0, 1),
+            ],
+        ];
+        let det = m[0][0] * adjugate[0][0] + m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+        if det == 0.0 {
+            return None;
+        }
+        Some(Self {
+            matrix: adjugate.map(|row| row.map(|value| value / det)),
+        })
+    }
+
+    /// Renders a top-down view of the surface from an image of `width` x `height` pixels, whose
+    /// pixels are read by `decode_rgb` like in [Scanner::scan](crate::Scanner::scan). The view is
+    /// `view_width` x `view_height` pixels, with its top left at the surface origin and
+    /// `pixels_per_unit` pixels per unit of the surface coordinates. Returns packed 8-bit RGB
+    /// pixels, interpolated bilinearly, with parts of the surface outside of the image in black.
+    pub fn rectify<T: ?Sized>(
+        &self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+        (width, height): (usize, usize),
+        (view_width, view_height): (usize, usize),
+        pixels_per_unit: Real,
+    ) -> Vec<u8> {
+        let Some(inverse) = self.inverse() else {
+            return vec![0; view_width * view_height * 3];
+        };
+        let mut view = Vec::with_capacity(view_width * view_height * 3);
+        for j in 0..view_height {
+            for i in 0..view_width {
+                let (x, y) = inverse.map(
+                    (i as Real + 0.5) / pixels_per_unit,
+                    (j as Real + 0.5) / pixels_per_unit,
+                );
+                // Pixel centers are at half-integer coordinates.
+                let (x, y) = (x - 0.5, y - 0.5);
+                if x < 0.0 || y < 0.0 || x > (width - 1) as Real || y > (height - 1) as Real {
+                    view.extend_from_slice(&[0, 0, 0]);
+                    continue;
+                }
+                let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+                let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+                let (fx, fy) = (x - x0 as Real, y - y0 as Real);
+                let corners = [
+                    (
+                        decode_rgb(image_buffer, y0 * width + x0),
+                        (1.0 - fx) * (1.0 - fy),
+                    ),
+                    (decode_rgb(image_buffer, y0 * width + x1), fx * (1.0 - fy)),
+                    (decode_rgb(image_buffer, y1 * width + x0), (1.0 - fx) * fy),
+                    (decode_rgb(image_buffer, y1 * width + x1), fx * fy),
+                ];
+                let channel = |c: fn((u32, u32, u32)) -> u32| {
+                    let value: Real = corners
+                        .iter()
+                        .map(|&(rgb, weight)| c(rgb) as Real * weight)
+                        .sum();
+                    value.round().clamp(0.0, 255.0) as u8
+                };
+                view.extend_from_slice(&[
+                    channel(|(r, _, _)| r),
+                    channel(|(_, g, _)| g),
+                    channel(|(_, _, b)| b),
+                ]);
+            }
+        }
+        view
+    }
+}
+
+/// A similarity transform moving the centroid of a set of points to the origin, and scaling their
+/// average distance from it to the square root of 2.
+struct Normalization {
+    center: (Real, Real),
+    scale: Real,
+}
+
+impl Normalization {
+    fn new(points: &[(Real, Real)]) -> Option<Self> {
+        let n = points.len() as Real;
+        let cx = points.iter().map(|p| p.0).sum::<Real>() / n;
+        let cy = points.iter().map(|p| p.1).sum::<Real>() / n;
+        let distance = points
+            .iter()
+            .map(|p| ((p.0 - cx) * (p.0 - cx) + (p.1 - cy) * (p.1 - cy)).sqrt())
+            .sum::<Real>()
+            / n;
+        if distance == 0.0 {
+            return None;
+        }
+        Some(Self {
+            center: (cx, cy),
+            scale: (2.0 as Real).sqrt() / distance,
+        })
+    }
+
+    fn apply(&self, (x, y): (Real, Real)) -> (Real, Real) {
+        (
+            (x - self.center.0) * self.scale,
+            (y - self.center.1) * self.scale,
+        )
+    }
+
+    fn matrix(&self) -> [[Real; 3]; 3] {
+        let (cx, cy) = self.center;
+        let s = self.scale;
+        [[s, 0.0, -s * cx], [0.0, s, -s * cy], [0.0, 0.0, 1.0]]
+    }
+
+    fn inverse(&self) -> [[Real; 3]; 3] {
+        let (cx, cy) = self.center;
+        let s = 1.0 / self.scale;
+        [[s, 0.0, cx], [0.0, s, cy], [0.0, 0.0, 1.0]]
+    }
+}
+
+fn apply(m: &[[Real; 3]; 3], (x, y): (Real, Real)) -> (Real, Real) {
+    let w = m[2][0] * x + m[2][1] * y + m[2][2];
+    (
+        (m[0][0] * x + m[0][1] * y + m[0][2]) / w,
+        (m[1][0] * x + m[1][1] * y + m[1][2]) / w,
+    )
+}
+
+fn multiply(a: &[[Real; 3]; 3], b: &[[Real; 3]; 3]) -> [[Real; 3]; 3] {
+    let mut product = [[0.0; 3]; 3];
+    for (i, row) in product.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    product
+}
+
+/// Solves the linear system `a x = b` by Gaussian elimination with partial pivoting, or returns
+/// `None` if it is singular.
+pub(crate) fn solve<const N: usize>(mut a: [[Real; N]; N], mut b: [Real; N]) -> Option<[Real; N]> {
+    for col in 0..N {
+        let pivot = (col..N).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let pivot_row = a[col];
+        for row in col + 1..N {
+            let factor = a[row][col] / pivot_row[col];
+            for (value, pivot) in a[row][col..].iter_mut().zip(&pivot_row[col..]) {
+                *value -= factor * pivot;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = [0.0; N];
+    for row in (0..N).rev() {
+        let sum: Real = (row + 1..N).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A homography of a surface seen at an angle, with the surface's 100x100 square imaged as a
+    /// trapezoid.
+    fn perspective() -> Homography {
+        Homography::from_points(
+            &[(60.0, 40.0), (240.0, 40.0), (280.0, 180.0), (20.0, 180.0)],
+            &[(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn anchors_map_detections_onto_the_surface() {
+        let homography = perspective();
+        let inverse = homography.inverse().unwrap();
+        let anchors = [
+            (31, (0.0, 0.0)),
+            (47, (100.0, 0.0)),
+            (55, (100.0, 100.0)),
+            (93, (0.0, 100.0)),
+        ];
+        let mut codes: Vec<TopCode> = anchors
+            .iter()
+            .map(|&(code, (u, v))| {
+                let (x, y) = inverse.map(u, v);
+                TopCode::mock(code, 5.0, 0.0, x, y)
+            })
+            .collect();
+        let (x, y) = inverse.map(30.0, 70.0);
+        codes.push(TopCode::mock(103, 5.0, 0.0, x, y));
+
+        let estimated = Homography::from_anchors(&codes, &anchors).unwrap();
+        let (u, v) = estimated.map(x, y);
+        assert!((u - 30.0).abs() < 1e-3 && (v - 70.0).abs() < 1e-3);
+        // The centre of the trapezoid lies above the middle of the surface.
+        let (_, v) = estimated.map(150.0, 110.0);
+        assert!(v > 50.0);
+
+        assert_eq!(Homography::from_anchors(&codes[1..], &anchors), None);
+    }
+
+    #[test]
+    fn rectified_views_are_top_down() {
+        let (width, height) = (300, 220);
+        let homography = perspective();
+        // A checkerboard of 20-unit squares on the surface, photographed at an angle.
+        let checker = |u: Real, v: Real| {
+            let on = (0.0..100.0).contains(&u) && (0.0..100.0).contains(&v);
+            if on && ((u / 20.0).floor() + (v / 20.0).floor()) as i32 % 2 == 0 {
+                255
+            } else {
+                0
+            }
+        };
+        let image: Vec<u8> = (0..width * height)
+            .map(|i| {
+                let (u, v) = homography.map((i % width) as Real + 0.5, (i / width) as Real + 0.5);
+                checker(u, v)
+            })
+            .collect();
+
+        let view = homography.rectify(
+            &image,
+            |buffer, index| {
+                let v = buffer[index] as u32;
+                (v, v, v)
+            },
+            (width, height),
+            (200, 200),
+            2.0,
+        );
+        assert_eq!(view.len(), 200 * 200 * 3);
+        let wrong = (0..200 * 200)
+            .filter(|i| {
+                let (u, v) = (
+                    (i % 200) as Real / 2.0 + 0.25,
+                    (i / 200) as Real / 2.0 + 0.25,
+                );
+                view[i * 3].abs_diff(checker(u, v)) > 128
+            })
+            .count();
+        assert!(wrong < 200 * 200 / 20, "{}", wrong);
+    }
+}
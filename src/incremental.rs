@@ -0,0 +1,257 @@
+use alloc::{vec, vec::Vec};
+use core::ops::Range;
+
+use crate::{
+    scanner::{average, Scanner},
+    topcode::{Real, TopCode},
+};
+
+/// Width and height (in pixels) of the cells in which frames are compared.
+const CELL: usize = 16;
+
+/// Pixels around each changed region which are also rescanned, for the running sum of the
+/// threshold to settle before reaching it.
+const MARGIN: usize = 16;
+
+/// Scans consecutive frames from a fixed camera, but only rescans the regions which changed since
+/// the previous frame. Codes found in the previous frame which lie entirely outside of the changed
+/// regions are carried over as they are.
+///
+/// Frames are compared in cells of 16x16 pixels, and a cell is changed if the intensity of any of
+/// its pixels differs by more than the difference threshold. Each group of adjacent changed cells
+/// is rescanned in a window extending a maximum code radius around it, so that codes moving into or
+/// out of it are found whole. When the windows cover more than half of the frame, the whole frame
+/// is scanned instead.
+#[derive(Clone)]
+pub struct IncrementalScanner {
+    scanner: Scanner,
+    difference_threshold: u32,
+    previous: Option<Vec<u8>>,
+    codes: Vec<TopCode>,
+    dirty: Vec<(Range<usize>, Range<usize>)>,
+}
+
+impl IncrementalScanner {
+    /// Wraps `scanner`, whose settings are used for every scan. The first frame is always scanned
+    /// in full, as there is no previous frame to compare it with.
+    pub fn new(scanner: Scanner) -> Self {
+        Self {
+            scanner,
+            difference_threshold: 24,
+            previous: None,
+            codes: Vec::new(),
+            dirty: Vec::new(),
+        }
+    }
+
+    /// Sets the largest difference in intensity (0-255) of a pixel between frames which is still
+    /// considered noise rather than a change. Defaults to 24.
+    pub fn set_difference_threshold(&mut self, threshold: u8) {
+        self.difference_threshold = threshold as u32;
+    }
+
+    /// The scanner used to scan each frame, e.g. to adjust its settings between frames. Changing
+    /// its settings does not rescan unchanged regions, see [IncrementalScanner::reset].
+    pub fn scanner_mut(&mut self) -> &mut Scanner {
+        &mut self.scanner
+    }
+
+    /// Forgets the previous frame, so that the next frame is scanned in full.
+    pub fn reset(&mut self) {
+        self.previous = None;
+        self.codes.clear();
+        self.dirty.clear();
+    }
+
+    /// The regions which changed in the last frame as `(x, y, width, height)` rectangles, before
+    /// they were extended into the windows which were rescanned. The whole frame is reported when
+    /// it was scanned in full.
+    pub fn dirty_regions(&self) -> Vec<(usize, usize, usize, usize)> {
+        self.dirty
+            .iter()
+            .map(|(columns, rows)| (columns.start, rows.start, columns.len(), rows.len()))
+            .collect()
+    }
+
+    /// Scan the next frame and return the TopCodes found in it.
+    pub fn update<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+    ) -> Vec<TopCode> {
+        let (width, height) = (self.scanner.image_width(), self.scanner.image_height());
+        let luma: Vec<u8> = (0..width * height)
+            .map(|index| average(decode_rgb(image_buffer, index)) as u8)
+            .collect();
+        self.dirty = match self.previous.take() {
+            Some(previous) => self.changed_regions(&previous, &luma),
+            None => vec![(0..width, 0..height)],
+        };
+        self.previous = Some(luma);
+        if self.dirty.is_empty() {
+            return self.codes.clone();
+        }
+
+        let margin = self.scanner.max_code_diameter() / 2 + MARGIN;
+        let mut windows: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+        for (columns, rows) in &self.dirty {
+            let mut window = (
+                columns.start.saturating_sub(margin)..(columns.end + margin).min(width),
+                rows.start.saturating_sub(margin)..(rows.end + margin).min(height),
+            );
+            // Merge overlapping windows, so that no region is scanned twice.
+            while let Some(i) = windows.iter().position(|other| intersects(other, &window)) {
+                let other = windows.swap_remove(i);
+                window = (
+                    window.0.start.min(other.0.start)..window.0.end.max(other.0.end),
+                    window.1.start.min(other.1.start)..window.1.end.max(other.1.end),
+                );
+            }
+            windows.push(window);
+        }
+
+        let area: usize = windows.iter().map(|(c, r)| c.len() * r.len()).sum();
+        if area * 2 > width * height {
+            self.dirty = vec![(0..width, 0..height)];
+            self.codes = self.scanner.scan(image_buffer, decode_rgb);
+            return self.codes.clone();
+        }
+
+        let mut codes: Vec<TopCode> = self
+            .codes
+            .iter()
+            .filter(|code| !self.touches_dirty(code))
+            .copied()
+            .collect();
+        let mut found = Vec::new();
+        for (columns, rows) in windows {
+            found.extend(
+                self.scanner
+                    .scan_window(image_buffer, &decode_rgb, columns, rows),
+            );
+        }
+        // Codes in a window which don't touch a changed region are either carried over already, or
+        // weren't found in the previous frame either.
+        for code in found {
+            if self.touches_dirty(&code)
+                && !codes.iter().any(|other| other.in_bullseye(code.x, code.y))
+            {
+                codes.push(code);
+            }
+        }
+
+        self.codes = codes;
+        self.codes.clone()
+    }
+
+    /// Whether the bounding box of the code overlaps any of the changed regions of the last frame.
+    fn touches_dirty(&self, code: &TopCode) -> bool {
+        let (x, y) = self.scanner.to_image_position(code.x, code.y);
+        let r = code.radius();
+        self.dirty.iter().any(|(columns, rows)| {
+            x + r >= columns.start as Real
+                && x - r < columns.end as Real
+                && y + r >= rows.start as Real
+                && y - r < rows.end as Real
+        })
+    }
+
+    /// The bounding boxes of the groups of adjacent cells which changed between `previous` and
+    /// `luma`.
+    fn changed_regions(&self, previous: &[u8], luma: &[u8]) -> Vec<(Range<usize>, Range<usize>)> {
+        let (width, height) = (self.scanner.image_width(), self.scanner.image_height());
+        let (cells_x, cells_y) = (width.div_ceil(CELL), height.div_ceil(CELL));
+        let mut changed = vec![false; cells_x * cells_y];
+        for (index, (&a, &b)) in previous.iter().zip(luma).enumerate() {
+            if a.abs_diff(b) as u32 > self.difference_threshold {
+                changed[(index / width / CELL) * cells_x + index % width / CELL] = true;
+            }
+        }
+
+        let mut regions = Vec::new();
+        let mut stack = Vec::new();
+        for start in 0..changed.len() {
+            if !changed[start] {
+                continue;
+            }
+            changed[start] = false;
+            stack.push(start);
+            let (mut x0, mut y0) = (start % cells_x, start / cells_x);
+            let (mut x1, mut y1) = (x0, y0);
+            while let Some(cell) = stack.pop() {
+                let (cx, cy) = (cell % cells_x, cell / cells_x);
+                (x0, y0) = (x0.min(cx), y0.min(cy));
+                (x1, y1) = (x1.max(cx), y1.max(cy));
+                for ny in cy.saturating_sub(1)..(cy + 2).min(cells_y) {
+                    for nx in cx.saturating_sub(1)..(cx + 2).min(cells_x) {
+                        let neighbour = ny * cells_x + nx;
+                        if changed[neighbour] {
+                            changed[neighbour] = false;
+                            stack.push(neighbour);
+                        }
+                    }
+                }
+            }
+            regions.push((
+                x0 * CELL..((x1 + 1) * CELL).min(width),
+                y0 * CELL..((y1 + 1) * CELL).min(height),
+            ));
+        }
+        regions
+    }
+}
+
+/// Whether two rectangles, given by their columns and rows, overlap.
+fn intersects(a: &(Range<usize>, Range<usize>), b: &(Range<usize>, Range<usize>)) -> bool {
+    a.0.start < b.0.end && b.0.start < a.0.end && a.1.start < b.1.end && b.1.start < a.1.end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::render_topcode;
+
+    fn render(moving: Real, (width, height): (usize, usize)) -> Vec<u8> {
+        let mut image = render_topcode(55, 8.0, 0.5, (80.0, 100.0), (width, height));
+        let other = render_topcode(93, 8.0, 0.0, (moving, 100.0), (width, height));
+        for (pixel, other) in image.iter_mut().zip(other) {
+            *pixel = (*pixel).min(other);
+        }
+        image
+    }
+
+    #[test]
+    fn only_changed_regions_are_rescanned() {
+        let (width, height) = (800, 400);
+        let mut scanner = IncrementalScanner::new(Scanner::new(width, height));
+        scanner.scanner_mut().set_max_code_diameter(100);
+        let decode_gray = |buffer: &Vec<u8>, index: usize| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        };
+        let positions = |codes: &[TopCode]| {
+            let mut positions: Vec<_> = codes.iter().map(|c| (c.code, c.x, c.y)).collect();
+            positions.sort_by_key(|(code, _, _)| *code);
+            positions
+        };
+
+        let first = scanner.update(&render(300.0, (width, height)), decode_gray);
+        assert_eq!(scanner.dirty_regions(), vec![(0, 0, width, height)]);
+        assert_eq!(first.len(), 2);
+
+        let moved = render(320.0, (width, height));
+        let second = scanner.update(&moved, decode_gray);
+        let dirty = scanner.dirty_regions();
+        assert_eq!(dirty.len(), 1);
+        let (x, _, w, _) = dirty[0];
+        assert!(x >= 240 && x + w <= 400, "{:?}", dirty);
+        let full = Scanner::new(width, height).scan(&moved, decode_gray);
+        assert_eq!(positions(&second), positions(&full));
+        // The static code is carried over from the first frame.
+        assert!(second.contains(&first.iter().find(|c| c.code == Some(55)).copied().unwrap()));
+
+        let third = scanner.update(&moved, decode_gray);
+        assert!(scanner.dirty_regions().is_empty());
+        assert_eq!(positions(&third), positions(&second));
+    }
+}
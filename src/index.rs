@@ -0,0 +1,228 @@
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::topcode::{Real, TopCode};
+use alloc::{vec, vec::Vec};
+
+/// A spatial index over the centers of TopCodes, for answering repeated nearest-marker queries
+/// on frames with many markers without scanning all of them. The centers are binned into a grid
+/// of cells at least as large as the largest code, so a query only visits the cells around it.
+#[derive(Clone, Debug)]
+pub struct DetectionIndex<'a> {
+    codes: &'a [TopCode],
+    origin: (Real, Real),
+    cell_size: Real,
+    cols: usize,
+    rows: usize,
+    /// Indices into `codes` of the centers in each cell, in row-major order
+    cells: Vec<Vec<usize>>,
+}
+
+impl<'a> DetectionIndex<'a> {
+    /// Indexes the centers of `codes`, e.g. the codes found in one frame. The index borrows the
+    /// codes, and queries return references into them.
+    pub fn new(codes: &'a [TopCode]) -> Self {
+        let (mut min_x, mut min_y) = (Real::MAX, Real::MAX);
+        let (mut max_x, mut max_y) = (Real::MIN, Real::MIN);
+        let mut cell_size: Real = 1.0;
+        for code in codes {
+            min_x = min_x.min(code.x);
+            min_y = min_y.min(code.y);
+            max_x = max_x.max(code.x);
+            max_y = max_y.max(code.y);
+            cell_size = cell_size.max(code.radius() * 2.0);
+        }
+
+        if codes.is_empty() {
+            return Self {
+                codes,
+                origin: (0.0, 0.0),
+                cell_size,
+                cols: 0,
+                rows: 0,
+                cells: Vec::new(),
+            };
+        }
+
+        // Sparse layouts would otherwise produce many empty cells, so aim for about one code per
+        // cell at most.
+        let area = (max_x - min_x) * (max_y - min_y);
+        cell_size = cell_size.max((area / codes.len() as Real).sqrt());
+
+        let cols = ((max_x - min_x) / cell_size) as usize + 1;
+        let rows = ((max_y - min_y) / cell_size) as usize + 1;
+        let mut index = Self {
+            codes,
+            origin: (min_x, min_y),
+            cell_size,
+            cols,
+            rows,
+            cells: vec![Vec::new(); cols * rows],
+        };
+        for (i, code) in codes.iter().enumerate() {
+            let (col, row) = index.cell(code.x, code.y);
+            index.cells[row as usize * cols + col as usize].push(i);
+        }
+        index
+    }
+
+    /// The TopCode whose center is nearest to (x, y), or `None` if the index is empty. Ties are
+    /// broken in favor of the code which comes first.
+    pub fn nearest(&self, x: Real, y: Real) -> Option<&'a TopCode> {
+        if self.codes.is_empty() {
+            return None;
+        }
+
+        // Searching from the nearest cell of the grid keeps queries far outside of it from
+        // walking rings of empty cells. Cells ring `r` away from it are still at least `r - 1`
+        // cells away from (x, y).
+        let (cols, rows) = (self.cols as isize, self.rows as isize);
+        let (col, row) = self.cell(x, y);
+        let (col, row) = (col.clamp(0, cols - 1), row.clamp(0, rows - 1));
+        let max_ring = [col, cols - 1 - col, row, rows - 1 - row]
+            .into_iter()
+            .max()
+            .unwrap_or(0);
+
+        let mut best: Option<(Real, usize)> = None;
+        for ring in 0..=max_ring {
+            for j in (row - ring).max(0)..=(row + ring).min(rows - 1) {
+                // Rows between the top and bottom of the ring only have cells at its sides.
+                let step = if (j - row).abs() == ring {
+                    1
+                } else {
+                    2 * ring as usize
+                };
+                for i in (col - ring..=col + ring).step_by(step.max(1)) {
+                    for &index in self.cell_codes(i, j) {
+                        let distance = Self::distance(&self.codes[index], x, y);
+                        let better = match best {
+                            Some((best_distance, best_index)) => {
+                                distance < best_distance
+                                    || (distance == best_distance && index < best_index)
+                            }
+                            None => true,
+                        };
+                        if better {
+                            best = Some((distance, index));
+                        }
+                    }
+                }
+            }
+
+            // Cells beyond this ring are at least `ring` cells away from (x, y).
+            if let Some((distance, _)) = best {
+                if distance < ring as Real * self.cell_size {
+                    break;
+                }
+            }
+        }
+
+        best.map(|(_, index)| &self.codes[index])
+    }
+
+    /// The TopCodes whose centers are within `radius` of (x, y), in the order they were indexed.
+    pub fn within_radius(&self, x: Real, y: Real, radius: Real) -> Vec<&'a TopCode> {
+        let (col0, row0) = self.cell(x - radius, y - radius);
+        let (col1, row1) = self.cell(x + radius, y + radius);
+
+        let mut found: Vec<usize> = Vec::new();
+        for j in row0.max(0)..=row1.min(self.rows as isize - 1) {
+            for i in col0.max(0)..=col1.min(self.cols as isize - 1) {
+                found.extend(
+                    self.cell_codes(i, j)
+                        .iter()
+                        .filter(|&&index| Self::distance(&self.codes[index], x, y) <= radius),
+                );
+            }
+        }
+
+        found.sort_unstable();
+        found.into_iter().map(|index| &self.codes[index]).collect()
+    }
+
+    /// The (possibly out of bounds) cell containing (x, y).
+    fn cell(&self, x: Real, y: Real) -> (isize, isize) {
+        (
+            ((x - self.origin.0) / self.cell_size).floor() as isize,
+            ((y - self.origin.1) / self.cell_size).floor() as isize,
+        )
+    }
+
+    fn cell_codes(&self, col: isize, row: isize) -> &[usize] {
+        if col < 0 || row < 0 || col >= self.cols as isize || row >= self.rows as isize {
+            return &[];
+        }
+        &self.cells[row as usize * self.cols + col as usize]
+    }
+
+    fn distance(code: &TopCode, x: Real, y: Real) -> Real {
+        ((code.x - x) * (code.x - x) + (code.y - y) * (code.y - y)).sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queries_match_a_brute_force_search() {
+        // A dense sheet of 20 x 15 markers, with some jitter so the grid is not regular.
+        let mut state: u64 = 7;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 33) as Real / (1u64 << 31) as Real
+        };
+        let mut codes = Vec::new();
+        for row in 0..15 {
+            for col in 0..20 {
+                let x = col as Real * 40.0 + next() * 10.0;
+                let y = row as Real * 40.0 + next() * 10.0;
+                codes.push(TopCode::mock(31, 4.0, 0.0, x, y));
+            }
+        }
+        let index = DetectionIndex::new(&codes);
+        let distance = |code: &TopCode, x: Real, y: Real| DetectionIndex::distance(code, x, y);
+
+        for _ in 0..200 {
+            let x = next() * 1000.0 - 100.0;
+            let y = next() * 800.0 - 100.0;
+
+            let expected = codes
+                .iter()
+                .min_by(|a, b| distance(a, x, y).total_cmp(&distance(b, x, y)));
+            assert_eq!(index.nearest(x, y), expected);
+
+            let radius = next() * 100.0;
+            let expected: Vec<_> = codes
+                .iter()
+                .filter(|code| distance(code, x, y) <= radius)
+                .collect();
+            assert_eq!(index.within_radius(x, y, radius), expected);
+        }
+
+        let empty = DetectionIndex::new(&[]);
+        assert_eq!(empty.nearest(0.0, 0.0), None);
+        assert!(empty.within_radius(0.0, 0.0, 10.0).is_empty());
+    }
+
+    #[test]
+    fn queries_far_off_the_grid_find_the_nearest_code() {
+        let codes: Vec<_> = (0..100)
+            .map(|i| {
+                TopCode::mock(
+                    31,
+                    4.0,
+                    0.0,
+                    (i % 10) as Real * 50.0,
+                    (i / 10) as Real * 50.0,
+                )
+            })
+            .collect();
+        let index = DetectionIndex::new(&codes);
+        assert_eq!(index.nearest(1e6, 1e6), codes.last());
+        assert_eq!(index.nearest(-1e6, 1e6), Some(&codes[90]));
+        assert_eq!(index.nearest(215.0, -1e5), Some(&codes[4]));
+    }
+}
@@ -0,0 +1,340 @@
+//! Arranges the codes detected in a frame into chains, as in tangible programming languages where
+//! statements are blocks placed one after another (e.g. Tern). Each code links to the next code
+//! found at a given distance along its orientation and turned about the same way.
+//!
+//! Codes placed on the cells of a board, as in board games, can instead be snapped to a [Grid]
+//! fitted to their centers.
+
+use alloc::{vec, vec::Vec};
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::topcode::{Real, TopCode};
+
+/// Number of times a grid is refitted to the codes snapped to its cells.
+const GRID_ITERATIONS: usize = 3;
+
+/// The rules deciding whether a code follows another in a chain.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChainRules {
+    /// Expected distance between the centers of consecutive codes, in units of the first code
+    spacing: Real,
+    /// Largest deviation from the spacing, as a fraction of it
+    tolerance: Real,
+    /// Largest angle (in radians) between the orientation of a code and the direction of the next
+    max_bearing: Real,
+    /// Largest difference (in radians) between the orientations of consecutive codes
+    max_rotation: Real,
+}
+
+impl ChainRules {
+    /// Creates rules for chains of codes whose centers are `spacing` units apart along their
+    /// orientation. A code is 8 units wide, so codes printed on touching blocks are at least 8
+    /// units apart.
+    pub fn new(spacing: Real) -> Self {
+        Self {
+            spacing,
+            tolerance: 0.25,
+            max_bearing: 0.35,
+            max_rotation: 0.35,
+        }
+    }
+
+    /// Sets the largest deviation from the spacing, as a fraction of it. Defaults to 0.25.
+    pub fn set_tolerance(&mut self, tolerance: Real) {
+        self.tolerance = tolerance;
+    }
+
+    /// Sets the largest angle (in radians) between the orientation of a code and the direction in
+    /// which the next code lies. Defaults to 0.35 (20 degrees).
+    pub fn set_max_bearing(&mut self, max_bearing: Real) {
+        self.max_bearing = max_bearing;
+    }
+
+    /// Sets the largest difference (in radians) between the orientations of consecutive codes.
+    /// Defaults to 0.35 (20 degrees).
+    pub fn set_max_rotation(&mut self, max_rotation: Real) {
+        self.max_rotation = max_rotation;
+    }
+
+    /// The index of the code following each of the codes, if any. Each code follows at most one
+    /// other code: if several codes could be followed by the same code, the closest to the
+    /// expected position is kept.
+    pub fn successors(&self, codes: &[TopCode]) -> Vec<Option<usize>> {
+        // The candidates for the next code, with their deviations from the expected position.
+        let mut candidates: Vec<Option<(usize, Real)>> = codes
+            .iter()
+            .enumerate()
+            .map(|(i, code)| {
+                codes
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| j != i)
+                    .filter_map(|(j, next)| Some((j, self.deviation(code, next)?)))
+                    .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            })
+            .collect();
+
+        // Only keep the closest of the codes followed by the same code.
+        for i in 0..candidates.len() {
+            if let Some((j, deviation)) = candidates[i] {
+                let closer = candidates.iter().enumerate().any(|(k, other)| {
+                    matches!(*other, Some((l, d)) if l == j && k != i && (d, k) < (deviation, i))
+                });
+                if closer {
+                    candidates[i] = None;
+                }
+            }
+        }
+        candidates
+            .into_iter()
+            .map(|candidate| candidate.map(|(j, _)| j))
+            .collect()
+    }
+
+    /// Arranges the codes into chains, in order along each chain. Codes which neither follow nor
+    /// are followed by another code form chains of their own. Chains closed into a loop start at
+    /// their first detected code.
+    pub fn chains(&self, codes: &[TopCode]) -> Vec<Vec<TopCode>> {
+        let successors = self.successors(codes);
+        let mut followed = vec![false; codes.len()];
+        for &j in successors.iter().flatten() {
+            followed[j] = true;
+        }
+
+        let mut visited = vec![false; codes.len()];
+        let mut chains = Vec::new();
+        // Start from the heads of chains, then from whatever is left, which are loops.
+        let heads = (0..codes.len()).filter(|&i| !followed[i]);
+        for start in heads.chain(0..codes.len()) {
+            let mut chain = Vec::new();
+            let mut current = Some(start);
+            while let Some(i) = current.filter(|&i| !visited[i]) {
+                visited[i] = true;
+                chain.push(codes[i]);
+                current = successors[i];
+            }
+            if !chain.is_empty() {
+                chains.push(chain);
+            }
+        }
+        chains
+    }
+
+    /// How far `next` is from where the code following `code` is expected, as a fraction of the
+    /// spacing, or `None` if it doesn't follow `code`.
+    fn deviation(&self, code: &TopCode, next: &TopCode) -> Option<Real> {
+        let pose = next.relative_to(code);
+        let deviation = (pose.distance - self.spacing).abs() / self.spacing;
+        (deviation <= self.tolerance
+            && pose.bearing.abs() <= self.max_bearing
+            && pose.orientation.abs() <= self.max_rotation)
+            .then_some(deviation)
+    }
+}
+
+/// A square grid in the image, with cells indexed by row and column.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Grid {
+    /// Distance between the centers of neighbouring cells, in pixels
+    pub pitch: Real,
+    /// Angle (in radians) of the rows from the horizontal, within `(-PI / 4, PI / 4]`
+    pub rotation: Real,
+    /// Position of the center of the cell in row 0 and column 0
+    pub offset: (Real, Real),
+}
+
+impl Grid {
+    /// Fits a grid to the centers of the codes, with one code per cell. Cells may be empty, as
+    /// long as most codes have a code in a neighbouring cell. The grid's origin is placed so that
+    /// the codes are in rows and columns from 0 up. Returns `None` for fewer than two codes, or if
+    /// they are all at the same position.
+    pub fn fit(codes: &[TopCode]) -> Option<Self> {
+        let points: Vec<(Real, Real)> = codes.iter().map(|code| (code.x, code.y)).collect();
+        // The offset from each point to its nearest neighbour, which is mostly in a neighbouring
+        // cell.
+        let neighbours: Vec<(Real, Real)> = points
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &(x, y))| {
+                points
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| j != i)
+                    .map(|(_, &(u, v))| (u - x, v - y))
+                    .min_by(|(ax, ay), (bx, by)| ax.hypot(*ay).total_cmp(&bx.hypot(*by)))
+            })
+            .collect();
+        let mut distances: Vec<Real> = neighbours.iter().map(|(x, y)| x.hypot(*y)).collect();
+        distances.sort_by(Real::total_cmp);
+        let pitch = *distances.get(distances.len() / 2)?;
+        if pitch == 0.0 {
+            return None;
+        }
+        // The directions of neighbours repeat every quarter turn, so their mean is taken over
+        // four times their angles.
+        let (sin, cos) = neighbours
+            .iter()
+            .map(|(x, y)| (4.0 * y.atan2(*x)).sin_cos())
+            .fold((0.0, 0.0), |(s, c), (sin, cos)| (s + sin, c + cos));
+
+        let mut grid = Self {
+            pitch,
+            rotation: sin.atan2(cos) / 4.0,
+            offset: points[0],
+        };
+        for _ in 0..GRID_ITERATIONS {
+            grid = grid.refit(&points)?;
+        }
+        let cells = grid.cells(codes);
+        let row = cells.iter().map(|&(row, _)| row).min()?;
+        let column = cells.iter().map(|&(_, column)| column).min()?;
+        grid.offset = grid.position(row, column);
+        Some(grid)
+    }
+
+    /// The `(row, column)` of the cell containing the point (x, y).
+    pub fn cell(&self, x: Real, y: Real) -> (i32, i32) {
+        let (sin, cos) = self.rotation.sin_cos();
+        let (dx, dy) = (x - self.offset.0, y - self.offset.1);
+        let column = (dx * cos + dy * sin) / self.pitch;
+        let row = (dy * cos - dx * sin) / self.pitch;
+        (row.round() as i32, column.round() as i32)
+    }
+
+    /// The `(row, column)` of the cell of each code.
+    pub fn cells(&self, codes: &[TopCode]) -> Vec<(i32, i32)> {
+        codes.iter().map(|code| self.cell(code.x, code.y)).collect()
+    }
+
+    /// The center of the cell in `row` and `column`, in the image.
+    pub fn position(&self, row: i32, column: i32) -> (Real, Real) {
+        let (sin, cos) = self.rotation.sin_cos();
+        let (row, column) = (row as Real * self.pitch, column as Real * self.pitch);
+        (
+            self.offset.0 + column * cos - row * sin,
+            self.offset.1 + column * sin + row * cos,
+        )
+    }
+
+    /// Least squares fit of the grid to the points, snapped to the cells of this grid.
+    fn refit(&self, points: &[(Real, Real)]) -> Option<Self> {
+        // Fit of `z = a w + b` over complex numbers z in the image and w = column + i row.
+        let cells: Vec<(Real, Real)> = points
+            .iter()
+            .map(|&(x, y)| {
+                let (row, column) = self.cell(x, y);
+                (column as Real, row as Real)
+            })
+            .collect();
+        let n = points.len() as Real;
+        let mean = |points: &[(Real, Real)]| {
+            let (x, y) = points
+                .iter()
+                .fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+            (x / n, y / n)
+        };
+        let (wx, wy) = mean(&cells);
+        let (zx, zy) = mean(points);
+        let (mut re, mut im, mut norm) = (0.0, 0.0, 0.0);
+        for (&(u, v), &(x, y)) in cells.iter().zip(points) {
+            let (u, v, x, y) = (u - wx, v - wy, x - zx, y - zy);
+            re += x * u + y * v;
+            im += y * u - x * v;
+            norm += u * u + v * v;
+        }
+        if norm == 0.0 {
+            return None;
+        }
+        let (a, b) = (re / norm, im / norm);
+        Some(Self {
+            pitch: a.hypot(b),
+            rotation: b.atan2(a),
+            offset: (zx - a * wx + b * wy, zy - b * wx - a * wy),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::topcode::PI;
+
+    #[test]
+    fn codes_are_chained_along_their_orientation() {
+        // A chain running right, and a separate pair pointing left.
+        let codes = [
+            TopCode::mock(55, 4.0, 0.0, 140.0, 100.0),
+            TopCode::mock(31, 4.0, 0.0, 100.0, 100.0),
+            TopCode::mock(93, 4.0, 0.2, 180.0, 102.0),
+            TopCode::mock(47, 4.0, PI, 300.0, 300.0),
+            TopCode::mock(61, 4.0, PI, 260.0, 300.0),
+            // Turned too far from its neighbour to follow it.
+            TopCode::mock(79, 4.0, 1.5, 220.0, 110.0),
+        ];
+        let rules = ChainRules::new(10.0);
+        let chains: Vec<Vec<_>> = rules
+            .chains(&codes)
+            .iter()
+            .map(|chain| chain.iter().map(|code| code.code.unwrap()).collect())
+            .collect();
+        assert_eq!(chains, [vec![31, 55, 93], vec![47, 61], vec![79]]);
+    }
+
+    #[test]
+    fn codes_follow_at_most_one_other_code() {
+        // Two codes could be followed by the last one, which follows the closer of the two.
+        let codes = [
+            TopCode::mock(31, 4.0, 0.0, 100.0, 100.0),
+            TopCode::mock(55, 4.0, 0.0, 103.0, 110.0),
+            TopCode::mock(93, 4.0, 0.0, 140.0, 100.0),
+        ];
+        let rules = ChainRules::new(10.0);
+        assert_eq!(rules.successors(&codes), [Some(2), None, None]);
+
+        // Loops have no head, and start at their first code.
+        let codes = [
+            TopCode::mock(31, 4.0, 0.0, 100.0, 100.0),
+            TopCode::mock(55, 4.0, PI, 140.0, 100.0),
+        ];
+        let mut rules = ChainRules::new(10.0);
+        rules.set_max_rotation(PI);
+        rules.set_max_bearing(PI);
+        assert_eq!(rules.successors(&codes), [Some(1), Some(0)]);
+        assert_eq!(rules.chains(&codes), [codes.to_vec()]);
+    }
+
+    #[test]
+    fn grids_are_fitted_despite_empty_cells() {
+        let truth = Grid {
+            pitch: 50.0,
+            rotation: 0.3,
+            offset: (120.0, 60.0),
+        };
+        let jitter = [0.8, -1.0, 0.3, -0.5, 1.0, -0.2, 0.6];
+        let mut codes = Vec::new();
+        let mut expected = Vec::new();
+        for row in 0..4 {
+            for column in 0..5 {
+                // Leave a few cells empty.
+                if (row * 5 + column) % 4 == 3 {
+                    continue;
+                }
+                let (x, y) = truth.position(row, column);
+                let i = codes.len();
+                let (dx, dy) = (jitter[i % 7], jitter[(i + 3) % 7]);
+                codes.push(TopCode::mock(31, 4.0, 0.0, x + dx, y + dy));
+                expected.push((row, column));
+            }
+        }
+
+        let grid = Grid::fit(&codes).unwrap();
+        assert!((grid.pitch - 50.0).abs() < 0.5, "{:?}", grid);
+        assert!((grid.rotation - 0.3).abs() < 0.02, "{:?}", grid);
+        assert!((grid.offset.0 - 120.0).abs() < 1.5, "{:?}", grid);
+        assert!((grid.offset.1 - 60.0).abs() < 1.5, "{:?}", grid);
+        assert_eq!(grid.cells(&codes), expected);
+
+        assert_eq!(Grid::fit(&codes[..1]), None);
+    }
+}
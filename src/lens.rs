@@ -0,0 +1,84 @@
+use crate::topcode::Real;
+
+/// A radial distortion model of a camera lens, used to correct the positions of TopCodes detected
+/// by cameras with wide-angle (barrel) or telephoto (pincushion) distortion.
+///
+/// A point at distance `r` from the principal point, measured in units of the focal length, is
+/// imaged at `1 + k1 * r^2 + k2 * r^4` times that distance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LensModel {
+    /// Second-order radial distortion coefficient (negative for barrel distortion)
+    pub k1: Real,
+    /// Fourth-order radial distortion coefficient
+    pub k2: Real,
+    /// Point (in pixels) where the optical axis meets the image, usually close to its center
+    pub principal_point: (Real, Real),
+    /// Focal length in pixels
+    pub focal_length: Real,
+}
+
+impl LensModel {
+    /// Maps an undistorted point to where the lens images it.
+    pub fn distort(&self, x: Real, y: Real) -> (Real, Real) {
+        let (nx, ny) = self.normalize(x, y);
+        let factor = self.factor(nx * nx + ny * ny);
+        self.denormalize(nx * factor, ny * factor)
+    }
+
+    /// Maps a point in the image to where it would be without distortion. The distortion is
+    /// inverted iteratively, which converges for the moderate distortion of real lenses.
+    pub fn undistort(&self, x: Real, y: Real) -> (Real, Real) {
+        let (dx, dy) = self.normalize(x, y);
+        let (mut nx, mut ny) = (dx, dy);
+        for _ in 0..20 {
+            let factor = self.factor(nx * nx + ny * ny);
+            nx = dx / factor;
+            ny = dy / factor;
+        }
+        self.denormalize(nx, ny)
+    }
+
+    fn factor(&self, r2: Real) -> Real {
+        1.0 + self.k1 * r2 + self.k2 * r2 * r2
+    }
+
+    fn normalize(&self, x: Real, y: Real) -> (Real, Real) {
+        (
+            (x - self.principal_point.0) / self.focal_length,
+            (y - self.principal_point.1) / self.focal_length,
+        )
+    }
+
+    fn denormalize(&self, x: Real, y: Real) -> (Real, Real) {
+        (
+            x * self.focal_length + self.principal_point.0,
+            y * self.focal_length + self.principal_point.1,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undistort_inverts_distort() {
+        let lens = LensModel {
+            k1: -0.2,
+            k2: 0.05,
+            principal_point: (150.0, 120.0),
+            focal_length: 150.0,
+        };
+        // The iterative inversion converges to within rounding errors of pixel coordinates.
+        let tolerance = if cfg!(feature = "f32") { 1e-3 } else { 1e-6 };
+        for (x, y) in [(150.0, 120.0), (10.0, 20.0), (290.0, 230.0), (200.0, 60.0)] {
+            let (dx, dy) = lens.distort(x, y);
+            let (ux, uy) = lens.undistort(dx, dy);
+            assert!((ux - x).abs() < tolerance && (uy - y).abs() < tolerance);
+        }
+        // Barrel distortion pulls points towards the principal point.
+        let (dx, dy) = lens.distort(250.0, 220.0);
+        assert!(dx < 250.0 && dy < 220.0);
+    }
+}
@@ -1,7 +1,92 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+// Tests link the standard library, whose float methods shadow those of `math::Float`.
+#![cfg_attr(all(test, not(feature = "std")), allow(unused_imports))]
+
+#[cfg(all(not(feature = "std"), not(feature = "libm")))]
+compile_error!("either the `std` or the `libm` feature must be enabled for float math");
+
+extern crate alloc;
+
+/// Asserts an internal invariant when the `debug_checks` feature is enabled. Without the feature
+/// the check is compiled out entirely.
+macro_rules! invariant {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "debug_checks")]
+        assert!($($arg)*);
+    };
+}
+
+/// Logs through the `log` crate when the `logging` feature is enabled. Without the feature the
+/// message is compiled out entirely.
+macro_rules! scan_log {
+    ($level:ident, $($arg:tt)*) => {
+        #[cfg(feature = "logging")]
+        log::$level!($($arg)*);
+    };
+}
+
+mod board;
+mod calibration;
+#[cfg(feature = "camera")]
+pub mod camera;
 mod candidate;
+pub mod codes;
+mod errors;
+#[cfg(feature = "std")]
+pub mod export;
+mod exposure;
+#[cfg(feature = "fixed-point")]
+mod fixed;
+mod generator;
+#[cfg(feature = "gpu")]
+mod gpu;
+mod heatmap;
+mod homography;
+mod incremental;
+mod index;
+pub mod layout;
+mod lens;
+#[cfg(not(feature = "std"))]
+mod math;
+#[cfg(any(feature = "tuio", feature = "osc"))]
+// Strings and time tags are only sent by TUIO.
+#[cfg_attr(not(feature = "tuio"), allow(dead_code))]
+mod osc;
+pub mod pose;
+#[cfg(feature = "print")]
+pub mod print;
+#[cfg(any(feature = "osc", feature = "mqtt"))]
+pub mod publish;
 mod scanner;
-mod topcode;
+#[cfg(feature = "simd")]
+mod simd;
+mod smoothing;
+mod tiled;
+pub mod topcode;
+mod tracking;
+#[cfg(feature = "tuio")]
+pub mod tuio;
 mod utils;
 
-pub use scanner::Scanner;
-pub use topcode::TopCode;
+pub use board::{Board, BoardPose};
+pub use calibration::{Calibration, CameraCalibration};
+pub use candidate::Candidate;
+pub use codes::is_valid_code;
+pub use errors::TopCodeError;
+pub use exposure::AutoExposure;
+pub use generator::SvgUnit;
+#[cfg(feature = "gpu")]
+pub use gpu::GpuThresholder;
+pub use heatmap::DetectionHeatmap;
+pub use homography::Homography;
+pub use incremental::IncrementalScanner;
+pub use index::DetectionIndex;
+pub use lens::LensModel;
+pub use scanner::{
+    BayerPattern, BorrowedScanner, Polarity, ScanBuffer, ScanLayout, Scanner, ScannerConfig,
+    ScannerState,
+};
+pub use smoothing::Smoothing;
+pub use tiled::TiledScanner;
+pub use topcode::{Real, RelativePose, TopCode};
+pub use tracking::{RoiTracker, StabilityConfig, TrackEvent, TrackSample, TrackedCode, Tracker};
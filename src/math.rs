@@ -0,0 +1,157 @@
+//! Float math for `no_std` builds, which lack the float methods of the standard library. The
+//! methods of [Float] mirror those of `f64` and `f32`, and are implemented with libm.
+
+pub(crate) trait Float: Sized {
+    fn sqrt(self) -> Self;
+    fn hypot(self, other: Self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn sin_cos(self) -> (Self, Self);
+    fn atan2(self, other: Self) -> Self;
+    fn acos(self) -> Self;
+    fn floor(self) -> Self;
+    fn ceil(self) -> Self;
+    fn round(self) -> Self;
+    fn rem_euclid(self, rhs: Self) -> Self;
+}
+
+impl Float for f64 {
+    fn sqrt(self) -> Self {
+        libm::sqrt(self)
+    }
+
+    fn hypot(self, other: Self) -> Self {
+        libm::hypot(self, other)
+    }
+
+    fn sin(self) -> Self {
+        libm::sin(self)
+    }
+
+    fn cos(self) -> Self {
+        libm::cos(self)
+    }
+
+    fn sin_cos(self) -> (Self, Self) {
+        libm::sincos(self)
+    }
+
+    fn atan2(self, other: Self) -> Self {
+        libm::atan2(self, other)
+    }
+
+    fn acos(self) -> Self {
+        libm::acos(self)
+    }
+
+    fn floor(self) -> Self {
+        libm::floor(self)
+    }
+
+    fn ceil(self) -> Self {
+        libm::ceil(self)
+    }
+
+    fn round(self) -> Self {
+        libm::round(self)
+    }
+
+    fn rem_euclid(self, rhs: Self) -> Self {
+        let r = self % rhs;
+        if r < 0.0 {
+            r + rhs.abs()
+        } else {
+            r
+        }
+    }
+}
+
+impl Float for f32 {
+    fn sqrt(self) -> Self {
+        libm::sqrtf(self)
+    }
+
+    fn hypot(self, other: Self) -> Self {
+        libm::hypotf(self, other)
+    }
+
+    fn sin(self) -> Self {
+        libm::sinf(self)
+    }
+
+    fn cos(self) -> Self {
+        libm::cosf(self)
+    }
+
+    fn sin_cos(self) -> (Self, Self) {
+        libm::sincosf(self)
+    }
+
+    fn atan2(self, other: Self) -> Self {
+        libm::atan2f(self, other)
+    }
+
+    fn acos(self) -> Self {
+        libm::acosf(self)
+    }
+
+    fn floor(self) -> Self {
+        libm::floorf(self)
+    }
+
+    fn ceil(self) -> Self {
+        libm::ceilf(self)
+    }
+
+    fn round(self) -> Self {
+        libm::roundf(self)
+    }
+
+    fn rem_euclid(self, rhs: Self) -> Self {
+        let r = self % rhs;
+        if r < 0.0 {
+            r + rhs.abs()
+        } else {
+            r
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tests link the standard library, whose inherent float methods take precedence over [Float]
+    // in the rest of the crate, so the libm implementations are compared with them here.
+    #[test]
+    fn libm_matches_the_standard_library() {
+        for x in [-7.25f64, -1.0, -0.3, 0.0, 0.5, 1.0, 2.0, 123.456] {
+            let close = |a: f64, b: f64| (a - b).abs() <= 1e-12 * b.abs().max(1.0);
+            assert!(close(Float::hypot(x, 3.0), x.hypot(3.0)));
+            assert!(close(Float::sin(x), x.sin()));
+            assert!(close(Float::cos(x), x.cos()));
+            assert_eq!(Float::sin_cos(x), (Float::sin(x), Float::cos(x)));
+            assert!(close(Float::atan2(x, -2.0), x.atan2(-2.0)));
+            assert_eq!(Float::floor(x), x.floor());
+            assert_eq!(Float::ceil(x), x.ceil());
+            assert_eq!(Float::round(x), x.round());
+            assert!(close(Float::rem_euclid(x, 2.5), x.rem_euclid(2.5)));
+            if x >= 0.0 {
+                assert!(close(Float::sqrt(x), x.sqrt()));
+            }
+            if x.abs() <= 1.0 {
+                assert!(close(Float::acos(x), x.acos()));
+            }
+
+            let y = x as f32;
+            let close = |a: f32, b: f32| (a - b).abs() <= 1e-5 * b.abs().max(1.0);
+            assert!(close(Float::hypot(y, 3.0), y.hypot(3.0)));
+            assert!(close(Float::sin(y), y.sin()));
+            assert!(close(Float::cos(y), y.cos()));
+            assert!(close(Float::atan2(y, -2.0), y.atan2(-2.0)));
+            assert_eq!(Float::floor(y), y.floor());
+            assert_eq!(Float::round(y), y.round());
+            assert!(close(Float::rem_euclid(y, 2.5), y.rem_euclid(2.5)));
+        }
+    }
+}
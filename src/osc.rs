@@ -0,0 +1,93 @@
+//! Encoding of Open Sound Control (OSC) messages and bundles, as sent over UDP by the `tuio` and
+//! `osc` features.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// OSC time tag which means "immediately".
+const IMMEDIATELY: u64 = 1;
+
+/// Seconds from the NTP epoch (1900), used by OSC time tags, to the Unix epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// An argument of an OSC message.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Arg<'a> {
+    Int(i32),
+    Float(f32),
+    Str(&'a str),
+    Time(u64),
+}
+
+/// Encodes an OSC message.
+pub(crate) fn message<'a>(address: &str, args: impl IntoIterator<Item = Arg<'a>>) -> Vec<u8> {
+    let args: Vec<Arg> = args.into_iter().collect();
+    let mut tags = String::from(",");
+    tags.extend(args.iter().map(|arg| match arg {
+        Arg::Int(_) => 'i',
+        Arg::Float(_) => 'f',
+        Arg::Str(_) => 's',
+        Arg::Time(_) => 't',
+    }));
+
+    let mut bytes = Vec::new();
+    push_str(&mut bytes, address);
+    push_str(&mut bytes, &tags);
+    for arg in args {
+        match arg {
+            Arg::Int(value) => bytes.extend(value.to_be_bytes()),
+            Arg::Float(value) => bytes.extend(value.to_be_bytes()),
+            Arg::Str(value) => push_str(&mut bytes, value),
+            Arg::Time(value) => bytes.extend(value.to_be_bytes()),
+        }
+    }
+    bytes
+}
+
+/// Encodes OSC messages as a bundle to be handled immediately.
+pub(crate) fn bundle(messages: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    push_str(&mut bytes, "#bundle");
+    bytes.extend(IMMEDIATELY.to_be_bytes());
+    for message in messages {
+        bytes.extend((message.len() as i32).to_be_bytes());
+        bytes.extend(message);
+    }
+    bytes
+}
+
+/// Appends an OSC string: null terminated, and padded with nulls to a multiple of 4 bytes.
+fn push_str(bytes: &mut Vec<u8>, value: &str) {
+    bytes.extend(value.as_bytes());
+    let padding = 4 - value.len() % 4;
+    bytes.resize(bytes.len() + padding, 0);
+}
+
+/// The current time as an OSC time tag.
+pub(crate) fn now() -> u64 {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = elapsed.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = (u64::from(elapsed.subsec_nanos()) << 32) / 1_000_000_000;
+    (seconds << 32) | fraction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_encoded_as_osc() {
+        let bytes = message("/tuio/2Dobj", [Arg::Str("fseq"), Arg::Int(7)]);
+        let mut expected = b"/tuio/2Dobj\0,si\0fseq\0\0\0\0".to_vec();
+        expected.extend([0, 0, 0, 7]);
+        assert_eq!(bytes, expected);
+
+        let bytes = bundle(&[message("/a", [Arg::Float(1.0)])]);
+        let mut expected = b"#bundle\0".to_vec();
+        expected.extend([0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 12]);
+        expected.extend(b"/a\0\0,f\0\0");
+        expected.extend(1.0f32.to_be_bytes());
+        assert_eq!(bytes, expected);
+    }
+}
@@ -0,0 +1,222 @@
+//! Estimates the 3D pose of a TopCode relative to the camera from a single detection, given the
+//! physical size of the code and the camera's intrinsics.
+//!
+//! The distance follows from the apparent size of the bullseye, and the tilt of the code's plane
+//! from how elliptical the bullseye appears. A circle tilted either way about the same axis
+//! projects to the same ellipse, so the side the plane is tilted towards is ambiguous.
+
+use alloc::vec::Vec;
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::{
+    homography::solve,
+    scanner::{ScanBuffer, Scanner},
+    topcode::{Real, TopCode, PI},
+};
+
+/// Number of directions in which the edge of the bullseye is measured.
+const RAYS: usize = 32;
+
+/// Radius of the outer edge of the black ring around the bullseye, as a fraction of the diameter
+/// of the code.
+const EDGE_RADIUS: Real = 0.25;
+
+/// The intrinsic parameters of a pinhole camera, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraIntrinsics {
+    /// Horizontal focal length
+    pub fx: Real,
+    /// Vertical focal length
+    pub fy: Real,
+    /// Horizontal position of the principal point
+    pub cx: Real,
+    /// Vertical position of the principal point
+    pub cy: Real,
+}
+
+/// The pose of a TopCode in camera coordinates, with x pointing right, y pointing down and z
+/// pointing out of the camera.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pose {
+    /// Position of the center of the code, in the unit of the diameter it was estimated with
+    /// (e.g. meters)
+    pub translation: [Real; 3],
+    /// Unit normal of the code's plane, facing the camera. Its x and y components may need to be
+    /// negated, see the [module documentation](self).
+    pub normal: [Real; 3],
+}
+
+/// Estimates the pose of `code`, detected by `scanner`, whose printed diameter is `diameter`
+/// (e.g. in meters). The edge of the bullseye is measured in the scanner's thresholded image, so
+/// this needs to be called before the scanner scans another image. Returns `None` if the edge
+/// could not be measured, e.g. for codes at the border of the image.
+pub fn estimate(
+    code: &TopCode,
+    scanner: &Scanner<impl ScanBuffer>,
+    intrinsics: &CameraIntrinsics,
+    diameter: Real,
+) -> Option<Pose> {
+    let normalize = |(x, y): (Real, Real)| {
+        (
+            (x - intrinsics.cx) / intrinsics.fx,
+            (y - intrinsics.cy) / intrinsics.fy,
+        )
+    };
+    let (x0, y0) = scanner.to_image_position(code.x, code.y);
+    let center = normalize((code.x, code.y));
+
+    // Points on the edge of the bullseye, relative to its center in normalized coordinates.
+    let points: Vec<(Real, Real)> = (0..RAYS)
+        .filter_map(|i| {
+            let angle = i as Real * 2.0 * PI / RAYS as Real;
+            let r = edge_distance(scanner, (x0, y0), angle, code.unit * 4.0)?;
+            let (x, y) = scanner.undistort_position(x0 + r * angle.cos(), y0 + r * angle.sin());
+            let (x, y) = normalize((x, y));
+            Some((x - center.0, y - center.1))
+        })
+        .collect();
+    if points.len() < RAYS / 2 {
+        return None;
+    }
+    let (major, minor, angle) = fit_ellipse(&points)?;
+
+    // The major axis of the ellipse is the diameter of the circle perpendicular to the tilt.
+    let z = diameter * EDGE_RADIUS / major;
+    let tilt = (minor / major).min(1.0).acos();
+    let (sin, cos) = angle.sin_cos();
+    Some(Pose {
+        translation: [center.0 * z, center.1 * z, z],
+        normal: [tilt.sin() * cos, tilt.sin() * sin, -tilt.cos()],
+    })
+}
+
+/// Distance (in pixels) from (x, y) along the direction `angle` to the second change in color of
+/// the thresholded image, which for a point in the bullseye is the outer edge of the black ring.
+/// Gives up after `max` pixels.
+fn edge_distance(
+    scanner: &Scanner<impl ScanBuffer>,
+    (x, y): (Real, Real),
+    angle: Real,
+    max: Real,
+) -> Option<Real> {
+    const STEP: Real = 0.5;
+    let (sin, cos) = angle.sin_cos();
+    let sample = |r: Real| {
+        let (sx, sy) = ((x + r * cos).round(), (y + r * sin).round());
+        let inside = sx >= 1.0
+            && sy >= 1.0
+            && sx < (scanner.image_width() - 1) as Real
+            && sy < (scanner.image_height() - 1) as Real;
+        inside.then(|| scanner.get_bw_3x3(sx as usize, sy as usize))
+    };
+
+    let mut color = sample(0.0)?;
+    let mut changes = 0;
+    let mut r = STEP;
+    while r <= max {
+        let next = sample(r)?;
+        if next != color {
+            changes += 1;
+            if changes == 2 {
+                return Some(r - STEP / 2.0);
+            }
+            color = next;
+        }
+        r += STEP;
+    }
+    None
+}
+
+/// Fits a centered ellipse `a x^2 + b xy + c y^2 = 1` to the points by least squares, returning
+/// its semi-major and semi-minor axes and the direction of its minor axis (in radians).
+fn fit_ellipse(points: &[(Real, Real)]) -> Option<(Real, Real, Real)> {
+    let mut m = [[0.0; 3]; 3];
+    let mut v = [0.0; 3];
+    for &(x, y) in points {
+        let f = [x * x, x * y, y * y];
+        for i in 0..3 {
+            for j in 0..3 {
+                m[i][j] += f[i] * f[j];
+            }
+            v[i] += f[i];
+        }
+    }
+    let [a, b, c] = solve(m, v)?;
+
+    // Eigenvalues of the quadratic form, the larger of which belongs to the minor axis.
+    let mean = (a + c) / 2.0;
+    let spread = ((a - c) * (a - c) / 4.0 + b * b / 4.0).sqrt();
+    let (low, high) = (mean - spread, mean + spread);
+    if low <= 0.0 {
+        return None;
+    }
+    Some((1.0 / low.sqrt(), 1.0 / high.sqrt(), b.atan2(a - c) / 2.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::render_topcode;
+
+    const INTRINSICS: CameraIntrinsics = CameraIntrinsics {
+        fx: 500.0,
+        fy: 500.0,
+        cx: 200.0,
+        cy: 150.0,
+    };
+
+    #[test]
+    fn codes_facing_the_camera_have_their_distance_estimated() {
+        let (width, height) = (400, 300);
+        let image = render_topcode(55, 10.0, 0.3, (250.0, 150.0), (width, height));
+        let mut scanner = Scanner::new(width, height);
+        let codes = scanner.scan(&image, |buffer, index| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        });
+        assert_eq!(codes.len(), 1);
+
+        // The code is 80 pixels wide, which is 8 cm at 50 cm from the camera.
+        let pose = estimate(&codes[0], &scanner, &INTRINSICS, 0.08).unwrap();
+        let [x, y, z] = pose.translation;
+        assert!((z - 0.5).abs() < 0.02, "{:?}", pose);
+        assert!((x - 0.05).abs() < 0.005 && y.abs() < 0.005, "{:?}", pose);
+        assert!(pose.normal[2] < -0.95, "{:?}", pose);
+    }
+
+    #[test]
+    fn tilted_codes_have_their_normal_estimated() {
+        // The code is squashed horizontally, as if it were turned by 60 degrees about the y-axis.
+        let (width, height) = (400, 300);
+        let upright = render_topcode(55, 10.0, 0.0, (200.0, 150.0), (width, height));
+        let image: Vec<u8> = (0..width * height)
+            .map(|i| {
+                let x = 200.0 + ((i % width) as Real - 200.0) * 2.0;
+                if (0.0..width as Real).contains(&x) {
+                    upright[i / width * width + x as usize]
+                } else {
+                    255
+                }
+            })
+            .collect();
+        let mut scanner = Scanner::new(width, height);
+        scanner.threshold_only(&image, |buffer, index| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        });
+
+        let code = TopCode::mock(55, 10.0, 0.0, 200.0, 150.0);
+        let pose = estimate(&code, &scanner, &INTRINSICS, 0.08).unwrap();
+        let [nx, ny, nz] = pose.normal;
+        let tilt = 60.0 as Real;
+        assert!(
+            (nx.abs() - tilt.to_radians().sin()).abs() < 0.05,
+            "{:?}",
+            pose
+        );
+        assert!(ny.abs() < 0.05, "{:?}", pose);
+        assert!((nz + tilt.to_radians().cos()).abs() < 0.05, "{:?}", pose);
+        assert!((pose.translation[2] - 0.5).abs() < 0.02, "{:?}", pose);
+    }
+}
@@ -0,0 +1,294 @@
+use std::f64::consts::PI;
+
+use pdf_writer::{Content, Finish, Name, Pdf, Rect, Ref, Str};
+
+use crate::{
+    codes::is_valid_code,
+    errors::TopCodeError,
+    generator::{black_runs, ARC},
+    topcode::{Code, WIDTH},
+};
+
+/// Points (the unit of PDF coordinates) per millimeter.
+const POINTS_PER_MM: f64 = 72.0 / 25.4;
+
+/// Font size of the labels in points.
+const LABEL_SIZE: f64 = 10.0;
+
+/// Width of a digit in Helvetica, as a fraction of the font size.
+const DIGIT_WIDTH: f64 = 0.556;
+
+/// Paper sizes for printed sheets of TopCodes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PageSize {
+    A4,
+    Letter,
+    /// Width and height in millimeters
+    Custom(f64, f64),
+}
+
+impl PageSize {
+    /// Width and height in millimeters.
+    fn dimensions(self) -> (f64, f64) {
+        match self {
+            Self::A4 => (210.0, 297.0),
+            Self::Letter => (215.9, 279.4),
+            Self::Custom(width, height) => (width, height),
+        }
+    }
+}
+
+/// Layout of the TopCodes on a printed sheet. All lengths are in millimeters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SheetLayout {
+    pub page_size: PageSize,
+    /// Diameter of each code
+    pub diameter: f64,
+    /// Space between neighboring codes (and their labels)
+    pub spacing: f64,
+    /// Space between the codes and the edges of the page
+    pub margin: f64,
+    /// Whether the number of each code is printed under it
+    pub labels: bool,
+}
+
+impl Default for SheetLayout {
+    fn default() -> Self {
+        Self {
+            page_size: PageSize::A4,
+            diameter: 40.0,
+            spacing: 10.0,
+            margin: 15.0,
+            labels: true,
+        }
+    }
+}
+
+impl SheetLayout {
+    /// Number of columns and rows of codes which fit on a page, at least one of each.
+    pub fn grid(&self) -> (usize, usize) {
+        let (width, height) = self.page_size.dimensions();
+        let fit = |space: f64, cell: f64| {
+            ((space - 2.0 * self.margin + self.spacing) / (cell + self.spacing)).max(1.0) as usize
+        };
+        (
+            fit(width, self.diameter),
+            fit(height, self.diameter + self.label_height()),
+        )
+    }
+
+    /// Height of the space reserved for a label under each code, in millimeters.
+    fn label_height(&self) -> f64 {
+        if self.labels {
+            1.5 * LABEL_SIZE / POINTS_PER_MM
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Lays `codes` out on as many pages as needed, in rows from the top left of each page, and
+/// returns the PDF document. The codes are drawn as vectors, laid out as in
+/// [TopCode::draw](crate::TopCode::draw). Codes which don't fit on a page because of a large
+/// diameter are drawn one per page, running off its edges. Fails if any of the codes is not valid
+/// (see [is_valid_code]).
+pub fn sheet_pdf(codes: &[Code], layout: &SheetLayout) -> Result<Vec<u8>, TopCodeError> {
+    if let Some(&code) = codes.iter().find(|&&code| !is_valid_code(code)) {
+        return Err(TopCodeError::InvalidCode(code));
+    }
+    let (page_width, page_height) = layout.page_size.dimensions();
+    let (columns, rows) = layout.grid();
+    let pages: Vec<&[Code]> = if codes.is_empty() {
+        vec![&[]]
+    } else {
+        codes.chunks(columns * rows).collect()
+    };
+
+    let mut ids = Ref::new(1);
+    let catalog = ids.bump();
+    let tree = ids.bump();
+    let font = ids.bump();
+    let page_ids: Vec<(Ref, Ref)> = pages.iter().map(|_| (ids.bump(), ids.bump())).collect();
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog).pages(tree);
+    pdf.pages(tree)
+        .kids(page_ids.iter().map(|(page, _)| *page))
+        .count(pages.len() as i32);
+    pdf.type1_font(font).base_font(Name(b"Helvetica"));
+
+    for (codes, &(page_id, content_id)) in pages.iter().zip(&page_ids) {
+        let mut page = pdf.page(page_id);
+        page.parent(tree)
+            .media_box(Rect::new(
+                0.0,
+                0.0,
+                (page_width * POINTS_PER_MM) as f32,
+                (page_height * POINTS_PER_MM) as f32,
+            ))
+            .contents(content_id);
+        page.resources().fonts().pair(Name(b"F1"), font);
+        page.finish();
+
+        let mut content = Content::new();
+        for (i, &code) in codes.iter().enumerate() {
+            let (column, row) = (i % columns, i / columns);
+            let x = layout.margin + column as f64 * (layout.diameter + layout.spacing);
+            let y = layout.margin
+                + row as f64 * (layout.diameter + layout.label_height() + layout.spacing);
+            let (cx, cy) = (x + layout.diameter / 2.0, y + layout.diameter / 2.0);
+            // PDF coordinates grow upwards from the bottom of the page.
+            let cy = page_height - cy;
+            draw_code(&mut content, code, cx, cy, layout.diameter);
+
+            if layout.labels {
+                let label = code.to_string();
+                let width = label.len() as f64 * DIGIT_WIDTH * LABEL_SIZE;
+                let baseline = cy - layout.diameter / 2.0 - LABEL_SIZE / POINTS_PER_MM;
+                content
+                    .begin_text()
+                    .set_font(Name(b"F1"), LABEL_SIZE as f32)
+                    .next_line(
+                        (cx * POINTS_PER_MM - width / 2.0) as f32,
+                        (baseline * POINTS_PER_MM) as f32,
+                    )
+                    .show(Str(label.as_bytes()))
+                    .end_text();
+            }
+        }
+        pdf.stream(content_id, &content.finish());
+    }
+
+    Ok(pdf.finish())
+}
+
+/// Draws the symbol for `code` centered on (cx, cy), with the given diameter in millimeters.
+fn draw_code(content: &mut Content, code: Code, cx: f64, cy: f64, diameter: f64) {
+    let scale = diameter / WIDTH as f64 * POINTS_PER_MM;
+    content.save_state();
+    // Draw in units from the center of the symbol, with y growing downwards as in images.
+    content.transform([
+        scale as f32,
+        0.0,
+        0.0,
+        -scale as f32,
+        (cx * POINTS_PER_MM) as f32,
+        (cy * POINTS_PER_MM) as f32,
+    ]);
+    content.set_fill_gray(0.0);
+
+    // The black ring, with the white bullseye cut out of it.
+    circle(content, 2.0);
+    circle(content, 1.0);
+    content.fill_even_odd();
+
+    for (start, end) in black_runs(code) {
+        let from = ARC * (start as f64 + 0.15);
+        let to = ARC * (end as f64 + 0.15);
+        content.move_to((4.0 * from.cos()) as f32, (4.0 * from.sin()) as f32);
+        arc(content, 4.0, from, to);
+        content.line_to((3.0 * to.cos()) as f32, (3.0 * to.sin()) as f32);
+        arc(content, 3.0, to, from);
+        content.close_path();
+        content.fill_nonzero();
+    }
+
+    content.restore_state();
+}
+
+/// Adds a closed circle of radius `r` around the origin to the current path.
+fn circle(content: &mut Content, r: f64) {
+    content.move_to(r as f32, 0.0);
+    arc(content, r, 0.0, 2.0 * PI);
+    content.close_path();
+}
+
+/// Continues the current path, which ends at angle `from` on the circle of radius `r` around the
+/// origin, along the circle to angle `to`, with Bézier curves spanning at most a quarter circle.
+fn arc(content: &mut Content, r: f64, from: f64, to: f64) {
+    let segments = ((to - from).abs() / (PI / 2.0)).ceil().max(1.0) as usize;
+    let delta = (to - from) / segments as f64;
+    // Length of the control arms, relative to the radius
+    let k = 4.0 / 3.0 * (delta / 4.0).tan();
+    for i in 0..segments {
+        let a0 = from + i as f64 * delta;
+        let a1 = a0 + delta;
+        let (sin0, cos0) = a0.sin_cos();
+        let (sin1, cos1) = a1.sin_cos();
+        content.cubic_to(
+            (r * (cos0 - k * sin0)) as f32,
+            (r * (sin0 + k * cos0)) as f32,
+            (r * (cos1 + k * sin1)) as f32,
+            (r * (sin1 - k * cos1)) as f32,
+            (r * cos1) as f32,
+            (r * sin1) as f32,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count(pdf: &[u8], needle: &str) -> usize {
+        pdf.windows(needle.len())
+            .filter(|window| *window == needle.as_bytes())
+            .count()
+    }
+
+    #[test]
+    fn codes_are_laid_out_on_pages() {
+        let layout = SheetLayout::default();
+        // 3 columns of 40mm codes with 10mm spacing fit within the 180mm between A4 margins.
+        assert_eq!(layout.grid(), (3, 5));
+
+        let codes: Vec<Code> = vec![31; 23];
+        let pdf = sheet_pdf(&codes, &layout).unwrap();
+        assert!(pdf.starts_with(b"%PDF-"));
+        assert_eq!(
+            count(&pdf, "/Type /Page\n") + count(&pdf, "/Type /Page>"),
+            2
+        );
+        assert_eq!(count(&pdf, "/Count 2"), 1);
+        assert_eq!(count(&pdf, "(31) Tj"), 23);
+
+        let unlabeled = SheetLayout {
+            page_size: PageSize::Letter,
+            labels: false,
+            ..layout
+        };
+        assert_eq!(unlabeled.grid(), (3, 5));
+        let pdf = sheet_pdf(&codes, &unlabeled).unwrap();
+        assert_eq!(count(&pdf, " Tj"), 0);
+
+        let huge = SheetLayout {
+            diameter: 500.0,
+            ..layout
+        };
+        assert_eq!(huge.grid(), (1, 1));
+        assert_eq!(count(&sheet_pdf(&[31, 55], &huge).unwrap(), "/Count 2"), 1);
+        assert_eq!(count(&sheet_pdf(&[], &huge).unwrap(), "/Count 1"), 1);
+        assert_eq!(
+            sheet_pdf(&[31, 32], &huge),
+            Err(TopCodeError::InvalidCode(32))
+        );
+    }
+
+    #[test]
+    fn arcs_stay_on_the_circle() {
+        let mut content = Content::new();
+        content.move_to(2.0, 0.0);
+        arc(&mut content, 2.0, 0.0, 3.0);
+        let ops = String::from_utf8(content.finish()).unwrap();
+        // A 3 radian arc needs 2 curves, and ends on the circle at 3 radians.
+        assert_eq!(ops.matches(" c").count(), 2);
+        let last = ops.lines().last().unwrap();
+        let values: Vec<f64> = last
+            .split(' ')
+            .take(6)
+            .map(|v| v.parse().unwrap())
+            .collect();
+        assert!((values[4] - 2.0 * 3.0f64.cos()).abs() < 1e-4);
+        assert!((values[5] - 2.0 * 3.0f64.sin()).abs() < 1e-4);
+    }
+}
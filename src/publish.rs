@@ -0,0 +1,168 @@
+//! Publishes the codes detected in each frame to other processes, so that installations and IoT
+//! setups can consume the scanner's output directly.
+//!
+//! With the `osc` feature, `OscPublisher` sends each frame as an OSC bundle over UDP, with a
+//! `/topcodes/frame` message holding the number of the frame and the number of codes, followed by
+//! a `/topcodes/code` message per code with its code, x, y, orientation and unit.
+//!
+//! With the `mqtt` feature, `MqttPublisher` publishes each frame to an MQTT broker as a JSON
+//! message of the form `{"frame":1,"codes":[{"code":31,"unit":4,"orientation":0,"x":10,"y":20}]}`.
+
+#[cfg(feature = "osc")]
+use std::{
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+};
+
+#[cfg(feature = "osc")]
+use crate::osc::{bundle, message, Arg};
+use crate::topcode::TopCode;
+
+/// Port OSC publishers send to by default.
+#[cfg(feature = "osc")]
+pub const DEFAULT_OSC_PORT: u16 = 9000;
+
+/// Sends the codes detected in each frame as OSC bundles over UDP.
+#[cfg(feature = "osc")]
+#[derive(Debug)]
+pub struct OscPublisher {
+    socket: UdpSocket,
+    frame: u32,
+}
+
+#[cfg(feature = "osc")]
+impl OscPublisher {
+    /// Creates a publisher sending to `address`, e.g. `("127.0.0.1", DEFAULT_OSC_PORT)`.
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(address)?;
+        Ok(Self { socket, frame: 0 })
+    }
+
+    /// Sends the codes detected in the next frame as one bundle. Invalid codes are left out.
+    pub fn publish(&mut self, codes: &[TopCode]) -> io::Result<()> {
+        self.frame = self.frame.wrapping_add(1);
+        let valid: Vec<_> = codes.iter().filter(|code| code.code.is_some()).collect();
+        let mut messages = vec![message(
+            "/topcodes/frame",
+            [Arg::Int(self.frame as i32), Arg::Int(valid.len() as i32)],
+        )];
+        // The float casts are no-ops with the `f32` feature.
+        #[allow(clippy::unnecessary_cast)]
+        messages.extend(valid.iter().map(|code| {
+            message(
+                "/topcodes/code",
+                [
+                    Arg::Int(code.code.unwrap_or_default() as i32),
+                    Arg::Float(code.x as f32),
+                    Arg::Float(code.y as f32),
+                    Arg::Float(code.orientation as f32),
+                    Arg::Float(code.unit as f32),
+                ],
+            )
+        }));
+        self.socket.send(&bundle(&messages))?;
+        Ok(())
+    }
+}
+
+/// Publishes the codes detected in each frame to an MQTT broker as JSON messages.
+#[cfg(feature = "mqtt")]
+pub struct MqttPublisher {
+    client: rumqttc::Client,
+    topic: String,
+    frame: u64,
+}
+
+#[cfg(feature = "mqtt")]
+impl MqttPublisher {
+    /// Connects to the broker with `options`, publishing to `topic`. The connection is kept up,
+    /// and re-established after errors, by a background thread which stops once the publisher is
+    /// dropped.
+    pub fn connect(options: rumqttc::MqttOptions, topic: &str) -> Self {
+        let (client, mut connection) = rumqttc::Client::new(options, 16);
+        std::thread::spawn(move || {
+            for notification in connection.iter() {
+                if notification.is_err() {
+                    // Wait before reconnecting rather than retrying in a busy loop.
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                }
+            }
+        });
+        Self {
+            client,
+            topic: String::from(topic),
+            frame: 0,
+        }
+    }
+
+    /// Publishes the codes detected in the next frame as one message. Invalid codes are left out.
+    /// Messages are sent at most once, and frames are dropped with an error rather than blocking
+    /// the caller while the broker can't keep up.
+    pub fn publish(&mut self, codes: &[TopCode]) -> Result<(), rumqttc::ClientError> {
+        self.frame += 1;
+        self.client.try_publish(
+            self.topic.as_str(),
+            rumqttc::QoS::AtMostOnce,
+            false,
+            json(self.frame, codes),
+        )
+    }
+}
+
+/// The JSON payload of a frame.
+#[cfg(feature = "mqtt")]
+fn json(frame: u64, codes: &[TopCode]) -> String {
+    let codes: Vec<String> = codes
+        .iter()
+        .filter(|code| code.code.is_some())
+        .map(TopCode::to_json)
+        .collect();
+    format!("{{\"frame\":{},\"codes\":[{}]}}", frame, codes.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "osc")]
+    #[test]
+    fn frames_are_sent_as_osc_bundles() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut publisher = OscPublisher::connect(receiver.local_addr().unwrap()).unwrap();
+        let mut invalid = TopCode::mock(31, 4.0, 0.0, 0.0, 0.0);
+        invalid.code = None;
+        publisher
+            .publish(&[TopCode::mock(55, 4.0, 0.5, 10.0, 20.0), invalid])
+            .unwrap();
+
+        let mut buffer = [0; 1024];
+        let length = receiver.recv(&mut buffer).unwrap();
+        let frame = message("/topcodes/frame", [Arg::Int(1), Arg::Int(1)]);
+        let code = message(
+            "/topcodes/code",
+            [
+                Arg::Int(55),
+                Arg::Float(10.0),
+                Arg::Float(20.0),
+                Arg::Float(0.5),
+                Arg::Float(4.0),
+            ],
+        );
+        assert_eq!(&buffer[..length], bundle(&[frame, code]));
+    }
+
+    #[cfg(feature = "mqtt")]
+    #[test]
+    fn frames_are_published_as_json() {
+        let codes = [
+            TopCode::mock(55, 4.0, 0.5, 10.0, 20.0),
+            TopCode::mock(93, 2.0, 0.0, 30.0, 40.0),
+        ];
+        let payload: serde_json::Value = serde_json::from_str(&json(3, &codes)).unwrap();
+        assert_eq!(payload["frame"], 3);
+        assert_eq!(payload["codes"][0]["code"], 55);
+        assert_eq!(payload["codes"][1]["x"], 30.0);
+        assert_eq!(json(4, &[]), "{\"frame\":4,\"codes\":[]}");
+    }
+}
@@ -1,11 +1,75 @@
+use alloc::{borrow::ToOwned, string::String, vec, vec::Vec};
+use core::ops::{DerefMut, Range};
+#[cfg(feature = "image-io")]
+use image::DynamicImage;
 #[cfg(feature = "visualize")]
-use image::{GrayImage, ImageBuffer};
+use image::GrayImage;
+#[cfg(feature = "ndarray")]
+use ndarray::{ArrayView, Dimension, Ix2, Ix3};
+#[cfg(feature = "opencv")]
+use opencv::core::{MatTraitConst, CV_8UC1, CV_8UC3, CV_8UC4};
 
-use crate::{candidate::Candidate, topcode::TopCode};
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::{
+    candidate::Candidate,
+    errors::TopCodeError,
+    lens::LensModel,
+    topcode::{Code, Real, TopCode},
+};
 
 /// Default maximum width of a TopCode unit/ring in pixels. This is equivalent to 640 pixels.
 const DEFAULT_MAX_UNIT: usize = 80;
 
+/// Minimum width and height of an image in pixels, so that the 3x3 sampling around a pixel fits.
+const MIN_DIMENSION: usize = 3;
+
+/// Minimum width of a TopCode unit/ring in pixels which can be reliably decoded. Each ring must be
+/// at least as wide as the 3x3 sampling footprint used while decoding.
+const MIN_UNIT: usize = 3;
+
+/// Number of rows tracked by each entry of the dirty-row bitmap used by [Scanner::scan_diff].
+const DIFF_BAND_HEIGHT: usize = 8;
+
+/// Default fraction of the local average intensity below which a pixel is considered black.
+pub(crate) const DEFAULT_THRESHOLD_BIAS: f64 = 0.975;
+
+/// Two concentric detections are only considered nested if the inner unit is at most this fraction
+/// of the outer unit.
+const NESTED_UNIT_RATIO: Real = 0.75;
+
+/// Number of candidates per thread decoded in parallel between overlap checks. Most candidates
+/// are rows of the bullseye of a code found shortly before, and are skipped without decoding.
+#[cfg(feature = "rayon")]
+const PARALLEL_BATCH: usize = 4;
+
+/// Largest intensity of 8-bit images.
+const MAX_LUMA_U8: u32 = 255;
+
+/// Largest intensity of 16-bit images, which is also the precision normalized floating point
+/// images are thresholded at. Running sums of 16-bit intensities still fit in the `u32` row sums.
+const MAX_LUMA_U16: u32 = u16::MAX as u32;
+
+/// Intensity (0-255) of an RGB pixel, as the average of its channels.
+pub(crate) fn average((r, g, b): (u32, u32, u32)) -> u32 {
+    (r + g + b) / 3
+}
+
+/// Channels (0-255) of an RGB565 pixel, with the low bits filled in so that full intensity maps
+/// to 255.
+fn rgb565(pixel: u16) -> (u32, u32, u32) {
+    let (r, g, b) = (
+        (pixel >> 11) as u32,
+        ((pixel >> 5) & 0x3f) as u32,
+        (pixel & 0x1f) as u32,
+    );
+    (
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    )
+}
+
 #[repr(u8)]
 enum UnitLevel {
     WhiteRegion = 0,
@@ -14,292 +78,4060 @@ enum UnitLevel {
     BlackRegionSecond = 3,
 }
 
+/// State of the search for bullseye patterns along a row of pixels: the widths of the first black
+/// region, the white region and the second black region seen so far.
+struct RowSearch {
+    level: UnitLevel,
+    b1: isize,
+    b2: isize,
+    w1: isize,
+}
+
+impl RowSearch {
+    fn new() -> Self {
+        Self {
+            level: UnitLevel::WhiteRegion,
+            b1: 0,
+            b2: 0,
+            w1: 0,
+        }
+    }
+
+    /// Advances the search by `n` more pixels of the same color `a` as the last pixel, which can
+    /// only widen the region the search is in.
+    fn extend(&mut self, a: isize, n: isize) {
+        match (&self.level, a) {
+            (UnitLevel::BlackRegion, 0) => self.b1 += n,
+            (UnitLevel::WhiteRegionSecond, 1) => self.w1 += n,
+            (UnitLevel::BlackRegionSecond, 0) => self.b2 += n,
+            _ => {}
+        }
+    }
+}
+
+/// Binary pixels and candidates of a band of rows thresholded on its own by
+/// [Scanner::threshold_band].
+struct ThresholdBand {
+    /// Binary pixels of each row, packed like the bitmask but with each row starting at a word
+    bits: Vec<u32>,
+    /// Candidates found in the band, in row order
+    candidates: Vec<Candidate>,
+    /// Running sums of the last row of the band
+    carry: Vec<u32>,
+}
+
+/// Number of consecutive pixels of a row with the same color as pixel `x`, starting at `x` and
+/// going right if `forward` is set or left otherwise. `bits` holds the row's binary pixels, packed
+/// like the bitmask of a scanner but starting at bit 0 of `bits[0]`.
+fn run_length(bits: &[u32], x: usize, width: usize, forward: bool) -> usize {
+    // Pixels of the other color are set in `flip(word)`.
+    let color = bits[x / 32] >> (x % 32) & 0x01;
+    let flip = |word: u32| if color == 1 { !word } else { word };
+    let mut run = 0;
+    if forward {
+        let mut i = x;
+        while i < width {
+            let available = 32 - i % 32;
+            let length = (flip(bits[i / 32]) >> (i % 32)).trailing_zeros() as usize;
+            run += length.min(available);
+            i += length.min(available);
+            if length < available {
+                break;
+            }
+        }
+        run.min(width - x)
+    } else {
+        let mut i = x + 1;
+        while i > 0 {
+            let available = (i - 1) % 32 + 1;
+            let length = (flip(bits[(i - 1) / 32]) << (32 - available)).leading_zeros() as usize;
+            run += length.min(available);
+            i -= length.min(available);
+            if length < available {
+                break;
+            }
+        }
+        run
+    }
+}
+
+/// Which codes the scanner detects, by the color of their ink relative to their background.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Polarity {
+    /// Dark codes on a light background
+    #[default]
+    Normal,
+    /// Light codes on a dark background, such as codes printed on dark material
+    Inverted,
+    /// Both normal and inverted codes
+    Both,
+}
+
+/// Arrangement of the color filters of a raw Bayer sensor, named after the colors of the top-left
+/// 2x2 block of pixels in row-major order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BayerPattern {
+    Rggb,
+    Bggr,
+    Grbg,
+    Gbrg,
+}
+
+impl BayerPattern {
+    /// Column and row (0 or 1) of the red pixel in each 2x2 block.
+    fn red_offset(self) -> (usize, usize) {
+        match self {
+            Self::Rggb => (0, 0),
+            Self::Bggr => (1, 1),
+            Self::Grbg => (1, 0),
+            Self::Gbrg => (0, 1),
+        }
+    }
+}
+
+/// Memory layout of an image whose rows may be padded, such as frames from capture APIs and GPU
+/// readbacks, for scanning it in place with [Scanner::scan_layout].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScanLayout {
+    /// Number of bytes between the starts of consecutive rows
+    pub stride: usize,
+    /// Number of bytes per pixel
+    pub bytes_per_pixel: usize,
+}
+
+impl ScanLayout {
+    /// Layout of an image whose rows are `width` pixels of `bytes_per_pixel` bytes each, followed
+    /// by padding up to `stride` bytes.
+    pub fn new(stride: usize, bytes_per_pixel: usize) -> Self {
+        Self {
+            stride,
+            bytes_per_pixel,
+        }
+    }
+
+    /// Layout of an image without any row padding.
+    pub fn packed(width: usize, bytes_per_pixel: usize) -> Self {
+        Self::new(width * bytes_per_pixel, bytes_per_pixel)
+    }
+}
+
+/// The thresholded pixel data of a [Scanner], which can be shipped elsewhere (e.g. to another
+/// process or machine) and turned back into a scanner to decode candidates there.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScannerState {
+    /// Image width
+    pub width: usize,
+    /// Image height
+    pub height: usize,
+    /// Processed pixel data, laid out as described by [Scanner::buffer_len]
+    pub data: Vec<u32>,
+}
+
+/// The settings of a [Scanner], which can be stored (e.g. as JSON with the `serde` feature) and
+/// applied to another scanner with [Scanner::set_config], so that scans can be reproduced. Each
+/// field is described by the scanner's setter of the same name. Flat-field factors and excluded
+/// pixels belong to the image rather than the scanner's settings, and are left out.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ScannerConfig {
+    /// See [Scanner::set_max_code_diameter]
+    pub max_code_diameter: usize,
+    /// See [Scanner::set_sector_supersamples]
+    pub sector_supersamples: usize,
+    /// See [Scanner::set_prefer_inner]
+    pub prefer_inner: bool,
+    /// See [Scanner::set_local_contrast_window]
+    pub local_contrast_window: usize,
+    /// See [Scanner::set_threshold_bands]
+    pub threshold_bands: usize,
+    /// The `(x, y, width, height)` of the region of interest, see [Scanner::set_roi]
+    pub roi: Option<(usize, usize, usize, usize)>,
+    /// See [Scanner::set_motion_compensation]
+    pub motion_compensation: Real,
+    /// See [Scanner::set_threshold_bias]
+    pub threshold_bias: f64,
+    /// See [Scanner::set_white_ring_bounds]
+    pub white_ring_bounds: (f64, f64),
+    /// See [Scanner::set_decode_retries]
+    pub decode_retries: usize,
+    /// See [Scanner::set_bw_majority]
+    pub bw_majority: u32,
+    /// See [Scanner::set_read_extended_ring]
+    pub extended_ring_bits: usize,
+    /// See [Scanner::set_extended_symbology]
+    pub extended_symbology: bool,
+    /// See [Scanner::set_polarity]
+    pub polarity: Polarity,
+    /// See [Scanner::set_mirrored]
+    pub mirrored: bool,
+    /// See [Scanner::set_lens_model]
+    pub lens_model: Option<LensModel>,
+}
+
+impl Default for ScannerConfig {
+    /// The settings of a newly created scanner.
+    fn default() -> Self {
+        Self {
+            max_code_diameter: 8 * DEFAULT_MAX_UNIT,
+            sector_supersamples: 1,
+            prefer_inner: false,
+            local_contrast_window: 0,
+            threshold_bands: 1,
+            roi: None,
+            motion_compensation: 0.0,
+            threshold_bias: DEFAULT_THRESHOLD_BIAS,
+            white_ring_bounds: (0.5, 2.0),
+            decode_retries: 0,
+            bw_majority: 5,
+            extended_ring_bits: 0,
+            extended_symbology: false,
+            polarity: Polarity::Normal,
+            mirrored: false,
+            lens_model: None,
+        }
+    }
+}
+
+/// Storage for the candidates and codes found while scanning, either a `Vec` or, for scanning
+/// without a heap, a fixed-capacity `heapless::Vec`.
+trait ScanList<T>: DerefMut<Target = [T]> {
+    /// Appends `item`, dropping it if the list is full.
+    fn push_item(&mut self, item: T);
+}
+
+impl<T> ScanList<T> for Vec<T> {
+    fn push_item(&mut self, item: T) {
+        self.push(item);
+    }
+}
+
+#[cfg(feature = "heapless")]
+impl<T, const N: usize> ScanList<T> for heapless::Vec<T, N> {
+    fn push_item(&mut self, item: T) {
+        let _ = self.push(item);
+    }
+}
+
+/// Storage for the pixel data of a [Scanner]. Scanners own a `Vec<u32>` by default, while a
+/// [BorrowedScanner] uses a buffer borrowed from the caller.
+pub trait ScanBuffer: DerefMut<Target = [u32]> + Sync {}
+
+impl<T: DerefMut<Target = [u32]> + Sync> ScanBuffer for T {}
+
 /// Loads and scans images for TopCodes.  The algorithm does a single sweep of an image (scanning
 /// one horizontal line at a time) looking for TopCode bullseye patterns.  If the pattern matches
 /// and the black and white regions meet certain ratio constraints, then the pixel is tested as the
 /// center of a candidate TopCode.
+///
+/// Scanners own their pixel data, except for a [BorrowedScanner] created with
+/// [BorrowedScanner::with_buffer], which scans into a buffer borrowed from the caller.
 #[derive(Clone)]
-pub struct Scanner {
+pub struct Scanner<B = Vec<u32>> {
     /// Expected image width
     width: usize,
     /// Expected image height
     height: usize,
-    /// Holds processed binary pixel data as a single u32 in the ARGB format.
-    data: Vec<u32>,
+    /// Holds the processed pixel data, laid out as described by [Scanner::buffer_len].
+    data: B,
     /// Maximum width of a TopCode unit in pixels
     max_unit: usize,
+    /// Number of samples taken along the arc of each data sector while decoding
+    sector_supersamples: usize,
+    /// Optional label describing the input being scanned, used only for diagnostics
+    source_label: Option<String>,
+    /// Whether to keep the inner code when two valid codes are concentric
+    prefer_inner: bool,
+    /// Window size (in pixels) for local mean thresholding, or 0 to use the running sum
+    local_contrast_window: usize,
+    /// Number of bands of rows packed 8-bit images are thresholded in
+    threshold_bands: usize,
+    /// Columns and rows thresholded and searched for candidates, or `None` for the whole image
+    roi: Option<(Range<usize>, Range<usize>)>,
+    /// Pixels ignored while scanning, with a set bit for each excluded pixel laid out like the
+    /// bitmask of binary pixels
+    exclusions: Option<Vec<u32>>,
+    /// Horizontal shift (in pixels) applied to candidate centers to counteract motion blur
+    motion_compensation: Real,
+    /// Fraction of the local average intensity below which a pixel is considered black
+    threshold_bias: f64,
+    /// Bounds on the width of the white bullseye relative to the combined width of the black ring
+    /// on either side of it
+    white_ring_bounds: (f64, f64),
+    /// Radius (in pixels) of the neighborhood of alternative centers tried when decoding fails
+    decode_retries: usize,
+    /// Per-pixel factors applied to the intensity of each pixel before thresholding
+    flat_field: Option<Vec<f32>>,
+    /// Number of white pixels in a 3x3 neighborhood for it to be sampled as white
+    bw_majority: u32,
+    /// Number of bits read from the extended ring outside the data ring, or 0 to skip it
+    extended_ring_bits: usize,
+    /// Whether the second data ring of the extended symbology is read
+    extended_symbology: bool,
+    /// Whether dark codes, light codes or both are detected
+    polarity: Polarity,
+    /// Whether images are horizontally mirrored, as delivered by front-facing cameras
+    mirrored: bool,
+    /// Lens distortion removed from the positions of detected codes
+    lens_model: Option<LensModel>,
+}
+
+/// A [Scanner] whose pixel data is held in a buffer borrowed for the lifetime `'a`, e.g. a static
+/// buffer on a microcontroller, rather than in a heap allocation of its own.
+pub type BorrowedScanner<'a> = Scanner<&'a mut [u32]>;
+
+/// Prefixes log messages with the source label of the scanner which emitted them, if it has one.
+#[cfg(feature = "logging")]
+struct LogSource<'s>(Option<&'s str>);
+
+#[cfg(feature = "logging")]
+impl core::fmt::Display for LogSource<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Some(label) => write!(f, "{label}: "),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Scanner {
+    /// Creates a scanner for images of the given dimensions.
+    ///
+    /// Panics if the dimensions are rejected by [Scanner::try_new].
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::try_new(width, height).unwrap_or_else(|err| panic!("{err}"))
+    }
+
+    /// Creates a scanner for images of the given dimensions. Fails if either dimension is below
+    /// the 3 pixels needed for the 3x3 sampling used while scanning, or if the number of pixels
+    /// does not fit in a `usize` (which is possible for large images on 32-bit targets).
+    pub fn try_new(width: usize, height: usize) -> Result<Self, TopCodeError> {
+        Self::validate_dimensions(width, height)?;
+        Ok(Self::with_data(
+            width,
+            height,
+            vec![0; Self::buffer_len(width, height)],
+        ))
+    }
+
+    /// Number of `u32` words of pixel data a scanner needs for images of the given dimensions,
+    /// which is the length of buffers passed to [BorrowedScanner::with_buffer] and
+    /// [Scanner::from_raw].
+    ///
+    /// The binary pixels are packed into a bitmask of one bit per pixel in row-major order,
+    /// followed by a bitmask of the pixels which were not fully opaque, and the running sums of
+    /// the last thresholded row (one word per column). This takes a little over 2 bits per pixel,
+    /// e.g. about 2 MB for a 4K frame.
+    pub const fn buffer_len(width: usize, height: usize) -> usize {
+        2 * Self::mask_len(width, height) + width
+    }
+
+    /// Number of words in each bitmask of the pixel data.
+    pub(crate) const fn mask_len(width: usize, height: usize) -> usize {
+        (width * height).div_ceil(32)
+    }
+
+    /// Checks that images of the given dimensions can be scanned, returning their pixel count.
+    fn validate_dimensions(width: usize, height: usize) -> Result<usize, TopCodeError> {
+        if width < MIN_DIMENSION || height < MIN_DIMENSION {
+            return Err(TopCodeError::DimensionsTooSmall { width, height });
+        }
+        width
+            .checked_mul(height)
+            .ok_or(TopCodeError::DimensionsOverflow { width, height })
+    }
+
+    /// Creates a scanner for images with the same dimensions as `image`.
+    #[cfg(feature = "image-io")]
+    pub fn for_image(image: &DynamicImage) -> Result<Self, TopCodeError> {
+        Self::try_new(image.width() as usize, image.height() as usize)
+    }
+
+    /// Creates a scanner with default settings from previously exported thresholded data. Fails
+    /// if the dimensions are not accepted by [Scanner::try_new], or if the data does not hold
+    /// exactly [Scanner::buffer_len] words, e.g. for a state deserialized from untrusted input.
+    pub fn from_state(state: ScannerState) -> Result<Self, TopCodeError> {
+        Self::from_raw(state.width, state.height, state.data)
+    }
+
+    /// Creates a scanner with default settings which adopts `data` as its pixel buffer instead of
+    /// allocating one, e.g. to reuse buffers from a pool. The buffer must hold exactly
+    /// [Scanner::buffer_len] words, and its contents are overwritten by the next scan. Use
+    /// [Scanner::take_data] to get the buffer back.
+    pub fn from_raw(width: usize, height: usize, data: Vec<u32>) -> Result<Self, TopCodeError> {
+        Self::validate_dimensions(width, height)?;
+        if data.len() != Self::buffer_len(width, height) {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        Ok(Self::with_data(width, height, data))
+    }
+
+    /// Consumes the scanner, returning its pixel buffer.
+    pub fn take_data(self) -> Vec<u32> {
+        self.data
+    }
+}
+
+impl<'a> BorrowedScanner<'a> {
+    /// Creates a scanner for images of the given dimensions around `buffer`, which is used to hold
+    /// the pixel data instead of a heap allocation, e.g. a static buffer on a microcontroller. The
+    /// buffer must hold exactly [Scanner::buffer_len] words, and its contents are overwritten by
+    /// each scan. Together with `Scanner::scan_fixed` (with the `heapless` feature), scanning
+    /// makes no heap allocations, though the crate still links `alloc`, so the target needs a
+    /// global allocator.
+    pub fn with_buffer(
+        width: usize,
+        height: usize,
+        buffer: &'a mut [u32],
+    ) -> Result<Self, TopCodeError> {
+        Scanner::validate_dimensions(width, height)?;
+        if buffer.len() != Scanner::buffer_len(width, height) {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        Ok(Self::with_data(width, height, buffer))
+    }
 }
 
-impl Scanner {
-    pub fn new(width: usize, height: usize) -> Self {
-        Self {
-            width,
-            height,
-            data: vec![0; width * height],
-            max_unit: DEFAULT_MAX_UNIT,
+impl<B: ScanBuffer> Scanner<B> {
+    /// Creates a scanner with default settings around existing pixel data, without validating
+    /// its dimensions.
+    fn with_data(width: usize, height: usize, data: B) -> Self {
+        Self {
+            width,
+            height,
+            data,
+            max_unit: DEFAULT_MAX_UNIT,
+            sector_supersamples: 1,
+            source_label: None,
+            prefer_inner: false,
+            local_contrast_window: 0,
+            threshold_bands: 1,
+            roi: None,
+            exclusions: None,
+            motion_compensation: 0.0,
+            threshold_bias: DEFAULT_THRESHOLD_BIAS,
+            white_ring_bounds: (0.5, 2.0),
+            decode_retries: 0,
+            flat_field: None,
+            bw_majority: 5,
+            extended_ring_bits: 0,
+            extended_symbology: false,
+            polarity: Polarity::Normal,
+            mirrored: false,
+            lens_model: None,
+        }
+    }
+
+    /// Exports the thresholded pixel data from the last scan, so that decoding can be performed
+    /// by another scanner created with [Scanner::from_state].
+    pub fn export_state(&self) -> ScannerState {
+        ScannerState {
+            width: self.width,
+            height: self.height,
+            data: self.data.to_vec(),
+        }
+    }
+
+    pub fn image_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn image_height(&self) -> usize {
+        self.height
+    }
+
+    fn pixel_count(&self) -> usize {
+        self.width * self.height
+    }
+
+    /// Scan the image and return a list of all TopCodes found in it.
+    pub fn scan<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+    ) -> Vec<TopCode> {
+        let candidates = self.threshold(image_buffer, decode_rgb);
+        self.find_codes(&candidates)
+    }
+
+    /// Scan an image of packed 8-bit RGB pixels. Fails if the buffer does not hold exactly
+    /// `width * height` pixels.
+    pub fn scan_rgb_u8(&mut self, image_buffer: &[u8]) -> Result<Vec<TopCode>, TopCodeError> {
+        self.scan_u8(image_buffer, 3, [0, 1, 2])
+    }
+
+    /// Scan an image of packed 8-bit RGBA pixels. The alpha channel is ignored. Fails if the
+    /// buffer does not hold exactly `width * height` pixels.
+    pub fn scan_rgba_u8(&mut self, image_buffer: &[u8]) -> Result<Vec<TopCode>, TopCodeError> {
+        self.scan_u8(image_buffer, 4, [0, 1, 2])
+    }
+
+    /// Scan an image of packed 8-bit BGR pixels. Fails if the buffer does not hold exactly
+    /// `width * height` pixels.
+    pub fn scan_bgr_u8(&mut self, image_buffer: &[u8]) -> Result<Vec<TopCode>, TopCodeError> {
+        self.scan_u8(image_buffer, 3, [2, 1, 0])
+    }
+
+    /// Scan an image of packed 8-bit BGRA pixels. The alpha channel is ignored. Fails if the
+    /// buffer does not hold exactly `width * height` pixels.
+    pub fn scan_bgra_u8(&mut self, image_buffer: &[u8]) -> Result<Vec<TopCode>, TopCodeError> {
+        self.scan_u8(image_buffer, 4, [2, 1, 0])
+    }
+
+    /// Scan an 8-bit grayscale image, such as the luma plane of a camera frame. This skips
+    /// averaging color channels, so it is faster than scanning the same image as RGB. Fails if
+    /// the buffer does not hold exactly `width * height` pixels.
+    pub fn scan_gray_u8(&mut self, image_buffer: &[u8]) -> Result<Vec<TopCode>, TopCodeError> {
+        if image_buffer.len() != self.pixel_count() {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        let candidates = self.threshold_u8(image_buffer, 1, [0, 0, 0]);
+        Ok(self.find_codes(&candidates))
+    }
+
+    /// Scan an image of RGB565 pixels, as output by many embedded camera modules. Fails if the
+    /// buffer does not hold exactly `width * height` pixels.
+    pub fn scan_rgb565(&mut self, image_buffer: &[u16]) -> Result<Vec<TopCode>, TopCodeError> {
+        if image_buffer.len() != self.pixel_count() {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        Ok(self.scan(image_buffer, |buffer, index| rgb565(buffer[index])))
+    }
+
+    /// Scan an image of RGB565 pixels stored as little-endian byte pairs, without converting it to
+    /// `u16`s first. Fails if the buffer does not hold exactly `width * height` pixels.
+    pub fn scan_rgb565_le(&mut self, image_buffer: &[u8]) -> Result<Vec<TopCode>, TopCodeError> {
+        if image_buffer.len() != self.pixel_count() * 2 {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        Ok(self.scan(image_buffer, |buffer, index| {
+            rgb565(u16::from_le_bytes([
+                buffer[index * 2],
+                buffer[index * 2 + 1],
+            ]))
+        }))
+    }
+
+    /// Scan an image of RGB565 pixels stored as big-endian byte pairs, without converting it to
+    /// `u16`s first. Fails if the buffer does not hold exactly `width * height` pixels.
+    pub fn scan_rgb565_be(&mut self, image_buffer: &[u8]) -> Result<Vec<TopCode>, TopCodeError> {
+        if image_buffer.len() != self.pixel_count() * 2 {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        Ok(self.scan(image_buffer, |buffer, index| {
+            rgb565(u16::from_be_bytes([
+                buffer[index * 2],
+                buffer[index * 2 + 1],
+            ]))
+        }))
+    }
+
+    /// Scan a raw 8-bit Bayer frame without demosaicing it first. The intensity of each pixel is
+    /// approximated from the 2x2 block of sensor pixels starting at it, which always holds one
+    /// red, two green and one blue pixel, and is computed while thresholding. Fails if the buffer
+    /// does not hold exactly `width * height` pixels.
+    pub fn scan_bayer_u8(
+        &mut self,
+        image_buffer: &[u8],
+        pattern: BayerPattern,
+    ) -> Result<Vec<TopCode>, TopCodeError> {
+        if image_buffer.len() != self.pixel_count() {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        let (width, height) = (self.width, self.height);
+        let (red_x, red_y) = pattern.red_offset();
+        let candidates = self.threshold_luma(
+            image_buffer,
+            |buffer, index| {
+                // The last row and column reuse the block of their neighbors.
+                let x = (index % width).min(width - 2);
+                let y = (index / width).min(height - 2);
+                let (dx, dy) = ((x + red_x) % 2, (y + red_y) % 2);
+                let pixel = |i: usize, j: usize| buffer[(y + j) * width + x + i] as u32;
+                let red = pixel(dx, dy);
+                let blue = pixel(1 - dx, 1 - dy);
+                let green = (pixel(1 - dx, dy) + pixel(dx, 1 - dy)) / 2;
+                average((red, green, blue))
+            },
+            MAX_LUMA_U8,
+        );
+        Ok(self.find_codes(&candidates))
+    }
+
+    /// Scan an image decoded by the image crate. 8-bit grayscale, RGB and RGBA images are
+    /// scanned in place, 16-bit and floating point images are thresholded at their full
+    /// precision, and any other images are converted to 16-bit grayscale first. Fails if the
+    /// image's dimensions don't match the scanner's.
+    #[cfg(feature = "image-io")]
+    pub fn scan_image(&mut self, image: &DynamicImage) -> Result<Vec<TopCode>, TopCodeError> {
+        if (image.width() as usize, image.height() as usize) != (self.width, self.height) {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        match image {
+            DynamicImage::ImageLuma8(gray) => self.scan_gray_u8(gray.as_raw()),
+            DynamicImage::ImageRgb8(rgb) => self.scan_rgb_u8(rgb.as_raw()),
+            DynamicImage::ImageRgba8(rgba) => self.scan_rgba_u8(rgba.as_raw()),
+            DynamicImage::ImageLuma16(gray) => self.scan_gray_u16(gray.as_raw(), 16),
+            DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+                self.scan_gray_f32(image.to_luma32f().as_raw())
+            }
+            _ => self.scan_gray_u16(image.to_luma16().as_raw(), 16),
+        }
+    }
+
+    /// Scan an ndarray image, which may be a height x width grayscale view or a height x width x
+    /// channels view with 1 (gray), 3 (RGB) or 4 (RGBA) channels. Views with arbitrary strides,
+    /// such as slices and transposes, are scanned without copying. Fails if the view has any
+    /// other shape or its dimensions don't match the scanner's.
+    #[cfg(feature = "ndarray")]
+    pub fn scan_ndarray<D: Dimension>(
+        &mut self,
+        image: ArrayView<u8, D>,
+    ) -> Result<Vec<TopCode>, TopCodeError> {
+        let shape = image.shape();
+        if shape.len() < 2 || shape[..2] != [self.height, self.width] {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        let width = self.width;
+        let candidates = if let Ok(gray) = image.view().into_dimensionality::<Ix2>() {
+            self.threshold_luma(
+                &gray,
+                |gray, index| gray[[index / width, index % width]] as u32,
+                MAX_LUMA_U8,
+            )
+        } else if let Ok(color) = image.into_dimensionality::<Ix3>() {
+            match color.shape()[2] {
+                1 => self.threshold_luma(
+                    &color,
+                    |color, index| color[[index / width, index % width, 0]] as u32,
+                    MAX_LUMA_U8,
+                ),
+                3 | 4 => self.threshold(&color, |color, index| {
+                    let (y, x) = (index / width, index % width);
+                    (
+                        color[[y, x, 0]] as u32,
+                        color[[y, x, 1]] as u32,
+                        color[[y, x, 2]] as u32,
+                    )
+                }),
+                _ => return Err(TopCodeError::IncorrectBufferSize),
+            }
+        } else {
+            return Err(TopCodeError::IncorrectBufferSize);
+        };
+        Ok(self.find_codes(&candidates))
+    }
+
+    /// Scan an OpenCV matrix in place, following its row step, so that submatrices (e.g. regions
+    /// of interest) are scanned without copying them. `CV_8UC1` matrices are scanned as grayscale,
+    /// and `CV_8UC3` and `CV_8UC4` matrices as BGR and BGRA, OpenCV's default channel orders.
+    /// Fails if the matrix holds any other type of pixel, or its dimensions don't match the
+    /// scanner's.
+    #[cfg(feature = "opencv")]
+    pub fn scan_mat(&mut self, mat: &impl MatTraitConst) -> Result<Vec<TopCode>, TopCodeError> {
+        let bytes_per_pixel = match mat.typ() {
+            CV_8UC1 => 1,
+            CV_8UC3 => 3,
+            CV_8UC4 => 4,
+            _ => return Err(TopCodeError::UnsupportedPixelFormat),
+        };
+        if (mat.rows(), mat.cols()) != (self.height as i32, self.width as i32)
+            || mat.data().is_null()
+        {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        // Number of bytes between the starts of consecutive rows.
+        let step = mat.mat_step()[0];
+        let len = step * (self.height - 1) + self.width * bytes_per_pixel;
+        // SAFETY: the matrix is two-dimensional with `height` rows `step` bytes apart, each holding
+        // `width` pixels of `bytes_per_pixel` bytes, so the `len` bytes from the start of its
+        // first row are all part of its allocation, and live as long as the borrow of `mat`.
+        let data = unsafe { core::slice::from_raw_parts(mat.data(), len) };
+        self.scan_layout(
+            data,
+            ScanLayout::new(step, bytes_per_pixel),
+            |pixel| match pixel {
+                [v] => (*v as u32, *v as u32, *v as u32),
+                [b, g, r, ..] => (*r as u32, *g as u32, *b as u32),
+                _ => unreachable!(),
+            },
+        )
+    }
+
+    /// Scan a YUV 4:2:0 camera frame using only its luma (Y) plane, so no color conversion is
+    /// needed. This works for planar (I420, YV12) and semi-planar (NV12, NV21) frames, as all of
+    /// them start with the Y plane, which is all that is read. `y_stride` is the number of bytes
+    /// between the starts of consecutive rows of the Y plane, which may be larger than the
+    /// width. A Y plane stored separately from the chroma planes can be passed on its own. Fails
+    /// if `y_stride` is smaller than the width or the frame is too short to hold the Y plane.
+    pub fn scan_yuv420(
+        &mut self,
+        frame: &[u8],
+        y_stride: usize,
+    ) -> Result<Vec<TopCode>, TopCodeError> {
+        let y_plane = y_stride
+            .checked_mul(self.height - 1)
+            .map(|len| len + self.width);
+        if y_stride < self.width || y_plane.is_none_or(|len| frame.len() < len) {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        let width = self.width;
+        let candidates = self.threshold_luma(
+            frame,
+            |buffer, index| buffer[index / width * y_stride + index % width] as u32,
+            MAX_LUMA_U8,
+        );
+        Ok(self.find_codes(&candidates))
+    }
+
+    /// Scan a grayscale image with up to 16 bits per pixel, such as the frames of scientific and
+    /// machine vision cameras. Intensities range from 0 to `2^bit_depth - 1` and larger values
+    /// are clamped. The full precision is used for thresholding, so codes can be found in dark or
+    /// low contrast frames whose 8-bit conversion would lose them. Fails if `bit_depth` is not
+    /// between 1 and 16, or the buffer does not hold exactly `width * height` pixels.
+    pub fn scan_gray_u16(
+        &mut self,
+        image_buffer: &[u16],
+        bit_depth: u32,
+    ) -> Result<Vec<TopCode>, TopCodeError> {
+        if !(1..=16).contains(&bit_depth) {
+            return Err(TopCodeError::UnsupportedBitDepth(bit_depth));
+        }
+        if image_buffer.len() != self.pixel_count() {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        let max = (1 << bit_depth) - 1;
+        let candidates = self.threshold_luma(
+            image_buffer,
+            |buffer, index| (buffer[index] as u32).min(max),
+            max,
+        );
+        Ok(self.find_codes(&candidates))
+    }
+
+    /// Scan a grayscale image of normalized intensities, from 0.0 (black) to 1.0 (white), such as
+    /// HDR frames. Intensities outside of that range are clamped, and they are thresholded with
+    /// 16 bits of precision. Fails if the buffer does not hold exactly `width * height` pixels.
+    pub fn scan_gray_f32(&mut self, image_buffer: &[f32]) -> Result<Vec<TopCode>, TopCodeError> {
+        if image_buffer.len() != self.pixel_count() {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        let candidates = self.threshold_luma(
+            image_buffer,
+            |buffer, index| (buffer[index].clamp(0.0, 1.0) * MAX_LUMA_U16 as f32).round() as u32,
+            MAX_LUMA_U16,
+        );
+        Ok(self.find_codes(&candidates))
+    }
+
+    /// Scan an image with padded rows in place. `decode_rgb` is given the bytes of each pixel.
+    /// Fails if a row of pixels doesn't fit in the stride, or the buffer is too short to hold the
+    /// last row. The padding after the last row may be left out.
+    pub fn scan_layout(
+        &mut self,
+        image_buffer: &[u8],
+        layout: ScanLayout,
+        decode_rgb: impl Fn(&[u8]) -> (u32, u32, u32),
+    ) -> Result<Vec<TopCode>, TopCodeError> {
+        let ScanLayout {
+            stride,
+            bytes_per_pixel,
+        } = layout;
+        let row = self
+            .width
+            .checked_mul(bytes_per_pixel)
+            .filter(|&row| row > 0 && row <= stride);
+        let len = row.and_then(|row| stride.checked_mul(self.height - 1)?.checked_add(row));
+        if len.is_none_or(|len| image_buffer.len() < len) {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        let width = self.width;
+        Ok(self.scan(image_buffer, |buffer, index| {
+            let offset = index / width * stride + index % width * bytes_per_pixel;
+            decode_rgb(&buffer[offset..offset + bytes_per_pixel])
+        }))
+    }
+
+    /// Scan an image of packed 8-bit pixels, taking the red, green and blue channels from the
+    /// given byte offsets within each pixel.
+    fn scan_u8(
+        &mut self,
+        image_buffer: &[u8],
+        bytes_per_pixel: usize,
+        [r, g, b]: [usize; 3],
+    ) -> Result<Vec<TopCode>, TopCodeError> {
+        if image_buffer.len() != self.pixel_count() * bytes_per_pixel {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        let candidates = self.threshold_u8(image_buffer, bytes_per_pixel, [r, g, b]);
+        Ok(self.find_codes(&candidates))
+    }
+
+    /// Scan the next frame of a mostly static scene, reusing `prev_codes` (the result of scanning
+    /// the previous frame with this scanner) wherever the thresholded image didn't change.
+    ///
+    /// The whole frame is thresholded, and the thresholded pixels are compared with those of the
+    /// previous frame in bands of 8 rows. Previous codes which only cover unchanged bands are kept
+    /// as is, and only candidates in changed bands (or bands covered by a code which changed) are
+    /// decoded. This greatly reduces decoding cost when only a few codes move between frames.
+    pub fn scan_diff<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+        prev_codes: &[TopCode],
+    ) -> Vec<TopCode> {
+        let previous = self.band_hashes();
+        let candidates = self.threshold(image_buffer, decode_rgb);
+        let mut dirty: Vec<bool> = previous
+            .iter()
+            .zip(self.band_hashes())
+            .map(|(previous, current)| *previous != current)
+            .collect();
+
+        let mut kept = Vec::with_capacity(prev_codes.len());
+        for code in prev_codes {
+            let mut code = *code;
+            let (x, y) = self.to_image_position(code.x, code.y);
+            code.set_location(x, y);
+
+            let bands = self.code_bands(&code);
+            if dirty[bands.clone()].iter().any(|dirty| *dirty) {
+                // Candidates belonging to the changed code may lie in unchanged bands.
+                dirty[bands].iter_mut().for_each(|dirty| *dirty = true);
+            } else {
+                kept.push(code);
+            }
+        }
+
+        let candidates = candidates.iter().filter(|c| dirty[c.y / DIFF_BAND_HEIGHT]);
+        let mut spots = kept;
+        self.decode_polarities(candidates, &mut spots);
+        self.undistort(&mut spots);
+        spots
+    }
+
+    /// Hashes of the thresholded pixels in each band of rows.
+    fn band_hashes(&self) -> Vec<u64> {
+        if self.width == 0 {
+            return Vec::new();
+        }
+
+        let band_pixels = self.width * DIFF_BAND_HEIGHT;
+        (0..self.pixel_count())
+            .step_by(band_pixels)
+            .map(|start| {
+                // FNV-1a over the binary pixels
+                let end = (start + band_pixels).min(self.pixel_count());
+                (start..end).fold(0xcbf29ce484222325, |hash, index| {
+                    (hash ^ self.bit(index) as u64).wrapping_mul(0x100000001b3)
+                })
+            })
+            .collect()
+    }
+
+    /// The range of row bands covered by a code.
+    fn code_bands(&self, code: &TopCode) -> Range<usize> {
+        let bands = self.height.div_ceil(DIFF_BAND_HEIGHT);
+        let top = (code.y - code.radius()).max(0.0) as usize / DIFF_BAND_HEIGHT;
+        let bottom = (code.y + code.radius()).max(0.0) as usize / DIFF_BAND_HEIGHT + 1;
+        top.min(bands)..bottom.min(bands)
+    }
+
+    /// Same as [Scanner::scan] for byte buffers with `bytes_per_pixel` bytes per pixel, except that
+    /// a buffer which is too short (e.g. due to a capture glitch) doesn't cause a panic. Any
+    /// missing trailing pixels are treated as black instead. Returns the TopCodes found along with
+    /// the number of pixels which had to be padded.
+    pub fn scan_padded(
+        &mut self,
+        image_buffer: &[u8],
+        bytes_per_pixel: usize,
+        decode_rgb: impl Fn(&[u8], usize) -> (u32, u32, u32),
+    ) -> (Vec<TopCode>, usize) {
+        let available = image_buffer.len() / bytes_per_pixel.max(1);
+        let padded = (self.width * self.height).saturating_sub(available);
+        let codes = self.scan(image_buffer, |buffer, index| {
+            if index < available {
+                decode_rgb(buffer, index)
+            } else {
+                (0, 0, 0)
+            }
+        });
+        (codes, padded)
+    }
+
+    /// Threshold the image and return the raw candidate TopCode centers, without decoding them.
+    /// This is useful for building alternative decoders on top of the candidate detector. The
+    /// candidates have not been deduplicated or validated, so a single TopCode usually produces
+    /// several candidates and some candidates may not be TopCodes at all.
+    pub fn candidates_only<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+    ) -> Vec<Candidate> {
+        self.threshold(image_buffer, decode_rgb)
+    }
+
+    /// Thresholds the image and ranks the codes that the symbol around (x, y) could hold, for
+    /// marginal detections where the single best reading may be wrong. Returns up to `k` codes
+    /// with the fraction (0 to 1) of the symbol's data ring which agrees with them, best first.
+    /// The result is empty if no symbol could be located around (x, y).
+    pub fn decode_hypotheses<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+        x: usize,
+        y: usize,
+        k: usize,
+    ) -> Vec<(Code, f32)> {
+        self.threshold(image_buffer, decode_rgb);
+        TopCode::default().hypotheses(self, x, y, k)
+    }
+
+    /// Runs only the thresholding pass over the image, without looking for candidates or decoding
+    /// any codes. Use [Scanner::threshold_mask] to read the resulting binary image.
+    pub fn threshold_only<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+    ) {
+        self.binarize(
+            image_buffer,
+            |buffer, index| average(decode_rgb(buffer, index)),
+            MAX_LUMA_U8,
+            &mut Vec::new(),
+            false,
+        );
+    }
+
+    /// The binary image produced by the last scan, in row-major order, with 0 for black pixels
+    /// and 255 for white pixels.
+    pub fn threshold_mask(&self) -> Vec<u8> {
+        (0..self.pixel_count())
+            .map(|index| (self.bit(index) * 0xff) as u8)
+            .collect()
+    }
+
+    /// Scan a large image in two stages: candidates are first found by thresholding a copy of the
+    /// image downsampled by `factor`, and then the image is rescanned at full resolution in a
+    /// small window around each of them to decode the codes. Only the pixels in those windows are
+    /// thresholded at full resolution, so this is much faster than [Scanner::scan] for high
+    /// resolution images (e.g. 4K video) with few codes.
+    ///
+    /// Candidates which already decode in the downsampled image are rescanned in a window sized to
+    /// their code, and the others in a window sized to the unit estimated from their bullseye, so
+    /// codes only need to be large enough for their bullseye to survive downsampling rather than
+    /// to be decoded at low resolution. Candidates inside an earlier window are not rescanned
+    /// again.
+    ///
+    /// Unlike [Scanner::scan], this does not update the scanner's thresholded pixel data.
+    pub fn scan_downsampled<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+        factor: usize,
+    ) -> Vec<TopCode> {
+        if factor <= 1 {
+            return self.scan(image_buffer, decode_rgb);
+        }
+
+        let width = self.width;
+        let offset = factor / 2;
+        let small_width = self.width / factor;
+        let source = |index| {
+            let x = (index % small_width) * factor + offset;
+            let y = (index / small_width) * factor + offset;
+            y * width + x
+        };
+        let mut small = self.resized(small_width, self.height / factor, source);
+        small.max_unit = self.max_unit.div_ceil(factor);
+        let candidates = small.threshold(image_buffer, |buffer, index| {
+            decode_rgb(buffer, source(index))
+        });
+        let coarse = small.find_codes(&candidates);
+
+        // Windows to rescan as (cx, cy, half) in image coordinates, leaving a margin around each
+        // code for the running sum to settle.
+        let to_image = |x: Real| x * factor as Real + offset as Real;
+        let window = |cx: Real, cy: Real, unit: Real| (cx, cy, 5.0 * unit * factor as Real + 16.0);
+        let mut windows: Vec<(Real, Real, Real)> = coarse
+            .iter()
+            .map(|code| window(to_image(code.x), to_image(code.y), code.unit))
+            .collect();
+        let mut flipped = None;
+        for c in &candidates {
+            let (cx, cy) = (to_image(c.x as Real), to_image(c.y as Real));
+            let covered = windows
+                .iter()
+                .any(|&(x, y, half)| (cx - x).abs() <= half && (cy - y).abs() <= half);
+            if covered {
+                continue;
+            }
+            let scanner = if c.inverted {
+                &*flipped.get_or_insert_with(|| {
+                    let mut flipped = small.clone();
+                    flipped.invert_binary();
+                    flipped
+                })
+            } else {
+                &small
+            };
+            let mut spot = TopCode::default();
+            spot.locate(scanner, c.x, c.y);
+            let unit = if spot.unit < MIN_UNIT as Real {
+                MIN_UNIT as Real
+            } else {
+                spot.unit.min(small.max_unit as Real)
+            };
+            windows.push(window(cx, cy, unit));
+        }
+
+        let mut spots: Vec<TopCode> = Vec::with_capacity(coarse.len());
+        for (cx, cy, half) in windows {
+            for spot in self.scan_around(image_buffer, &decode_rgb, cx, cy, half) {
+                if !self.overlaps(&spots, spot.x as usize, spot.y as usize) {
+                    spots.push(spot);
+                }
+            }
+        }
+
+        spots
+    }
+
+    /// Scan the image at `levels` scales, each downsampled by a factor of 2 from the one before,
+    /// starting with the full resolution image. Every level looks for codes of up to the maximum
+    /// code diameter (see [Scanner::set_max_code_diameter]) at its own scale, so a pyramid finds
+    /// codes up to `2^(levels - 1)` times larger than [Scanner::scan] does, along with the small
+    /// ones. The positions and units of codes found at coarser levels are scaled back to the full
+    /// resolution image, and codes found at several levels are reported once, from the finest
+    /// level that found them.
+    ///
+    /// Only the full resolution level updates the scanner's thresholded pixel data.
+    pub fn scan_pyramid<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+        levels: usize,
+    ) -> Vec<TopCode> {
+        let mut codes = self.scan(image_buffer, &decode_rgb);
+
+        let width = self.width;
+        for level in 1..levels {
+            let factor = 1 << level;
+            let offset = factor / 2;
+            let (small_width, small_height) = (self.width / factor, self.height / factor);
+            if small_width == 0 || small_height == 0 {
+                break;
+            }
+            let source = |index| {
+                let x = (index % small_width) * factor + offset;
+                let y = (index / small_width) * factor + offset;
+                y * width + x
+            };
+            let mut small = self.resized(small_width, small_height, source);
+            small.motion_compensation = self.motion_compensation / factor as Real;
+            let mut found = small.scan(image_buffer, |buffer, index| {
+                decode_rgb(buffer, source(index))
+            });
+            for code in &mut found {
+                code.set_location(
+                    code.x * factor as Real + offset as Real,
+                    code.y * factor as Real + offset as Real,
+                );
+                code.unit *= factor as Real;
+            }
+            self.undistort(&mut found);
+            for code in found {
+                if !codes.iter().any(|other| other.in_bullseye(code.x, code.y)) {
+                    codes.push(code);
+                }
+            }
+        }
+
+        codes
+    }
+
+    /// Scans only the square window extending `half` pixels around (cx, cy), clipped to the image,
+    /// and returns the TopCodes found in it in image coordinates (undistorted if a lens model is
+    /// set). The window is thresholded on its own, and the scanner's thresholded pixel data is
+    /// left untouched.
+    pub(crate) fn scan_around<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: &impl Fn(&T, usize) -> (u32, u32, u32),
+        cx: Real,
+        cy: Real,
+        half: Real,
+    ) -> Vec<TopCode> {
+        let x0 = (cx - half).max(0.0) as usize;
+        let y0 = (cy - half).max(0.0) as usize;
+        let x1 = (cx + half).max(0.0) as usize;
+        let y1 = (cy + half).max(0.0) as usize;
+        self.scan_window(image_buffer, decode_rgb, x0..x1, y0..y1)
+    }
+
+    /// Same as [Scanner::scan_around], for the window spanning `columns` and `rows`.
+    pub(crate) fn scan_window<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: &impl Fn(&T, usize) -> (u32, u32, u32),
+        columns: Range<usize>,
+        rows: Range<usize>,
+    ) -> Vec<TopCode> {
+        let width = self.width;
+        let (x0, y0) = (columns.start, rows.start);
+        let x1 = columns.end.min(self.width);
+        let y1 = rows.end.min(self.height);
+        if x0 >= x1 || y0 >= y1 {
+            return Vec::new();
+        }
+
+        let window_width = x1 - x0;
+        let source = |index| (y0 + index / window_width) * width + x0 + index % window_width;
+        let mut window = self.resized(window_width, y1 - y0, source);
+        let mut spots = window.scan(image_buffer, |buffer, index| {
+            decode_rgb(buffer, source(index))
+        });
+        for spot in &mut spots {
+            spot.set_location(spot.x + x0 as Real, spot.y + y0 as Real);
+        }
+        self.undistort(&mut spots);
+        spots
+    }
+
+    /// Creates a scanner with the same settings as this one, but for different image dimensions.
+    /// `source` maps each pixel index of the new image to the index of the pixel it is taken from
+    /// in this scanner's image.
+    fn resized(&self, width: usize, height: usize, source: impl Fn(usize) -> usize) -> Scanner {
+        Scanner {
+            width,
+            height,
+            data: vec![0; Scanner::buffer_len(width, height)],
+            max_unit: self.max_unit,
+            sector_supersamples: self.sector_supersamples,
+            source_label: self.source_label.clone(),
+            prefer_inner: self.prefer_inner,
+            local_contrast_window: self.local_contrast_window,
+            threshold_bands: self.threshold_bands,
+            // The lens model and region of interest apply to this scanner's image as a whole.
+            roi: None,
+            exclusions: self.exclusions.as_ref().map(|_| {
+                let mut exclusions = vec![0; Scanner::mask_len(width, height)];
+                for i in (0..width * height).filter(|&i| self.is_excluded(source(i))) {
+                    exclusions[i / 32] |= 1 << (i % 32);
+                }
+                exclusions
+            }),
+            motion_compensation: self.motion_compensation,
+            threshold_bias: self.threshold_bias,
+            white_ring_bounds: self.white_ring_bounds,
+            decode_retries: self.decode_retries,
+            flat_field: self
+                .flat_field
+                .as_ref()
+                .map(|flat_field| (0..width * height).map(|i| flat_field[source(i)]).collect()),
+            bw_majority: self.bw_majority,
+            extended_ring_bits: self.extended_ring_bits,
+            extended_symbology: self.extended_symbology,
+            polarity: self.polarity,
+            mirrored: self.mirrored,
+            lens_model: None,
+        }
+    }
+
+    /// Same as [Scanner::scan] for images with an alpha channel. The alpha channel does not affect
+    /// detection, but pixels which are not fully opaque (alpha below 255) are remembered, so that
+    /// [TopCode::visible_fraction] can report how much of each code was actually visible.
+    pub fn scan_with_alpha<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgba: impl Fn(&T, usize) -> (u32, u32, u32, u32),
+    ) -> Vec<TopCode> {
+        let candidates = self.threshold(image_buffer, |buffer, index| {
+            let (r, g, b, _) = decode_rgba(buffer, index);
+            (r, g, b)
+        });
+        let transparent = Scanner::mask_len(self.width, self.height) * 32;
+        for index in 0..self.pixel_count() {
+            if decode_rgba(image_buffer, self.source_index(index)).3 < 0xff {
+                self.data[(transparent + index) / 32] |= 1 << (index % 32);
+            }
+        }
+        self.find_codes(&candidates)
+    }
+
+    /// Scan each frame in turn and return the TopCodes found in each of them. All frames must match
+    /// the dimensions of the scanner.
+    pub fn scan_batch<T: ?Sized>(
+        &mut self,
+        frames: &[&T],
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+    ) -> Vec<Vec<TopCode>> {
+        self.scan_batch_with_progress(frames, decode_rgb, |_, _| {})
+    }
+
+    /// Same as [Scanner::scan_batch], but invokes `progress` with `(done, total)` after each frame
+    /// has been scanned. The callback runs on the scanning thread, so it should return quickly to
+    /// avoid stalling the batch.
+    pub fn scan_batch_with_progress<T: ?Sized>(
+        &mut self,
+        frames: &[&T],
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+        mut progress: impl FnMut(usize, usize),
+    ) -> Vec<Vec<TopCode>> {
+        let total = frames.len();
+        let mut results = Vec::with_capacity(total);
+
+        for (i, frame) in frames.iter().enumerate() {
+            results.push(self.scan(*frame, &decode_rgb));
+            progress(i + 1, total);
+        }
+
+        results
+    }
+
+    /// An estimate of the smallest diameter (in pixels) at which a TopCode can still be reliably
+    /// decoded. A TopCode is 8 units wide, and every unit needs to cover at least 3 pixels for the
+    /// 3x3 samples taken while decoding to land entirely within a single ring, so this is
+    /// `8 * 3 = 24` pixels. Smaller codes may occasionally be detected, but not dependably.
+    pub fn min_detectable_diameter(&self) -> usize {
+        8 * MIN_UNIT
+    }
+
+    /// The largest diameter (in pixels) of a TopCode identified by the scanner, see
+    /// [Scanner::set_max_code_diameter].
+    pub(crate) fn max_code_diameter(&self) -> usize {
+        8 * self.max_unit
+    }
+
+    /// Sets the maximum allowable diameter (in pixels) for a TopCode identified by the scanner.
+    /// Setting this to a reasonable value for your application will reduce false positives
+    /// (recognizing codes that aren't actually there) and improve performance (because fewer
+    /// candidate codes will be tested). Setting this value to as low as 50 or 60 pixels could be
+    /// advisable for some applications. However, setting the maximum diameter too low will prevent
+    /// valid codes from being recognized.
+    pub fn set_max_code_diameter(&mut self, diameter: usize) {
+        let f = diameter as f64 / 8.0;
+        self.max_unit = f.ceil() as usize;
+    }
+
+    /// When enabled, candidates that fall inside an already detected code are still decoded, and
+    /// if they produce a valid code that is concentric with, but much smaller than, the existing
+    /// one, the smaller code replaces it. This resolves false positives produced by large
+    /// out-of-focus blobs surrounding a real code, at the cost of decoding more candidates.
+    /// Defaults to false.
+    pub fn set_prefer_inner(&mut self, prefer_inner: bool) {
+        self.prefer_inner = prefer_inner;
+    }
+
+    /// Switches thresholding from the fast running sum to a true local mean, computed over a
+    /// `px` x `px` window centered on each pixel. This is more robust for codes on gradients or
+    /// colored panels, where the running sum carries over too much of the surrounding intensity,
+    /// but is slower and needs an extra buffer the size of the image. Setting this to 0 (the
+    /// default) restores the running sum.
+    pub fn set_local_contrast_window(&mut self, px: usize) {
+        self.local_contrast_window = px;
+    }
+
+    /// Splits the thresholding of packed 8-bit images ([Scanner::scan_rgb_u8],
+    /// [Scanner::scan_gray_u8] and the like) into `bands` horizontal bands of rows, which are
+    /// thresholded in parallel with the `rayon` feature. The running sum normally sweeps the whole
+    /// image, so each band seeds it from a pre-pass over the two rows above it instead, which only
+    /// changes a few pixels near the top of each band. Ignored with local contrast thresholding or
+    /// excluded pixels. Defaults to 1.
+    pub fn set_threshold_bands(&mut self, bands: usize) {
+        self.threshold_bands = bands;
+    }
+
+    /// Restricts thresholding and the search for candidates to the rectangle of `width` x
+    /// `height` pixels with its top left corner at (`x`, `y`), clipped to the image. Pixels
+    /// outside of it are left black, so only codes which lie entirely inside the rectangle are
+    /// found, but their positions are still given in image coordinates. Not applied by
+    /// [Scanner::scan_downsampled]. See [Scanner::clear_roi] to scan whole images again.
+    pub fn set_roi(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        let columns = x.min(self.width)..x.saturating_add(width).min(self.width);
+        let rows = y.min(self.height)..y.saturating_add(height).min(self.height);
+        self.roi = Some((columns, rows));
+    }
+
+    /// Removes the region of interest set by [Scanner::set_roi].
+    pub fn clear_roi(&mut self) {
+        self.roi = None;
+    }
+
+    /// Sets pixels for the scanner to ignore, e.g. reflective fixtures which produce false
+    /// candidates, from a mask with one entry per pixel (in row-major order) which is `true` for
+    /// each excluded pixel. Excluded pixels are left black while thresholding, are left out of
+    /// the running sum and break up bullseye patterns, and candidates centered on them are not
+    /// decoded. While any pixels are excluded, packed 8-bit images are thresholded one pixel at a
+    /// time, without [Scanner::set_threshold_bands] or the `simd` feature. Fails if `mask` does
+    /// not hold exactly one entry per pixel.
+    pub fn set_exclusion_mask(&mut self, mask: &[bool]) -> Result<(), TopCodeError> {
+        if mask.len() != self.pixel_count() {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        let mut exclusions = vec![0; Scanner::mask_len(self.width, self.height)];
+        for (k, _) in mask.iter().enumerate().filter(|(_, excluded)| **excluded) {
+            exclusions[k / 32] |= 1 << (k % 32);
+        }
+        self.exclusions = Some(exclusions);
+        Ok(())
+    }
+
+    /// Excludes the rectangle of `width` x `height` pixels with its top left corner at (`x`, `y`),
+    /// clipped to the image, in addition to any pixels already excluded. See
+    /// [Scanner::set_exclusion_mask].
+    pub fn exclude_rect(&mut self, x: usize, y: usize, width: usize, height: usize) {
+        let columns = x.min(self.width)..x.saturating_add(width).min(self.width);
+        let rows = y.min(self.height)..y.saturating_add(height).min(self.height);
+        let mask_len = Scanner::mask_len(self.width, self.height);
+        let exclusions = self.exclusions.get_or_insert_with(|| vec![0; mask_len]);
+        for j in rows {
+            for k in columns.clone().map(|x| j * self.width + x) {
+                exclusions[k / 32] |= 1 << (k % 32);
+            }
+        }
+    }
+
+    /// Removes all excluded pixels.
+    pub fn clear_exclusions(&mut self) {
+        self.exclusions = None;
+    }
+
+    /// Whether the pixel at `index` is excluded from scanning.
+    fn is_excluded(&self, index: usize) -> bool {
+        self.exclusions
+            .as_ref()
+            .is_some_and(|exclusions| exclusions[index / 32] >> (index % 32) & 0x01 == 1)
+    }
+
+    /// Columns and rows which are thresholded, those of the region of interest if one is set.
+    fn roi_bounds(&self) -> (Range<usize>, Range<usize>) {
+        self.roi.clone().unwrap_or((0..self.width, 0..self.height))
+    }
+
+    /// Shifts every candidate center found while thresholding, and the center of every code
+    /// decoded from them, by `dx` pixels horizontally. Under horizontal motion blur a code is
+    /// smeared along its direction of travel, which skews its detected center towards the middle
+    /// of the smear, so this can be set to counteract a known, consistent blur (e.g. codes on a
+    /// conveyor). Defaults to 0.
+    pub fn set_motion_compensation(&mut self, dx: Real) {
+        self.motion_compensation = dx;
+    }
+
+    /// Sets the fraction of the local average intensity below which a pixel is considered black
+    /// while thresholding. Raising the bias helps with low contrast codes (e.g. grey ink or glare),
+    /// while lowering it suppresses noise in evenly lit scenes. Defaults to 0.975.
+    pub fn set_threshold_bias(&mut self, bias: f64) {
+        self.threshold_bias = bias;
+    }
+
+    pub fn threshold_bias(&self) -> f64 {
+        self.threshold_bias
+    }
+
+    /// Sets the accepted width of the white bullseye crossed by a scanline, relative to the
+    /// combined width of the black ring crossed on either side of it. Standard TopCodes have a
+    /// ratio of 1, and the default bounds of 0.5 and 2.0 accept some distortion around that.
+    /// Widening the bounds allows for codes printed with unusually thin or thick rings, at the
+    /// cost of more false candidates.
+    pub fn set_white_ring_bounds(&mut self, min_factor: f64, max_factor: f64) {
+        self.white_ring_bounds = (min_factor, max_factor);
+    }
+
+    /// Attaches a label (e.g. a file name) describing the input being scanned, so that diagnostics
+    /// collected across a corpus of images can be traced back to the input that produced them.
+    /// The label is carried into the metadata of exported detections by
+    /// `export::FrameMetadata::for_scanner` and prefixes the scanner's log messages with the
+    /// `logging` feature. It has no effect on detection results.
+    pub fn with_source_label(&mut self, label: &str) {
+        self.source_label = Some(label.to_owned());
+    }
+
+    /// The label attached with [Scanner::with_source_label], if any.
+    pub fn source_label(&self) -> Option<&str> {
+        self.source_label.as_deref()
+    }
+
+    #[cfg(feature = "logging")]
+    fn log_source(&self) -> LogSource<'_> {
+        LogSource(self.source_label.as_deref())
+    }
+
+    /// Sets the number of points sampled along the arc of each data sector when decoding. The
+    /// median of the samples is used as the sector's value, so a single noisy sample can no longer
+    /// flip a bit. Higher values are more robust against grainy images, at the cost of decoding
+    /// time. Defaults to 1 (a single sample per sector), and values below 1 are treated as 1.
+    pub fn set_sector_supersamples(&mut self, n: usize) {
+        self.sector_supersamples = n.max(1);
+    }
+
+    /// Sets per-pixel factors (in row-major order) which each pixel's intensity is multiplied by
+    /// before thresholding, e.g. the inverse of a camera's vignetting or of a fixed uneven
+    /// illumination. Corrected intensities are clamped to 255. Fails if `correction` does not hold
+    /// exactly one factor per pixel.
+    pub fn set_flat_field(&mut self, correction: Vec<f32>) -> Result<(), TopCodeError> {
+        if correction.len() != self.pixel_count() {
+            return Err(TopCodeError::IncorrectBufferSize);
+        }
+        self.flat_field = Some(correction);
+        Ok(())
+    }
+
+    /// Sets how many of the 9 pixels around a point must be white for the point to be sampled as
+    /// white while measuring a symbol's rings. Defaults to a simple majority of 5. Lower values
+    /// keep thin white rings of small symbols from being eroded by the surrounding black pixels,
+    /// but make stray white pixels in black rings (e.g. from noise or glare) more likely to break
+    /// a ring. Values are clamped to the range 1 to 9.
+    pub fn set_bw_majority(&mut self, threshold: u32) {
+        self.bw_majority = threshold.clamp(1, 9);
+    }
+
+    /// Sets how far (in pixels) from a candidate's center the scanner retries decoding when the
+    /// candidate fails to decode, e.g. 1 retries the 3x3 neighborhood and 2 the 5x5 neighborhood.
+    /// Closer centers are tried first and the first valid code is kept. This helps when the
+    /// candidate's center lands just off the bullseye. Defaults to 0 (no retries).
+    pub fn set_decode_retries(&mut self, n: usize) {
+        self.decode_retries = n;
+    }
+
+    /// Reads `bits` (up to 32) extra bits from an extended ring just outside the data ring of each
+    /// decoded code, for custom marker schemes which carry more data than the 13-bit code. The
+    /// ring spans 4 to 5 units from the center and is split into `bits` equal arcs starting at the
+    /// first data sector, each holding one bit (white is 1). The bits are available through
+    /// [TopCode::extended_data]. Defaults to 0, which skips the extended ring.
+    pub fn set_read_extended_ring(&mut self, bits: usize) {
+        self.extended_ring_bits = bits.min(32);
+    }
+
+    /// Enables the extended symbology, whose symbols carry a second data ring of 13 sectors just
+    /// outside the standard data ring, lined up with its sectors. Exactly five of the second
+    /// ring's sectors must be white, which gives 1287 patterns per code and 127413 distinct IDs
+    /// overall (see [crate::codes::extended_from_id]). The pattern of each decoded code is
+    /// available through [TopCode::second_ring] and its ID through [TopCode::extended_id].
+    /// Standard symbols are still detected, and are reported without a second ring. Disabled by
+    /// default, so standard scanning is unaffected.
+    pub fn set_extended_symbology(&mut self, enabled: bool) {
+        self.extended_symbology = enabled;
+    }
+
+    /// Sets which codes are detected by the colors of their ink and background. Inverted codes are
+    /// light symbols on a dark background, as when codes are printed in white on dark material or
+    /// shown on a dark screen. With [Polarity::Inverted] the thresholded image is inverted, so
+    /// [Scanner::threshold_mask] and [Scanner::export_state] report light pixels as black. With
+    /// [Polarity::Both] the image is searched for bullseyes of both polarities in a single pass,
+    /// and each candidate is decoded with its own polarity. Defaults to [Polarity::Normal].
+    pub fn set_polarity(&mut self, polarity: Polarity) {
+        self.polarity = polarity;
+    }
+
+    /// Sets whether images are horizontally mirrored, as the frames of front-facing cameras usually
+    /// are. Mirrored images are read from right to left, so codes are decoded and reported as if
+    /// the image had been flipped back: `x` is measured from the left edge of the unmirrored image
+    /// and the sectors of each code are read in their printed order, giving the true code and
+    /// orientation. Flat-field factors and transparency still refer to pixels of the image as
+    /// delivered. Disabled by default.
+    pub fn set_mirrored(&mut self, mirrored: bool) {
+        self.mirrored = mirrored;
+    }
+
+    /// Corrects the positions of detected codes for the distortion of the camera's lens, so that
+    /// `x` and `y` of every code found by this scanner are reported as if the lens had no
+    /// distortion. The `unit` and `orientation` of codes are measured in the distorted image and
+    /// left as is, which approximately preserves their orientation, as distortion varies slowly
+    /// across a single code. Codes passed back to the scanner (e.g. to [Scanner::scan_diff]) are
+    /// mapped back into the image automatically. By default, no correction is applied.
+    pub fn set_lens_model(&mut self, model: LensModel) {
+        self.lens_model = Some(model);
+    }
+
+    /// The scanner's current settings.
+    pub fn config(&self) -> ScannerConfig {
+        ScannerConfig {
+            max_code_diameter: self.max_code_diameter(),
+            sector_supersamples: self.sector_supersamples,
+            prefer_inner: self.prefer_inner,
+            local_contrast_window: self.local_contrast_window,
+            threshold_bands: self.threshold_bands,
+            roi: self
+                .roi
+                .as_ref()
+                .map(|(columns, rows)| (columns.start, rows.start, columns.len(), rows.len())),
+            motion_compensation: self.motion_compensation,
+            threshold_bias: self.threshold_bias,
+            white_ring_bounds: self.white_ring_bounds,
+            decode_retries: self.decode_retries,
+            bw_majority: self.bw_majority,
+            extended_ring_bits: self.extended_ring_bits,
+            extended_symbology: self.extended_symbology,
+            polarity: self.polarity,
+            mirrored: self.mirrored,
+            lens_model: self.lens_model,
+        }
+    }
+
+    /// Applies the settings of `config`, e.g. as stored from another scanner's
+    /// [Scanner::config]. Values are clamped as by the individual setters, and the region of
+    /// interest is clipped to this scanner's image.
+    pub fn set_config(&mut self, config: &ScannerConfig) {
+        self.set_max_code_diameter(config.max_code_diameter);
+        self.set_sector_supersamples(config.sector_supersamples);
+        self.set_prefer_inner(config.prefer_inner);
+        self.set_local_contrast_window(config.local_contrast_window);
+        self.set_threshold_bands(config.threshold_bands);
+        match config.roi {
+            Some((x, y, width, height)) => self.set_roi(x, y, width, height),
+            None => self.clear_roi(),
+        }
+        self.set_motion_compensation(config.motion_compensation);
+        self.set_threshold_bias(config.threshold_bias);
+        let (min_factor, max_factor) = config.white_ring_bounds;
+        self.set_white_ring_bounds(min_factor, max_factor);
+        self.set_decode_retries(config.decode_retries);
+        self.set_bw_majority(config.bw_majority);
+        self.set_read_extended_ring(config.extended_ring_bits);
+        self.set_extended_symbology(config.extended_symbology);
+        self.set_polarity(config.polarity);
+        self.set_mirrored(config.mirrored);
+        self.lens_model = config.lens_model;
+    }
+
+    /// Removes the lens model, returning it.
+    pub(crate) fn take_lens_model(&mut self) -> Option<LensModel> {
+        self.lens_model.take()
+    }
+
+    pub(crate) fn extended_ring_bits(&self) -> usize {
+        self.extended_ring_bits
+    }
+
+    pub(crate) fn extended_symbology(&self) -> bool {
+        self.extended_symbology
+    }
+
+    pub(crate) fn sector_supersamples(&self) -> usize {
+        self.sector_supersamples
+    }
+
+    /// Whether the pixel at (x, y) was fully opaque in the last image scanned. Only images scanned
+    /// with [Scanner::scan_with_alpha] can contain transparent pixels.
+    pub(crate) fn is_opaque(&self, x: usize, y: usize) -> bool {
+        let transparent = Scanner::mask_len(self.width, self.height) * 32;
+        x < self.width && y < self.height && self.bit(transparent + y * self.width + x) == 0
+    }
+
+    /// Binary pixel (0 for black, 1 for white) at `index`, or the bit at `index` of the words of
+    /// pixel data in general.
+    fn bit(&self, index: usize) -> u32 {
+        self.data[index / 32] >> (index % 32) & 0x01
+    }
+
+    /// Number of white pixels among the 3 consecutive pixels in a row centered on (x, y).
+    fn row_whites(&self, x: usize, y: usize) -> u32 {
+        let start = y * self.width + x - 1;
+        let (word, shift) = (start / 32, start % 32);
+        invariant!(start + 2 < self.pixel_count(), "sample out of bounds");
+        let mut bits = (self.data[word] as u64) >> shift;
+        if shift > 29 {
+            bits |= (self.data[word + 1] as u64) << (32 - shift);
+        }
+        (bits & 0b111).count_ones()
+    }
+
+    /// Average of thresholded pixels in a 3x3 region around (x, y). Returned value is between 0
+    /// (black) and 255 (white).
+    pub(crate) fn get_sample_3x3(&self, x: usize, y: usize) -> usize {
+        if x < 1 || x + 1 >= self.width || y < 1 || y + 1 >= self.height {
+            return 0;
+        }
+
+        let whites: u32 = (y - 1..=y + 1).map(|j| self.row_whites(x, j)).sum();
+        (0xff * whites / 9) as usize
+    }
+
+    /// Average of thresholded pixels in a 3x3 region around (x, y). Returned value is either 0
+    /// (black) or 1 (white).
+    pub(crate) fn get_bw_3x3(&self, x: usize, y: usize) -> u32 {
+        if x < 1 || x + 1 >= self.width || y < 1 || y + 1 >= self.height {
+            return 0;
+        }
+
+        let whites: u32 = (y - 1..=y + 1).map(|j| self.row_whites(x, j)).sum();
+        if whites >= self.bw_majority {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Perform Wellner adaptive thresholding to produce binary pixel data. Also mark candidate
+    /// SpotCode locations.
+    ///
+    /// "Adaptive Thresholding for the DigitalDesk"
+    /// EuroPARC Technical Report EPC-93-110
+    fn threshold<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+    ) -> Vec<Candidate> {
+        self.threshold_luma(
+            image_buffer,
+            |buffer, index| average(decode_rgb(buffer, index)),
+            MAX_LUMA_U8,
+        )
+    }
+
+    /// Same as [Scanner::threshold], for images whose pixel intensities (0-`max_luma`) are
+    /// decoded directly by `decode_luma`. `max_luma` may be at most [MAX_LUMA_U16].
+    fn threshold_luma<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_luma: impl Fn(&T, usize) -> u32,
+        max_luma: u32,
+    ) -> Vec<Candidate> {
+        let mut candidates = Vec::with_capacity(50);
+        self.binarize(image_buffer, decode_luma, max_luma, &mut candidates, true);
+        scan_log!(
+            debug,
+            target: "topcodes::threshold",
+            "{}found {} candidates in {}x{} image",
+            self.log_source(),
+            candidates.len(),
+            self.width,
+            self.height
+        );
+        candidates
+    }
+
+    /// Same as [Scanner::threshold], for packed 8-bit pixels of `bytes_per_pixel` bytes with
+    /// their red, green and blue channels at the offsets `[r, g, b]`. Unless local contrast
+    /// thresholding is enabled or pixels are excluded, the image is thresholded in the bands set by
+    /// [Scanner::set_threshold_bands], or with the `simd` feature in a single band with rows
+    /// thresholded 16 pixels at a time.
+    fn threshold_u8(
+        &mut self,
+        image_buffer: &[u8],
+        bytes_per_pixel: usize,
+        [r, g, b]: [usize; 3],
+    ) -> Vec<Candidate> {
+        if self.local_contrast_window == 0
+            && self.exclusions.is_none()
+            && (cfg!(feature = "simd") || self.threshold_bands > 1)
+        {
+            let candidates = self.binarize_bands(image_buffer, bytes_per_pixel, [r, g, b]);
+            scan_log!(
+                debug,
+                target: "topcodes::threshold",
+                "{}found {} candidates in {}x{} image",
+                self.log_source(),
+                candidates.len(),
+                self.width,
+                self.height
+            );
+            return candidates;
+        }
+        self.threshold(image_buffer, |buffer, index| {
+            let pixel = index * bytes_per_pixel;
+            (
+                buffer[pixel + r] as u32,
+                buffer[pixel + g] as u32,
+                buffer[pixel + b] as u32,
+            )
+        })
+    }
+
+    /// Same as [Scanner::binarize] for packed 8-bit pixels, with the image split into
+    /// [Scanner::set_threshold_bands] bands of rows which are thresholded independently (in
+    /// parallel with the `rayon` feature), and their binary pixels and candidates joined in row
+    /// order.
+    fn binarize_bands(
+        &mut self,
+        image_buffer: &[u8],
+        bytes_per_pixel: usize,
+        channels: [usize; 3],
+    ) -> Vec<Candidate> {
+        let sums = 2 * Scanner::mask_len(self.width, self.height);
+        self.data[..sums].fill(0);
+        let (columns, roi_rows) = self.roi_bounds();
+        if columns.is_empty() || roi_rows.is_empty() {
+            return Vec::new();
+        }
+
+        let count = self.threshold_bands.clamp(1, roi_rows.len());
+        let band_rows = |band: usize| roi_rows.start + band * roi_rows.len() / count;
+        let rows: Vec<_> = (0..count)
+            .map(|band| band_rows(band)..band_rows(band + 1))
+            .collect();
+        let scanner = &*self;
+        let threshold = |rows: &Range<usize>| {
+            scanner.threshold_band(image_buffer, bytes_per_pixel, channels, rows.clone())
+        };
+        #[cfg(feature = "rayon")]
+        let bands: Vec<ThresholdBand> = {
+            use rayon::prelude::*;
+            rows.par_iter().map(threshold).collect()
+        };
+        #[cfg(not(feature = "rayon"))]
+        let bands: Vec<ThresholdBand> = rows.iter().map(threshold).collect();
+
+        let words = self.width.div_ceil(32);
+        let mut candidates = Vec::with_capacity(50);
+        for (band, rows) in bands.into_iter().zip(rows) {
+            for (j, bits) in rows.zip(band.bits.chunks_exact(words)) {
+                self.store_row(j, bits);
+            }
+            candidates.extend(band.candidates);
+            self.data[sums..].copy_from_slice(&band.carry);
+        }
+        candidates
+    }
+
+    /// Thresholds the rows `rows` of a packed 8-bit image on their own. The running sum is seeded
+    /// by a pre-pass over the two rows above the band, which leaves it and the sums carried over
+    /// from the row above nearly as they would be at this point of a sweep over the whole image.
+    fn threshold_band(
+        &self,
+        image_buffer: &[u8],
+        bytes_per_pixel: usize,
+        channels: [usize; 3],
+        rows: Range<usize>,
+    ) -> ThresholdBand {
+        // Unlike in [Scanner::binarize] the running sum is unsigned, which is cheaper to divide.
+        let mut sum: u32 = 128;
+        let words = self.width.div_ceil(32);
+        let mut luma = vec![0; self.width];
+        let mut limits = vec![0; self.width];
+        let mut carry = vec![0; self.width];
+        let mut bits = vec![0; words * rows.len()];
+        let mut candidates = Vec::new();
+        let ceilings = self.threshold_ceilings();
+        let (columns, roi_rows) = self.roi_bounds();
+
+        for j in rows.start.saturating_sub(2).max(roi_rows.start)..rows.start {
+            self.row_luma(image_buffer, bytes_per_pixel, channels, j, &mut luma);
+            self.sweep_row(j, &luma, &mut sum, &mut carry, &mut limits, &ceilings);
+        }
+
+        for (j, bits) in rows.zip(bits.chunks_exact_mut(words)) {
+            self.row_luma(image_buffer, bytes_per_pixel, channels, j, &mut luma);
+            self.sweep_row(j, &luma, &mut sum, &mut carry, &mut limits, &ceilings);
+
+            #[cfg(feature = "simd")]
+            crate::simd::classify(&luma, &limits, bits);
+            #[cfg(not(feature = "simd"))]
+            for (x, (a, limit)) in luma.iter().zip(&limits).enumerate() {
+                bits[x / 32] |= u32::from(a >= limit) << (x % 32);
+            }
+            if self.polarity == Polarity::Inverted {
+                for word in bits.iter_mut() {
+                    *word = !*word;
+                }
+            }
+            // Pixels outside of the region of interest, and bits past the end of the row, are
+            // black.
+            for (w, word) in bits.iter_mut().enumerate() {
+                let start = columns.start.clamp(w * 32, w * 32 + 32) - w * 32;
+                let end = columns.end.clamp(w * 32, w * 32 + 32) - w * 32;
+                *word &= ((1u64 << end) - (1u64 << start)) as u32;
+            }
+            self.search_bits(j, bits, &mut candidates);
+        }
+
+        ThresholdBand {
+            bits,
+            candidates,
+            carry,
+        }
+    }
+
+    /// Parameters of the compute shader of [GpuThresholder](crate::GpuThresholder) for packed
+    /// 8-bit pixels of `bytes_per_pixel` bytes with their channels at the offsets `[r, g, b]`,
+    /// keeping at most `capacity` candidates.
+    #[cfg(feature = "gpu")]
+    pub(crate) fn gpu_params(
+        &self,
+        bytes_per_pixel: usize,
+        [r, g, b]: [usize; 3],
+        capacity: u32,
+    ) -> [u32; 16] {
+        let polarity = match self.polarity {
+            Polarity::Normal => 0,
+            Polarity::Inverted => 1,
+            Polarity::Both => 2,
+        };
+        let (min_white, max_white) = self.white_ring_bounds;
+        // A no-op with the `f32` feature.
+        #[allow(clippy::unnecessary_cast)]
+        let motion_compensation = self.motion_compensation as f32;
+        [
+            self.width as u32,
+            self.height as u32,
+            bytes_per_pixel as u32,
+            polarity,
+            r as u32,
+            g as u32,
+            b as u32,
+            self.mirrored as u32,
+            self.max_unit as u32,
+            (min_white as f32).to_bits(),
+            (max_white as f32).to_bits(),
+            motion_compensation.to_bits(),
+            capacity,
+            0,
+            0,
+            0,
+        ]
+    }
+
+    /// Replaces the binary pixels with `mask`, laid out like the bitmask, marking every pixel
+    /// as opaque.
+    #[cfg(feature = "gpu")]
+    pub(crate) fn load_mask(&mut self, mask: &[u32]) {
+        let mask_len = Scanner::mask_len(self.width, self.height);
+        self.data[..mask_len].copy_from_slice(mask);
+        self.data[mask_len..2 * mask_len].fill(0);
+    }
+
+    /// Width and height of the images scanned.
+    #[cfg(feature = "gpu")]
+    pub(crate) fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Smallest intensity (0-255) of a white pixel for each threshold of an 8-bit image. A pixel
+    /// is black if its intensity is below `threshold * bias`, i.e. below the ceiling of that
+    /// product. Thresholds are averages of intensities, so they are at most 255.
+    pub(crate) fn threshold_ceilings(&self) -> [u32; MAX_LUMA_U8 as usize + 1] {
+        let bias = self.threshold_bias;
+        core::array::from_fn(|threshold| {
+            (0..=MAX_LUMA_U8)
+                .find(|&a| a as f64 >= threshold as f64 * bias)
+                .unwrap_or(MAX_LUMA_U8 + 1)
+        })
+    }
+
+    /// Computes the intensity (0-255) of each pixel of row `j` of a packed 8-bit image within the
+    /// region of interest into `luma`, 16 pixels at a time with the `simd` feature unless
+    /// flat-field correction or mirroring is enabled.
+    fn row_luma(
+        &self,
+        image_buffer: &[u8],
+        bytes_per_pixel: usize,
+        [r, g, b]: [usize; 3],
+        j: usize,
+        luma: &mut [u32],
+    ) {
+        let (columns, _) = self.roi_bounds();
+        #[cfg(feature = "simd")]
+        if self.flat_field.is_none() && !self.mirrored {
+            let start = (j * self.width + columns.start) * bytes_per_pixel;
+            let row = &image_buffer[start..start + columns.len() * bytes_per_pixel];
+            crate::simd::luma(row, bytes_per_pixel, [r, g, b], &mut luma[columns]);
+            return;
+        }
+        for (x, luma) in luma
+            .iter_mut()
+            .enumerate()
+            .take(columns.end)
+            .skip(columns.start)
+        {
+            let source = self.source_index(j * self.width + x);
+            let pixel = source * bytes_per_pixel;
+            let rgb = (
+                image_buffer[pixel + r] as u32,
+                image_buffer[pixel + g] as u32,
+                image_buffer[pixel + b] as u32,
+            );
+            *luma = self.intensity(average(rgb), source, MAX_LUMA_U8) as u32;
+        }
+    }
+
+    /// Advances the running sum over the region of interest of row `j`, following the same
+    /// serpentine sweep as [Scanner::binarize]. Sets the smallest intensity of each pixel which is
+    /// white in `limits`, using the sums of the row above in `carry`, which are then replaced by
+    /// those of this row.
+    fn sweep_row(
+        &self,
+        j: usize,
+        luma: &[u32],
+        sum: &mut u32,
+        carry: &mut [u32],
+        limits: &mut [u32],
+        ceilings: &[u32; MAX_LUMA_U8 as usize + 1],
+    ) {
+        let s = 32;
+        let (columns, rows) = self.roi_bounds();
+        let pixels = luma[columns.clone()]
+            .iter()
+            .zip(limits[columns.clone()].iter_mut())
+            .zip(carry[columns].iter_mut());
+        let mut update = |((a, limit), carry): ((&u32, &mut u32), &mut u32)| {
+            *sum = *sum + *a - *sum / s;
+            let threshold = if j > rows.start {
+                (*sum + *carry) / (2 * s)
+            } else {
+                *sum / s
+            };
+            *limit = ceilings[(threshold as usize).min(MAX_LUMA_U8 as usize)];
+            *carry = *sum;
+        };
+        if j.is_multiple_of(2) {
+            pixels.for_each(&mut update);
+        } else {
+            pixels.rev().for_each(&mut update);
+        }
+    }
+
+    /// Searches the region of interest of row `j`, whose binary pixels are `bits`, for bullseye
+    /// patterns in the direction of the serpentine sweep, adding the candidates found to
+    /// `candidates`.
+    fn search_bits(&self, j: usize, bits: &[u32], candidates: &mut Vec<Candidate>) {
+        let mut search = RowSearch::new();
+        let mut inverted_search = RowSearch::new();
+        let (columns, _) = self.roi_bounds();
+        let mut i = 0;
+        while i < columns.len() {
+            // Only the first pixel of each run of equal pixels can end a bullseye pattern, so the
+            // rest of the run is skipped a word at a time.
+            let x = if j.is_multiple_of(2) {
+                columns.start + i
+            } else {
+                columns.end - 1 - i
+            };
+            let a = (bits[x / 32] >> (x % 32) & 0x01) as isize;
+            let run = run_length(bits, x, self.width, j.is_multiple_of(2)) as isize;
+            let k = j * self.width + x;
+            if let Some(candidate) = self.search_row(&mut search, a, k, j) {
+                candidates.push(candidate);
+            }
+            search.extend(a, run - 1);
+            if self.polarity == Polarity::Both {
+                if let Some(candidate) = self.search_row(&mut inverted_search, 1 - a, k, j) {
+                    candidates.push(candidate.into_inverted());
+                }
+                inverted_search.extend(1 - a, run - 1);
+            }
+            i += run as usize;
+        }
+    }
+
+    /// ORs the binary pixels of row `j`, packed like the bitmask but starting at bit 0 of
+    /// `bits[0]`, into the bitmask. Bits past the end of the row must be clear.
+    fn store_row(&mut self, j: usize, bits: &[u32]) {
+        let start = j * self.width;
+        for (w, &word) in bits.iter().enumerate() {
+            let index = start + w * 32;
+            let (i, shift) = (index / 32, index % 32);
+            self.data[i] |= word << shift;
+            if shift > 0 {
+                self.data[i + 1] |= word >> (32 - shift);
+            }
+        }
+    }
+
+    /// Produces the binary pixel data, only adding candidates to `candidates` if `find_candidates`
+    /// is set.
+    fn binarize<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_luma: impl Fn(&T, usize) -> u32,
+        max_luma: u32,
+        candidates: &mut impl ScanList<Candidate>,
+        find_candidates: bool,
+    ) {
+        invariant!(
+            max_luma <= MAX_LUMA_U16,
+            "running sums overflow for {}",
+            max_luma
+        );
+        let mut sum = 128 * max_luma as isize / MAX_LUMA_U8 as isize;
+        let s = 32;
+
+        // An empty image has no pixels to threshold, and the serpentine start index below would
+        // underflow.
+        if self.width == 0 || self.height == 0 {
+            return;
+        }
+
+        let integral = if self.local_contrast_window > 0 {
+            Some(self.integral_image(image_buffer, &decode_luma, max_luma))
+        } else {
+            None
+        };
+
+        // Pixels are set to white as they are thresholded, and are all opaque until marked
+        // otherwise by [Scanner::scan_with_alpha].
+        let sums = 2 * Scanner::mask_len(self.width, self.height);
+        self.data[..sums].fill(0);
+        let (columns, rows) = self.roi_bounds();
+        if columns.is_empty() {
+            return;
+        }
+
+        for j in rows.clone() {
+            let mut search = RowSearch::new();
+            let mut inverted_search = RowSearch::new();
+
+            for i in 0..columns.len() {
+                let x = if j % 2 == 0 {
+                    columns.start + i
+                } else {
+                    columns.end - 1 - i
+                };
+                let k = j * self.width + x;
+
+                // Excluded pixels stay black, are left out of the running sum and break up
+                // bullseye patterns.
+                if self.is_excluded(k) {
+                    self.data[sums + x] = sum as u32;
+                    search = RowSearch::new();
+                    inverted_search = RowSearch::new();
+                    continue;
+                }
+
+                // Calculate pixel intensity (0-max_luma)
+                let source = self.source_index(k);
+                let mut a = self.intensity(decode_luma(image_buffer, source), source, max_luma);
+
+                // Calculate the average sum as an approximate sum of the last s pixels
+                sum += a - (sum / s);
+
+                // Factor in sum from the previous row
+                let threshold = if let Some(integral) = &integral {
+                    self.local_mean(integral, k % self.width, j)
+                } else if j > rows.start {
+                    (sum + self.data[sums + k % self.width] as isize) / (2 * s)
+                } else {
+                    sum / s
+                };
+
+                // Compare the average sum to current pixel to decide black or white
+                a = if (a as f64) < (threshold as f64 * self.threshold_bias) {
+                    0
+                } else {
+                    1
+                };
+                if self.polarity == Polarity::Inverted {
+                    a = 1 - a;
+                }
+
+                // Store the binary pixel, and carry the running sum over to the next row.
+                invariant!(k / self.width == j, "pixel {} is outside of row {}", k, j);
+                self.data[k / 32] |= (a as u32) << (k % 32);
+                self.data[sums + k % self.width] = sum as u32;
+
+                if find_candidates {
+                    if let Some(candidate) = self.search_row(&mut search, a, k, j) {
+                        candidates.push_item(candidate);
+                    }
+                    if self.polarity == Polarity::Both {
+                        if let Some(candidate) = self.search_row(&mut inverted_search, 1 - a, k, j)
+                        {
+                            candidates.push_item(candidate.into_inverted());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Advances the search for bullseye patterns along row `j` by the binary pixel `a` (0 for
+    /// black) at index `k`, returning a candidate if a bullseye pattern ends at that pixel.
+    fn search_row(
+        &self,
+        search: &mut RowSearch,
+        a: isize,
+        k: usize,
+        j: usize,
+    ) -> Option<Candidate> {
+        let RowSearch { level, b1, b2, w1 } = search;
+        let mut candidate = None;
+        match level {
+            UnitLevel::WhiteRegion => {
+                if a == 0 {
+                    // First black pixel encountered
+                    *level = UnitLevel::BlackRegion;
+                    *b1 = 1;
+                    *w1 = 0;
+                    *b2 = 0;
+                }
+            }
+            UnitLevel::BlackRegion => {
+                if a == 0 {
+                    *b1 += 1;
+                } else {
+                    *level = UnitLevel::WhiteRegionSecond;
+                    *w1 = 1;
+                }
+            }
+            UnitLevel::WhiteRegionSecond => {
+                if a == 0 {
+                    *level = UnitLevel::BlackRegionSecond;
+                    *b2 = 1;
+                } else {
+                    *w1 += 1;
+                }
+            }
+            UnitLevel::BlackRegionSecond => {
+                let max_u = self.max_unit as isize;
+                let (min_white, max_white) = self.white_ring_bounds;
+                let (b1, b2, w1) = (*b1, *b2, *w1);
+                if a == 0 {
+                    search.b2 += 1;
+                } else {
+                    if b1 >= 2
+                        && b2 >= 2
+                        && b1 <= max_u
+                        && b2 <= max_u
+                        && w1 <= (max_u + max_u)
+                        && w1 as f64 >= (b1 + b2) as f64 * min_white
+                        && w1 as f64 <= (b1 + b2) as f64 * max_white
+                        && (b1 - b2).abs() <= b1
+                        && (b1 - b2).abs() <= b2
+                    {
+                        let mut dk: usize = 1 + b2 as usize + (w1 as usize >> 1);
+                        dk = if j.is_multiple_of(2) { k - dk } else { k + dk };
+                        invariant!(dk / self.width == j, "candidate outside of row {}", j);
+
+                        let x = (dk % self.width) as Real + self.motion_compensation;
+                        let x = x.round().clamp(0.0, (self.width - 1) as Real);
+                        candidate = Some(Candidate::new(x as usize, j));
+                    }
+                    search.b1 = b2;
+                    search.w1 = 1;
+                    search.b2 = 0;
+                    search.level = UnitLevel::WhiteRegionSecond;
+                }
+            }
+        }
+        candidate
+    }
+
+    /// Intensity (0-`max_luma`) of the pixel at `index`, after flat-field correction.
+    fn intensity(&self, luma: u32, index: usize, max_luma: u32) -> isize {
+        let intensity = luma as isize;
+        match &self.flat_field {
+            Some(flat_field) => (intensity as f32 * flat_field[index])
+                .round()
+                .clamp(0.0, max_luma as f32) as isize,
+            None => intensity,
+        }
+    }
+
+    /// Index in the image buffer of the pixel at `index` in the scanner's (unmirrored) data.
+    fn source_index(&self, index: usize) -> usize {
+        if self.mirrored {
+            let x = index % self.width;
+            index - x + self.width - 1 - x
+        } else {
+            index
+        }
+    }
+
+    /// Builds a summed-area table of pixel intensities, with a leading row and column of zeros.
+    fn integral_image<T: ?Sized>(
+        &self,
+        image_buffer: &T,
+        decode_luma: &impl Fn(&T, usize) -> u32,
+        max_luma: u32,
+    ) -> Vec<u64> {
+        let stride = self.width + 1;
+        let mut integral = vec![0; stride * (self.height + 1)];
+
+        for j in 0..self.height {
+            let mut row_sum = 0;
+            for i in 0..self.width {
+                let index = self.source_index(j * self.width + i);
+                row_sum += self.intensity(decode_luma(image_buffer, index), index, max_luma) as u64;
+                integral[(j + 1) * stride + i + 1] = integral[j * stride + i + 1] + row_sum;
+            }
+        }
+
+        integral
+    }
+
+    /// Mean intensity of the local contrast window around (x, y), clipped to the image.
+    fn local_mean(&self, integral: &[u64], x: usize, y: usize) -> isize {
+        let half = self.local_contrast_window / 2;
+        let stride = self.width + 1;
+        let x0 = x.saturating_sub(half);
+        let y0 = y.saturating_sub(half);
+        let x1 = (x + half + 1).min(self.width);
+        let y1 = (y + half + 1).min(self.height);
+
+        let sum = integral[y1 * stride + x1] + integral[y0 * stride + x0]
+            - integral[y0 * stride + x1]
+            - integral[y1 * stride + x0];
+        (sum / ((x1 - x0) * (y1 - y0)) as u64) as isize
+    }
+
+    /// Decode the given candidates against the thresholded image, and return all valid TopCodes
+    /// found. Candidates inside the bullseye of an already decoded TopCode are skipped.
+    pub fn find_codes(&self, candidates: &[Candidate]) -> Vec<TopCode> {
+        let mut spots = Vec::with_capacity(candidates.len());
+        self.decode_polarities(candidates.iter(), &mut spots);
+        self.undistort(&mut spots);
+        spots
+    }
+
+    /// Same as [Scanner::scan], but collects the candidates found while thresholding in
+    /// `candidates` and the valid codes in `codes`, both of which are cleared first. Reusing the
+    /// same vectors across frames avoids allocating them for every frame once they have grown
+    /// large enough. Inverted candidates are decoded on the thresholded image flipped in place,
+    /// rather than on a copy.
+    pub fn scan_into<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+        candidates: &mut Vec<Candidate>,
+        codes: &mut Vec<TopCode>,
+    ) {
+        candidates.clear();
+        codes.clear();
+        self.scan_lists(image_buffer, decode_rgb, candidates, codes);
+    }
+
+    /// Same as [Scanner::scan], but without any heap allocations: the candidates found while
+    /// thresholding are collected in `candidates` and the valid codes in `codes`, both of which
+    /// are cleared first. Candidates and codes beyond the capacity of their vectors are dropped,
+    /// so size `candidates` generously (each code usually produces several candidates).
+    /// Combined with [BorrowedScanner::with_buffer], this scans images without allocating, as long
+    /// as the flat-field correction, local contrast thresholding and sector supersampling are left
+    /// disabled.
+    #[cfg(feature = "heapless")]
+    pub fn scan_fixed<T: ?Sized, const C: usize, const N: usize>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+        candidates: &mut heapless::Vec<Candidate, C>,
+        codes: &mut heapless::Vec<TopCode, N>,
+    ) {
+        candidates.clear();
+        codes.clear();
+        self.scan_lists(image_buffer, decode_rgb, candidates, codes);
+    }
+
+    /// Thresholds the image and decodes its candidates into the given (empty) lists, flipping
+    /// the binary pixels in place to decode inverted candidates.
+    fn scan_lists<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+        candidates: &mut impl ScanList<Candidate>,
+        codes: &mut impl ScanList<TopCode>,
+    ) {
+        self.binarize(
+            image_buffer,
+            |buffer, index| average(decode_rgb(buffer, index)),
+            MAX_LUMA_U8,
+            candidates,
+            true,
+        );
+        self.decode_candidates(candidates.iter().filter(|c| !c.inverted), codes);
+        if candidates.iter().any(|c| c.inverted) {
+            self.invert_binary();
+            self.decode_candidates(candidates.iter().filter(|c| c.inverted), codes);
+            self.invert_binary();
+        }
+        self.undistort(codes);
+    }
+
+    /// Decodes candidates which don't overlap any of the given spots, adding valid codes to them.
+    /// Inverted candidates are decoded after the others, on a copy of the thresholded image with
+    /// its binary pixels flipped.
+    fn decode_polarities<'c>(
+        &self,
+        candidates: impl Iterator<Item = &'c Candidate>,
+        spots: &mut Vec<TopCode>,
+    ) {
+        let (inverted, normal): (Vec<&Candidate>, Vec<&Candidate>) =
+            candidates.partition(|c| c.inverted);
+        #[cfg(not(feature = "rayon"))]
+        self.decode_candidates(normal.into_iter(), spots);
+        #[cfg(feature = "rayon")]
+        self.decode_candidates_parallel(normal.into_iter(), spots);
+        if inverted.is_empty() {
+            return;
+        }
+
+        let mut flipped = self.resized(self.width, self.height, |index| index);
+        flipped.data.copy_from_slice(&self.data);
+        flipped.roi = self.roi.clone();
+        flipped.lens_model = self.lens_model;
+        flipped.invert_binary();
+        #[cfg(not(feature = "rayon"))]
+        flipped.decode_candidates(inverted.into_iter(), spots);
+        #[cfg(feature = "rayon")]
+        flipped.decode_candidates_parallel(inverted.into_iter(), spots);
+    }
+
+    /// Flips the binary pixels of the thresholded image, turning black into white and back.
+    fn invert_binary(&mut self) {
+        let mask_len = Scanner::mask_len(self.width, self.height);
+        for word in self.data[..mask_len].iter_mut() {
+            *word = !*word;
+        }
+    }
+
+    /// Removes the lens distortion from the positions of codes found in the image, if a lens
+    /// model is set.
+    fn undistort(&self, spots: &mut [TopCode]) {
+        if let Some(lens) = &self.lens_model {
+            for spot in spots {
+                let (x, y) = lens.undistort(spot.x, spot.y);
+                spot.set_location(x, y);
+            }
+        }
+    }
+
+    /// Maps a position reported by this scanner back to the image, applying the lens distortion
+    /// if a lens model is set.
+    pub(crate) fn to_image_position(&self, x: Real, y: Real) -> (Real, Real) {
+        match &self.lens_model {
+            Some(lens) => lens.distort(x, y),
+            None => (x, y),
+        }
+    }
+
+    /// Maps a position in the image to the undistorted position the scanner reports for it, if a
+    /// lens model is set.
+    pub(crate) fn undistort_position(&self, x: Real, y: Real) -> (Real, Real) {
+        match &self.lens_model {
+            Some(lens) => lens.undistort(x, y),
+            None => (x, y),
+        }
+    }
+
+    /// Decode candidates which don't overlap any of the given spots, adding valid codes to them.
+    fn decode_candidates<'c>(
+        &self,
+        candidates: impl Iterator<Item = &'c Candidate>,
+        spots: &mut impl ScanList<TopCode>,
+    ) {
+        for c in candidates {
+            self.add_candidate(c, || self.decode_candidate(c), spots);
+        }
+
+        scan_log!(
+            debug,
+            target: "topcodes::decode",
+            "{}found {} valid codes",
+            self.log_source(),
+            spots.len()
+        );
+    }
+
+    /// Adds the code decoded from the candidate to the spots, unless it is invalid or the
+    /// candidate is excluded or lies inside one of the spots. `decode` is only called when the
+    /// code is needed.
+    fn add_candidate(
+        &self,
+        c: &Candidate,
+        decode: impl FnOnce() -> TopCode,
+        spots: &mut impl ScanList<TopCode>,
+    ) {
+        if self.is_excluded(c.y * self.width + c.x) {
+            scan_log!(
+                trace,
+                target: "topcodes::decode",
+                "{}candidate at ({}, {}) skipped as it is excluded",
+                self.log_source(),
+                c.x,
+                c.y
+            );
+        } else if !self.overlaps(spots, c.x, c.y) {
+            let spot = decode();
+            scan_log!(
+                trace,
+                target: "topcodes::decode",
+                "{}candidate at ({}, {}) decoded to {:?}",
+                self.log_source(),
+                c.x,
+                c.y,
+                spot.code
+            );
+            if spot.is_valid() {
+                spots.push_item(spot);
+            }
+        } else if self.prefer_inner {
+            let spot = decode();
+            scan_log!(
+                trace,
+                target: "topcodes::decode",
+                "{}candidate at ({}, {}) overlaps a code and decoded to {:?}",
+                self.log_source(),
+                c.x,
+                c.y,
+                spot.code
+            );
+            if spot.is_valid() {
+                Self::replace_outer(spots, spot);
+            }
+        } else {
+            scan_log!(
+                trace,
+                target: "topcodes::decode",
+                "{}candidate at ({}, {}) skipped as it overlaps a code",
+                self.log_source(),
+                c.x,
+                c.y
+            );
+        }
+    }
+
+    /// Same as [Scanner::decode_candidates], but decodes the candidates on rayon's thread pool.
+    /// Candidates are taken in batches of a few per thread: those which don't overlap the spots
+    /// found so far are decoded in parallel, and the results are then added one by one in
+    /// candidate order, so the codes found are exactly those of the serial decoding.
+    #[cfg(feature = "rayon")]
+    fn decode_candidates_parallel<'c>(
+        &self,
+        candidates: impl Iterator<Item = &'c Candidate>,
+        spots: &mut Vec<TopCode>,
+    ) {
+        use rayon::prelude::*;
+
+        let candidates: Vec<&Candidate> = candidates.collect();
+        let batch = rayon::current_num_threads() * PARALLEL_BATCH;
+        for batch in candidates.chunks(batch) {
+            let decoded: Vec<Option<TopCode>> = batch
+                .par_iter()
+                .map(|c| {
+                    // Spots only ever grow without `prefer_inner`, so a candidate overlapping
+                    // them now will still overlap them when it is added.
+                    (self.prefer_inner || !self.overlaps(spots, c.x, c.y))
+                        .then(|| self.decode_candidate(c))
+                })
+                .collect();
+            for (c, spot) in batch.iter().zip(decoded) {
+                self.add_candidate(
+                    c,
+                    || spot.unwrap_or_else(|| self.decode_candidate(c)),
+                    spots,
+                );
+            }
+        }
+
+        scan_log!(
+            debug,
+            target: "topcodes::decode",
+            "{}found {} valid codes",
+            self.log_source(),
+            spots.len()
+        );
+    }
+
+    fn decode_candidate(&self, candidate: &Candidate) -> TopCode {
+        let mut spot = TopCode::default();
+        spot.decode(self, candidate.x, candidate.y);
+        let mut retries = self.retry_offsets();
+        while !spot.is_valid() {
+            let Some((dx, dy)) = retries.next() else {
+                break;
+            };
+            let (Some(x), Some(y)) = (
+                candidate.x.checked_add_signed(dx),
+                candidate.y.checked_add_signed(dy),
+            ) else {
+                continue;
+            };
+            if x < self.width && y < self.height {
+                spot = TopCode::default();
+                spot.decode(self, x, y);
+            }
+        }
+        if spot.is_valid() {
+            spot.x += self.motion_compensation;
+        }
+        spot
+    }
+
+    /// Offsets of the alternative centers tried when decoding fails, in rings of increasing
+    /// distance from the candidate.
+    fn retry_offsets(&self) -> impl Iterator<Item = (isize, isize)> {
+        let n = self.decode_retries as isize;
+        (1..=n).flat_map(|r| {
+            (-r..=r).flat_map(move |dy| {
+                (-r..=r)
+                    .filter(move |dx| dx.abs() == r || dy.abs() == r)
+                    .map(move |dx| (dx, dy))
+            })
+        })
+    }
+
+    /// Replaces the first spot that is concentric with `inner` and has a much larger unit.
+    fn replace_outer(spots: &mut [TopCode], inner: TopCode) {
+        let outer = spots.iter_mut().find(|outer| {
+            outer.in_bullseye(inner.x, inner.y)
+                && inner.in_bullseye(outer.x, outer.y)
+                && inner.unit <= outer.unit * NESTED_UNIT_RATIO
+        });
+
+        if let Some(outer) = outer {
+            *outer = inner;
+        }
+    }
+
+    fn overlaps(&self, spots: &[TopCode], x: usize, y: usize) -> bool {
+        for top in spots {
+            if top.in_bullseye(x as Real, y as Real) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Counts the number of pixels from (x, y) until a color change is perceived.
+    pub(crate) fn dist(&self, x: usize, y: usize, dx: isize, dy: isize) -> isize {
+        let start = self.get_bw_3x3(x, y);
+
+        let mut i = x as isize + dx;
+        let mut j = y as isize + dy;
+
+        loop {
+            if i <= 1 || i >= self.width as isize - 1 || j <= 1 || j >= self.height as isize {
+                break;
+            }
+
+            let sample = self.get_bw_3x3(i as usize, j as usize);
+            if start + sample == 1 {
+                let x_dist = (i - x as isize).abs();
+                let y_dist = (j - y as isize).abs();
+                return x_dist + y_dist;
+            }
+
+            i += dx;
+            j += dy;
+        }
+
+        -1
+    }
+
+    #[cfg(feature = "visualize")]
+    pub fn write_thresholding_image(&self, path: &str) {
+        let img = GrayImage::from_raw(self.width as u32, self.height as u32, self.threshold_mask())
+            .expect("Thresholded data does not match the image dimensions");
+        img.save(path).expect("Failed to save png image");
+    }
+}
+
+#[cfg(test)]
+// The fixtures are recorded with f64 geometry.
+#[cfg_attr(feature = "f32", allow(clippy::excessive_precision))]
+mod test {
+    use alloc::format;
+
+    use super::*;
+    use crate::topcode::PI;
+    use crate::utils::{add_noise, render_topcode};
+    use image::io::Reader as ImageReader;
+
+    fn setup(asset_name: &str) -> (Scanner, Vec<u8>) {
+        let img = ImageReader::open(format!("assets/{}.png", asset_name))
+            .unwrap()
+            .decode()
+            .unwrap();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let image_raw = img.into_rgb8().into_raw();
+        (Scanner::new(width, height), image_raw)
+    }
+
+    fn scan_gray(scanner: &mut Scanner<impl ScanBuffer>, buffer: &[u8]) -> Vec<TopCode> {
+        scanner.scan(buffer, |buffer, index| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        })
+    }
+
+    /// Compares scanned codes with fixtures recorded with `f64` geometry. With the `f32` feature
+    /// the codes, cores and positions are the same, but orientations may differ in the last bits.
+    fn assert_matches_fixture(topcodes: &[TopCode], fixture: &[TopCode]) {
+        if cfg!(feature = "f32") {
+            assert_eq!(topcodes.len(), fixture.len());
+            for (topcode, expected) in topcodes.iter().zip(fixture) {
+                let orientation = (topcode.orientation - expected.orientation).abs();
+                assert!(orientation < 1e-6, "{:?} {:?}", topcode, expected);
+                assert_eq!(
+                    TopCode {
+                        orientation: expected.orientation,
+                        ..*topcode
+                    },
+                    *expected
+                );
+            }
+        } else {
+            assert_eq!(topcodes, fixture);
+        }
+    }
+
+    #[test]
+    fn it_does_not_panic_on_degenerate_image_sizes() {
+        // Such scanners can't be created publicly, but are used internally for small windows.
+        for (width, height) in [(0, 0), (0, 5), (5, 0), (1, 1), (1, 7), (7, 1), (2, 2)] {
+            let mut scanner =
+                Scanner::with_data(width, height, vec![0; Scanner::buffer_len(width, height)]);
+            let buffer = vec![0; width * height];
+            assert!(scan_gray(&mut scanner, &buffer).is_empty());
+            assert_eq!(scanner.get_sample_3x3(0, 0), 0);
+            assert_eq!(scanner.get_bw_3x3(0, 0), 0);
+            assert_eq!(scanner.dist(0, 0, 1, 0), -1);
+        }
+    }
+
+    #[test]
+    fn it_rejects_images_below_the_minimum_dimensions() {
+        assert!(Scanner::try_new(3, 3).is_ok());
+        for (width, height) in [(0, 0), (2, 3), (3, 2), (2, 100)] {
+            assert_eq!(
+                Scanner::try_new(width, height).err(),
+                Some(TopCodeError::DimensionsTooSmall { width, height })
+            );
+        }
+        assert_eq!(
+            Scanner::from_raw(2, 2, vec![0; 4]).err(),
+            Some(TopCodeError::DimensionsTooSmall {
+                width: 2,
+                height: 2
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "1x1")]
+    fn new_panics_below_the_minimum_dimensions() {
+        Scanner::new(1, 1);
+    }
+
+    #[test]
+    fn it_reports_overflowing_dimensions() {
+        assert_eq!(
+            Scanner::try_new(usize::MAX, 3).err(),
+            Some(TopCodeError::DimensionsOverflow {
+                width: usize::MAX,
+                height: 3
+            })
+        );
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[test]
+    fn it_reports_overflowing_dimensions_on_32_bit_targets() {
+        assert!(Scanner::try_new(1 << 16, 1 << 16).is_err());
+    }
+
+    #[test]
+    fn it_recovers_noisy_sectors_with_supersampling() {
+        let (width, height) = (200, 200);
+        let mut buffer = render_topcode(55, 12.0, 0.3, (100.0, 100.0), (width, height));
+        add_noise(&mut buffer, width, 31, 0.1, 3);
+
+        let mut scanner = Scanner::new(width, height);
+        let codes: Vec<_> = scan_gray(&mut scanner, &buffer)
+            .iter()
+            .map(|c| c.code)
+            .collect();
+        assert_ne!(codes, vec![Some(55)]);
+
+        scanner.set_sector_supersamples(5);
+        let codes: Vec<_> = scan_gray(&mut scanner, &buffer)
+            .iter()
+            .map(|c| c.code)
+            .collect();
+        assert_eq!(codes, vec![Some(55)]);
+    }
+
+    #[test]
+    fn decode_retries_recover_off_center_candidates() {
+        let (width, height) = (120, 120);
+        let buffer = render_topcode(55, 8.0, 0.0, (60.3, 60.6), (width, height));
+        let mut scanner = Scanner::new(width, height);
+        assert_eq!(scan_gray(&mut scanner, &buffer).len(), 1);
+
+        // The candidate lies on the inner edge of the black ring.
+        let candidate = Candidate::new(59, 52);
+        assert!(scanner.find_codes(&[candidate]).is_empty());
+
+        scanner.set_decode_retries(1);
+        let codes = scanner.find_codes(&[candidate]);
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].code, Some(55));
+        assert!((codes[0].x - 60.3).abs() < 1.0 && (codes[0].y - 60.6).abs() < 1.0);
+    }
+
+    #[test]
+    fn source_labels_do_not_affect_results() {
+        let (width, height) = (200, 200);
+        let buffer = render_topcode(93, 12.0, 0.0, (100.0, 100.0), (width, height));
+        let mut scanner = Scanner::new(width, height);
+        assert_eq!(scanner.source_label(), None);
+        let unlabelled = scan_gray(&mut scanner, &buffer);
+
+        scanner.with_source_label("frame_0001.png");
+        assert_eq!(scanner.source_label(), Some("frame_0001.png"));
+        assert_eq!(scan_gray(&mut scanner, &buffer), unlabelled);
+
+        // Log messages are prefixed with the label.
+        #[cfg(feature = "logging")]
+        assert_eq!(
+            alloc::format!("{}found", scanner.log_source()),
+            "frame_0001.png: found"
+        );
+    }
+
+    #[test]
+    fn concentric_codes_keep_the_inner_code() {
+        let outer = TopCode::mock(31, 30.0, 0.0, 100.0, 100.0);
+        let inner = TopCode::mock(55, 6.0, 0.0, 102.0, 99.0);
+        let neighbour = TopCode::mock(93, 6.0, 0.0, 300.0, 100.0);
+        let mut spots = vec![outer, neighbour];
+
+        <Scanner>::replace_outer(&mut spots, inner);
+        assert_eq!(spots, vec![inner, neighbour]);
+    }
+
+    #[test]
+    fn similar_sized_concentric_codes_are_not_replaced() {
+        let outer = TopCode::mock(31, 10.0, 0.0, 100.0, 100.0);
+        let inner = TopCode::mock(55, 9.0, 0.0, 101.0, 100.0);
+        let mut spots = vec![outer];
+
+        <Scanner>::replace_outer(&mut spots, inner);
+        assert_eq!(spots, vec![outer]);
+    }
+
+    #[test]
+    fn preferring_inner_codes_keeps_regular_results() {
+        let (mut scanner, buffer) = setup("source");
+        scanner.set_prefer_inner(true);
+        let codes: Vec<_> = scanner
+            .scan(&buffer, |buffer, index| {
+                (
+                    buffer[index * 3] as u32,
+                    buffer[index * 3 + 1] as u32,
+                    buffer[index * 3 + 2] as u32,
+                )
+            })
+            .iter()
+            .map(|c| c.code)
+            .collect();
+        assert_eq!(codes, vec![Some(55), Some(31), Some(93)]);
+    }
+
+    #[test]
+    fn it_can_scan_a_code_at_the_minimum_detectable_diameter() {
+        let (width, height) = (100, 100);
+        let mut scanner = Scanner::new(width, height);
+        let unit = scanner.min_detectable_diameter() as Real / 8.0;
+        for i in 0..4 {
+            let orientation = i as Real * 0.4;
+            let buffer = render_topcode(93, unit, orientation, (50.0, 50.0), (width, height));
+            let codes: Vec<_> = scan_gray(&mut scanner, &buffer)
+                .iter()
+                .map(|c| c.code)
+                .collect();
+            assert_eq!(codes, vec![Some(93)]);
+        }
+    }
+
+    #[test]
+    fn local_contrast_thresholding_handles_codes_on_a_panel_edge() {
+        // A low contrast code on a bright panel, next to a dark gradient.
+        let (width, height) = (300, 200);
+        let edge = 40;
+        let buffer: Vec<u8> = render_topcode(93, 10.0, 0.2, (82.0, 100.0), (width, height))
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| match (i % width, v) {
+                (x, _) if x < edge => (x * 60 / edge) as u8,
+                (_, 0) => 170,
+                _ => 240,
+            })
+            .collect();
+
+        let mut scanner = Scanner::new(width, height);
+        let codes: Vec<_> = scan_gray(&mut scanner, &buffer)
+            .iter()
+            .map(|c| c.code)
+            .collect();
+        assert_ne!(codes, vec![Some(93)]);
+
+        scanner.set_local_contrast_window(41);
+        let codes: Vec<_> = scan_gray(&mut scanner, &buffer)
+            .iter()
+            .map(|c| c.code)
+            .collect();
+        assert_eq!(codes, vec![Some(93)]);
+    }
+
+    #[test]
+    fn it_can_scan_a_downsampled_image() {
+        let (width, height) = (600, 400);
+        let mut buffer = render_topcode(55, 12.0, 0.5, (150.0, 200.0), (width, height));
+        let other = render_topcode(93, 9.0, 2.0, (420.0, 150.0), (width, height));
+        for (pixel, other) in buffer.iter_mut().zip(other) {
+            *pixel = (*pixel).min(other);
+        }
+
+        let mut scanner = Scanner::new(width, height);
+        let full = scan_gray(&mut scanner, &buffer);
+        let downsampled = scanner.scan_downsampled(
+            &buffer,
+            |buffer, index| {
+                let v = buffer[index] as u32;
+                (v, v, v)
+            },
+            2,
+        );
+
+        assert_eq!(full.len(), 2);
+        assert_eq!(downsampled.len(), 2);
+        for (full, downsampled) in full.iter().zip(&downsampled) {
+            assert_eq!(full.code, downsampled.code);
+            assert!((full.x - downsampled.x).abs() <= 1.0);
+            assert!((full.y - downsampled.y).abs() <= 1.0);
+        }
+    }
+
+    #[test]
+    fn downsampled_candidates_are_decoded_at_full_resolution() {
+        // At a quarter of the resolution, the small code's bullseye is still found but it no longer
+        // decodes.
+        let (width, height) = (800, 600);
+        let mut buffer = render_topcode(55, 6.0, 0.5, (300.0, 250.0), (width, height));
+        let other = render_topcode(93, 12.0, 2.0, (600.0, 400.0), (width, height));
+        for (pixel, other) in buffer.iter_mut().zip(other) {
+            *pixel = (*pixel).min(other);
+        }
+        let gray = |buffer: &Vec<u8>, index: usize| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        };
+
+        let mut scanner = Scanner::new(width, height);
+        let mut small = Scanner::new(width / 4, height / 4);
+        let coarse = small.scan(&buffer, |buffer, index| {
+            gray(buffer, (index / 200 * 4 + 2) * width + index % 200 * 4 + 2)
+        });
+        assert_eq!(
+            coarse.iter().map(|c| c.code).collect::<Vec<_>>(),
+            vec![Some(93)]
+        );
+
+        let full = scanner.scan(&buffer, gray);
+        let downsampled = scanner.scan_downsampled(&buffer, gray, 4);
+        assert_eq!(full.len(), 2);
+        assert_eq!(downsampled.len(), 2);
+        for downsampled in &downsampled {
+            let full = full.iter().find(|c| c.code == downsampled.code).unwrap();
+            assert!((full.x - downsampled.x).abs() <= 1.0);
+            assert!((full.y - downsampled.y).abs() <= 1.0);
+        }
+    }
+
+    #[test]
+    fn pyramids_find_codes_larger_than_the_maximum_diameter() {
+        let (width, height) = (800, 500);
+        let mut buffer = render_topcode(55, 8.0, 0.5, (120.0, 120.0), (width, height));
+        let other = render_topcode(93, 40.0, 2.0, (500.0, 250.0), (width, height));
+        for (pixel, other) in buffer.iter_mut().zip(other) {
+            *pixel = (*pixel).min(other);
+        }
+        let gray = |buffer: &Vec<u8>, index: usize| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        };
+
+        let mut scanner = Scanner::new(width, height);
+        scanner.set_max_code_diameter(120);
+        let codes: Vec<_> = scanner.scan(&buffer, gray).iter().map(|c| c.code).collect();
+        assert_eq!(codes, vec![Some(55)]);
+
+        let pyramid = scanner.scan_pyramid(&buffer, gray, 3);
+        assert_eq!(pyramid.len(), 2);
+        for (code, unit, (x, y)) in [(55, 8.0, (120.0, 120.0)), (93, 40.0, (500.0, 250.0))] {
+            let found = pyramid.iter().find(|c| c.code == Some(code)).unwrap();
+            assert!(
+                (found.x - x).abs() <= 2.0 && (found.y - y).abs() <= 2.0,
+                "{:?}",
+                found
+            );
+            assert!((found.unit - unit).abs() <= unit * 0.1, "{:?}", found);
+        }
+    }
+
+    #[test]
+    fn motion_compensation_recovers_the_center_of_a_smeared_code() {
+        // The code moves 12 pixels to the right during the exposure, ending up at x = 162.
+        let (width, height) = (300, 200);
+        let smear = 12;
+        let code = render_topcode(55, 10.0, 0.3, (150.0, 100.0), (width, height));
+        let buffer: Vec<u8> = (0..width * height)
+            .map(|i| {
+                let x = i % width;
+                let sum: usize = (0..=smear)
+                    .map(|o| if x >= o { code[i - o] as usize } else { 255 })
+                    .sum();
+                (sum / (smear + 1)) as u8
+            })
+            .collect();
+
+        let mut scanner = Scanner::new(width, height);
+        let topcodes = scan_gray(&mut scanner, &buffer);
+        assert_eq!(topcodes.len(), 1);
+        assert!((topcodes[0].x - 162.0).abs() > 3.0);
+
+        scanner.set_motion_compensation(smear as Real / 2.0);
+        let topcodes = scan_gray(&mut scanner, &buffer);
+        assert_eq!(topcodes.len(), 1);
+        assert_eq!(topcodes[0].code, Some(55));
+        assert!((topcodes[0].x - 162.0).abs() <= 1.0);
+    }
+
+    #[test]
+    fn candidates_are_found_in_the_bullseye() {
+        let (width, height) = (200, 200);
+        let buffer = render_topcode(31, 10.0, 0.0, (100.0, 100.0), (width, height));
+        let mut scanner = Scanner::new(width, height);
+        let candidates = scanner.candidates_only(&buffer, |buffer, index| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        });
+
+        assert!(!candidates.is_empty());
+        assert!(candidates.iter().any(|c| {
+            let dx = c.x() as Real - 100.0;
+            let dy = c.y() as Real - 100.0;
+            (dx * dx + dy * dy).sqrt() <= 10.0
+        }));
+    }
+
+    #[test]
+    fn it_can_scan_a_truncated_buffer() {
+        let (width, height) = (200, 200);
+        let buffer: Vec<u8> = render_topcode(55, 10.0, 0.0, (100.0, 80.0), (width, height))
+            .iter()
+            .flat_map(|&v| [v, v, v])
+            .collect();
+        let truncated = &buffer[..buffer.len() - 3 * 250 - 1];
+
+        let mut scanner = Scanner::new(width, height);
+        let (codes, padded) = scanner.scan_padded(truncated, 3, |buffer, index| {
+            (
+                buffer[index * 3] as u32,
+                buffer[index * 3 + 1] as u32,
+                buffer[index * 3 + 2] as u32,
+            )
+        });
+        assert_eq!(padded, 251);
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].code, Some(55));
+    }
+
+    #[test]
+    fn it_only_decodes_changed_bands() {
+        let (width, height) = (300, 300);
+        let render = |moving_x: Real| {
+            let mut buffer = render_topcode(31, 8.0, 0.0, (80.0, 60.0), (width, height));
+            let moving = render_topcode(93, 8.0, 0.0, (moving_x, 220.0), (width, height));
+            for (pixel, moving) in buffer.iter_mut().zip(moving) {
+                *pixel = (*pixel).min(moving);
+            }
+            buffer
+        };
+        let decode_gray = |buffer: &Vec<u8>, index: usize| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        };
+
+        let mut scanner = Scanner::new(width, height);
+        let first = scanner.scan_diff(&render(100.0), decode_gray, &[]);
+        assert_eq!(first.len(), 2);
+
+        // Mark the previous results, so reused codes can be told apart from decoded ones.
+        let prev: Vec<_> = first
+            .iter()
+            .map(|code| TopCode {
+                orientation: 42.0,
+                ..*code
+            })
+            .collect();
+        let second = scanner.scan_diff(&render(180.0), decode_gray, &prev);
+        assert_eq!(second.len(), 2);
+        assert_eq!(second[0].code, Some(31));
+        assert_eq!(second[0].orientation, 42.0);
+        assert_eq!(second[1].code, Some(93));
+        assert_ne!(second[1].orientation, 42.0);
+        assert!((second[1].x - 180.0).abs() <= 1.0);
+    }
+
+    #[test]
+    fn it_can_scan_codes_with_a_thick_black_ring() {
+        // The black ring spans 0.6 to 2 units, rather than 1 to 2 units.
+        let (width, height, unit) = (200, 200, 12.0);
+        let mut buffer = render_topcode(55, unit, 0.0, (100.0, 100.0), (width, height));
+        for (i, pixel) in buffer.iter_mut().enumerate() {
+            let dx = (i % width) as Real + 0.5 - 100.0;
+            let dy = (i / width) as Real + 0.5 - 100.0;
+            let r = (dx * dx + dy * dy).sqrt() / unit;
+            if (0.6..1.0).contains(&r) {
+                *pixel = 0;
+            }
+        }
+
+        let mut scanner = Scanner::new(width, height);
+        assert!(scan_gray(&mut scanner, &buffer).is_empty());
+
+        scanner.set_white_ring_bounds(0.3, 2.0);
+        let codes: Vec<_> = scan_gray(&mut scanner, &buffer)
+            .iter()
+            .map(|c| c.code)
+            .collect();
+        assert_eq!(codes, vec![Some(55)]);
+    }
+
+    #[test]
+    fn exported_state_decodes_identically() {
+        let (mut scanner, buffer) = setup("source");
+        let candidates = scanner.candidates_only(&buffer, |buffer, index| {
+            (
+                buffer[index * 3] as u32,
+                buffer[index * 3 + 1] as u32,
+                buffer[index * 3 + 2] as u32,
+            )
+        });
+        let expected = scanner.find_codes(&candidates);
+        assert_eq!(expected.len(), 3);
+
+        let state = scanner.export_state();
+        #[cfg(feature = "serde")]
+        let state: ScannerState =
+            serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+        let mut truncated = state.clone();
+        truncated.data.pop();
+        assert_eq!(
+            Scanner::from_state(truncated).err(),
+            Some(TopCodeError::IncorrectBufferSize)
+        );
+        let restored = Scanner::from_state(state).unwrap();
+        assert_eq!(restored.find_codes(&candidates), expected);
+    }
+
+    #[cfg(feature = "opencv")]
+    #[test]
+    fn opencv_mats_are_scanned() {
+        use opencv::core::{Mat, Rect, Vec3b};
+
+        let (mut scanner, buffer) = setup("source");
+        let (width, height) = (scanner.image_width(), scanner.image_height());
+        let expected = scanner.scan(&buffer, |buffer, index| {
+            (
+                buffer[index * 3] as u32,
+                buffer[index * 3 + 1] as u32,
+                buffer[index * 3 + 2] as u32,
+            )
+        });
+        assert_eq!(expected.len(), 3);
+
+        let bgr: Vec<Vec3b> = buffer
+            .chunks_exact(3)
+            .map(|p| Vec3b::from([p[2], p[1], p[0]]))
+            .collect();
+        let mat = Mat::new_rows_cols_with_data(height as i32, width as i32, &bgr).unwrap();
+        assert_eq!(scanner.scan_mat(&mat).unwrap(), expected);
+
+        // A region of interest of a wider matrix, whose rows are not contiguous, scanned in place.
+        let padded: Vec<Vec3b> = bgr
+            .chunks_exact(width)
+            .flat_map(|row| row.iter().copied().chain([Vec3b::from([0; 3]); 5]))
+            .collect();
+        let padded = Mat::new_rows_cols_with_data(height as i32, width as i32 + 5, &padded)
+            .unwrap()
+            .try_clone()
+            .unwrap();
+        let roi = Mat::roi(&padded, Rect::new(0, 0, width as i32, height as i32)).unwrap();
+        assert!(!roi.is_continuous());
+        assert_eq!(scanner.scan_mat(&roi).unwrap(), expected);
+
+        let floats = vec![0.0f32; width * height];
+        let mat = Mat::new_rows_cols_with_data(height as i32, width as i32, &floats).unwrap();
+        assert_eq!(
+            scanner.scan_mat(&mat).err(),
+            Some(TopCodeError::UnsupportedPixelFormat)
+        );
+    }
+
+    #[test]
+    fn configs_are_restored_by_other_scanners() {
+        let mut scanner = Scanner::new(640, 480);
+        scanner.set_max_code_diameter(200);
+        scanner.set_roi(10, 20, 300, 200);
+        scanner.set_threshold_bias(0.9);
+        scanner.set_polarity(Polarity::Both);
+        scanner.set_decode_retries(1);
+        scanner.set_lens_model(LensModel {
+            k1: -0.1,
+            k2: 0.0,
+            principal_point: (320.0, 240.0),
+            focal_length: 600.0,
+        });
+        let config = scanner.config();
+        assert_eq!(config.roi, Some((10, 20, 300, 200)));
+        assert_eq!(Scanner::new(640, 480).config(), ScannerConfig::default());
+
+        #[cfg(feature = "serde")]
+        let config: ScannerConfig =
+            serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap();
+        let mut restored = Scanner::new(640, 480);
+        restored.set_config(&config);
+        assert_eq!(restored.config(), scanner.config());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn detections_round_trip_through_json() {
+        let (mut scanner, buffer) = setup("source");
+        let candidates = scanner.candidates_only(&buffer, |buffer, index| {
+            (
+                buffer[index * 3] as u32,
+                buffer[index * 3 + 1] as u32,
+                buffer[index * 3 + 2] as u32,
+            )
+        });
+        let codes = scanner.find_codes(&candidates);
+        let json = serde_json::to_string(&(&candidates, &codes)).unwrap();
+        let (restored_candidates, restored_codes): (Vec<Candidate>, Vec<TopCode>) =
+            serde_json::from_str(&json).unwrap();
+        assert_eq!(restored_candidates, candidates);
+        assert_eq!(restored_codes.len(), codes.len());
+        for (restored, code) in restored_codes.iter().zip(&codes) {
+            assert_eq!(
+                (restored.code, restored.x, restored.y),
+                (code.code, code.x, code.y)
+            );
+        }
+
+        // Stored configs may leave out settings, which keep their defaults.
+        let config: ScannerConfig = serde_json::from_str("{\"mirrored\":true}").unwrap();
+        assert!(config.mirrored);
+        assert_eq!(config.threshold_bias, DEFAULT_THRESHOLD_BIAS);
+    }
+
+    #[test]
+    fn threshold_only_matches_the_scan_threshold() {
+        let (mut scanner, buffer) = setup("photo");
+        let decode = |buffer: &Vec<u8>, index: usize| {
+            (
+                buffer[index * 3] as u32,
+                buffer[index * 3 + 1] as u32,
+                buffer[index * 3 + 2] as u32,
+            )
+        };
+        scanner.scan(&buffer, decode);
+        let expected = scanner.threshold_mask();
+
+        let mut scanner = Scanner::new(scanner.image_width(), scanner.image_height());
+        scanner.threshold_only(&buffer, decode);
+        let mask = scanner.threshold_mask();
+        assert_eq!(mask, expected);
+        assert!(mask.iter().all(|&pixel| pixel == 0 || pixel == 255));
+        assert!(mask.contains(&0) && mask.contains(&255));
+    }
+
+    #[test]
+    fn flat_field_correction_recovers_vignetted_codes() {
+        let (width, height) = (200, 200);
+        let code = render_topcode(55, 8.0, 0.3, (120.0, 120.0), (width, height));
+        // Darken the image towards the edges of a circle around (60, 60), on top of a low
+        // contrast print.
+        let vignette: Vec<f32> = (0..width * height)
+            .map(|i| {
+                let x = (i % width) as f32 - 60.0;
+                let y = (i / width) as f32 - 60.0;
+                1.0 - 0.8 * ((x * x + y * y) / 10000.0).min(1.0)
+            })
+            .collect();
+        let buffer: Vec<u8> = code
+            .iter()
+            .zip(&vignette)
+            .map(|(&pixel, &factor)| ((60.0 + pixel as f32 * 0.75) * factor) as u8)
+            .collect();
+
+        let mut scanner = Scanner::new(width, height);
+        assert!(scan_gray(&mut scanner, &buffer).is_empty());
+
+        assert_eq!(
+            scanner.set_flat_field(vec![1.0; 10]),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
+        scanner
+            .set_flat_field(vignette.iter().map(|factor| 1.0 / factor).collect())
+            .unwrap();
+        let codes: Vec<_> = scan_gray(&mut scanner, &buffer)
+            .iter()
+            .map(|c| c.code)
+            .collect();
+        assert_eq!(codes, vec![Some(55)]);
+    }
+
+    #[test]
+    fn ambiguous_symbols_have_several_hypotheses() {
+        let (width, height) = (200, 200);
+        let crisp = render_topcode(55, 10.0, 0.3, (100.0, 100.0), (width, height));
+        // 55 and 87 only differ in two adjacent sectors, which are checkered in the ambiguous
+        // symbol.
+        let other = render_topcode(87, 10.0, 0.3, (100.0, 100.0), (width, height));
+        let ambiguous: Vec<u8> = crisp
+            .iter()
+            .zip(&other)
+            .enumerate()
+            .map(|(i, (&a, &b))| {
+                if (i % width + i / width) % 2 == 0 {
+                    a
+                } else {
+                    b
+                }
+            })
+            .collect();
+        let decode_gray = |buffer: &Vec<u8>, index: usize| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        };
+
+        let mut scanner = Scanner::new(width, height);
+        let hypotheses = scanner.decode_hypotheses(&crisp, decode_gray, 100, 100, 3);
+        assert_eq!(hypotheses.len(), 3);
+        assert_eq!(hypotheses[0], (55, 1.0));
+        assert!(hypotheses[1].1 < 0.9);
+
+        let hypotheses = scanner.decode_hypotheses(&ambiguous, decode_gray, 100, 100, 3);
+        let mut best: Vec<_> = hypotheses[..2].iter().map(|(code, _)| *code).collect();
+        best.sort();
+        assert_eq!(best, vec![55, 87]);
+        assert!((hypotheses[0].1 - hypotheses[1].1).abs() < 0.01);
+        assert!(hypotheses[0].1 < 1.0);
+        assert!(hypotheses[2].1 < hypotheses[1].1 - 0.05);
+    }
+
+    #[test]
+    fn lower_bw_majority_keeps_thin_rings() {
+        let (width, height) = (100, 100);
+        let (cx, cy, unit) = (50.5, 50.5, 5.0);
+        let mut buffer = render_topcode(31, unit, 0.2, (cx, cy), (width, height));
+        // Thin the white ring to 0.4 units (2 pixels) around its middle.
+        for y in 0..height {
+            for x in 0..width {
+                let dx = x as Real + 0.5 - cx;
+                let dy = y as Real + 0.5 - cy;
+                let r = (dx * dx + dy * dy).sqrt() / unit;
+                if (2.0..2.3).contains(&r) || (2.7..3.0).contains(&r) {
+                    buffer[y * width + x] = 0;
+                }
+            }
+        }
+
+        let mut scanner = Scanner::new(width, height);
+        assert!(scan_gray(&mut scanner, &buffer).is_empty());
+
+        scanner.set_bw_majority(3);
+        let codes: Vec<_> = scan_gray(&mut scanner, &buffer)
+            .iter()
+            .map(|c| c.code)
+            .collect();
+        assert_eq!(codes, vec![Some(31)]);
+    }
+
+    #[test]
+    fn extended_rings_are_read_when_enabled() {
+        let (width, height) = (200, 200);
+        let (cx, cy, unit) = (100.0, 100.0, 8.0);
+        let extended = 0b1011_0110;
+        for orientation in [0.0, 1.0, 4.0] {
+            let mut buffer = render_topcode(31, unit, orientation, (cx, cy), (width, height));
+            for y in 0..height {
+                for x in 0..width {
+                    let dx = x as Real + 0.5 - cx;
+                    let dy = y as Real + 0.5 - cy;
+                    let r = (dx * dx + dy * dy).sqrt() / unit;
+                    let angle = (dy.atan2(dx) - orientation).rem_euclid(2.0 * PI);
+                    let bit = (angle / (2.0 * PI / 8.0)) as usize;
+                    if (4.0..5.0).contains(&r) && extended >> bit & 0x01 == 0 {
+                        buffer[y * width + x] = 0;
+                    }
+                }
+            }
+
+            let mut scanner = Scanner::new(width, height);
+            let codes = scan_gray(&mut scanner, &buffer);
+            assert_eq!(codes.len(), 1);
+            assert_eq!(codes[0].extended_data(), None);
+
+            scanner.set_read_extended_ring(8);
+            let codes = scan_gray(&mut scanner, &buffer);
+            assert_eq!(codes.len(), 1);
+            assert_eq!(codes[0].code, Some(31));
+            assert_eq!(codes[0].extended_data(), Some(extended));
+        }
+    }
+
+    #[test]
+    fn lens_distortion_is_removed_from_positions() {
+        let (width, height) = (300, 300);
+        let lens = LensModel {
+            k1: -0.2,
+            k2: 0.0,
+            principal_point: (150.0, 150.0),
+            focal_length: 150.0,
+        };
+        let (cx, cy) = (225.0, 215.0);
+        let undistorted = render_topcode(55, 6.0, 0.5, (cx, cy), (width, height));
+        // Each pixel of the distorted image shows the point that the lens maps onto it.
+        let buffer: Vec<u8> = (0..width * height)
+            .map(|i| {
+                let (x, y) = lens.undistort((i % width) as Real + 0.5, (i / width) as Real + 0.5);
+                if x < 0.0 || y < 0.0 || x >= width as Real || y >= height as Real {
+                    255
+                } else {
+                    undistorted[y as usize * width + x as usize]
+                }
+            })
+            .collect();
+
+        let mut scanner = Scanner::new(width, height);
+        let codes = scan_gray(&mut scanner, &buffer);
+        assert_eq!(codes.len(), 1);
+        assert!((codes[0].x - cx).abs() > 5.0 && (codes[0].y - cy).abs() > 5.0);
+
+        scanner.set_lens_model(lens);
+        let codes = scan_gray(&mut scanner, &buffer);
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].code, Some(55));
+        assert!((codes[0].x - cx).abs() < 1.5, "{}", codes[0].x);
+        assert!((codes[0].y - cy).abs() < 1.5, "{}", codes[0].y);
+
+        // Codes fed back into the scanner are mapped back into the image.
+        let decode_gray = |buffer: &Vec<u8>, index: usize| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        };
+        let kept = scanner.scan_diff(&buffer, decode_gray, &codes);
+        assert_eq!(kept.len(), 1);
+        let tolerance = if cfg!(feature = "f32") { 1e-3 } else { 1e-6 };
+        assert!(
+            (kept[0].x - codes[0].x).abs() < tolerance
+                && (kept[0].y - codes[0].y).abs() < tolerance
+        );
+    }
+
+    #[test]
+    fn pixel_format_scans_match_the_closure_scan() {
+        let (mut scanner, rgb) = setup("source");
+        let expected = scanner.scan(&rgb, |buffer, index| {
+            (
+                buffer[index * 3] as u32,
+                buffer[index * 3 + 1] as u32,
+                buffer[index * 3 + 2] as u32,
+            )
+        });
+        assert_eq!(expected.len(), 3);
+
+        let bgr: Vec<u8> = rgb.chunks(3).flat_map(|p| [p[2], p[1], p[0]]).collect();
+        let rgba: Vec<u8> = rgb.chunks(3).flat_map(|p| [p[0], p[1], p[2], 0]).collect();
+        let bgra: Vec<u8> = rgb.chunks(3).flat_map(|p| [p[2], p[1], p[0], 0]).collect();
+        assert_eq!(scanner.scan_rgb_u8(&rgb), Ok(expected.clone()));
+        assert_eq!(scanner.scan_bgr_u8(&bgr), Ok(expected.clone()));
+        assert_eq!(scanner.scan_rgba_u8(&rgba), Ok(expected.clone()));
+        assert_eq!(scanner.scan_bgra_u8(&bgra), Ok(expected));
+
+        assert_eq!(
+            scanner.scan_rgb_u8(&rgb[1..]),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
+        assert_eq!(
+            scanner.scan_rgba_u8(&rgb),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn simd_thresholding_matches_the_scalar_thresholding() {
+        let (mut scanner, rgb) = setup("photo");
+        let gray: Vec<u8> = rgb.chunks(3).map(|p| p[1]).collect();
+        for polarity in [Polarity::Normal, Polarity::Inverted, Polarity::Both] {
+            scanner.set_polarity(polarity);
+            let expected = scanner.scan(&rgb, |buffer, index| {
+                (
+                    buffer[index * 3] as u32,
+                    buffer[index * 3 + 1] as u32,
+                    buffer[index * 3 + 2] as u32,
+                )
+            });
+            let mask = scanner.threshold_mask();
+            assert_eq!(scanner.scan_rgb_u8(&rgb), Ok(expected));
+            assert_eq!(scanner.threshold_mask(), mask);
+
+            let bgra: Vec<u8> = rgb.chunks(3).flat_map(|p| [p[2], p[1], p[0], 0]).collect();
+            let candidates = scanner.threshold(&bgra, |buffer, index| {
+                (
+                    buffer[index * 4 + 2] as u32,
+                    buffer[index * 4 + 1] as u32,
+                    buffer[index * 4] as u32,
+                )
+            });
+            assert!(!candidates.is_empty());
+            assert_eq!(scanner.threshold_u8(&bgra, 4, [2, 1, 0]), candidates);
+
+            let expected = scan_gray(&mut scanner, &gray);
+            let mask = scanner.threshold_mask();
+            assert_eq!(scanner.scan_gray_u8(&gray), Ok(expected));
+            assert_eq!(scanner.threshold_mask(), mask);
         }
     }
 
-    pub fn image_width(&self) -> usize {
-        self.width
-    }
+    #[test]
+    fn banded_thresholding_nearly_matches_the_full_sweep() {
+        for asset in ["source", "photo"] {
+            let (mut scanner, buffer) = setup(asset);
+            let expected = scanner.scan_rgb_u8(&buffer).unwrap();
+            let mask = scanner.threshold_mask();
 
-    pub fn image_height(&self) -> usize {
-        self.height
+            scanner.set_threshold_bands(8);
+            let codes = scanner.scan_rgb_u8(&buffer).unwrap();
+            let differing = mask
+                .iter()
+                .zip(scanner.threshold_mask())
+                .filter(|(a, b)| **a != *b)
+                .count();
+            assert!(differing * 1000 < mask.len(), "{} pixels differ", differing);
+            assert_eq!(codes, expected);
+        }
     }
 
-    /// Scan the image and return a list of all TopCodes found in it.
-    pub fn scan<T: ?Sized>(
-        &mut self,
-        image_buffer: &T,
-        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
-    ) -> Vec<TopCode> {
-        let candidates = self.threshold(image_buffer, decode_rgb);
-        self.find_codes(&candidates)
+    #[test]
+    fn regions_of_interest_restrict_the_scan() {
+        let (width, height) = (300, 300);
+        let top = render_topcode(55, 8.0, 0.0, (100.0, 60.0), (width, height));
+        let bottom = render_topcode(93, 8.0, 0.3, (180.0, 240.0), (width, height));
+        let buffer: Vec<u8> = top.iter().zip(&bottom).map(|(a, b)| *a.min(b)).collect();
+        let mut scanner = Scanner::new(width, height);
+        let all = scan_gray(&mut scanner, &buffer);
+        assert_eq!(all.len(), 2);
+
+        // The lower third of the image, with positions still in image coordinates.
+        scanner.set_roi(0, 200, width, 100);
+        let codes = scan_gray(&mut scanner, &buffer);
+        assert_eq!(codes, vec![all[1]]);
+        let mask = scanner.threshold_mask();
+        assert!(mask[..200 * width].iter().all(|v| *v == 0));
+        for bands in [1, 3] {
+            scanner.set_threshold_bands(bands);
+            assert_eq!(scanner.scan_gray_u8(&buffer).unwrap(), codes);
+            assert_eq!(scanner.threshold_mask(), mask);
+        }
+
+        // Rectangles are clipped to the image.
+        scanner.set_roi(50, 10, 1000, 1000);
+        assert_eq!(scan_gray(&mut scanner, &buffer).len(), 2);
+        scanner.set_roi(400, 0, 10, 10);
+        assert!(scan_gray(&mut scanner, &buffer).is_empty());
+        assert!(scanner.scan_gray_u8(&buffer).unwrap().is_empty());
+
+        scanner.clear_roi();
+        assert_eq!(scan_gray(&mut scanner, &buffer), all);
     }
 
-    /// Sets the maximum allowable diameter (in pixels) for a TopCode identified by the scanner.
-    /// Setting this to a reasonable value for your application will reduce false positives
-    /// (recognizing codes that aren't actually there) and improve performance (because fewer
-    /// candidate codes will be tested). Setting this value to as low as 50 or 60 pixels could be
-    /// advisable for some applications. However, setting the maximum diameter too low will prevent
-    /// valid codes from being recognized.
-    pub fn set_max_code_diameter(&mut self, diameter: usize) {
-        let f = diameter as f64 / 8.0;
-        self.max_unit = f.ceil() as usize;
+    #[test]
+    fn excluded_pixels_are_ignored() {
+        let (width, height) = (400, 200);
+        let left = render_topcode(55, 8.0, 0.0, (100.0, 100.0), (width, height));
+        let right = render_topcode(93, 8.0, 0.3, (300.0, 100.0), (width, height));
+        let buffer: Vec<u8> = left.iter().zip(&right).map(|(a, b)| *a.min(b)).collect();
+        let mut scanner = Scanner::new(width, height);
+        let all = scan_gray(&mut scanner, &buffer);
+        assert_eq!(all.len(), 2);
+        let mut candidates = Vec::new();
+        let decode_gray = |buffer: &Vec<u8>, index: usize| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        };
+        scanner.scan_into(&buffer, decode_gray, &mut candidates, &mut Vec::new());
+
+        scanner.exclude_rect(200, 0, 200, 200);
+        let codes = scan_gray(&mut scanner, &buffer);
+        assert_eq!(codes.len(), 1);
+        assert!(all.contains(&codes[0]) && codes[0].code == Some(55));
+        assert_eq!(scanner.scan_gray_u8(&buffer).unwrap(), codes);
+        let mask = scanner.threshold_mask();
+        assert!((0..height).all(|y| mask[y * width + 200..(y + 1) * width]
+            .iter()
+            .all(|v| *v == 0)));
+        // Candidates centered on excluded pixels aren't decoded either.
+        assert_eq!(scanner.find_codes(&candidates), codes);
+
+        let excluded: Vec<bool> = (0..width * height).map(|i| i % width >= 200).collect();
+        scanner.clear_exclusions();
+        scanner.set_exclusion_mask(&excluded).unwrap();
+        assert_eq!(scan_gray(&mut scanner, &buffer), codes);
+        assert_eq!(
+            scanner.set_exclusion_mask(&excluded[1..]),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
+
+        scanner.clear_exclusions();
+        assert_eq!(scan_gray(&mut scanner, &buffer), all);
     }
 
-    /// Average of thresholded pixels in a 3x3 region around (x, y). Returned value is between 0
-    /// (black) and 255 (white).
-    pub(crate) fn get_sample_3x3(&self, x: usize, y: usize) -> usize {
-        if x < 1 || x >= self.width - 1 || y < 1 || y >= self.height - 1 {
-            return 0;
-        }
+    #[test]
+    fn padded_rows_are_scanned_in_place() {
+        let (mut scanner, rgb) = setup("source");
+        let expected = scanner.scan_rgb_u8(&rgb).unwrap();
+        assert_eq!(expected.len(), 3);
+        let decode = |pixel: &[u8]| (pixel[0] as u32, pixel[1] as u32, pixel[2] as u32);
 
-        let mut sum = 0;
-        for j in y - 1..=y + 1 {
-            for i in x - 1..=x + 1 {
-                let pixel = self.data[j * self.width + i];
-                sum += 0xff * (pixel >> 24 & 0x01);
-            }
+        let row = scanner.image_width() * 3;
+        let layout = ScanLayout::packed(scanner.image_width(), 3);
+        assert_eq!(
+            scanner.scan_layout(&rgb, layout, decode),
+            Ok(expected.clone())
+        );
+
+        let stride = row + 13;
+        let mut padded = vec![0xff; stride * scanner.image_height()];
+        for (y, pixels) in rgb.chunks(row).enumerate() {
+            padded[y * stride..y * stride + row].copy_from_slice(pixels);
         }
+        let layout = ScanLayout::new(stride, 3);
+        assert_eq!(
+            scanner.scan_layout(&padded, layout, decode),
+            Ok(expected.clone())
+        );
+        let trimmed = &padded[..padded.len() - 13];
+        assert_eq!(scanner.scan_layout(trimmed, layout, decode), Ok(expected));
 
-        (sum / 9) as usize
+        assert_eq!(
+            scanner.scan_layout(&padded[..padded.len() - 14], layout, decode),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
+        assert_eq!(
+            scanner.scan_layout(&padded, ScanLayout::new(row - 1, 3), decode),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
     }
 
-    /// Average of thresholded pixels in a 3x3 region around (x, y). Returned value is either 0
-    /// (black) or 1 (white).
-    pub(crate) fn get_bw_3x3(&self, x: usize, y: usize) -> u32 {
-        if x < 1 || x >= self.width - 1 || y < 1 || y >= self.height - 1 {
-            return 0;
+    #[test]
+    #[cfg(feature = "image-io")]
+    fn decoded_images_are_scanned_directly() {
+        let (_, rgb) = setup("source");
+        let image = ImageReader::open("assets/source.png")
+            .unwrap()
+            .decode()
+            .unwrap();
+        let mut scanner = Scanner::for_image(&image).unwrap();
+        let expected = scanner.scan_rgb_u8(&rgb).unwrap();
+        assert_eq!(expected.len(), 3);
+        assert_eq!(scanner.scan_image(&image), Ok(expected.clone()));
+        let rgba = DynamicImage::ImageRgba8(image.to_rgba8());
+        assert_eq!(scanner.scan_image(&rgba), Ok(expected.clone()));
+
+        let codes = |codes: Vec<TopCode>| {
+            let mut codes: Vec<_> = codes.iter().map(|code| code.code).collect();
+            codes.sort();
+            codes
+        };
+        for converted in [
+            DynamicImage::ImageLuma8(image.to_luma8()),
+            DynamicImage::ImageLuma16(image.to_luma16()),
+            DynamicImage::ImageRgb16(image.to_rgb16()),
+            DynamicImage::ImageRgb32F(image.to_rgb32f()),
+        ] {
+            let found = scanner.scan_image(&converted).unwrap();
+            assert_eq!(codes(found), codes(expected.clone()));
         }
 
-        let mut sum = 0;
-        for j in y - 1..=y + 1 {
-            for i in x - 1..=x + 1 {
-                let pixel = self.data[j * self.width + i];
-                sum += pixel >> 24 & 0x01;
+        let cropped = image.crop_imm(0, 0, 10, 10);
+        assert_eq!(
+            scanner.scan_image(&cropped),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ndarray")]
+    fn ndarray_views_are_scanned_in_place() {
+        use ndarray::{s, Array2, Array3};
+
+        let (mut scanner, rgb) = setup("source");
+        let (width, height) = (scanner.image_width(), scanner.image_height());
+        let expected = scanner.scan_rgb_u8(&rgb).unwrap();
+        assert_eq!(expected.len(), 3);
+
+        let image = Array3::from_shape_vec((height, width, 3), rgb.clone()).unwrap();
+        assert_eq!(scanner.scan_ndarray(image.view()), Ok(expected.clone()));
+
+        // Every pixel is followed by an unused fourth channel, so the RGB slice isn't contiguous.
+        let mut rgbx = Array3::zeros((height, width, 4));
+        rgbx.slice_mut(s![.., .., ..3]).assign(&image);
+        let sliced = rgbx.slice(s![.., .., ..3]);
+        assert_eq!(scanner.scan_ndarray(sliced), Ok(expected.clone()));
+
+        let luma: Vec<u8> = rgb
+            .chunks(3)
+            .map(|p| average((p[0] as u32, p[1] as u32, p[2] as u32)) as u8)
+            .collect();
+        let gray_expected = scanner.scan_gray_u8(&luma).unwrap();
+        // A column-major copy of the grayscale image, viewed through its transpose.
+        let transposed = Array2::from_shape_vec((width, height), {
+            let mut columns = vec![0; luma.len()];
+            for (index, &v) in luma.iter().enumerate() {
+                columns[(index % width) * height + index / width] = v;
             }
-        }
+            columns
+        })
+        .unwrap();
+        let gray = transposed.t();
+        assert_eq!(scanner.scan_ndarray(gray), Ok(gray_expected.clone()));
+        let single = gray.insert_axis(ndarray::Axis(2));
+        assert_eq!(scanner.scan_ndarray(single), Ok(gray_expected));
 
-        if sum >= 5 {
-            1
-        } else {
-            0
-        }
+        assert_eq!(
+            scanner.scan_ndarray(transposed.view()),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
+        assert_eq!(
+            scanner.scan_ndarray(rgbx.slice(s![.., .., ..2])),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
     }
 
-    /// Perform Wellner adaptive thresholding to produce binary pixel data. Also mark candidate
-    /// SpotCode locations.
-    ///
-    /// "Adaptive Thresholding for the DigitalDesk"
-    /// EuroPARC Technical Report EPC-93-110
-    fn threshold<T: ?Sized>(
-        &mut self,
-        image_buffer: &T,
-        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
-    ) -> Vec<Candidate> {
-        let mut candidates = Vec::with_capacity(50);
-        let mut sum = 128;
-        let s = 32;
+    #[test]
+    fn gray_scans_match_the_closure_scan() {
+        let (width, height) = (200, 200);
+        let buffer = render_topcode(93, 12.0, 0.7, (100.0, 100.0), (width, height));
+        let mut scanner = Scanner::new(width, height);
+        let expected = scan_gray(&mut scanner, &buffer);
+        assert_eq!(expected.len(), 1);
+        assert_eq!(scanner.scan_gray_u8(&buffer), Ok(expected));
+        assert_eq!(
+            scanner.scan_gray_u8(&buffer[1..]),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
+    }
 
-        for j in 0..self.height {
-            let mut level = UnitLevel::WhiteRegion;
-            let mut b1: isize = 0;
-            let mut b2: isize = 0;
-            let mut w1: isize = 0;
+    #[test]
+    fn rgb565_scans_match_the_expanded_rgb_scan() {
+        let (width, height) = (200, 200);
+        let gray = render_topcode(93, 12.0, 0.7, (100.0, 100.0), (width, height));
+        let pixels: Vec<u16> = gray
+            .iter()
+            .map(|&v| ((v as u16 >> 3) << 11) | ((v as u16 >> 2) << 5) | (v as u16 >> 3))
+            .collect();
+        let rgb: Vec<u8> = pixels
+            .iter()
+            .flat_map(|&pixel| {
+                let (r, g, b) = rgb565(pixel);
+                [r as u8, g as u8, b as u8]
+            })
+            .collect();
+        let mut scanner = Scanner::new(width, height);
+        let expected = scanner.scan_rgb_u8(&rgb).unwrap();
+        assert_eq!(expected.len(), 1);
 
-            let mut k = if j % 2 == 0 { 0 } else { self.width - 1 };
-            k += j * self.width;
+        let le: Vec<u8> = pixels.iter().flat_map(|p| p.to_le_bytes()).collect();
+        let be: Vec<u8> = pixels.iter().flat_map(|p| p.to_be_bytes()).collect();
+        assert_eq!(scanner.scan_rgb565(&pixels), Ok(expected.clone()));
+        assert_eq!(scanner.scan_rgb565_le(&le), Ok(expected.clone()));
+        assert_eq!(scanner.scan_rgb565_be(&be), Ok(expected));
+
+        assert_eq!(rgb565(0xffff), (255, 255, 255));
+        assert_eq!(
+            scanner.scan_rgb565_le(&le[1..]),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
+    }
 
-            for _i in 0..self.width {
-                // Calculate pixel intensity (0-255)
-                let (r, g, b) = decode_rgb(image_buffer, k);
-                let mut a: isize = (r + g + b) as isize / 3;
+    #[test]
+    fn deep_scans_keep_the_precision_of_dark_images() {
+        let (width, height) = (200, 200);
+        let gray = render_topcode(93, 12.0, 0.7, (100.0, 100.0), (width, height));
+        // A very dark frame, which is black once converted to 8 bits.
+        let deep: Vec<u16> = gray.iter().map(|&v| 40 + v as u16 * 200 / 255).collect();
+        let mut scanner = Scanner::new(width, height);
+        let truncated: Vec<u8> = deep.iter().map(|&v| (v >> 8) as u8).collect();
+        assert!(scanner.scan_gray_u8(&truncated).unwrap().is_empty());
 
-                // Calculate the average sum as an approximate sum of the last s pixels
-                sum += a - (sum / s);
+        let codes = scanner.scan_gray_u16(&deep, 16).unwrap();
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].code, Some(93));
+        assert_eq!(scanner.scan_gray_u16(&deep, 12), Ok(codes.clone()));
 
-                // Factor in sum from the previous row
-                let threshold = if k >= self.width {
-                    (sum + (self.data[k - self.width] as isize & 0xffffff)) / (2 * s)
-                } else {
-                    sum / s
-                };
+        let normalized: Vec<f32> = deep.iter().map(|&v| v as f32 / 65535.0).collect();
+        assert_eq!(scanner.scan_gray_f32(&normalized), Ok(codes));
 
-                // Compare the average sum to current pixel to decide black or white
-                a = if (a as f64) < (threshold as f64 * 0.975) {
-                    0
+        assert_eq!(
+            scanner.scan_gray_u16(&deep, 17),
+            Err(TopCodeError::UnsupportedBitDepth(17))
+        );
+        assert_eq!(
+            scanner.scan_gray_f32(&normalized[1..]),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
+    }
+
+    #[test]
+    fn bayer_scans_find_codes_in_raw_frames() {
+        let (width, height) = (200, 200);
+        let gray = render_topcode(93, 12.0, 0.7, (100.0, 100.0), (width, height));
+        // Dark blue ink on yellowish paper.
+        let rgb: Vec<[u8; 3]> = gray
+            .iter()
+            .map(|&v| {
+                if v > 127 {
+                    [250, 230, 160]
                 } else {
-                    1
-                };
+                    [20, 30, 90]
+                }
+            })
+            .collect();
+        let mut scanner = Scanner::new(width, height);
+        let expected = scanner.scan_rgb_u8(&rgb.concat()).unwrap();
+        assert_eq!(expected.len(), 1);
 
-                // Repack pixel data with binary data in the alpha channel, and the running some
-                // for this pixel in the RGB channels.
-                self.data[k] = ((a << 24) + (sum & 0xffffff)) as u32;
-
-                match level {
-                    UnitLevel::WhiteRegion => {
-                        if a == 0 {
-                            // First black pixel encountered
-                            level = UnitLevel::BlackRegion;
-                            b1 = 1;
-                            w1 = 0;
-                            b2 = 0;
-                        }
-                    }
-                    UnitLevel::BlackRegion => {
-                        if a == 0 {
-                            b1 += 1;
-                        } else {
-                            level = UnitLevel::WhiteRegionSecond;
-                            w1 = 1;
-                        }
+        for (pattern, (red_x, red_y)) in [
+            (BayerPattern::Rggb, (0, 0)),
+            (BayerPattern::Bggr, (1, 1)),
+            (BayerPattern::Grbg, (1, 0)),
+            (BayerPattern::Gbrg, (0, 1)),
+        ] {
+            let raw: Vec<u8> = rgb
+                .iter()
+                .enumerate()
+                .map(|(index, pixel)| {
+                    let (x, y) = (index % width, index / width);
+                    match ((x + red_x) % 2, (y + red_y) % 2) {
+                        (0, 0) => pixel[0],
+                        (1, 1) => pixel[2],
+                        _ => pixel[1],
                     }
-                    UnitLevel::WhiteRegionSecond => {
-                        if a == 0 {
-                            level = UnitLevel::BlackRegionSecond;
-                            b2 = 1;
-                        } else {
-                            w1 += 1;
-                        }
-                    }
-                    UnitLevel::BlackRegionSecond => {
-                        let max_u = self.max_unit as isize;
-                        if a == 0 {
-                            b2 += 1;
-                        } else {
-                            if b1 >= 2
-                                && b2 >= 2
-                                && b1 <= max_u
-                                && b2 <= max_u
-                                && w1 <= (max_u + max_u)
-                                && (b1 + b2 - w1).abs() <= (b1 + b2)
-                                && (b1 + b2 - w1).abs() <= w1
-                                && (b1 - b2).abs() <= b1
-                                && (b1 - b2).abs() <= b2
-                            {
-                                let mut dk: usize = 1 + b2 as usize + (w1 as usize >> 1);
-                                dk = if j % 2 == 0 { k - dk } else { k + dk };
-
-                                candidates.push(Candidate::new(dk % self.width, j));
-                            }
-                            b1 = b2;
-                            w1 = 1;
-                            b2 = 0;
-                            level = UnitLevel::WhiteRegionSecond;
-                        }
-                    }
-                }
-                if j % 2 == 0 {
-                    k += 1
-                } else {
-                    k -= 1
-                };
-            }
+                })
+                .collect();
+            let codes = scanner.scan_bayer_u8(&raw, pattern).unwrap();
+            assert_eq!(codes.len(), 1, "{:?}", pattern);
+            assert_eq!(codes[0].code, expected[0].code);
+            // Intensities are sampled half a pixel down and to the right.
+            assert!((codes[0].x - expected[0].x).abs() < 1.5);
+            assert!((codes[0].y - expected[0].y).abs() < 1.5);
         }
 
-        candidates
+        assert_eq!(
+            scanner.scan_bayer_u8(&gray[1..], BayerPattern::Rggb),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
     }
 
-    /// Scan the image line by line looking for TopCodes.
-    fn find_codes(&self, candidates: &Vec<Candidate>) -> Vec<TopCode> {
-        let mut spots = Vec::with_capacity(candidates.len());
+    #[test]
+    fn yuv_scans_read_the_luma_plane() {
+        let (width, height) = (200, 200);
+        let gray = render_topcode(93, 12.0, 0.7, (100.0, 100.0), (width, height));
+        let mut scanner = Scanner::new(width, height);
+        let expected = scanner.scan_gray_u8(&gray).unwrap();
+        assert_eq!(expected.len(), 1);
 
-        for c in candidates {
-            if !self.overlaps(&spots, c.x, c.y) {
-                let mut spot = TopCode::default();
-                spot.decode(self, c.x, c.y);
-                if spot.is_valid() {
-                    spots.push(spot);
-                }
-            }
+        // An NV12 frame with padded rows and an interleaved chroma plane.
+        let stride = width + 24;
+        let mut frame = vec![0; stride * height + stride * height / 2];
+        for (row, pixels) in gray.chunks(width).enumerate() {
+            frame[row * stride..row * stride + width].copy_from_slice(pixels);
         }
+        frame[stride * height..].fill(128);
+        assert_eq!(scanner.scan_yuv420(&frame, stride), Ok(expected.clone()));
 
-        spots
+        // The Y plane on its own, without padding after the last row.
+        let y_plane = &frame[..stride * (height - 1) + width];
+        assert_eq!(scanner.scan_yuv420(y_plane, stride), Ok(expected));
+
+        assert_eq!(
+            scanner.scan_yuv420(&frame[..stride * (height - 1)], stride),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
+        assert_eq!(
+            scanner.scan_yuv420(&frame, width - 1),
+            Err(TopCodeError::IncorrectBufferSize)
+        );
     }
 
-    fn overlaps(&self, spots: &Vec<TopCode>, x: usize, y: usize) -> bool {
-        for top in spots {
-            if top.in_bullseye(x as f64, y as f64) {
-                return true;
+    #[test]
+    fn inverted_codes_are_found_with_their_polarity() {
+        let (width, height) = (200, 200);
+        let gray = render_topcode(93, 12.0, 0.7, (100.0, 100.0), (width, height));
+        let inverted: Vec<u8> = gray.iter().map(|&v| 255 - v).collect();
+        let mut scanner = Scanner::new(width, height);
+        assert!(scanner.scan_gray_u8(&inverted).unwrap().is_empty());
+
+        scanner.set_polarity(Polarity::Inverted);
+        let codes = scanner.scan_gray_u8(&inverted).unwrap();
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].code, Some(93));
+        assert!(scanner.scan_gray_u8(&gray).unwrap().is_empty());
+
+        // A normal code on the left, and an inverted code on a dark panel on the right.
+        let (width, height) = (400, 200);
+        let left = render_topcode(93, 12.0, 0.7, (100.0, 100.0), (width, height));
+        let right = render_topcode(55, 12.0, 0.0, (300.0, 100.0), (width, height));
+        let both: Vec<u8> = left
+            .iter()
+            .zip(&right)
+            .enumerate()
+            .map(|(index, (&l, &r))| if index % width < 200 { l } else { 255 - r })
+            .collect();
+        let mut scanner = Scanner::new(width, height);
+        scanner.set_polarity(Polarity::Both);
+        let mut codes: Vec<_> = scanner
+            .scan_gray_u8(&both)
+            .unwrap()
+            .iter()
+            .map(|c| c.code)
+            .collect();
+        codes.sort();
+        assert_eq!(codes, vec![Some(55), Some(93)]);
+    }
+
+    #[test]
+    fn mirrored_images_are_read_as_if_flipped_back() {
+        let (width, height) = (240, 200);
+        let gray = render_topcode(47, 12.0, 0.7, (90.0, 110.0), (width, height));
+        let mut scanner = Scanner::new(width, height);
+        let expected = scanner.scan_gray_u8(&gray).unwrap();
+        assert_eq!(expected.len(), 1);
+        assert_eq!(expected[0].code, Some(47));
+
+        let mirrored: Vec<u8> = gray
+            .chunks(width)
+            .flat_map(|row| row.iter().rev().copied())
+            .collect();
+        let codes = scanner.scan_gray_u8(&mirrored).unwrap();
+        // The mirror image of 47 is a different code.
+        assert_eq!(codes.len(), 1);
+        assert_ne!(codes[0].code, Some(47));
+
+        scanner.set_mirrored(true);
+        let codes = scanner.scan_gray_u8(&mirrored).unwrap();
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].code, Some(47));
+        assert!((codes[0].x - expected[0].x).abs() < 1.0);
+        assert!((codes[0].y - expected[0].y).abs() < 1.0);
+        assert!((codes[0].orientation - expected[0].orientation).abs() < 0.1);
+    }
+
+    #[test]
+    fn raw_buffers_are_adopted_and_returned() {
+        let (width, height) = (200, 200);
+        let buffer = render_topcode(55, 12.0, 0.0, (100.0, 100.0), (width, height));
+
+        assert_eq!(
+            Scanner::from_raw(width, height, vec![0; 10]).err(),
+            Some(TopCodeError::IncorrectBufferSize)
+        );
+
+        let data = vec![0; Scanner::buffer_len(width, height)];
+        let pointer = data.as_ptr();
+        let mut scanner = Scanner::from_raw(width, height, data).unwrap();
+        let codes: Vec<_> = scan_gray(&mut scanner, &buffer)
+            .iter()
+            .map(|c| c.code)
+            .collect();
+        assert_eq!(codes, vec![Some(55)]);
+
+        let data = scanner.take_data();
+        assert_eq!(data.as_ptr(), pointer);
+        assert_eq!(data.len(), Scanner::buffer_len(width, height));
+    }
+
+    #[test]
+    fn pixel_data_takes_a_little_over_two_bits_per_pixel() {
+        const LEN: usize = Scanner::buffer_len(3840, 2160);
+        assert_eq!(LEN, 2 * 3840 * 2160 / 32 + 3840);
+        // An order of magnitude less than one word per pixel.
+        assert_eq!(LEN * 10 / (3840 * 2160), 0);
+        assert_eq!(Scanner::buffer_len(3, 3), 2 + 3);
+    }
+
+    #[test]
+    fn samples_match_the_threshold_mask() {
+        let (mut scanner, buffer) = setup("photo");
+        scanner.scan(&buffer, |buffer, index| {
+            (
+                buffer[index * 3] as u32,
+                buffer[index * 3 + 1] as u32,
+                buffer[index * 3 + 2] as u32,
+            )
+        });
+        let mask = scanner.threshold_mask();
+        let width = scanner.image_width();
+        // Every column of a few rows, so that the samples straddle words of the bitmask.
+        for y in [1, 250, 493, 1000] {
+            for x in 1..width - 1 {
+                let whites: usize = (y - 1..=y + 1)
+                    .flat_map(|j| (x - 1..=x + 1).map(move |i| j * width + i))
+                    .map(|index| mask[index] as usize / 0xff)
+                    .sum();
+                assert_eq!(scanner.get_sample_3x3(x, y), 0xff * whites / 9);
+                assert_eq!(scanner.get_bw_3x3(x, y), (whites >= 5) as u32);
             }
         }
+    }
 
-        false
+    #[test]
+    fn borrowed_buffers_are_scanned_in_place() {
+        let (width, height) = (200, 200);
+        let buffer = render_topcode(55, 12.0, 0.0, (100.0, 100.0), (width, height));
+        let expected = scan_gray(&mut Scanner::new(width, height), &buffer);
+
+        let len = Scanner::buffer_len(width, height);
+        let mut data = vec![0; len - 1];
+        assert_eq!(
+            BorrowedScanner::with_buffer(width, height, &mut data).err(),
+            Some(TopCodeError::IncorrectBufferSize)
+        );
+        let mut data = vec![0; len];
+        let mut scanner = BorrowedScanner::with_buffer(width, height, &mut data).unwrap();
+        assert_eq!(scan_gray(&mut scanner, &buffer), expected);
+        drop(scanner);
+        // The thresholded image was written to the borrowed buffer.
+        assert!(data.iter().any(|&word| word != 0));
     }
 
-    /// Counts the number of pixels from (x, y) until a color change is perceived.
-    pub(crate) fn dist(&self, x: usize, y: usize, dx: isize, dy: isize) -> isize {
-        let start = self.get_bw_3x3(x, y);
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn fixed_scans_match_the_heap_scan() {
+        let (width, height) = (400, 200);
+        let left = render_topcode(55, 12.0, 0.0, (100.0, 100.0), (width, height));
+        let right = render_topcode(93, 12.0, 0.7, (300.0, 100.0), (width, height));
+        let buffer: Vec<u8> = left.iter().zip(&right).map(|(l, r)| *l.min(r)).collect();
+        let decode = |buffer: &Vec<u8>, index: usize| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        };
+        let expected = Scanner::new(width, height).scan(&buffer, decode);
+        assert_eq!(expected.len(), 2);
 
-        let mut i = x as isize + dx;
-        let mut j = y as isize + dy;
+        let mut data = vec![0; Scanner::buffer_len(width, height)];
+        let mut scanner = BorrowedScanner::with_buffer(width, height, &mut data).unwrap();
+        let mut candidates = heapless::Vec::<Candidate, 256>::new();
+        let mut codes = heapless::Vec::<TopCode, 4>::new();
+        scanner.scan_fixed(&buffer, decode, &mut candidates, &mut codes);
+        assert!(!candidates.is_empty());
+        assert_eq!(codes.as_slice(), expected.as_slice());
 
-        loop {
-            if i <= 1 || i >= self.width as isize - 1 || j <= 1 || j >= self.height as isize {
-                break;
-            }
+        // Codes beyond the capacity are dropped.
+        let mut codes = heapless::Vec::<TopCode, 1>::new();
+        scanner.scan_fixed(&buffer, decode, &mut candidates, &mut codes);
+        assert_eq!(codes.as_slice(), &expected[..1]);
+    }
 
-            let sample = self.get_bw_3x3(i as usize, j as usize);
-            if start + sample == 1 {
-                let x_dist = (i - x as isize).abs();
-                let y_dist = (j - y as isize).abs();
-                return x_dist + y_dist;
+    #[test]
+    fn scans_into_reused_vectors_match_the_scan() {
+        let (width, height) = (400, 200);
+        let left = render_topcode(55, 12.0, 0.0, (100.0, 100.0), (width, height));
+        let right = render_topcode(93, 12.0, 0.7, (300.0, 100.0), (width, height));
+        let mut buffer: Vec<u8> = left.iter().zip(&right).map(|(l, r)| *l.min(r)).collect();
+        let decode = |buffer: &Vec<u8>, index: usize| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        };
+        let mut scanner = Scanner::new(width, height);
+        scanner.set_polarity(Polarity::Both);
+        let expected = scanner.scan(&buffer, decode);
+        assert_eq!(expected.len(), 2);
+
+        let (mut candidates, mut codes) = (Vec::new(), Vec::new());
+        scanner.scan_into(&buffer, decode, &mut candidates, &mut codes);
+        assert!(!candidates.is_empty());
+        assert_eq!(codes, expected);
+
+        // The vectors are cleared and reused for the next frame, here with the left code inverted.
+        let pointers = (candidates.as_ptr(), codes.as_ptr());
+        for row in buffer.chunks_mut(width) {
+            for pixel in &mut row[..width / 2] {
+                *pixel = 255 - *pixel;
             }
+        }
+        let expected = scanner.scan(&buffer, decode);
+        assert_eq!(expected.len(), 2);
+        scanner.scan_into(&buffer, decode, &mut candidates, &mut codes);
+        assert_eq!(codes, expected);
+        assert_eq!((candidates.as_ptr(), codes.as_ptr()), pointers);
+    }
 
-            i += dx;
-            j += dy;
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_decoding_matches_the_serial_decoding() {
+        // A grid of 24 codes, decoded serially by `scan_into` and in parallel by `find_codes`.
+        let (width, height) = (600, 400);
+        let mut buffer = vec![255; width * height];
+        for (i, code) in crate::codes::CODES.iter().take(24).enumerate() {
+            let center = (
+                50.0 + (i % 6) as Real * 100.0,
+                50.0 + (i / 6) as Real * 100.0,
+            );
+            let single = render_topcode(*code, 5.0, 0.3, center, (width, height));
+            for (pixel, v) in buffer.iter_mut().zip(single) {
+                *pixel = (*pixel).min(v);
+            }
+        }
+        let decode = |buffer: &Vec<u8>, index: usize| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        };
+        let mut scanner = Scanner::new(width, height);
+        for prefer_inner in [false, true] {
+            scanner.set_prefer_inner(prefer_inner);
+            let (mut candidates, mut serial) = (Vec::new(), Vec::new());
+            scanner.scan_into(&buffer, decode, &mut candidates, &mut serial);
+            assert_eq!(serial.len(), 24);
+            assert_eq!(scanner.find_codes(&candidates), serial);
         }
 
-        -1
+        let (mut scanner, buffer) = setup("source");
+        scanner.set_prefer_inner(true);
+        let (mut candidates, mut serial) = (Vec::new(), Vec::new());
+        let decode_rgb = |buffer: &Vec<u8>, index: usize| {
+            (
+                buffer[index * 3] as u32,
+                buffer[index * 3 + 1] as u32,
+                buffer[index * 3 + 2] as u32,
+            )
+        };
+        scanner.scan_into(&buffer, decode_rgb, &mut candidates, &mut serial);
+        assert_eq!(serial.len(), 3);
+        assert_eq!(scanner.find_codes(&candidates), serial);
     }
 
-    #[cfg(feature = "visualize")]
-    pub fn write_thresholding_image(&self, path: &str) {
-        let img = GrayImage::from_fn(self.width as u32, self.height as u32, |x, y| {
-            let index = (y * self.width as u32 + x) as usize;
-            let pixel = self.data[index];
-            let a = ((pixel >> 24) * 0xff) as u8;
-            image::Luma([a])
-        });
-        img.save(path).expect("Failed to save png image");
-    }
-}
+    #[test]
+    fn it_reports_progress_for_each_frame_in_a_batch() {
+        let (width, height) = (200, 200);
+        let first = render_topcode(55, 12.0, 0.0, (100.0, 100.0), (width, height));
+        let second = render_topcode(31, 12.0, 0.0, (90.0, 110.0), (width, height));
+        let blank = vec![255; width * height];
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use image::io::Reader as ImageReader;
+        let mut scanner = Scanner::new(width, height);
+        let mut reports = Vec::new();
+        let results = scanner.scan_batch_with_progress(
+            &[&first[..], &blank[..], &second[..]],
+            |buffer, index| {
+                let v = buffer[index] as u32;
+                (v, v, v)
+            },
+            |done, total| reports.push((done, total)),
+        );
 
-    fn setup(asset_name: &str) -> (Scanner, Vec<u8>) {
-        let img = ImageReader::open(format!("assets/{}.png", asset_name))
-            .unwrap()
-            .decode()
-            .unwrap();
-        let (width, height) = (img.width() as usize, img.height() as usize);
-        let image_raw = img.into_rgb8().into_raw();
-        (Scanner::new(width, height), image_raw)
+        assert_eq!(reports, vec![(1, 3), (2, 3), (3, 3)]);
+        let codes: Vec<Vec<_>> = results
+            .iter()
+            .map(|codes| codes.iter().map(|c| c.code).collect())
+            .collect();
+        assert_eq!(codes, vec![vec![Some(55)], vec![], vec![Some(31)]]);
     }
 
     #[test]
@@ -313,16 +4145,18 @@ mod test {
             )
         });
 
-        assert_eq!(
-            topcodes,
-            vec![
+        assert_matches_fixture(
+            &topcodes,
+            &[
                 TopCode {
                     code: Some(55),
                     unit: 48.8125,
                     orientation: -0.07249829200591831,
                     x: 1803.0,
                     y: 878.0,
-                    core: [0, 255, 0, 255, 255, 0, 255, 255]
+                    core: [0, 255, 0, 255, 255, 0, 255, 255],
+                    extended: None,
+                    second: None,
                 },
                 TopCode {
                     code: Some(31),
@@ -330,7 +4164,9 @@ mod test {
                     orientation: -0.07249829200591831,
                     x: 618.0,
                     y: 923.0,
-                    core: [0, 255, 0, 255, 255, 0, 255, 255]
+                    core: [0, 255, 0, 255, 255, 0, 255, 255],
+                    extended: None,
+                    second: None,
                 },
                 TopCode {
                     code: Some(93),
@@ -338,9 +4174,11 @@ mod test {
                     orientation: -0.07249829200591831,
                     x: 1275.3333333333333,
                     y: 1704.0,
-                    core: [56, 255, 0, 255, 255, 0, 255, 255]
-                }
-            ]
+                    core: [56, 255, 0, 255, 255, 0, 255, 255],
+                    extended: None,
+                    second: None,
+                },
+            ],
         );
     }
 
@@ -355,16 +4193,18 @@ mod test {
             )
         });
 
-        assert_eq!(
-            topcodes,
-            vec![
+        assert_matches_fixture(
+            &topcodes,
+            &[
                 TopCode {
                     code: Some(55),
                     unit: 22.44375,
                     orientation: -0.07249829200591831,
                     x: 996.8333333333334,
                     y: 493.5,
-                    core: [0, 255, 0, 255, 255, 0, 255, 255]
+                    core: [0, 255, 0, 255, 255, 0, 255, 255],
+                    extended: None,
+                    second: None,
                 },
                 TopCode {
                     code: Some(31),
@@ -372,7 +4212,9 @@ mod test {
                     orientation: 0.024166097335306114,
                     x: 366.5,
                     y: 510.0,
-                    core: [0, 255, 0, 255, 255, 0, 255, 255]
+                    core: [0, 255, 0, 255, 255, 0, 255, 255],
+                    extended: None,
+                    second: None,
                 },
                 TopCode {
                     code: Some(93),
@@ -380,9 +4222,11 @@ mod test {
                     orientation: -0.07249829200591831,
                     x: 718.8333333333334,
                     y: 929.5,
-                    core: [113, 255, 0, 255, 255, 0, 255, 255]
-                }
-            ]
+                    core: [113, 255, 0, 255, 255, 0, 255, 255],
+                    extended: None,
+                    second: None,
+                },
+            ],
         );
     }
 }
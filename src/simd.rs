@@ -0,0 +1,110 @@
+//! SIMD kernels for thresholding rows of packed 8-bit pixels, processing 16 pixels at a time.
+//! Each kernel gives exactly the same results as the scalar code in the scanner.
+
+use wide::{i32x8, CmpLt};
+
+/// Number of pixels processed per step.
+const LANES: usize = 16;
+
+/// Computes the intensity (0-255) of each pixel of `row`, which holds `out.len()` pixels of
+/// `bytes_per_pixel` bytes each, as the average of the channels at the offsets `[r, g, b]`.
+pub(crate) fn luma(row: &[u8], bytes_per_pixel: usize, channels: [usize; 3], out: &mut [u32]) {
+    match bytes_per_pixel {
+        1 => luma_packed::<1>(row, channels, out),
+        3 => luma_packed::<3>(row, channels, out),
+        4 => luma_packed::<4>(row, channels, out),
+        _ => luma_scalar(row, bytes_per_pixel, channels, out),
+    }
+}
+
+/// Same as [luma] for pixels of `N` bytes.
+fn luma_packed<const N: usize>(row: &[u8], channels: [usize; 3], out: &mut [u32]) {
+    let [r, g, b] = channels.map(|offset| offset.min(N - 1));
+    let chunks = out.len() / LANES;
+    let pixels = row.chunks_exact(LANES * N);
+    for (pixels, out) in pixels.zip(out.chunks_exact_mut(LANES)) {
+        let pixels: &[u8; LANES * 4] = &pad(pixels);
+        for half in 0..2 {
+            let channel = |offset: usize| {
+                i32x8::new(core::array::from_fn(|i| {
+                    pixels[(half * 8 + i) * N + offset] as i32
+                }))
+            };
+            // Exact division of sums up to 765 by 3.
+            let luma: i32x8 = ((channel(r) + channel(g) + channel(b)) * 43691) >> 17;
+            for (out, luma) in out[half * 8..half * 8 + 8].iter_mut().zip(luma.to_array()) {
+                *out = luma as u32;
+            }
+        }
+    }
+    let start = chunks * LANES;
+    luma_scalar(&row[start * N..], N, [r, g, b], &mut out[start..]);
+}
+
+/// Copies a chunk of up to `LANES` pixels into a fixed-size array, so that indexing it needs no
+/// bounds checks.
+fn pad(pixels: &[u8]) -> [u8; LANES * 4] {
+    let mut padded = [0; LANES * 4];
+    padded[..pixels.len()].copy_from_slice(pixels);
+    padded
+}
+
+/// Same as [luma], one pixel at a time.
+fn luma_scalar(row: &[u8], bytes_per_pixel: usize, [r, g, b]: [usize; 3], out: &mut [u32]) {
+    for (pixel, out) in row.chunks_exact(bytes_per_pixel).zip(out) {
+        *out = (pixel[r] as u32 + pixel[g] as u32 + pixel[b] as u32) / 3;
+    }
+}
+
+/// Classifies each pixel of a row as black (intensity below its limit) or white, setting bit
+/// `i % 32` of `bits[i / 32]` for white pixels. Bits past the end of the row are cleared.
+pub(crate) fn classify(luma: &[u32], limits: &[u32], bits: &mut [u32]) {
+    bits.fill(0);
+    let lanes = |values: &[u32; LANES], half: usize| {
+        i32x8::new(core::array::from_fn(|i| values[half * 8 + i] as i32))
+    };
+    let chunks = luma.chunks_exact(LANES).zip(limits.chunks_exact(LANES));
+    for (chunk, (luma, limits)) in chunks.enumerate() {
+        let (luma, limits) = (luma.try_into().unwrap(), limits.try_into().unwrap());
+        let mut black = 0;
+        for half in 0..2 {
+            let mask = lanes(luma, half).cmp_lt(lanes(limits, half)).move_mask() as u32;
+            black |= mask << (half * 8);
+        }
+        let white = !black & 0xffff;
+        bits[chunk / 2] |= white << (chunk % 2 * LANES);
+    }
+    for i in luma.len() / LANES * LANES..luma.len() {
+        if luma[i] >= limits[i] {
+            bits[i / 32] |= 1 << (i % 32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernels_match_the_scalar_math() {
+        let width = 37;
+        let row: Vec<u8> = (0..width * 4).map(|i| (i * 97 % 256) as u8).collect();
+        let mut out = vec![0; width];
+        luma(&row, 4, [2, 1, 0], &mut out);
+        for (i, luma) in out.iter().enumerate() {
+            let pixel = &row[i * 4..];
+            assert_eq!(
+                *luma,
+                (pixel[0] as u32 + pixel[1] as u32 + pixel[2] as u32) / 3
+            );
+        }
+
+        let limits: Vec<u32> = (0..width as u32).map(|i| i * 53 % 256).collect();
+        let mut bits = vec![u32::MAX; 2];
+        classify(&out, &limits, &mut bits);
+        for i in 0..64 {
+            let white = i < width && out[i] >= limits[i];
+            assert_eq!(bits[i / 32] >> (i % 32) & 0x01 == 1, white, "{}", i);
+        }
+    }
+}
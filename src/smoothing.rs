@@ -0,0 +1,175 @@
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::topcode::{Real, PI};
+
+/// Filters which smooth the positions and orientations reported by a
+/// [Tracker](crate::Tracker), to steady overlays against the jitter of per-frame detections. Time
+/// is measured in frames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Smoothing {
+    /// The 1€ filter by Casiez et al., a low-pass filter whose cutoff frequency rises with the
+    /// speed of the marker, so that static markers are steady while moving markers lag little.
+    OneEuro {
+        /// Cutoff frequency (in cycles per frame) while the marker is at rest. Lower values
+        /// smooth more. Around 0.05 is a good start.
+        min_cutoff: Real,
+        /// Increase of the cutoff frequency per unit of speed (in pixels or radians per frame).
+        /// Higher values reduce the lag of moving markers.
+        beta: Real,
+    },
+    /// A Kalman filter with a constant velocity model.
+    Kalman {
+        /// Variance of the change in velocity between frames. Higher values follow accelerating
+        /// markers more closely.
+        process_noise: Real,
+        /// Variance of the detected values. Higher values smooth more.
+        measurement_noise: Real,
+    },
+}
+
+/// Cutoff frequency (in cycles per frame) of the low-pass filter of the speed in the 1€ filter.
+const DERIVATIVE_CUTOFF: Real = 1.0;
+
+/// Smooths a single value with [Smoothing] across frames.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Filter {
+    smoothing: Smoothing,
+    value: Real,
+    /// Smoothed speed of the 1€ filter, or the estimated velocity of the Kalman filter
+    rate: Real,
+    /// Covariance of the value and velocity of the Kalman filter
+    covariance: [[Real; 2]; 2],
+}
+
+impl Filter {
+    pub(crate) fn new(smoothing: Smoothing, value: Real) -> Self {
+        let variance = match smoothing {
+            Smoothing::OneEuro { .. } => 0.0,
+            Smoothing::Kalman {
+                measurement_noise, ..
+            } => measurement_noise,
+        };
+        Self {
+            smoothing,
+            value,
+            rate: 0.0,
+            covariance: [[variance, 0.0], [0.0, variance]],
+        }
+    }
+
+    /// Feeds the value detected in the next frame through the filter, returning the smoothed
+    /// value.
+    pub(crate) fn update(&mut self, measurement: Real) -> Real {
+        match self.smoothing {
+            Smoothing::OneEuro { min_cutoff, beta } => {
+                let speed = measurement - self.value;
+                self.rate += alpha(DERIVATIVE_CUTOFF) * (speed - self.rate);
+                let cutoff = min_cutoff + beta * self.rate.abs();
+                self.value += alpha(cutoff) * (measurement - self.value);
+            }
+            Smoothing::Kalman {
+                process_noise,
+                measurement_noise,
+            } => {
+                let [[p00, p01], [p10, p11]] = self.covariance;
+                // Predict one frame ahead, with the velocity changing by white noise.
+                self.value += self.rate;
+                let q = process_noise;
+                let p00 = p00 + p01 + p10 + p11 + q / 4.0;
+                let p01 = p01 + p11 + q / 2.0;
+                let p10 = p10 + p11 + q / 2.0;
+                let p11 = p11 + q;
+
+                let s = p00 + measurement_noise;
+                let (k0, k1) = (p00 / s, p10 / s);
+                let residual = measurement - self.value;
+                self.value += k0 * residual;
+                self.rate += k1 * residual;
+                self.covariance = [
+                    [(1.0 - k0) * p00, (1.0 - k0) * p01],
+                    [p10 - k1 * p00, p11 - k1 * p01],
+                ];
+            }
+        }
+        self.value
+    }
+
+    /// Same as [Filter::update] for angles (in radians), which are unwrapped to the turn closest to
+    /// the smoothed angle before filtering. The smoothed angle is returned in the same turn as
+    /// `angle`.
+    pub(crate) fn update_angle(&mut self, angle: Real) -> Real {
+        let delta = (angle - self.value + PI).rem_euclid(2.0 * PI) - PI;
+        let unwrapped = self.value + delta;
+        self.update(unwrapped) - unwrapped + angle
+    }
+}
+
+/// Smoothing factor of an exponential low-pass filter with the given cutoff frequency (in cycles
+/// per frame).
+fn alpha(cutoff: Real) -> Real {
+    let tau = 1.0 / (2.0 * PI * cutoff);
+    1.0 / (1.0 + tau)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Root mean square of the differences between the values and the truth.
+    fn error(values: &[Real], truth: impl Fn(usize) -> Real) -> Real {
+        let sum: Real = values
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (v - truth(i)) * (v - truth(i)))
+            .sum();
+        (sum / values.len() as Real).sqrt()
+    }
+
+    #[test]
+    fn filters_reduce_jitter() {
+        let jitter = [1.5, -1.5, 0.5, -0.5, 1.0, -1.0];
+        let kalman = Smoothing::Kalman {
+            process_noise: 0.01,
+            measurement_noise: 1.0,
+        };
+        let one_euro = Smoothing::OneEuro {
+            min_cutoff: 0.05,
+            beta: 0.1,
+        };
+
+        // Static markers, and one moving 2 pixels per frame, which the Kalman filter follows
+        // without lag.
+        for (smoothing, speed) in [(one_euro, 0.0), (kalman, 0.0), (kalman, 2.0)] {
+            let truth = |i: usize| 100.0 + speed * i as Real;
+            let detected: Vec<Real> = (0..60).map(|i| truth(i) + jitter[i % 6]).collect();
+            let mut filter = Filter::new(smoothing, detected[0]);
+            let smoothed: Vec<Real> = detected.iter().map(|&x| filter.update(x)).collect();
+            // Skip the frames in which the filter settles.
+            let settled = |i| truth(i + 20);
+            assert!(
+                error(&smoothed[20..], settled) < error(&detected[20..], settled) / 2.0,
+                "{:?}",
+                smoothing
+            );
+        }
+    }
+
+    #[test]
+    fn angles_are_smoothed_across_the_wrap_around() {
+        let mut filter = Filter::new(
+            Smoothing::OneEuro {
+                min_cutoff: 0.05,
+                beta: 0.0,
+            },
+            0.05,
+        );
+        for i in 0..20 {
+            let angle = if i % 2 == 0 { 2.0 * PI - 0.05 } else { 0.05 };
+            let smoothed = filter.update_angle(angle);
+            let delta = (smoothed - angle + PI).rem_euclid(2.0 * PI) - PI;
+            assert!(delta.abs() < 0.11, "{} {}", angle, smoothed);
+        }
+    }
+}
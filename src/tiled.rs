@@ -0,0 +1,220 @@
+use alloc::vec::Vec;
+
+use crate::{
+    errors::TopCodeError,
+    scanner::{ScanBuffer, Scanner, ScannerConfig},
+    topcode::{Real, TopCode},
+};
+
+/// Scans images too large to threshold in one go (e.g. 100-megapixel scans of posters) in
+/// overlapping tiles, so that memory use is bounded by the size of a tile rather than that of the
+/// image.
+///
+/// Tiles are requested one at a time from a callback and scanned by a scanner of the tile's
+/// dimensions. Neighbouring tiles overlap by the scanner's maximum code diameter, so every code
+/// lies entirely within at least one tile. Codes cut off by the border of a tile are dropped, and
+/// codes found in several tiles are reported once, in image coordinates.
+#[derive(Clone)]
+pub struct TiledScanner {
+    scanner: Scanner,
+    width: usize,
+    height: usize,
+}
+
+impl TiledScanner {
+    /// Creates a tiled scanner for images of `width` x `height` pixels, scanning tiles of the
+    /// dimensions of `scanner` with its settings. Fails if the tiles are no larger than the
+    /// maximum code diameter of the scanner (see [Scanner::set_max_code_diameter]).
+    pub fn new(scanner: Scanner, width: usize, height: usize) -> Result<Self, TopCodeError> {
+        check_tile(&scanner)?;
+        Ok(Self {
+            scanner,
+            width,
+            height,
+        })
+    }
+
+    /// The scanner used to scan each tile.
+    pub fn scanner(&self) -> &Scanner {
+        &self.scanner
+    }
+
+    /// Applies the settings of `config` to the scanner used to scan each tile, e.g. to adjust
+    /// them between images. Fails, leaving the settings unchanged, if the tiles would be no larger
+    /// than the new maximum code diameter.
+    pub fn set_config(&mut self, config: &ScannerConfig) -> Result<(), TopCodeError> {
+        let previous = self.scanner.config();
+        self.scanner.set_config(config);
+        check_tile(&self.scanner).inspect_err(|_| self.scanner.set_config(&previous))
+    }
+
+    /// Scans the image a tile at a time and returns the TopCodes found in it.
+    ///
+    /// `load_tile(x, y, width, height)` is called for each tile with the rectangle it covers,
+    /// which is clipped to the image, and returns its pixels. `decode_rgb` then reads them like
+    /// in [Scanner::scan], with indices running over the rectangle in row-major order. Tiles
+    /// are requested from left to right and top to bottom, and each is dropped before the next
+    /// is loaded.
+    pub fn scan<T>(
+        &mut self,
+        mut load_tile: impl FnMut(usize, usize, usize, usize) -> T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+    ) -> Vec<TopCode> {
+        let (tile_width, tile_height) = (self.scanner.image_width(), self.scanner.image_height());
+        let overlap = self.scanner.max_code_diameter();
+        // The lens model applies to the image as a whole rather than to each tile.
+        let lens_model = self.scanner.take_lens_model();
+
+        let mut codes: Vec<TopCode> = Vec::new();
+        for y0 in tile_starts(self.height, tile_height, overlap) {
+            for x0 in tile_starts(self.width, tile_width, overlap) {
+                let width = tile_width.min(self.width - x0);
+                let height = tile_height.min(self.height - y0);
+                let tile = load_tile(x0, y0, width, height);
+                // Edge tiles are padded with white, which never forms part of a bullseye.
+                let found = self.scanner.scan(&tile, |tile, index| {
+                    let (x, y) = (index % tile_width, index / tile_width);
+                    if x < width && y < height {
+                        decode_rgb(tile, y * width + x)
+                    } else {
+                        (255, 255, 255)
+                    }
+                });
+
+                for mut code in found {
+                    // Whether the code lies within the tile along one axis, or is only cut off by
+                    // the border of the image.
+                    let r = code.radius();
+                    let inside = |center: Real, start: usize, len: usize, end: usize| {
+                        (start == 0 || center - r >= 0.0)
+                            && (start + len == end || center + r <= len as Real)
+                    };
+                    if !inside(code.x, x0, width, self.width)
+                        || !inside(code.y, y0, height, self.height)
+                    {
+                        continue;
+                    }
+                    code.set_location(code.x + x0 as Real, code.y + y0 as Real);
+                    if !codes.iter().any(|other| other.in_bullseye(code.x, code.y)) {
+                        codes.push(code);
+                    }
+                }
+            }
+        }
+
+        if let Some(lens) = lens_model {
+            for code in &mut codes {
+                let (x, y) = lens.undistort(code.x, code.y);
+                code.set_location(x, y);
+            }
+            self.scanner.set_lens_model(lens);
+        }
+        codes
+    }
+}
+
+/// Checks that the tiles scanned by `scanner` are larger than its maximum code diameter, so that
+/// consecutive tiles can overlap by it.
+fn check_tile(scanner: &Scanner<impl ScanBuffer>) -> Result<(), TopCodeError> {
+    let (width, height) = (scanner.image_width(), scanner.image_height());
+    let overlap = scanner.max_code_diameter();
+    if width <= overlap || height <= overlap {
+        return Err(TopCodeError::TileTooSmall { width, height });
+    }
+    Ok(())
+}
+
+/// Offsets of the tiles of length `tile` covering `len` pixels, with consecutive tiles
+/// overlapping by `overlap` pixels.
+fn tile_starts(len: usize, tile: usize, overlap: usize) -> impl Iterator<Item = usize> {
+    let step = tile - overlap;
+    (0..len)
+        .step_by(step)
+        .take_while(move |start| *start == 0 || start + overlap < len)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::utils::render_topcode;
+
+    #[test]
+    fn tiles_overlap_to_cover_the_image() {
+        assert_eq!(tile_starts(100, 100, 20).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(tile_starts(101, 100, 20).collect::<Vec<_>>(), vec![0, 80]);
+        assert_eq!(
+            tile_starts(250, 100, 20).collect::<Vec<_>>(),
+            vec![0, 80, 160]
+        );
+        assert_eq!(tile_starts(10, 100, 20).collect::<Vec<_>>(), vec![0]);
+    }
+
+    #[test]
+    fn tiled_scans_find_codes_across_tile_borders() {
+        let (width, height) = (700, 500);
+        let centers = [
+            (93, (60.0, 60.0)),
+            (55, (200.0, 250.0)),
+            (31, (330.0, 90.0)),
+            (47, (640.0, 440.0)),
+        ];
+        let mut image = vec![255u8; width * height];
+        for (code, center) in centers {
+            let single = render_topcode(code, 6.0, 0.2, center, (width, height));
+            for (pixel, v) in image.iter_mut().zip(single) {
+                *pixel = (*pixel).min(v);
+            }
+        }
+
+        let mut scanner = Scanner::new(256, 256);
+        scanner.set_max_code_diameter(80);
+        let mut tiled = TiledScanner::new(scanner, width, height).unwrap();
+        let mut tiles = Vec::new();
+        let mut codes = tiled.scan(
+            |x, y, w, h| {
+                tiles.push((x, y, w, h));
+                (0..h)
+                    .flat_map(|j| image[(y + j) * width + x..][..w].to_vec())
+                    .collect::<Vec<u8>>()
+            },
+            |tile, index| {
+                let v = tile[index] as u32;
+                (v, v, v)
+            },
+        );
+        assert_eq!(tiles.len(), 4 * 3);
+        assert!(tiles.contains(&(352, 352, 256, 148)));
+
+        codes.sort_by_key(|code| code.code);
+        let mut expected = centers.to_vec();
+        expected.sort_by_key(|(code, _)| *code);
+        assert_eq!(codes.len(), expected.len());
+        for (code, (id, (x, y))) in codes.iter().zip(expected) {
+            assert_eq!(code.code, Some(id));
+            assert!(
+                (code.x - x).abs() < 1.0 && (code.y - y).abs() < 1.0,
+                "{:?}",
+                code
+            );
+        }
+
+        // The maximum code diameter can't be raised to the size of a tile later on either.
+        let config = ScannerConfig {
+            max_code_diameter: 256,
+            ..tiled.scanner().config()
+        };
+        assert!(tiled.set_config(&config).is_err());
+        assert_eq!(tiled.scanner().max_code_diameter(), 80);
+
+        let scanner = Scanner::new(64, 64);
+        assert_eq!(
+            TiledScanner::new(scanner, width, height).err(),
+            Some(TopCodeError::TileTooSmall {
+                width: 64,
+                height: 64
+            })
+        );
+    }
+}
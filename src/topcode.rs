@@ -1,18 +1,53 @@
-use std::f64::consts::PI;
+use alloc::{format, string::String, vec, vec::Vec};
 
-use crate::scanner::Scanner;
+#[cfg(feature = "f32")]
+pub(crate) use core::f32::consts::PI;
+#[cfg(not(feature = "f32"))]
+pub(crate) use core::f64::consts::PI;
+#[cfg(feature = "visualize")]
+use image::{DynamicImage, GrayImage};
+
+#[cfg(feature = "fixed-point")]
+use crate::fixed;
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::scanner::{ScanBuffer, Scanner};
+
+/// Floating-point type of the geometry of TopCodes (their position, unit and orientation) and of
+/// the math used to decode them. This is `f64`, or `f32` with the `f32` feature, which is faster
+/// on many ARM cores and in WASM at a small cost in accuracy.
+///
+/// The `f32` feature changes this public type for every crate in the build, so it should only be
+/// enabled by the final binary. Libraries should not assume either width.
+#[cfg(not(feature = "f32"))]
+pub type Real = f64;
+/// Floating-point type of the geometry of TopCodes (their position, unit and orientation) and of
+/// the math used to decode them. This is `f32` with the `f32` feature, which is faster on many
+/// ARM cores and in WASM at a small cost in accuracy, or `f64` otherwise.
+///
+/// The `f32` feature changes this public type for every crate in the build, so it should only be
+/// enabled by the final binary. Libraries should not assume either width.
+#[cfg(feature = "f32")]
+pub type Real = f32;
+
+/// Tolerance for values of [Real] that are zero up to rounding errors.
+#[cfg(not(feature = "f32"))]
+pub(crate) const EPSILON: Real = 1e-9;
+/// Tolerance for values of [Real] that are zero up to rounding errors.
+#[cfg(feature = "f32")]
+pub(crate) const EPSILON: Real = 1e-5;
 
 /// Number of sectors in the data ring
 pub(crate) const SECTORS: usize = 13;
 
 /// Width of the code in units (ring widths)
-const WIDTH: usize = 8;
+pub(crate) const WIDTH: usize = 8;
 
 /// The default diameter for a TopCode
-const DEFAULT_DIAMETER: f64 = 72.0;
+const DEFAULT_DIAMETER: Real = 72.0;
 
 /// Span of a data sector in radians
-const ARC: f64 = 2.0 * PI / (SECTORS as f64);
+pub(crate) const ARC: Real = 2.0 * PI / (SECTORS as Real);
 
 const MAX_PIXELS: usize = 100;
 
@@ -24,6 +59,26 @@ const MAX_PIXELS: usize = 100;
 /// line of code that should have to change.
 pub type Code = u32;
 
+/// The pose of a symbol relative to another, as returned by [TopCode::relative_to]. Positions are
+/// in units (ring widths) of the anchor symbol, along its orientation, and angles are in radians,
+/// wrapped to `(-PI, PI]`. A symbol to the right of and aligned with the anchor has a bearing and
+/// orientation close to zero.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RelativePose {
+    /// Position along the anchor's orientation
+    pub x: Real,
+    /// Position perpendicular to the anchor's orientation
+    pub y: Real,
+    /// Distance between the centers of the symbols, in units
+    pub distance: Real,
+    /// Distance between the centers of the symbols, in pixels
+    pub distance_px: Real,
+    /// Direction of the symbol as seen from the anchor, relative to the anchor's orientation
+    pub bearing: Real,
+    /// Orientation of the symbol relative to the anchor's orientation
+    pub orientation: Real,
+}
+
 /// TopCodes (Tangible Object Placement Codes) are black-and-white circular fiducials designed to
 /// be recognized quickly by low-resolution digital cameras with poor optics. The TopCode symmbol
 /// format is based on the open SpotCode format:
@@ -33,30 +88,38 @@ pub type Code = u32;
 /// Each TopCode encodes a 13-bit number in a single data ring on the outer edge of the symbol.
 /// Zero is represented by a black sector and one is represented by a white sector.
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TopCode {
     /// The symbol's code, if valid
     pub code: Option<Code>,
     /// Width of a single ring
-    pub unit: f64,
+    pub unit: Real,
     /// Angular orientation of the symbol (in radians)
-    pub orientation: f64,
+    pub orientation: Real,
     /// Horizontal center of a symbol
-    pub x: f64,
+    pub x: Real,
     /// Vertical center of a symbol
-    pub y: f64,
+    pub y: Real,
     /// Buffer used to decode sectors
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub(crate) core: [usize; WIDTH],
+    /// Bits read from the extended ring, if enabled with [Scanner::set_read_extended_ring]
+    pub(crate) extended: Option<u32>,
+    /// Pattern of the second data ring, if enabled with [Scanner::set_extended_symbology]
+    pub(crate) second: Option<Code>,
 }
 
 impl Default for TopCode {
     fn default() -> Self {
         Self {
             code: None,
-            unit: DEFAULT_DIAMETER / WIDTH as f64,
+            unit: DEFAULT_DIAMETER / WIDTH as Real,
             orientation: 0.0,
             x: 0.0,
             y: 0.0,
             core: [0; WIDTH],
+            extended: None,
+            second: None,
         }
     }
 }
@@ -71,10 +134,12 @@ impl TopCode {
     }
 
     /// Allows the creation of mock TopCodes for testing.
-    pub fn mock(code: Code, unit: f64, orientation: f64, x: f64, y: f64) -> TopCode {
+    pub fn mock(code: Code, unit: Real, orientation: Real, x: Real, y: Real) -> TopCode {
         TopCode {
             code: Some(code),
             core: [0; WIDTH],
+            extended: None,
+            second: None,
             orientation,
             unit,
             x,
@@ -82,8 +147,8 @@ impl TopCode {
         }
     }
 
-    pub fn radius(&self) -> f64 {
-        self.unit * WIDTH as f64 / 2.0
+    pub fn radius(&self) -> Real {
+        self.unit * WIDTH as Real / 2.0
     }
 
     pub fn to_json(&self) -> String {
@@ -94,8 +159,149 @@ impl TopCode {
         )
     }
 
+    /// The samples of the last decoded sector, rescaled so that the darkest sample maps to 0 and
+    /// the brightest to 255. This makes the samples comparable across lighting conditions. If all
+    /// samples are identical, they are all mapped to 0.
+    pub fn normalized_core(&self) -> [u8; WIDTH] {
+        let min = *self.core.iter().min().unwrap();
+        let max = *self.core.iter().max().unwrap();
+        let mut normalized = [0; WIDTH];
+
+        if max > min {
+            for (n, &c) in normalized.iter_mut().zip(&self.core) {
+                *n = ((c - min) * 0xff / (max - min)) as u8;
+            }
+        }
+
+        normalized
+    }
+
+    /// Expresses this symbol's pose in the local frame of `anchor`: translated to the anchor's
+    /// center, rotated by the anchor's orientation and scaled by the anchor's unit.
+    pub fn relative_to(&self, anchor: &TopCode) -> RelativePose {
+        let dx = self.x - anchor.x;
+        let dy = self.y - anchor.y;
+        let (sin, cos) = anchor.orientation.sin_cos();
+        let x = (dx * cos + dy * sin) / anchor.unit;
+        let y = (dy * cos - dx * sin) / anchor.unit;
+        let distance_px = dx.hypot(dy);
+
+        RelativePose {
+            x,
+            y,
+            distance: distance_px / anchor.unit,
+            distance_px,
+            bearing: y.atan2(x),
+            orientation: wrap_angle(self.orientation - anchor.orientation),
+        }
+    }
+
+    /// The center of the symbol as fractions of the scanned image's width and height, so that
+    /// application logic doesn't depend on the camera's resolution.
+    pub fn normalized(&self, scanner: &Scanner<impl ScanBuffer>) -> (Real, Real) {
+        (
+            self.x / scanner.image_width() as Real,
+            self.y / scanner.image_height() as Real,
+        )
+    }
+
+    /// The diameter of the symbol as a fraction of the scanned image's width.
+    pub fn diameter_normalized(&self, scanner: &Scanner<impl ScanBuffer>) -> Real {
+        self.radius() * 2.0 / scanner.image_width() as Real
+    }
+
+    /// The scale of the symbol's plane in millimeters per pixel, given the printed diameter of the
+    /// symbol. Only accurate when the symbol faces the camera.
+    pub fn mm_per_pixel(&self, physical_diameter_mm: Real) -> Real {
+        physical_diameter_mm / (self.radius() * 2.0)
+    }
+
+    /// A quick estimate of the distance (in millimeters) from the camera to the symbol, from its
+    /// printed diameter and the camera's focal length in pixels. Tilted symbols appear narrower
+    /// and so further away; see the [pose](crate::pose) module for a full estimate.
+    pub fn distance_mm(&self, physical_diameter_mm: Real, focal_length_px: Real) -> Real {
+        self.mm_per_pixel(physical_diameter_mm) * focal_length_px
+    }
+
+    /// Measures how crisp the symbol's ring edges are in the thresholded image, from 0 (no
+    /// contrast) to 1 (perfectly sharp). Samples are taken just inside and outside the edges of
+    /// the black ring around the bullseye. Out-of-focus symbols have ragged or displaced edges
+    /// after thresholding, so their samples straddle the edges and the contrast drops. Unlike
+    /// decoding confidence, this reflects how precisely the symbol's pose can be measured.
+    pub fn sharpness(&self, scanner: &Scanner<impl ScanBuffer>) -> f32 {
+        const ANGLES: usize = 16;
+        let offset = (self.unit * 0.25).max(1.5);
+        let sample = |radius: Real, angle: Real| {
+            let x = (self.x + radius * angle.cos()).round();
+            let y = (self.y + radius * angle.sin()).round();
+            if x < 0.0 || y < 0.0 {
+                0
+            } else {
+                scanner.get_sample_3x3(x as usize, y as usize)
+            }
+        };
+
+        let mut contrast = 0;
+        for i in 0..ANGLES {
+            let angle = i as Real * 2.0 * PI / ANGLES as Real;
+            for edge in [self.unit, self.unit * 2.0] {
+                contrast += sample(edge - offset, angle).abs_diff(sample(edge + offset, angle));
+            }
+        }
+        contrast as f32 / (ANGLES * 2 * 0xff) as f32
+    }
+
+    /// The fraction of the symbol's bullseye (the area within one unit of its center) whose
+    /// pixels were fully opaque in the image scanned with [Scanner::scan_with_alpha]. Codes which
+    /// are largely covered by transparent pixels (e.g. composited content) have a less reliable
+    /// pose. Pixels outside of the image count as not visible.
+    pub fn visible_fraction(&self, scanner: &Scanner<impl ScanBuffer>) -> f32 {
+        let x0 = (self.x - self.unit).floor().max(0.0) as usize;
+        let y0 = (self.y - self.unit).floor().max(0.0) as usize;
+        let x1 = (self.x + self.unit).ceil().max(0.0) as usize;
+        let y1 = (self.y + self.unit).ceil().max(0.0) as usize;
+
+        let mut total = 0;
+        let mut visible = 0;
+        for y in y0..=y1 {
+            for x in x0..=x1 {
+                if self.in_bullseye(x as Real, y as Real) {
+                    total += 1;
+                    if scanner.is_opaque(x, y) {
+                        visible += 1;
+                    }
+                }
+            }
+        }
+
+        if total == 0 {
+            0.0
+        } else {
+            visible as f32 / total as f32
+        }
+    }
+
+    /// The bits read from the extended ring outside the data ring, if the scanner was configured
+    /// to read one with [Scanner::set_read_extended_ring] and the code is valid.
+    pub fn extended_data(&self) -> Option<u32> {
+        self.extended
+    }
+
+    /// The pattern of the second data ring of the extended symbology, if the scanner was
+    /// configured to read one with [Scanner::set_extended_symbology] and it holds exactly five
+    /// white sectors.
+    pub fn second_ring(&self) -> Option<Code> {
+        self.second
+    }
+
+    /// The ID of the symbol in the extended symbology, combining its code with the pattern of its
+    /// second data ring (see [crate::codes::extended_to_id]), or `None` if either is missing.
+    pub fn extended_id(&self) -> Option<usize> {
+        crate::codes::extended_to_id(self.code?, self.second?)
+    }
+
     /// Sets the x- and y- coordinates for the center point of the symbol.
-    pub fn set_location(&mut self, x: f64, y: f64) {
+    pub fn set_location(&mut self, x: Real, y: Real) {
         self.x = x;
         self.y = y;
     }
@@ -106,8 +312,9 @@ impl TopCode {
         self.code.is_some()
     }
 
-    /// Decodes a symbol given any point (cx, by) inside the center circle (bullseye) of the code.
-    pub fn decode(&mut self, scanner: &Scanner, cx: usize, cy: usize) -> Option<Code> {
+    /// Estimates the center and unit of the symbol around any point (cx, cy) inside its bullseye.
+    /// The unit is negative if it could not be determined.
+    pub(crate) fn locate(&mut self, scanner: &Scanner<impl ScanBuffer>, cx: usize, cy: usize) {
         let up = scanner.dist(cx, cy, 0, -1)
             + scanner.dist(cx.saturating_sub(1), cy, 0, -1)
             + scanner.dist(cx + 1, cy, 0, -1);
@@ -121,31 +328,41 @@ impl TopCode {
             + scanner.dist(cx, cy.saturating_sub(1), 1, 0)
             + scanner.dist(cx, cy + 1, 1, 0);
 
-        self.x = cx as f64;
-        self.y = cy as f64;
-        self.x += (right - left) as f64 / 6.0;
-        self.y += (down - up) as f64 / 6.0;
+        self.x = cx as Real;
+        self.y = cy as Real;
+        self.x += (right - left) as Real / 6.0;
+        self.y += (down - up) as Real / 6.0;
         self.code = None;
+        self.extended = None;
+        self.second = None;
         self.unit = self.read_unit(scanner); // Try to make this an option. Consider a valid vs. invalid TopCode enum.
+    }
 
+    /// Decodes a symbol given any point (cx, by) inside the center circle (bullseye) of the code.
+    pub fn decode(
+        &mut self,
+        scanner: &Scanner<impl ScanBuffer>,
+        cx: usize,
+        cy: usize,
+    ) -> Option<Code> {
+        self.locate(scanner, cx, cy);
         if self.unit < 0.0 {
             return None;
         }
 
         let mut max_c = 0;
-        let mut max_a = 0.0;
+        let mut max_a = 0;
         let mut max_u = 0.0;
 
         // Try different unit and arc adjustments. Save the one that produces a maximum confidence
         // reading....
         for u in -2..=2 {
             for a in 0..10 {
-                let arc_adjustment = a as f64 * ARC * 0.1;
-                let unit = self.unit + (self.unit * 0.05 * u as f64);
-                let c = self.read_code(scanner, unit, arc_adjustment);
+                let unit = self.unit + (self.unit * 0.05 * u as Real);
+                let c = self.read_code(scanner, unit, a);
                 if c > max_c {
                     max_c = c;
-                    max_a = arc_adjustment;
+                    max_a = a;
                     max_u = unit;
                 }
             }
@@ -155,7 +372,22 @@ impl TopCode {
         if max_c > 0 {
             self.unit = max_u;
             self.read_code(scanner, self.unit, max_a);
-            self.code = self.code.map(|code| self.rotate_lowest(code, max_a));
+            let arc_adjustment = max_a as Real * ARC * 0.1;
+            self.code = self
+                .code
+                .map(|code| self.rotate_lowest(code, arc_adjustment));
+            if self.code.is_some() && scanner.extended_ring_bits() > 0 {
+                self.extended =
+                    Some(self.read_extended_ring(scanner, scanner.extended_ring_bits()));
+            }
+            if self.code.is_some() && scanner.extended_symbology() {
+                self.second = self.read_second_ring(scanner);
+            }
+            invariant!(
+                self.code.is_none() || self.unit > 0.0,
+                "accepted unit {}",
+                self.unit
+            );
         }
 
         self.code
@@ -163,54 +395,23 @@ impl TopCode {
 
     /// Attempts to decode the binary pixels of an image into a code value.
     ///
-    /// The `unit` is the width of a single ring and `arc_adjustment` corrects the rotation.
-    fn read_code(&mut self, scanner: &Scanner, unit: f64, arc_adjustment: f64) -> usize {
+    /// The `unit` is the width of a single ring and `tenths` corrects the rotation, in tenths of
+    /// a sector.
+    fn read_code(
+        &mut self,
+        scanner: &Scanner<impl ScanBuffer>,
+        unit: Real,
+        tenths: usize,
+    ) -> usize {
         let mut c = 0;
         let mut bits = 0;
 
         for sector in (0..SECTORS).rev() {
-            let sector_f = sector as f64;
-            let dx = (ARC * sector_f + arc_adjustment).cos();
-            let dy = (ARC * sector_f + arc_adjustment).sin();
-
-            // Take 8 samples across the diameter of the symbol
-            for i in 0..WIDTH {
-                let i_f = i as f64;
-                let dist = (i_f - 3.5) * unit;
-
-                let sx = (self.x + dx * dist).round() as usize;
-                let sy = (self.y + dy * dist).round() as usize;
-                self.core[i] = scanner.get_sample_3x3(sx, sy);
+            match self.read_sector_at(scanner, unit, sector, tenths) {
+                Some(confidence) => c += confidence,
+                None => return 0,
             }
 
-            // White rings
-            if self.core[1] <= 128
-                || self.core[3] <= 128
-                || self.core[4] <= 128
-                || self.core[6] <= 128
-            {
-                return 0;
-            }
-
-            // Black ring
-            if self.core[2] > 128 || self.core[5] > 128 {
-                return 0;
-            }
-
-            // Compute confidence interval in core sample
-            c += self.core[1] // White rings
-                + self.core[3]
-                + self.core[4]
-                + self.core[6]
-                + (0xff - self.core[2]) // Black ring
-                + (0xff - self.core[5]);
-
-            // Data rings
-            c += (self.core[7] as isize * 2 - 0xff).abs() as usize;
-
-            // Opposite data ring
-            c += (0xff - (self.core[0] as isize * 2 - 0xff)) as usize;
-
             let bit = if self.core[7] > 128 { 1 } else { 0 };
             bits <<= 1;
             bits += bit;
@@ -225,8 +426,233 @@ impl TopCode {
         }
     }
 
+    /// Reads the data sector `sector`, with the rotation corrected by `tenths` tenths of a sector,
+    /// as [TopCode::read_sector] does.
+    #[cfg(not(feature = "fixed-point"))]
+    fn read_sector_at(
+        &mut self,
+        scanner: &Scanner<impl ScanBuffer>,
+        unit: Real,
+        sector: usize,
+        tenths: usize,
+    ) -> Option<usize> {
+        let arc_adjustment = tenths as Real * ARC * 0.1;
+        self.read_sector(scanner, unit, ARC * sector as Real + arc_adjustment)
+    }
+
+    /// Reads the data sector `sector`, with the rotation corrected by `tenths` tenths of a sector,
+    /// as [TopCode::read_sector] does but with the samples positioned in fixed point.
+    #[cfg(feature = "fixed-point")]
+    fn read_sector_at(
+        &mut self,
+        scanner: &Scanner<impl ScanBuffer>,
+        unit: Real,
+        sector: usize,
+        tenths: usize,
+    ) -> Option<usize> {
+        let (x, y) = (fixed::to_fixed(self.x), fixed::to_fixed(self.y));
+        let fixed_unit = fixed::to_fixed(unit);
+        let index = sector * fixed::ADJUSTMENTS + tenths;
+        for i in 0..WIDTH {
+            let (sx, sy) = fixed::sample_position(x, y, fixed_unit, index, i);
+            self.core[i] = scanner.get_sample_3x3(sx, sy);
+        }
+        let arc_adjustment = tenths as Real * ARC * 0.1;
+        self.check_sector(scanner, unit, ARC * sector as Real + arc_adjustment)
+    }
+
+    /// Samples the symbol across its diameter along `angle` into [TopCode::core], so that the data
+    /// sector at `angle` is the last sample. Returns the confidence of the reading, or `None` if
+    /// the rings are not where they are expected to be.
+    #[cfg_attr(feature = "fixed-point", allow(dead_code))]
+    fn read_sector(
+        &mut self,
+        scanner: &Scanner<impl ScanBuffer>,
+        unit: Real,
+        angle: Real,
+    ) -> Option<usize> {
+        let dx = angle.cos();
+        let dy = angle.sin();
+
+        // Take 8 samples across the diameter of the symbol
+        for i in 0..WIDTH {
+            let i_f = i as Real;
+            let dist = (i_f - 3.5) * unit;
+
+            let sx = (self.x + dx * dist).round() as usize;
+            let sy = (self.y + dy * dist).round() as usize;
+            self.core[i] = scanner.get_sample_3x3(sx, sy);
+        }
+
+        self.check_sector(scanner, unit, angle)
+    }
+
+    /// Checks the samples in [TopCode::core] taken by [TopCode::read_sector] along `angle`,
+    /// returning the confidence of the reading.
+    fn check_sector(
+        &mut self,
+        scanner: &Scanner<impl ScanBuffer>,
+        unit: Real,
+        angle: Real,
+    ) -> Option<usize> {
+        let supersamples = scanner.sector_supersamples();
+        if supersamples > 1 {
+            self.core[WIDTH - 1] = self.supersample_data(scanner, unit, angle, supersamples);
+        }
+
+        invariant!(self.core.iter().all(|&c| c <= 0xff), "core out of range");
+
+        // White rings
+        if self.core[1] <= 128 || self.core[3] <= 128 || self.core[4] <= 128 || self.core[6] <= 128
+        {
+            return None;
+        }
+
+        // Black ring
+        if self.core[2] > 128 || self.core[5] > 128 {
+            return None;
+        }
+
+        // Compute confidence interval in core sample
+        let mut c = self.core[1] // White rings
+            + self.core[3]
+            + self.core[4]
+            + self.core[6]
+            + (0xff - self.core[2]) // Black ring
+            + (0xff - self.core[5]);
+
+        // Data rings
+        c += (self.core[7] as isize * 2 - 0xff).unsigned_abs();
+
+        // Opposite data ring
+        c += (0xff - (self.core[0] as isize * 2 - 0xff)) as usize;
+
+        Some(c)
+    }
+
+    /// Ranks every valid code by how well it agrees with the data ring of the symbol around
+    /// (cx, cy), across the same unit and rotation adjustments tried by [TopCode::decode]. Returns
+    /// up to `k` codes (in their lowest rotation) with the fraction of the data ring agreeing
+    /// with them, best first.
+    pub(crate) fn hypotheses(
+        &mut self,
+        scanner: &Scanner<impl ScanBuffer>,
+        cx: usize,
+        cy: usize,
+        k: usize,
+    ) -> Vec<(Code, f32)> {
+        self.locate(scanner, cx, cy);
+        if self.unit < 0.0 {
+            return Vec::new();
+        }
+
+        let valid: Vec<Code> = (0..1 << SECTORS)
+            .filter(|&bits| Self::checksum(bits))
+            .collect();
+        let base_unit = self.unit;
+        let mut hypotheses: Vec<(Code, f32)> = Vec::new();
+
+        for u in -2..=2 {
+            for a in 0..10 {
+                let arc_adjustment = a as Real * ARC * 0.1;
+                let unit = base_unit + (base_unit * 0.05 * u as Real);
+
+                let mut samples = [0; SECTORS];
+                let mut readable = true;
+                for (sector, sample) in samples.iter_mut().enumerate() {
+                    if self.read_sector_at(scanner, unit, sector, a).is_none() {
+                        readable = false;
+                        break;
+                    }
+                    *sample = self.core[7];
+                }
+                if !readable {
+                    continue;
+                }
+
+                for &bits in &valid {
+                    let agreement: usize = samples
+                        .iter()
+                        .enumerate()
+                        .map(|(sector, &sample)| {
+                            if bits >> sector & 0x01 == 1 {
+                                sample
+                            } else {
+                                0xff - sample
+                            }
+                        })
+                        .sum();
+                    let confidence = agreement as f32 / (SECTORS * 0xff) as f32;
+                    let code = self.rotate_lowest(bits, arc_adjustment);
+                    match hypotheses.iter_mut().find(|(other, _)| *other == code) {
+                        Some((_, best)) => *best = best.max(confidence),
+                        None => hypotheses.push((code, confidence)),
+                    }
+                }
+            }
+        }
+
+        hypotheses.sort_by(|a, b| b.1.total_cmp(&a.1));
+        hypotheses.truncate(k);
+        hypotheses
+    }
+
+    /// Reads the second data ring of the extended symbology, which spans 4 to 5 units from the
+    /// center and is split into sectors lined up with those of the inner data ring. Only patterns
+    /// with exactly five white sectors are accepted, which catches most misreadings.
+    fn read_second_ring(&self, scanner: &Scanner<impl ScanBuffer>) -> Option<Code> {
+        let bits = self.read_extended_ring(scanner, SECTORS);
+        Self::checksum(bits).then_some(bits)
+    }
+
+    /// Reads `bits` bits from the extended ring, which spans 4 to 5 units from the center. Bit `i`
+    /// is sampled in the middle of the `i`th of `bits` equal arcs, starting from the beginning of
+    /// the first data sector and running in the same direction as the data sectors. White is 1.
+    fn read_extended_ring(&self, scanner: &Scanner<impl ScanBuffer>, bits: usize) -> u32 {
+        // The first data sector was read 0.65 sectors after the orientation, in its middle.
+        let start = self.orientation + ARC * 0.15;
+        let dist = 4.5 * self.unit;
+        let mut data = 0;
+        for i in 0..bits {
+            let angle = start + (i as Real + 0.5) * 2.0 * PI / bits as Real;
+            let sx = (self.x + angle.cos() * dist).round();
+            let sy = (self.y + angle.sin() * dist).round();
+            if sx >= 0.0 && sy >= 0.0 && scanner.get_sample_3x3(sx as usize, sy as usize) > 128 {
+                data |= 1 << i;
+            }
+        }
+        data
+    }
+
+    /// Samples the data ring at `n` points spread across the middle half of the sector centered
+    /// on `angle` and returns the median, which acts as a majority vote over the samples.
+    fn supersample_data(
+        &self,
+        scanner: &Scanner<impl ScanBuffer>,
+        unit: Real,
+        angle: Real,
+        n: usize,
+    ) -> usize {
+        let dist = 3.5 * unit;
+        let mut samples: Vec<usize> = (0..n)
+            .map(|k| {
+                let offset = ((k as Real + 0.5) / n as Real - 0.5) * ARC * 0.5;
+                let sx = (self.x + (angle + offset).cos() * dist).round() as usize;
+                let sy = (self.y + (angle + offset).sin() * dist).round() as usize;
+                scanner.get_sample_3x3(sx, sy)
+            })
+            .collect();
+        samples.sort_unstable();
+        samples[n / 2]
+    }
+
     /// Tries each of the possible rotations and returns the lowest.
-    fn rotate_lowest(&mut self, mut bits: Code, mut arc_adjustment: f64) -> Code {
+    ///
+    /// Since there is an odd number of sectors, no valid code reads the same after being rotated
+    /// by half a turn (or any other rotation, as 13 is prime and valid codes have exactly five
+    /// bits set). The lowest rotation is therefore unique, and the resulting orientation is never
+    /// ambiguous by PI.
+    fn rotate_lowest(&mut self, mut bits: Code, mut arc_adjustment: Real) -> Code {
         let mut min = bits;
         let mask = 0x1fff;
 
@@ -238,7 +664,7 @@ impl TopCode {
             bits = ((bits << 1) & mask) | (bits >> (SECTORS - 1));
             if bits < min {
                 min = bits;
-                self.orientation = i as f64 * -ARC;
+                self.orientation = i as Real * -ARC;
             }
         }
 
@@ -258,14 +684,14 @@ impl TopCode {
     }
 
     /// Returns true if the given point is inside the bullseye
-    pub(crate) fn in_bullseye(&self, px: f64, py: f64) -> bool {
+    pub(crate) fn in_bullseye(&self, px: Real, py: Real) -> bool {
         ((self.x - px) * (self.x - px) + (self.y - py) * (self.y - py)) <= (self.unit * self.unit)
     }
 
     /// Determines the symbol's unit length by counting the number of pixels between the outer
     /// edges of the first black ring. North, south, east, and west readings are taken and the
     /// average is returned.
-    fn read_unit(&self, scanner: &Scanner) -> f64 {
+    fn read_unit(&self, scanner: &Scanner<impl ScanBuffer>) -> Real {
         let sx = self.x.round() as usize;
         let sy = self.y.round() as usize;
 
@@ -283,7 +709,7 @@ impl TopCode {
         let mut dist_down = 0;
 
         for i in 1..=MAX_PIXELS {
-            if sx < 1 + i || sx + i >= image_width - 1 || sy < 1 + i || sy + i >= image_height - 1 {
+            if sx < 1 + i || sx + i + 1 >= image_width || sy < 1 + i || sy + i + 1 >= image_height {
                 return -1.0;
             }
 
@@ -328,11 +754,13 @@ impl TopCode {
             }
 
             if dist_right > 0 && dist_left > 0 && dist_up > 0 && dist_down > 0 {
-                let u = (dist_right + dist_left + dist_up + dist_down) as f64 / 8.0;
-                return if (dist_right + dist_left - dist_up - dist_down).abs() as f64 > u {
+                // The unit is an eighth of the sum of the distances, which is compared in integers
+                // so that no floating point is needed to reject lopsided readings.
+                let sum = dist_right + dist_left + dist_up + dist_down;
+                return if 8 * (dist_right + dist_left - dist_up - dist_down).abs() > sum {
                     -1.0
                 } else {
-                    u
+                    sum as Real / 8.0
                 };
             }
         }
@@ -340,12 +768,239 @@ impl TopCode {
         -1.0
     }
 
-    /// A method used to draw the current TopCode. This should only be conditionally compiled for
-    /// experimentation and testing. Otherwise, consumers of this library are responsible for
-    /// implementing methods to draw the TopCodes.
+    /// Samples a `size` x `size` grayscale patch covering the symbol, de-rotated by its
+    /// orientation and scaled so that the patch spans the symbol's full diameter. Pixels are
+    /// bilinearly interpolated and anything outside of `img` is treated as black.
     #[cfg(feature = "visualize")]
-    pub fn draw(&self) {
-        unimplemented!()
+    pub fn rectified_crop(&self, img: &DynamicImage, size: u32) -> GrayImage {
+        let gray = img.to_luma8();
+        let (width, height) = gray.dimensions();
+        let pixel = |x: i64, y: i64| -> Real {
+            if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+                0.0
+            } else {
+                gray.get_pixel(x as u32, y as u32)[0] as Real
+            }
+        };
+
+        let (sin, cos) = self.orientation.sin_cos();
+        let scale = self.unit * WIDTH as Real / size as Real;
+
+        GrayImage::from_fn(size, size, |u, v| {
+            // Local coordinates relative to the symbol's center, in pixels
+            let lx = (u as Real + 0.5 - size as Real / 2.0) * scale;
+            let ly = (v as Real + 0.5 - size as Real / 2.0) * scale;
+
+            // Source coordinates relative to pixel centers
+            let sx = self.x + lx * cos - ly * sin - 0.5;
+            let sy = self.y + lx * sin + ly * cos - 0.5;
+            let x0 = sx.floor();
+            let y0 = sy.floor();
+            let fx = sx - x0;
+            let fy = sy - y0;
+            let (x0, y0) = (x0 as i64, y0 as i64);
+
+            let top = pixel(x0, y0) * (1.0 - fx) + pixel(x0 + 1, y0) * fx;
+            let bottom = pixel(x0, y0 + 1) * (1.0 - fx) + pixel(x0 + 1, y0 + 1) * fx;
+            image::Luma([(top * (1.0 - fy) + bottom * fy).round() as u8])
+        })
+    }
+}
+
+/// Returns the code whose center is nearest to the center of the images scanned by `scanner`,
+/// e.g. to pick the marker the camera is pointed at. Codes at the same distance are ordered by
+/// their code value, so the lowest one is picked. Returns `None` if there are no codes.
+pub fn most_central<'a>(
+    codes: &'a [TopCode],
+    scanner: &Scanner<impl ScanBuffer>,
+) -> Option<&'a TopCode> {
+    let cx = scanner.image_width() as Real / 2.0;
+    let cy = scanner.image_height() as Real / 2.0;
+    let distance = |code: &TopCode| (code.x - cx).hypot(code.y - cy);
+    codes.iter().min_by(|a, b| {
+        distance(a)
+            .total_cmp(&distance(b))
+            .then(a.code.cmp(&b.code))
+    })
+}
+
+/// Returns the `expected` codes which are not present in `found`, in the order they were expected.
+/// This is useful for fixed setups, to notice when a known marker is occluded or damaged.
+pub fn missing(expected: &[Code], found: &[TopCode]) -> Vec<Code> {
+    expected
+        .iter()
+        .filter(|&&code| !found.iter().any(|topcode| topcode.code == Some(code)))
+        .copied()
+        .collect()
+}
+
+/// Version of the layout written by [encode_detections].
+const DETECTIONS_VERSION: u8 = 1;
+
+/// Size in bytes of a single detection written by [encode_detections].
+const DETECTION_SIZE: usize = 20;
+
+/// Encodes detections into a compact binary format, for recording large numbers of detections.
+///
+/// The first byte holds the version of the layout (currently 1), followed by 20 bytes for each
+/// detection, with every field stored in little-endian byte order:
+///
+/// | Offset | Type  | Field                                 |
+/// |--------|-------|---------------------------------------|
+/// | 0      | `u32` | code (`u32::MAX` if it was not valid) |
+/// | 4      | `f32` | x                                     |
+/// | 8      | `f32` | y                                     |
+/// | 12     | `f32` | orientation                           |
+/// | 16     | `f32` | unit                                  |
+pub fn encode_detections(codes: &[TopCode]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(1 + codes.len() * DETECTION_SIZE);
+    bytes.push(DETECTIONS_VERSION);
+    for code in codes {
+        bytes.extend_from_slice(&code.code.unwrap_or(u32::MAX).to_le_bytes());
+        for value in [code.x, code.y, code.orientation, code.unit] {
+            // Real is already f32 with the f32 feature.
+            #[allow(clippy::unnecessary_cast)]
+            bytes.extend_from_slice(&(value as f32).to_le_bytes());
+        }
+    }
+    bytes
+}
+
+/// Decodes detections written by [encode_detections]. Returns no detections if the layout's
+/// version is not supported, and ignores a trailing incomplete detection.
+pub fn decode_detections(bytes: &[u8]) -> Vec<TopCode> {
+    match bytes.split_first() {
+        Some((&DETECTIONS_VERSION, records)) => records
+            .chunks_exact(DETECTION_SIZE)
+            .map(|record| {
+                let field = |i: usize| record[i * 4..i * 4 + 4].try_into().unwrap();
+                let code = u32::from_le_bytes(field(0));
+                let value = |i: usize| f32::from_le_bytes(field(i)) as Real;
+                TopCode {
+                    code: (code != u32::MAX).then_some(code),
+                    x: value(1),
+                    y: value(2),
+                    orientation: value(3),
+                    unit: value(4),
+                    ..Default::default()
+                }
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the convex hull of the centers of `codes`, in counter-clockwise order (with respect to
+/// the usual mathematical orientation of the x- and y-axes), starting from the lowest point on the
+/// x-axis. Duplicate and collinear points are excluded, so a single point yields one vertex and
+/// collinear points yield the two end points.
+pub fn hull(codes: &[TopCode]) -> Vec<(Real, Real)> {
+    let mut points: Vec<(Real, Real)> = codes.iter().map(|code| (code.x, code.y)).collect();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    points.dedup();
+
+    if points.len() < 3 {
+        return points;
+    }
+
+    let cross = |o: (Real, Real), a: (Real, Real), b: (Real, Real)| {
+        (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+    };
+
+    // Andrew's monotone chain: build the lower hull, then the upper hull.
+    let mut hull: Vec<(Real, Real)> = Vec::with_capacity(points.len() * 2);
+    for &point in points.iter() {
+        while hull.len() >= 2 && cross(hull[hull.len() - 2], hull[hull.len() - 1], point) <= 0.0 {
+            hull.pop();
+        }
+        hull.push(point);
+    }
+    let lower = hull.len() + 1;
+    for &point in points.iter().rev().skip(1) {
+        while hull.len() >= lower && cross(hull[hull.len() - 2], hull[hull.len() - 1], point) <= 0.0
+        {
+            hull.pop();
+        }
+        hull.push(point);
+    }
+    // The last point is the first point again.
+    hull.pop();
+    hull
+}
+
+/// Estimates the camera's roll from markers lying on a common horizontal surface, as the circular
+/// mean of their orientations (in radians, within `(-PI, PI]`). Returns `None` for fewer than two
+/// codes, or when the orientations cancel out and have no meaningful mean.
+pub fn estimate_roll(codes: &[TopCode]) -> Option<Real> {
+    if codes.len() < 2 {
+        return None;
+    }
+
+    circular_mean(codes)
+}
+
+/// The circular mean of the orientations of `codes`, or `None` if they cancel out.
+fn circular_mean<'a>(codes: impl IntoIterator<Item = &'a TopCode>) -> Option<Real> {
+    let (sin, cos, count) = codes
+        .into_iter()
+        .fold((0.0, 0.0, 0), |(sin, cos, count), code| {
+            (
+                sin + code.orientation.sin(),
+                cos + code.orientation.cos(),
+                count + 1,
+            )
+        });
+    if sin.hypot(cos) < EPSILON * count as Real {
+        return None;
+    }
+    Some(sin.atan2(cos))
+}
+
+/// Merges detections of the same code whose centers are within `max_dist` pixels of each other,
+/// e.g. duplicates of a partially occluded marker. Each group is replaced by a single detection
+/// at the group's average center, with the circular mean of the group's orientations and the
+/// largest unit. Groups are formed greedily in order, by comparing each detection with the first
+/// detection of every group, and merged detections keep the position of the group's first
+/// detection in the result.
+pub fn consensus(codes: &[TopCode], max_dist: Real) -> Vec<TopCode> {
+    let mut groups: Vec<Vec<&TopCode>> = Vec::new();
+    for code in codes {
+        let group = groups.iter_mut().find(|group| {
+            let first = group[0];
+            first.code == code.code && (first.x - code.x).hypot(first.y - code.y) <= max_dist
+        });
+        match group {
+            Some(group) => group.push(code),
+            None => groups.push(vec![code]),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|group| {
+            let count = group.len() as Real;
+            let mut merged = *group[0];
+            merged.x = group.iter().map(|code| code.x).sum::<Real>() / count;
+            merged.y = group.iter().map(|code| code.y).sum::<Real>() / count;
+            merged.unit = group
+                .iter()
+                .map(|code| code.unit)
+                .fold(Real::MIN, Real::max);
+            if let Some(orientation) = circular_mean(group.iter().copied()) {
+                merged.orientation = orientation;
+            }
+            merged
+        })
+        .collect()
+}
+
+/// Wraps an angle (in radians) to `(-PI, PI]`.
+fn wrap_angle(angle: Real) -> Real {
+    let angle = angle.rem_euclid(2.0 * PI);
+    if angle > PI {
+        angle - 2.0 * PI
+    } else {
+        angle
     }
 }
 
@@ -363,6 +1018,387 @@ mod tests {
         assert!(!TopCode::checksum(0b10101));
     }
 
+    #[test]
+    fn normalized_core_is_independent_of_brightness() {
+        let topcode = TopCode {
+            core: [10, 180, 20, 190, 200, 15, 170, 60],
+            ..Default::default()
+        };
+        let brightened = TopCode {
+            core: topcode.core.map(|c| c + 40),
+            ..topcode
+        };
+
+        let normalized = topcode.normalized_core();
+        assert_eq!(normalized, brightened.normalized_core());
+        assert_eq!(normalized[0], 0);
+        assert_eq!(normalized[4], 255);
+        assert_eq!(TopCode::default().normalized_core(), [0; WIDTH]);
+    }
+
+    #[test]
+    fn relative_pose_is_in_the_anchor_frame() {
+        let anchor = TopCode::mock(31, 10.0, PI / 2.0, 100.0, 100.0);
+        let other = TopCode::mock(55, 10.0, PI, 100.0, 120.0);
+        let pose = other.relative_to(&anchor);
+        assert!((pose.x - 2.0).abs() < EPSILON);
+        assert!(pose.y.abs() < EPSILON);
+        assert!((pose.distance - 2.0).abs() < EPSILON);
+        assert!((pose.distance_px - 20.0).abs() < EPSILON);
+        assert!(pose.bearing.abs() < EPSILON);
+        assert!((pose.orientation - PI / 2.0).abs() < EPSILON);
+
+        // Behind the anchor and turned the other way.
+        let other = TopCode::mock(55, 10.0, 0.0, 100.0, 70.0);
+        let pose = other.relative_to(&anchor);
+        assert!((pose.bearing.abs() - PI).abs() < EPSILON);
+        assert!((pose.orientation + PI / 2.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn relative_pose_is_invariant_to_translation() {
+        let anchor = TopCode::mock(31, 12.0, 0.4, 50.0, 80.0);
+        let other = TopCode::mock(55, 11.0, -2.9, 130.0, 20.0);
+        let mut shifted_anchor = anchor;
+        shifted_anchor.set_location(anchor.x + 321.0, anchor.y - 45.5);
+        let mut shifted_other = other;
+        shifted_other.set_location(other.x + 321.0, other.y - 45.5);
+
+        let pose = other.relative_to(&anchor);
+        let shifted = shifted_other.relative_to(&shifted_anchor);
+        assert!((pose.x - shifted.x).abs() < EPSILON);
+        assert!((pose.y - shifted.y).abs() < EPSILON);
+        assert!((pose.bearing - shifted.bearing).abs() < EPSILON);
+        assert!((pose.orientation - shifted.orientation).abs() < EPSILON);
+        assert!(pose.orientation > -PI && pose.orientation <= PI);
+    }
+
+    #[cfg(feature = "visualize")]
+    #[test]
+    fn rectified_crop_is_centered_on_the_bullseye() {
+        let (width, height) = (200, 200);
+        let buffer = crate::utils::render_topcode(55, 10.0, 1.0, (90.0, 110.0), (width, height));
+        let img = DynamicImage::ImageLuma8(
+            GrayImage::from_raw(width as u32, height as u32, buffer).unwrap(),
+        );
+        let topcode = TopCode::mock(55, 10.0, 1.0, 90.0, 110.0);
+
+        let crop = topcode.rectified_crop(&img, 64);
+        assert_eq!(crop.dimensions(), (64, 64));
+        // White center, surrounded by the black ring 1-2 units out.
+        assert_eq!(crop.get_pixel(32, 32)[0], 255);
+        assert_eq!(crop.get_pixel(32 + 12, 32)[0], 0);
+        assert_eq!(crop.get_pixel(32, 32 - 12)[0], 0);
+        assert_eq!(crop.get_pixel(32 + 20, 32)[0], 255);
+    }
+
+    #[test]
+    fn missing_codes_are_reported() {
+        let found = [
+            TopCode::mock(55, 10.0, 0.0, 10.0, 10.0),
+            TopCode::mock(93, 10.0, 0.0, 50.0, 10.0),
+        ];
+        assert_eq!(missing(&[31, 55, 93], &found), vec![31]);
+        assert!(missing(&[55, 93], &found).is_empty());
+    }
+
+    #[test]
+    fn normalized_coordinates_are_relative_to_the_image() {
+        let scanner = Scanner::new(640, 480);
+        let topcode = TopCode::mock(55, 8.0, 0.0, 320.0, 240.0);
+        assert_eq!(topcode.normalized(&scanner), (0.5, 0.5));
+        assert_eq!(topcode.diameter_normalized(&scanner), 0.1);
+    }
+
+    #[test]
+    fn distances_follow_from_the_physical_diameter() {
+        // 64 pixels wide, printed 40 mm wide.
+        let topcode = TopCode::mock(55, 8.0, 0.0, 320.0, 240.0);
+        assert_eq!(topcode.mm_per_pixel(40.0), 0.625);
+        assert_eq!(topcode.distance_mm(40.0, 800.0), 500.0);
+    }
+
+    fn at(points: &[(Real, Real)]) -> Vec<TopCode> {
+        points
+            .iter()
+            .map(|&(x, y)| TopCode::mock(31, 10.0, 0.0, x, y))
+            .collect()
+    }
+
+    #[test]
+    fn hull_of_a_square_arrangement() {
+        let codes = at(&[
+            (0.0, 0.0),
+            (10.0, 10.0),
+            (5.0, 5.0),
+            (0.0, 10.0),
+            (10.0, 0.0),
+            (5.0, 0.0),
+        ]);
+        assert_eq!(
+            hull(&codes),
+            vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)]
+        );
+    }
+
+    #[test]
+    fn hull_of_degenerate_arrangements() {
+        assert!(hull(&[]).is_empty());
+        assert_eq!(hull(&at(&[(1.0, 2.0)])), vec![(1.0, 2.0)]);
+        assert_eq!(hull(&at(&[(1.0, 2.0), (1.0, 2.0)])), vec![(1.0, 2.0)]);
+        assert_eq!(
+            hull(&at(&[(3.0, 3.0), (1.0, 1.0), (2.0, 2.0), (4.0, 4.0)])),
+            vec![(1.0, 1.0), (4.0, 4.0)]
+        );
+    }
+
+    #[test]
+    fn visible_fraction_ignores_transparent_pixels() {
+        let (width, height) = (200, 200);
+        let gray = crate::utils::render_topcode(55, 10.0, 0.0, (100.0, 100.0), (width, height));
+        // Everything left of the code's center is transparent.
+        let buffer: Vec<u8> = gray
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &v)| [v, if i % width < 100 { 0 } else { 255 }])
+            .collect();
+
+        let mut scanner = Scanner::new(width, height);
+        let codes = scanner.scan_with_alpha(&buffer, |buffer, index| {
+            let v = buffer[index * 2] as u32;
+            (v, v, v, buffer[index * 2 + 1] as u32)
+        });
+        assert_eq!(codes.len(), 1);
+        let fraction = codes[0].visible_fraction(&scanner);
+        assert!((fraction - 0.5).abs() < 0.1, "{}", fraction);
+
+        let opaque = scanner.scan(&gray, |buffer, index| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        });
+        assert_eq!(opaque[0].visible_fraction(&scanner), 1.0);
+    }
+
+    #[test]
+    fn orientation_is_not_ambiguous_by_half_a_turn() {
+        let (width, height) = (200, 200);
+        let decode = |orientation: Real| {
+            let buffer = crate::utils::render_topcode(
+                31,
+                10.0,
+                orientation,
+                (100.0, 100.0),
+                (width, height),
+            );
+            let mut scanner = Scanner::new(width, height);
+            let codes = scanner.scan(&buffer, |buffer, index| {
+                let v = buffer[index] as u32;
+                (v, v, v)
+            });
+            assert_eq!(codes.len(), 1);
+            assert_eq!(codes[0].code, Some(31));
+            codes[0].orientation
+        };
+
+        for orientation in [0.0, 0.7, 2.0] {
+            let delta = (decode(orientation + PI) - decode(orientation)).rem_euclid(2.0 * PI);
+            assert!((delta - PI).abs() < ARC / 2.0, "{}", delta);
+        }
+    }
+
+    #[test]
+    fn roll_is_the_circular_mean_of_orientations() {
+        let codes = [
+            TopCode::mock(31, 10.0, 2.0 * PI - 0.05, 0.0, 0.0),
+            TopCode::mock(47, 10.0, 0.03, 40.0, 0.0),
+            TopCode::mock(55, 10.0, 0.08, 80.0, 0.0),
+        ];
+        let roll = estimate_roll(&codes).unwrap();
+        assert!((roll - 0.02).abs() < 1e-3, "{}", roll);
+
+        assert_eq!(estimate_roll(&codes[..1]), None);
+        let opposite = [codes[1], TopCode::mock(47, 10.0, 0.03 + PI, 40.0, 0.0)];
+        assert_eq!(estimate_roll(&opposite), None);
+    }
+
+    #[test]
+    fn blurred_symbols_are_less_sharp() {
+        let (width, height) = (200, 200);
+        let crisp = crate::utils::render_topcode(55, 8.0, 0.4, (100.0, 100.0), (width, height));
+        let sharpness = |buffer: &Vec<u8>| {
+            let mut scanner = Scanner::new(width, height);
+            let codes = scanner.scan(buffer, |buffer, index| {
+                let v = buffer[index] as u32;
+                (v, v, v)
+            });
+            assert_eq!(codes.len(), 1);
+            codes[0].sharpness(&scanner)
+        };
+
+        let crisp_sharpness = sharpness(&crisp);
+        let blurred_sharpness = sharpness(&crate::utils::box_blur(&crisp, width, 2));
+        assert!(crisp_sharpness > 0.9, "{}", crisp_sharpness);
+        assert!(blurred_sharpness < 0.9, "{}", blurred_sharpness);
+    }
+
+    /// The code read by [TopCode::decode] with the samples positioned in floating point, along
+    /// with its unit and rotation adjustment.
+    #[cfg(feature = "fixed-point")]
+    fn decode_in_float(
+        top: &mut TopCode,
+        scanner: &Scanner<impl ScanBuffer>,
+    ) -> (usize, Option<Code>) {
+        let mut best = (0, None);
+        let base_unit = top.unit;
+        for u in -2..=2 {
+            let unit = base_unit + (base_unit * 0.05 * u as Real);
+            for a in 0..10 {
+                let mut c = 0;
+                let mut bits = 0;
+                for sector in (0..SECTORS).rev() {
+                    let angle = ARC * sector as Real + a as Real * ARC * 0.1;
+                    match top.read_sector(scanner, unit, angle) {
+                        Some(confidence) => c += confidence,
+                        None => {
+                            c = 0;
+                            break;
+                        }
+                    }
+                    bits = bits << 1 | (top.core[7] > 128) as Code;
+                }
+                if c > best.0 && TopCode::checksum(bits) {
+                    best = (c, Some(bits));
+                }
+            }
+        }
+        best
+    }
+
+    #[cfg(feature = "fixed-point")]
+    #[test]
+    fn fixed_point_decoding_matches_float_decoding_on_the_assets() {
+        for asset in ["source", "photo"] {
+            let img = image::io::Reader::open(format!("assets/{}.png", asset))
+                .unwrap()
+                .decode()
+                .unwrap();
+            let mut scanner = Scanner::new(img.width() as usize, img.height() as usize);
+            let buffer = img.into_rgb8().into_raw();
+            let candidates = scanner.candidates_only(&buffer, |buffer, index| {
+                (
+                    buffer[index * 3] as u32,
+                    buffer[index * 3 + 1] as u32,
+                    buffer[index * 3 + 2] as u32,
+                )
+            });
+            assert!(!candidates.is_empty());
+
+            for candidate in &candidates {
+                let mut fixed = TopCode::default();
+                fixed.locate(&scanner, candidate.x, candidate.y);
+                if fixed.unit < 0.0 {
+                    continue;
+                }
+                let expected = decode_in_float(&mut fixed.clone(), &scanner);
+
+                let mut best = (0, None);
+                let base_unit = fixed.unit;
+                for u in -2..=2 {
+                    let unit = base_unit + (base_unit * 0.05 * u as Real);
+                    for a in 0..10 {
+                        let c = fixed.read_code(&scanner, unit, a);
+                        if c > best.0 {
+                            best = (c, fixed.code);
+                        }
+                    }
+                }
+                assert_eq!(best, expected, "{} {:?}", asset, candidate);
+            }
+        }
+    }
+
+    #[test]
+    // Real is already f32 with the f32 feature.
+    #[allow(clippy::unnecessary_cast)]
+    fn detections_round_trip_through_binary_encoding() {
+        let img = image::io::Reader::open("assets/source.png")
+            .unwrap()
+            .decode()
+            .unwrap();
+        let mut scanner = Scanner::new(img.width() as usize, img.height() as usize);
+        let buffer = img.into_rgb8().into_raw();
+        let mut codes = scanner.scan(&buffer, |buffer, index| {
+            (
+                buffer[index * 3] as u32,
+                buffer[index * 3 + 1] as u32,
+                buffer[index * 3 + 2] as u32,
+            )
+        });
+        assert_eq!(codes.len(), 3);
+        codes.push(TopCode::default());
+
+        let bytes = encode_detections(&codes);
+        assert_eq!(bytes.len(), 1 + codes.len() * 20);
+        assert_eq!(bytes[0], 1);
+
+        let decoded = decode_detections(&bytes);
+        assert_eq!(decoded.len(), codes.len());
+        for (decoded, code) in decoded.iter().zip(&codes) {
+            assert_eq!(decoded.code, code.code);
+            assert_eq!(decoded.x, code.x as f32 as Real);
+            assert_eq!(decoded.y, code.y as f32 as Real);
+            assert_eq!(decoded.orientation, code.orientation as f32 as Real);
+            assert_eq!(decoded.unit, code.unit as f32 as Real);
+        }
+
+        assert!(decode_detections(&[2]).is_empty());
+        assert!(decode_detections(&[]).is_empty());
+        assert_eq!(
+            decode_detections(&bytes[..bytes.len() - 1]).len(),
+            codes.len() - 1
+        );
+    }
+
+    #[test]
+    fn most_central_picks_the_code_nearest_the_center() {
+        let scanner = Scanner::new(200, 100);
+        assert_eq!(most_central(&[], &scanner), None);
+
+        let codes = [
+            TopCode::mock(93, 10.0, 0.0, 20.0, 20.0),
+            TopCode::mock(55, 10.0, 0.0, 110.0, 40.0),
+            TopCode::mock(47, 10.0, 0.0, 150.0, 90.0),
+        ];
+        assert_eq!(most_central(&codes, &scanner), Some(&codes[1]));
+
+        // Codes at the same distance from the center are ordered by code.
+        let tied = [
+            TopCode::mock(55, 10.0, 0.0, 110.0, 50.0),
+            TopCode::mock(31, 10.0, 0.0, 90.0, 50.0),
+            TopCode::mock(47, 10.0, 0.0, 100.0, 60.0),
+        ];
+        assert_eq!(most_central(&tied, &scanner), Some(&tied[1]));
+    }
+
+    #[test]
+    fn consensus_merges_nearby_detections_of_the_same_code() {
+        let codes = [
+            TopCode::mock(55, 10.0, 0.1, 100.0, 100.0),
+            TopCode::mock(31, 10.0, 0.0, 104.0, 100.0),
+            TopCode::mock(55, 11.0, -0.1, 104.0, 102.0),
+            TopCode::mock(55, 10.0, 0.0, 200.0, 100.0),
+        ];
+        let merged = consensus(&codes, 10.0);
+        assert_eq!(merged.len(), 3);
+
+        assert_eq!(merged[0].code, Some(55));
+        assert_eq!((merged[0].x, merged[0].y), (102.0, 101.0));
+        assert_eq!(merged[0].unit, 11.0);
+        assert!(merged[0].orientation.abs() < EPSILON);
+        assert_eq!(merged[1], codes[1]);
+        assert_eq!(merged[2], codes[3]);
+    }
+
     #[test]
     fn point_is_in_bullseye() {
         let topcode = TopCode::default();
@@ -0,0 +1,791 @@
+use alloc::{vec, vec::Vec};
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::{
+    scanner::Scanner,
+    smoothing::{Filter, Smoothing},
+    topcode::{Code, Real, TopCode, PI},
+};
+
+/// Controls how detections are associated with existing tracks between frames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StabilityConfig {
+    /// Maximum distance (in units of the detected code) between a track's predicted position and
+    /// a detection for them to be considered the same marker
+    pub max_distance: Real,
+    /// Number of consecutive frames a track may go undetected before it is dropped
+    pub max_missed_frames: usize,
+    /// Number of frames out of the last [StabilityConfig::hit_window] a new marker needs to be
+    /// detected in before it is reported, to suppress single-frame false positives. At most
+    /// [StabilityConfig::hit_window], or it is lowered to it by [Tracker::new].
+    pub min_hits: usize,
+    /// Number of latest frames in which [StabilityConfig::min_hits] are counted, between 1 and
+    /// 64. Other values are clamped to that range by [Tracker::new].
+    pub hit_window: usize,
+    /// Number of past detections kept in the history of each track
+    pub history_len: usize,
+}
+
+impl Default for StabilityConfig {
+    fn default() -> Self {
+        Self {
+            max_distance: 2.0,
+            max_missed_frames: 5,
+            min_hits: 1,
+            hit_window: 1,
+            history_len: 30,
+        }
+    }
+}
+
+/// A TopCode which has been associated with a persistent identifier across frames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrackedCode {
+    /// Identifier which remains stable for as long as the marker is tracked
+    pub id: u64,
+    /// The symbol's code
+    pub code: Code,
+    /// Horizontal center of the symbol
+    pub x: Real,
+    /// Vertical center of the symbol
+    pub y: Real,
+    /// Angular orientation of the symbol (in radians)
+    pub orientation: Real,
+    /// Estimated movement of the symbol in pixels per frame
+    pub velocity: (Real, Real),
+}
+
+/// A past detection of a tracked marker.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrackSample {
+    /// Number of the frame the marker was detected in, counting from 0
+    pub frame: u64,
+    /// Horizontal center of the symbol
+    pub x: Real,
+    /// Vertical center of the symbol
+    pub y: Real,
+    /// Angular orientation of the symbol (in radians)
+    pub orientation: Real,
+}
+
+/// A change in the markers tracked by a [Tracker], see [Tracker::events].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrackEvent {
+    /// A marker was detected which is not yet being tracked.
+    CodeAppeared(TrackedCode),
+    /// A tracked marker moved or turned by more than the event thresholds since its last event.
+    CodeMoved(TrackedCode),
+    /// A tracked marker went undetected for more than [StabilityConfig::max_missed_frames]
+    /// frames, with its last tracked state.
+    CodeRemoved(TrackedCode),
+}
+
+#[derive(Clone, Debug)]
+struct Track {
+    tracked: TrackedCode,
+    /// State of the marker in its latest event
+    reported: TrackedCode,
+    missed: usize,
+    /// Number of the frame in which the position was last updated
+    updated: u64,
+    /// Whether the marker was detected in each of the latest frames, latest in the lowest bit
+    hits: u64,
+    /// Whether the marker has been detected often enough to be reported
+    confirmed: bool,
+    /// Latest detections, oldest first
+    history: Vec<TrackSample>,
+    /// Filters of the position and orientation, if smoothing is enabled
+    filters: Option<[Filter; 3]>,
+}
+
+/// Scans consecutive frames and assigns persistent identifiers to the TopCodes found in them.
+/// Detections are matched to the track of the same code whose predicted position (based on its
+/// velocity) is nearest, so a marker keeps its identifier while it moves.
+#[derive(Clone)]
+pub struct Tracker {
+    scanner: Scanner,
+    config: StabilityConfig,
+    tracks: Vec<Track>,
+    next_id: u64,
+    frame: u64,
+    max_orientation_jitter: Option<Real>,
+    smoothing: Option<Smoothing>,
+    /// Distance (in pixels) and rotation (in radians) which trigger [TrackEvent::CodeMoved]
+    event_thresholds: (Real, Real),
+    events: Vec<TrackEvent>,
+}
+
+impl Tracker {
+    /// Creates a tracker which scans frames with `scanner`. A [StabilityConfig::hit_window]
+    /// outside of 1 to 64 frames is clamped to that range, and [StabilityConfig::min_hits] is
+    /// lowered to the window, as new markers could never be reported otherwise.
+    pub fn new(scanner: Scanner, config: StabilityConfig) -> Self {
+        let hit_window = config.hit_window.clamp(1, 64);
+        Self {
+            scanner,
+            config: StabilityConfig {
+                hit_window,
+                min_hits: config.min_hits.min(hit_window),
+                ..config
+            },
+            tracks: Vec::new(),
+            next_id: 0,
+            frame: 0,
+            max_orientation_jitter: None,
+            smoothing: None,
+            event_thresholds: (2.0, 0.05),
+            events: Vec::new(),
+        }
+    }
+
+    /// Rejects detections whose orientation differs from their track's previous orientation by
+    /// more than `radians`, unless the marker also moved by more than one unit from its predicted
+    /// position. A static marker's orientation should be stable, so large jumps are usually caused
+    /// by reflections. Rejected detections are not reported and leave their track unchanged, so
+    /// the frame counts as one in which the marker went undetected. By default, orientation
+    /// changes are not limited.
+    pub fn set_max_orientation_jitter(&mut self, radians: Real) {
+        self.max_orientation_jitter = Some(radians);
+    }
+
+    /// Smooths the positions and orientations of tracked codes across frames before they are
+    /// reported. Orientations are unwrapped, so markers turning past a full turn are smoothed
+    /// correctly. Only tracks started after this call are smoothed. By default, detections are
+    /// reported as they are.
+    pub fn set_smoothing(&mut self, smoothing: Smoothing) {
+        self.smoothing = Some(smoothing);
+    }
+
+    /// Sets how far (in pixels) a tracked marker needs to move, or how far (in radians) it needs
+    /// to turn, since its last event for [TrackEvent::CodeMoved] to be emitted. Smaller changes
+    /// accumulate until they cross a threshold. Defaults to 2 pixels and 0.05 radians.
+    pub fn set_event_thresholds(&mut self, distance: Real, rotation: Real) {
+        self.event_thresholds = (distance, rotation);
+    }
+
+    /// The events emitted by the latest frame, in the order of the detections which caused them
+    /// followed by the markers which were removed.
+    pub fn events(&self) -> &[TrackEvent] {
+        &self.events
+    }
+
+    /// The scanner used to scan each frame, e.g. to adjust its settings between frames.
+    pub fn scanner_mut(&mut self) -> &mut Scanner {
+        &mut self.scanner
+    }
+
+    /// The tracked codes which are currently being tracked, including those which went
+    /// undetected in the latest frames but have not been dropped yet. New markers which have not
+    /// been detected in [StabilityConfig::min_hits] frames yet are left out.
+    pub fn tracks(&self) -> impl Iterator<Item = &TrackedCode> {
+        self.tracks
+            .iter()
+            .filter(|track| track.confirmed)
+            .map(|track| &track.tracked)
+    }
+
+    /// The latest detections of the track with the given identifier, oldest first, or `None` if
+    /// it is not being tracked. At most [StabilityConfig::history_len] detections are kept.
+    pub fn history(&self, id: u64) -> Option<&[TrackSample]> {
+        self.tracks
+            .iter()
+            .find(|track| track.tracked.id == id)
+            .map(|track| &track.history[..])
+    }
+
+    /// Scan the next frame and return the tracked codes found in it.
+    pub fn update<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+    ) -> Vec<TrackedCode> {
+        let codes = self.scanner.scan(image_buffer, decode_rgb);
+        self.update_codes(&codes)
+    }
+
+    /// Associate the TopCodes detected in the next frame with existing tracks, for when frames
+    /// are scanned separately.
+    pub fn update_codes(&mut self, codes: &[TopCode]) -> Vec<TrackedCode> {
+        self.events.clear();
+        for track in &mut self.tracks {
+            track.hits <<= 1;
+        }
+        let mut matched = vec![false; self.tracks.len()];
+        let mut result = Vec::with_capacity(codes.len());
+
+        for topcode in codes {
+            let code = match topcode.code {
+                Some(code) => code,
+                None => continue,
+            };
+
+            let max_distance = self.config.max_distance * topcode.unit;
+            let nearest = self
+                .tracks
+                .iter()
+                .enumerate()
+                .filter(|(i, track)| !matched[*i] && track.tracked.code == code)
+                .map(|(i, track)| {
+                    let frames = (self.frame - track.updated) as Real;
+                    (i, Self::predicted_distance(&track.tracked, frames, topcode))
+                })
+                .filter(|(_, distance)| *distance <= max_distance)
+                .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+            let track = match nearest {
+                Some((i, distance)) => {
+                    let track = &mut self.tracks[i];
+                    if let Some(max_jitter) = self.max_orientation_jitter {
+                        let jitter = Self::angle_between(track.tracked.orientation, topcode);
+                        if jitter > max_jitter && distance <= topcode.unit {
+                            continue;
+                        }
+                    }
+                    matched[i] = true;
+                    track.missed = 0;
+                    track.hits |= 1;
+                    let (x, y, orientation) = match &mut track.filters {
+                        Some([fx, fy, fo]) => (
+                            fx.update(topcode.x),
+                            fy.update(topcode.y),
+                            fo.update_angle(topcode.orientation),
+                        ),
+                        None => (topcode.x, topcode.y, topcode.orientation),
+                    };
+                    // The marker may have gone undetected since its last update.
+                    let frames = (self.frame - track.updated) as Real;
+                    track.tracked = TrackedCode {
+                        velocity: (
+                            (x - track.tracked.x) / frames,
+                            (y - track.tracked.y) / frames,
+                        ),
+                        x,
+                        y,
+                        orientation,
+                        ..track.tracked
+                    };
+                    track.updated = self.frame;
+                    Self::record(track, self.frame, self.config.history_len);
+                    track
+                }
+                None => {
+                    let tracked = TrackedCode {
+                        id: self.next_id,
+                        code,
+                        x: topcode.x,
+                        y: topcode.y,
+                        orientation: topcode.orientation,
+                        velocity: (0.0, 0.0),
+                    };
+                    self.next_id += 1;
+                    let mut track = Track {
+                        tracked,
+                        reported: tracked,
+                        missed: 0,
+                        updated: self.frame,
+                        hits: 1,
+                        confirmed: false,
+                        history: Vec::new(),
+                        filters: self.smoothing.map(|smoothing| {
+                            [
+                                Filter::new(smoothing, topcode.x),
+                                Filter::new(smoothing, topcode.y),
+                                Filter::new(smoothing, topcode.orientation),
+                            ]
+                        }),
+                    };
+                    Self::record(&mut track, self.frame, self.config.history_len);
+                    self.tracks.push(track);
+                    matched.push(true);
+                    self.tracks.last_mut().unwrap()
+                }
+            };
+
+            if !track.confirmed {
+                let window = match self.config.hit_window {
+                    64 => u64::MAX,
+                    window => (1 << window) - 1,
+                };
+                if ((track.hits & window).count_ones() as usize) < self.config.min_hits {
+                    continue;
+                }
+                track.confirmed = true;
+                track.reported = track.tracked;
+                self.events.push(TrackEvent::CodeAppeared(track.tracked));
+            } else {
+                let (distance, rotation) = self.event_thresholds;
+                let (dx, dy) = (
+                    track.tracked.x - track.reported.x,
+                    track.tracked.y - track.reported.y,
+                );
+                let turned =
+                    (track.tracked.orientation - track.reported.orientation).rem_euclid(2.0 * PI);
+                if (dx * dx + dy * dy).sqrt() > distance || turned.min(2.0 * PI - turned) > rotation
+                {
+                    track.reported = track.tracked;
+                    self.events.push(TrackEvent::CodeMoved(track.tracked));
+                }
+            }
+            result.push(track.tracked);
+        }
+
+        for (track, matched) in self.tracks.iter_mut().zip(&matched) {
+            if !matched {
+                track.missed += 1;
+            }
+        }
+        let max_missed = self.config.max_missed_frames;
+        let events = &mut self.events;
+        self.tracks.retain(|track| {
+            if track.missed > max_missed && track.confirmed {
+                events.push(TrackEvent::CodeRemoved(track.tracked));
+            }
+            track.missed <= max_missed
+        });
+        self.frame += 1;
+
+        result
+    }
+
+    /// Appends the track's latest detection to its history, dropping the oldest ones beyond
+    /// `history_len`.
+    fn record(track: &mut Track, frame: u64, history_len: usize) {
+        track.history.push(TrackSample {
+            frame,
+            x: track.tracked.x,
+            y: track.tracked.y,
+            orientation: track.tracked.orientation,
+        });
+        if track.history.len() > history_len {
+            let excess = track.history.len() - history_len;
+            track.history.drain(..excess);
+        }
+    }
+
+    /// Absolute difference between an orientation and a detection's orientation, in `[0, PI]`.
+    fn angle_between(orientation: Real, topcode: &TopCode) -> Real {
+        let delta = (topcode.orientation - orientation).rem_euclid(2.0 * PI);
+        delta.min(2.0 * PI - delta)
+    }
+
+    /// Distance between a detection and the position a track is expected to be at this frame,
+    /// `frames` after its last update.
+    fn predicted_distance(tracked: &TrackedCode, frames: Real, topcode: &TopCode) -> Real {
+        let dx = tracked.x + tracked.velocity.0 * frames - topcode.x;
+        let dy = tracked.y + tracked.velocity.1 * frames - topcode.y;
+        (dx * dx + dy * dy).sqrt()
+    }
+}
+
+/// Scans consecutive frames of a video, but only scans regions of interest around the codes found
+/// in the previous frame, which is much cheaper than scanning the full frame. To pick up codes
+/// which enter the frame, a full scan is performed every `redetect_interval` frames (starting with
+/// the first frame), as well as whenever no codes are being tracked.
+#[derive(Clone)]
+pub struct RoiTracker {
+    scanner: Scanner,
+    redetect_interval: usize,
+    roi_scale: Real,
+    frame: usize,
+    last: Vec<TopCode>,
+}
+
+impl RoiTracker {
+    /// Wraps `scanner`, scanning the full frame every `redetect_interval` frames and only the
+    /// regions around the previous frame's codes in between. An interval of 0 is treated as 1,
+    /// which scans every frame in full.
+    pub fn new(scanner: Scanner, redetect_interval: usize) -> Self {
+        Self {
+            scanner,
+            redetect_interval: redetect_interval.max(1),
+            roi_scale: 2.0,
+            frame: 0,
+            last: Vec::new(),
+        }
+    }
+
+    /// Sets the size of each region of interest, as a multiple of the tracked code's radius. The
+    /// default of 2.0 allows codes to move up to their own radius between frames.
+    pub fn set_roi_scale(&mut self, scale: Real) {
+        self.roi_scale = scale;
+    }
+
+    /// Scan the next frame and return the TopCodes found in it.
+    pub fn update<T: ?Sized>(
+        &mut self,
+        image_buffer: &T,
+        decode_rgb: impl Fn(&T, usize) -> (u32, u32, u32),
+    ) -> Vec<TopCode> {
+        let full_scan = self.frame.is_multiple_of(self.redetect_interval) || self.last.is_empty();
+        self.frame += 1;
+
+        let codes = if full_scan {
+            self.scanner.scan(image_buffer, decode_rgb)
+        } else {
+            let mut codes: Vec<TopCode> = Vec::with_capacity(self.last.len());
+            for last in &self.last {
+                let half = last.radius() * self.roi_scale;
+                let (x, y) = self.scanner.to_image_position(last.x, last.y);
+                for code in self
+                    .scanner
+                    .scan_around(image_buffer, &decode_rgb, x, y, half)
+                {
+                    if !codes.iter().any(|other| other.in_bullseye(code.x, code.y)) {
+                        codes.push(code);
+                    }
+                }
+            }
+            codes
+        };
+
+        self.last = codes.clone();
+        codes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::render_topcode;
+
+    #[test]
+    fn it_tracks_a_moving_marker_across_frames() {
+        let (width, height) = (300, 200);
+        let mut tracker = Tracker::new(Scanner::new(width, height), StabilityConfig::default());
+
+        let mut frames = Vec::new();
+        for frame in 0..4 {
+            let x = 80.0 + frame as Real * 12.0;
+            let buffer = render_topcode(55, 10.0, 0.0, (x, 100.0), (width, height));
+            frames.push(tracker.update(&buffer[..], |buffer, index| {
+                let v = buffer[index] as u32;
+                (v, v, v)
+            }));
+        }
+
+        for tracked in &frames {
+            assert_eq!(tracked.len(), 1);
+            assert_eq!(tracked[0].id, 0);
+            assert_eq!(tracked[0].code, 55);
+        }
+        let (vx, vy) = frames[3][0].velocity;
+        assert!((vx - 12.0).abs() < 1.0);
+        assert!(vy.abs() < 1.0);
+    }
+
+    #[test]
+    fn velocities_are_per_frame_across_missed_frames() {
+        let mut tracker = Tracker::new(Scanner::new(3, 3), StabilityConfig::default());
+        for frame in 0..6 {
+            // The marker moves 5 pixels per frame, but goes undetected in frames 2 and 3.
+            let codes = match frame {
+                2 | 3 => vec![],
+                _ => vec![TopCode::mock(
+                    31,
+                    10.0,
+                    0.0,
+                    50.0 + frame as Real * 5.0,
+                    50.0,
+                )],
+            };
+            for tracked in tracker.update_codes(&codes) {
+                assert_eq!(tracked.id, 0);
+                if frame > 0 {
+                    assert_eq!(tracked.velocity, (5.0, 0.0), "frame {frame}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_suppresses_orientation_flicker() {
+        let mut tracker = Tracker::new(Scanner::new(3, 3), StabilityConfig::default());
+        tracker.set_max_orientation_jitter(0.3);
+        let stable = TopCode::mock(55, 10.0, 0.1, 50.0, 50.0);
+        let flipped = TopCode::mock(55, 10.0, 2.6, 51.0, 50.0);
+        let moved = TopCode::mock(55, 10.0, 1.4, 68.0, 50.0);
+
+        assert_eq!(tracker.update_codes(&[stable]).len(), 1);
+        assert!(tracker.update_codes(&[flipped]).is_empty());
+        let tracked = tracker.update_codes(&[stable]);
+        assert_eq!(tracked.len(), 1);
+        assert_eq!(tracked[0].id, 0);
+        assert_eq!(tracked[0].orientation, 0.1);
+
+        // Orientation may change along with a large movement.
+        let tracked = tracker.update_codes(&[moved]);
+        assert_eq!(tracked.len(), 1);
+        assert_eq!(tracked[0].id, 0);
+        assert_eq!(tracked[0].orientation, 1.4);
+
+        // Rejected detections neither confirm a new track nor keep it alive.
+        let mut tracker = Tracker::new(
+            Scanner::new(3, 3),
+            StabilityConfig {
+                min_hits: 2,
+                hit_window: 2,
+                ..Default::default()
+            },
+        );
+        tracker.set_max_orientation_jitter(0.3);
+        tracker.update_codes(&[stable]);
+        for _ in 0..5 {
+            assert!(tracker.update_codes(&[flipped]).is_empty());
+            assert_eq!(tracker.tracks().count(), 0);
+            assert!(tracker.history(0).is_some());
+        }
+        tracker.update_codes(&[flipped]);
+        assert_eq!(tracker.history(0), None);
+    }
+
+    #[test]
+    fn roi_tracking_finds_new_markers_on_full_scans() {
+        let (width, height) = (300, 200);
+        let decode_gray = |buffer: &Vec<u8>, index: usize| {
+            let v = buffer[index] as u32;
+            (v, v, v)
+        };
+        let mut tracker = RoiTracker::new(Scanner::new(width, height), 3);
+
+        let mut codes = Vec::new();
+        for frame in 0..4 {
+            let mut buffer = render_topcode(
+                55,
+                8.0,
+                0.0,
+                (80.0 + frame as Real * 8.0, 100.0),
+                (width, height),
+            );
+            if frame > 0 {
+                let other = render_topcode(93, 8.0, 0.0, (230.0, 100.0), (width, height));
+                for (pixel, other) in buffer.iter_mut().zip(other) {
+                    *pixel = (*pixel).min(other);
+                }
+            }
+            let mut found = tracker
+                .update(&buffer, decode_gray)
+                .iter()
+                .map(|code| code.code)
+                .collect::<Vec<_>>();
+            found.sort();
+            codes.push(found);
+        }
+
+        // The new marker appears in frame 1, but is only found by the full scan in frame 3.
+        assert_eq!(codes[0], vec![Some(55)]);
+        assert_eq!(codes[1], vec![Some(55)]);
+        assert_eq!(codes[2], vec![Some(55)]);
+        assert_eq!(codes[3], vec![Some(55), Some(93)]);
+    }
+
+    #[test]
+    fn impossible_hit_requirements_are_clamped() {
+        for (min_hits, hit_window) in [(1, 0), (3, 2), (100, 100)] {
+            let mut tracker = Tracker::new(
+                Scanner::new(3, 3),
+                StabilityConfig {
+                    min_hits,
+                    hit_window,
+                    ..Default::default()
+                },
+            );
+            let codes = vec![TopCode::mock(31, 10.0, 0.0, 50.0, 50.0)];
+            let reported = (0..64).find(|_| {
+                tracker.update_codes(&codes);
+                tracker.tracks().count() == 1
+            });
+            assert!(reported.is_some(), "{min_hits} of {hit_window}");
+        }
+    }
+
+    #[test]
+    fn tracks_keep_a_history_of_their_detections() {
+        let mut tracker = Tracker::new(
+            Scanner::new(3, 3),
+            StabilityConfig {
+                history_len: 3,
+                ..Default::default()
+            },
+        );
+        for frame in 0..5 {
+            let x = 50.0 + frame as Real * 5.0;
+            let codes = if frame == 2 {
+                // A brief dropout keeps the track alive.
+                vec![]
+            } else {
+                vec![TopCode::mock(31, 10.0, 0.1, x, 50.0)]
+            };
+            tracker.update_codes(&codes);
+        }
+
+        assert_eq!(tracker.tracks().count(), 1);
+        let history = tracker.history(0).unwrap();
+        let frames: Vec<_> = history.iter().map(|sample| sample.frame).collect();
+        assert_eq!(frames, vec![1, 3, 4]);
+        assert_eq!(history[2].x, 70.0);
+        assert_eq!(history[2].orientation, 0.1);
+        assert_eq!(tracker.history(1), None);
+    }
+
+    #[test]
+    fn smoothing_steadies_jittery_detections() {
+        let mut tracker = Tracker::new(Scanner::new(3, 3), StabilityConfig::default());
+        tracker.set_smoothing(Smoothing::OneEuro {
+            min_cutoff: 0.05,
+            beta: 0.0,
+        });
+        let mut last = None;
+        for frame in 0..10 {
+            let jitter = if frame % 2 == 0 { 2.0 } else { -2.0 };
+            let tracked =
+                tracker.update_codes(&[TopCode::mock(31, 10.0, 0.0, 50.0 + jitter, 50.0)]);
+            assert_eq!(tracked.len(), 1);
+            assert_eq!(tracked[0].id, 0);
+            last = Some(tracked[0]);
+        }
+        assert!((last.unwrap().x - 50.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn trackers_emit_events_for_changes() {
+        let mut tracker = Tracker::new(
+            Scanner::new(3, 3),
+            StabilityConfig {
+                max_missed_frames: 1,
+                ..Default::default()
+            },
+        );
+        let kinds = |tracker: &Tracker| {
+            tracker
+                .events()
+                .iter()
+                .map(|event| match event {
+                    TrackEvent::CodeAppeared(tracked) => ("appeared", tracked.id),
+                    TrackEvent::CodeMoved(tracked) => ("moved", tracked.id),
+                    TrackEvent::CodeRemoved(tracked) => ("removed", tracked.id),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        tracker.update_codes(&[TopCode::mock(31, 10.0, 0.0, 50.0, 50.0)]);
+        assert_eq!(kinds(&tracker), vec![("appeared", 0)]);
+        // Small movements are debounced until they add up.
+        tracker.update_codes(&[TopCode::mock(31, 10.0, 0.0, 51.0, 50.0)]);
+        assert!(tracker.events().is_empty());
+        tracker.update_codes(&[TopCode::mock(31, 10.0, 0.0, 52.5, 50.0)]);
+        assert_eq!(kinds(&tracker), vec![("moved", 0)]);
+        tracker.update_codes(&[TopCode::mock(31, 10.0, 0.2, 52.5, 50.0)]);
+        assert_eq!(kinds(&tracker), vec![("moved", 0)]);
+
+        // Removal waits for the missed frames to run out.
+        tracker.update_codes(&[]);
+        assert!(tracker.events().is_empty());
+        tracker.update_codes(&[]);
+        assert_eq!(kinds(&tracker), vec![("removed", 0)]);
+        assert_eq!(
+            tracker.events()[0],
+            TrackEvent::CodeRemoved(TrackedCode {
+                id: 0,
+                code: 31,
+                x: 52.5,
+                y: 50.0,
+                orientation: 0.2,
+                velocity: (0.0, 0.0),
+            })
+        );
+    }
+
+    #[test]
+    fn markers_are_reported_once_seen_in_enough_frames() {
+        let mut tracker = Tracker::new(
+            Scanner::new(3, 3),
+            StabilityConfig {
+                max_missed_frames: 2,
+                min_hits: 2,
+                hit_window: 3,
+                ..Default::default()
+            },
+        );
+        let marker = [TopCode::mock(31, 10.0, 0.0, 50.0, 50.0)];
+        let flicker = [TopCode::mock(93, 10.0, 0.0, 150.0, 50.0)];
+
+        // A single-frame false positive is never reported.
+        assert!(tracker.update_codes(&flicker).is_empty());
+        assert!(tracker.update_codes(&marker).is_empty());
+        assert!(tracker.update_codes(&[]).is_empty());
+        assert_eq!(tracker.tracks().count(), 0);
+        let tracked = tracker.update_codes(&marker);
+        assert_eq!(tracked.len(), 1);
+        assert_eq!(tracked[0].code, 31);
+        assert!(matches!(
+            tracker.events(),
+            [TrackEvent::CodeAppeared(TrackedCode { code: 31, .. })]
+        ));
+
+        // The marker survives two missed frames.
+        tracker.update_codes(&[]);
+        tracker.update_codes(&[]);
+        assert_eq!(tracker.tracks().count(), 1);
+        assert_eq!(tracker.update_codes(&marker)[0].id, tracked[0].id);
+        assert!(tracker.events().is_empty());
+        assert!(tracker.update_codes(&flicker).is_empty());
+        assert!(tracker.events().is_empty());
+    }
+
+    #[test]
+    fn jitter_gated_frames_count_as_missed_for_persistence() {
+        let mut tracker = Tracker::new(
+            Scanner::new(3, 3),
+            StabilityConfig {
+                max_missed_frames: 2,
+                min_hits: 2,
+                hit_window: 3,
+                ..Default::default()
+            },
+        );
+        tracker.set_max_orientation_jitter(0.3);
+        let marker = [TopCode::mock(31, 10.0, 0.0, 50.0, 50.0)];
+        let reflection = [TopCode::mock(31, 10.0, 2.5, 51.0, 50.0)];
+
+        // Gated-out frames are no sightings, so two of the last three frames are never reached.
+        tracker.update_codes(&marker);
+        tracker.update_codes(&reflection);
+        tracker.update_codes(&reflection);
+        assert_eq!(tracker.tracks().count(), 0);
+        assert_eq!(tracker.update_codes(&marker).len(), 0);
+        assert_eq!(tracker.update_codes(&marker).len(), 1);
+        let id = tracker.tracks().next().unwrap().id;
+
+        // Nor do they keep a confirmed marker alive beyond two missed frames.
+        tracker.update_codes(&reflection);
+        tracker.update_codes(&reflection);
+        assert_eq!(tracker.tracks().count(), 1);
+        tracker.update_codes(&reflection);
+        assert_eq!(tracker.tracks().count(), 0);
+        assert!(matches!(
+            tracker.events(),
+            [TrackEvent::CodeRemoved(TrackedCode { id: removed, .. })] if *removed == id
+        ));
+    }
+
+    #[test]
+    fn it_assigns_new_ids_to_new_and_lost_markers() {
+        let mut tracker = Tracker::new(
+            Scanner::new(3, 3),
+            StabilityConfig {
+                max_missed_frames: 1,
+                ..Default::default()
+            },
+        );
+        let first = TopCode::mock(31, 10.0, 0.0, 50.0, 50.0);
+        let second = TopCode::mock(93, 10.0, 0.0, 150.0, 50.0);
+
+        let ids = |tracked: Vec<TrackedCode>| tracked.iter().map(|t| t.id).collect::<Vec<_>>();
+        assert_eq!(ids(tracker.update_codes(&[first])), vec![0]);
+        assert_eq!(ids(tracker.update_codes(&[first, second])), vec![0, 1]);
+        assert_eq!(ids(tracker.update_codes(&[second])), vec![1]);
+        assert_eq!(ids(tracker.update_codes(&[second])), vec![1]);
+        assert_eq!(ids(tracker.update_codes(&[first, second])), vec![2, 1]);
+    }
+}
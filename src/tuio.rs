@@ -0,0 +1,251 @@
+//! Sends tracked TopCodes as TUIO object messages over UDP, so that applications built for
+//! reacTIVision and other tangible tabletop trackers can use TopCodes directly.
+//!
+//! TUIO 1.1 sends `/tuio/2Dobj` messages and TUIO 2.0 sends `/tuio2/tok` messages, bundled per
+//! frame with the list of codes alive in the frame. Positions are normalized to the size of the
+//! image, and the symbol's code is sent as the class ID of the object.
+
+use std::{
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+};
+
+use crate::{
+    osc::{bundle, message, now, Arg},
+    topcode::{Real, PI},
+    tracking::TrackedCode,
+};
+
+/// The port TUIO clients listen on by default.
+pub const DEFAULT_PORT: u16 = 3333;
+
+/// Version of the TUIO protocol to send.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TuioVersion {
+    /// TUIO 1.1, understood by most existing clients
+    #[default]
+    V1,
+    /// TUIO 2.0
+    V2,
+}
+
+/// Sends the codes tracked in each frame to a TUIO client.
+#[derive(Debug)]
+pub struct TuioSender {
+    socket: UdpSocket,
+    encoder: TuioEncoder,
+}
+
+impl TuioSender {
+    /// Creates a sender for images of the given dimensions, sending to `address`, e.g.
+    /// `("127.0.0.1", DEFAULT_PORT)`.
+    pub fn connect(address: impl ToSocketAddrs, width: usize, height: usize) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(address)?;
+        Ok(Self {
+            socket,
+            encoder: TuioEncoder::new(width, height),
+        })
+    }
+
+    /// The encoder of the messages, to change the version of the protocol, the frame rate or the
+    /// source.
+    pub fn encoder_mut(&mut self) -> &mut TuioEncoder {
+        &mut self.encoder
+    }
+
+    /// Sends the codes tracked in a frame, e.g. by a [Tracker](crate::Tracker), as one bundle.
+    pub fn send(&mut self, codes: &[TrackedCode]) -> io::Result<()> {
+        let bundle = self.encoder.encode(codes);
+        self.socket.send(&bundle)?;
+        Ok(())
+    }
+}
+
+/// Encodes the codes tracked in each frame as TUIO bundles.
+#[derive(Clone, Debug)]
+pub struct TuioEncoder {
+    version: TuioVersion,
+    width: usize,
+    height: usize,
+    frame_rate: Real,
+    source: String,
+    frame: i32,
+}
+
+impl TuioEncoder {
+    /// Creates an encoder for images of the given dimensions.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            version: TuioVersion::V1,
+            width,
+            height,
+            frame_rate: 30.0,
+            source: String::from("topcodes"),
+            frame: 0,
+        }
+    }
+
+    /// Sets the version of the protocol. Defaults to TUIO 1.1.
+    pub fn set_version(&mut self, version: TuioVersion) {
+        self.version = version;
+    }
+
+    /// Sets the number of frames per second, to convert the velocities of the codes from pixels
+    /// per frame to TUIO's units per second. Defaults to 30.
+    pub fn set_frame_rate(&mut self, frame_rate: Real) {
+        self.frame_rate = frame_rate;
+    }
+
+    /// Sets the name the messages are sent from, which lets clients tell several trackers apart.
+    /// Defaults to "topcodes".
+    pub fn set_source(&mut self, source: &str) {
+        self.source = String::from(source);
+    }
+
+    /// Encodes the codes tracked in the next frame as an OSC bundle.
+    pub fn encode(&mut self, codes: &[TrackedCode]) -> Vec<u8> {
+        self.frame = self.frame.wrapping_add(1);
+        let (width, height) = (self.width as Real, self.height as Real);
+        // Session IDs of TUIO are 32 bits. The float casts are no-ops with the `f32` feature.
+        #[allow(clippy::unnecessary_cast)]
+        let objects = codes.iter().map(|code| {
+            let (vx, vy) = code.velocity;
+            (
+                code.id as i32,
+                code.code as i32,
+                (code.x / width) as f32,
+                (code.y / height) as f32,
+                code.orientation.rem_euclid(2.0 * PI) as f32,
+                (vx / width * self.frame_rate) as f32,
+                (vy / height * self.frame_rate) as f32,
+            )
+        });
+        let alive = codes.iter().map(|code| Arg::Int(code.id as i32));
+
+        let mut messages = Vec::new();
+        match self.version {
+            TuioVersion::V1 => {
+                let address = "/tuio/2Dobj";
+                let source = format!("{}@localhost", self.source);
+                messages.push(message(address, [Arg::Str("source"), Arg::Str(&source)]));
+                messages.push(message(
+                    address,
+                    [Arg::Str("alive")].into_iter().chain(alive),
+                ));
+                for (session, class, x, y, angle, vx, vy) in objects {
+                    // Rotation speed, motion acceleration and rotation acceleration are not
+                    // tracked.
+                    messages.push(message(
+                        address,
+                        [
+                            Arg::Str("set"),
+                            Arg::Int(session),
+                            Arg::Int(class),
+                            Arg::Float(x),
+                            Arg::Float(y),
+                            Arg::Float(angle),
+                            Arg::Float(vx),
+                            Arg::Float(vy),
+                            Arg::Float(0.0),
+                            Arg::Float(0.0),
+                            Arg::Float(0.0),
+                        ],
+                    ));
+                }
+                messages.push(message(address, [Arg::Str("fseq"), Arg::Int(self.frame)]));
+            }
+            TuioVersion::V2 => {
+                let dimensions = ((self.width as i32) << 16) | (self.height as i32 & 0xffff);
+                messages.push(message(
+                    "/tuio2/frm",
+                    [
+                        Arg::Int(self.frame),
+                        Arg::Time(now()),
+                        Arg::Int(dimensions),
+                        Arg::Str(&self.source),
+                    ],
+                ));
+                for (session, class, x, y, angle, vx, vy) in objects {
+                    messages.push(message(
+                        "/tuio2/tok",
+                        [
+                            Arg::Int(session),
+                            Arg::Int(0),
+                            Arg::Int(class),
+                            Arg::Float(x),
+                            Arg::Float(y),
+                            Arg::Float(angle),
+                            Arg::Float(vx),
+                            Arg::Float(vy),
+                            Arg::Float(0.0),
+                            Arg::Float(0.0),
+                            Arg::Float(0.0),
+                        ],
+                    ));
+                }
+                messages.push(message("/tuio2/alv", alive));
+            }
+        }
+        bundle(&messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracked(id: u64, code: u32, x: Real, y: Real) -> TrackedCode {
+        TrackedCode {
+            id,
+            code,
+            x,
+            y,
+            orientation: 1.0 - 2.0 * PI,
+            velocity: (2.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn tracked_codes_are_sent_as_tuio_bundles() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut sender = TuioSender::connect(receiver.local_addr().unwrap(), 640, 480).unwrap();
+        sender
+            .send(&[tracked(4, 55, 320.0, 120.0), tracked(9, 93, 64.0, 48.0)])
+            .unwrap();
+
+        let mut buffer = [0; 1024];
+        let length = receiver.recv(&mut buffer).unwrap();
+        let mut expected = message(
+            "/tuio/2Dobj",
+            [
+                Arg::Str("set"),
+                Arg::Int(4),
+                Arg::Int(55),
+                Arg::Float(0.5),
+                Arg::Float(0.25),
+                Arg::Float(1.0),
+                Arg::Float(2.0 / 640.0 * 30.0),
+                Arg::Float(0.0),
+                Arg::Float(0.0),
+                Arg::Float(0.0),
+                Arg::Float(0.0),
+            ],
+        );
+        let set = &buffer[..length]
+            .windows(expected.len())
+            .position(|window| window == expected);
+        assert!(set.is_some());
+
+        // The frame ends with its sequence number.
+        expected = message("/tuio/2Dobj", [Arg::Str("fseq"), Arg::Int(1)]);
+        assert!(buffer[..length].ends_with(&expected));
+
+        // TUIO 2.0 lists the alive codes at the end of the frame.
+        sender.encoder_mut().set_version(TuioVersion::V2);
+        sender.send(&[tracked(4, 55, 320.0, 120.0)]).unwrap();
+        let length = receiver.recv(&mut buffer).unwrap();
+        let alive = message("/tuio2/alv", [Arg::Int(4)]);
+        assert!(buffer[..length].ends_with(&alive));
+    }
+}
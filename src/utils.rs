@@ -1,6 +1,15 @@
+use alloc::{format, string::String};
+
 use crate::topcode::SECTORS;
 
+#[cfg(test)]
+use alloc::{vec, vec::Vec};
+
+#[cfg(test)]
+use crate::topcode::{Real, ARC, PI};
+
 /// Debug method that prints the 13 least significant bits of an integer.
+#[allow(dead_code)]
 pub(crate) fn print_bits(bits: isize) -> String {
     let mut lsb = String::new();
 
@@ -18,6 +27,90 @@ pub(crate) fn print_bits(bits: isize) -> String {
     format!("{}= {}", lsb, bits)
 }
 
+/// Renders a grayscale TopCode onto a white `width` x `height` image for use in tests. Sector `i`
+/// holds bit `i` of `code` and spans the angles `[i * ARC, (i + 1) * ARC)` offset by `orientation`.
+#[cfg(test)]
+pub(crate) fn render_topcode(
+    code: u32,
+    unit: Real,
+    orientation: Real,
+    (cx, cy): (Real, Real),
+    (width, height): (usize, usize),
+) -> Vec<u8> {
+    let mut buffer = vec![255; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let dx = x as Real + 0.5 - cx;
+            let dy = y as Real + 0.5 - cy;
+            let r = (dx * dx + dy * dy).sqrt() / unit;
+            let white = if r < 1.0 {
+                true
+            } else if r < 2.0 {
+                false
+            } else if r < 3.0 {
+                true
+            } else if r < 4.0 {
+                let angle = (dy.atan2(dx) - orientation).rem_euclid(2.0 * PI);
+                let sector = (angle / ARC) as usize % SECTORS;
+                (code >> sector) & 0x01 == 1
+            } else {
+                true
+            };
+            if !white {
+                buffer[y * width + x] = 0;
+            }
+        }
+    }
+    buffer
+}
+
+/// Scatters `size` x `size` black or white squares over roughly `density` of a grayscale image.
+/// The noise is generated from `seed`, so results are reproducible.
+#[cfg(test)]
+pub(crate) fn add_noise(buffer: &mut [u8], width: usize, seed: u64, density: f64, size: usize) {
+    let height = buffer.len() / width;
+    let mut state = seed;
+    let mut next = || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 33) as f64 / (1u64 << 31) as f64
+    };
+
+    let count = (density * (width * height) as f64 / (size * size) as f64) as usize;
+    for _ in 0..count {
+        let x = (next() * (width - size) as f64) as usize;
+        let y = (next() * (height - size) as f64) as usize;
+        let value = if next() < 0.5 { 0 } else { 255 };
+        for j in 0..size {
+            for i in 0..size {
+                buffer[(y + j) * width + x + i] = value;
+            }
+        }
+    }
+}
+
+/// Blurs a grayscale image by averaging each pixel with its neighbors within `radius` pixels,
+/// simulating an out-of-focus camera.
+#[cfg(test)]
+pub(crate) fn box_blur(buffer: &[u8], width: usize, radius: usize) -> Vec<u8> {
+    let height = buffer.len() / width;
+    let mut blurred = vec![0; buffer.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let (mut sum, mut count) = (0u32, 0u32);
+            for j in y.saturating_sub(radius)..(y + radius + 1).min(height) {
+                for i in x.saturating_sub(radius)..(x + radius + 1).min(width) {
+                    sum += buffer[j * width + i] as u32;
+                    count += 1;
+                }
+            }
+            blurred[y * width + x] = (sum / count) as u8;
+        }
+    }
+    blurred
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;